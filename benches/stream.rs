@@ -0,0 +1,107 @@
+//! The throughput of a `ChokeStream` compared to the channel it wraps, and of a `ChokeChain` compared to nested
+//! streams.
+//!
+//! Run with `cargo bench`. The runtime's clock is paused, so delays are skipped and only the overhead of the shaping is
+//! measured.
+
+use bytes::Bytes;
+use chokepoint::{
+    ChokeSettings,
+    ChokeStream,
+    Latency,
+};
+use criterion::{
+    criterion_group,
+    criterion_main,
+    BatchSize,
+    Criterion,
+    Throughput,
+};
+use futures::StreamExt as _;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+const ITEMS: u64 = 10_000;
+
+/// A channel that holds all items and is closed, so that the stream ends after the last item.
+fn channel() -> UnboundedReceiverStream<Bytes> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    for _ in 0..ITEMS {
+        tx.send(Bytes::from_static(b"payload")).unwrap();
+    }
+    UnboundedReceiverStream::new(rx)
+}
+
+fn stream(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("stream");
+    group.throughput(Throughput::Elements(ITEMS));
+    group.bench_function("channel", |b| {
+        b.iter_batched(channel, |rx| runtime.block_on(rx.count()), BatchSize::LargeInput)
+    });
+
+    let mut bench_settings = |name: &str, settings: fn() -> ChokeSettings| {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                channel,
+                |rx| runtime.block_on(ChokeStream::new(rx, settings()).count()),
+                BatchSize::LargeInput,
+            )
+        });
+    };
+    // No random numbers are drawn for probabilities of zero
+    bench_settings("passthrough", ChokeSettings::default);
+    bench_settings("probabilities", || {
+        ChokeSettings::default()
+            .set_drop_probability(Some(0.01))
+            .set_corrupt_probability(Some(0.01))
+            .set_duplicate_probability(Some(0.01))
+            .set_seed(Some(1))
+    });
+    bench_settings("latency", || {
+        ChokeSettings::default()
+            .set_latency(Some(Latency::Normal {
+                mean: Duration::from_millis(20),
+                std_dev: Duration::from_millis(5),
+                max: Duration::from_millis(100),
+            }))
+            .set_seed(Some(1))
+    });
+    bench_settings("bandwidth_limit", || {
+        ChokeSettings::default().set_bandwidth_limit(Some(10_000))
+    });
+
+    // Three hops, once as nested streams and once as a chain
+    group.bench_function("nested", |b| {
+        b.iter_batched(
+            channel,
+            |rx| {
+                let inner = ChokeStream::new(ChokeStream::new(rx, Default::default()), Default::default());
+                runtime.block_on(ChokeStream::new(inner, Default::default()).count())
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("chain", |b| {
+        b.iter_batched(
+            channel,
+            |rx| {
+                let chain = ChokeStream::new(rx, Default::default())
+                    .chain(Default::default())
+                    .chain(Default::default());
+                runtime.block_on(chain.count())
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, stream);
+criterion_main!(benches);
@@ -0,0 +1,151 @@
+//! Configuration files for `--config`, which define a run like its flags do, so that experiments can be versioned
+//! alongside the code under test, e.g.
+//!
+//! ```toml
+//! mode = "tcp"
+//! listen = "127.0.0.1:9000"
+//! upstream = "127.0.0.1:8000"
+//! preset = "3g"
+//! drop_prob = 0.02
+//! shape_host = ["api.example.com"]
+//! output = "events.csv"
+//!
+//! # The steps of a scenario, like a `--scenario` file
+//! [[step]]
+//! at = 10
+//! bandwidth_limit = "20KB"
+//! ```
+//!
+//! The keys are the names of the flags, with `_` instead of `-`, or the keys of the scenario steps, e.g. `drop` or
+//! `drop_prob` for `--drop`. The flags given on the command line override the values of the file, repeated flags like
+//! `--shape-host` add to them.
+
+use crate::{
+    scenario::Scenario,
+    Args,
+};
+use clap::{
+    error::ErrorKind,
+    Arg,
+    Command,
+    CommandFactory as _,
+};
+use std::ffi::OsString;
+
+/// The command of the CLI. Flags that are given again override the earlier values, e.g. those of a config file.
+pub fn command() -> Command {
+    Args::command().args_override_self(true)
+}
+
+/// The command line of the run: the flags of the `--config` file, if any, followed by the given ones, so that these
+/// override the file, and the steps of a scenario in the file.
+pub fn command_line() -> (Vec<OsString>, Option<Scenario>) {
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+    let Some(path) = config_path(&args) else {
+        return (args, None);
+    };
+    let (flags, scenario) = load(&path).unwrap_or_else(|err| {
+        command()
+            .bin_name("chokepoint")
+            .error(ErrorKind::InvalidValue, err)
+            .exit()
+    });
+    let mode_given = command()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+        .is_ok_and(|matches| matches.contains_id("mode"));
+    let mut mode = Vec::new();
+    let mut file_flags = Vec::new();
+    for (id, values) in flags {
+        match id.as_str() {
+            "mode" if mode_given => {}
+            "mode" => mode = values,
+            _ => file_flags.extend(values),
+        }
+    }
+    // The mode comes first, where the positional argument is expected
+    args.splice(1..1, mode.into_iter().chain(file_flags));
+    (args, scenario)
+}
+
+/// The value of `--config`, parsed before the other flags since they depend on it.
+fn config_path(args: &[OsString]) -> Option<String> {
+    let mut args = args.iter().skip(1).map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+        if arg == "--config" {
+            return args.next().map(|path| path.into_owned());
+        }
+    }
+    None
+}
+
+/// The command line arguments of a flag by the id of its argument.
+type Flag = (String, Vec<OsString>);
+
+/// The flags of the config file at `path`, and the steps of its scenario.
+fn load(path: &str) -> Result<(Vec<Flag>, Option<Scenario>), String> {
+    let contents = std::fs::read_to_string(path).map_err(|err| format!("failed to read {path}: {err}"))?;
+    let mut table = toml::from_str::<toml::Table>(&contents).map_err(|err| format!("invalid config {path}: {err}"))?;
+    let scenario = match table.remove("step") {
+        Some(steps) => {
+            let steps = toml::Table::from_iter([("step".to_string(), steps)]);
+            Some(Scenario::parse(&steps.to_string()).map_err(|err| format!("{path}: {err}"))?)
+        }
+        None => None,
+    };
+
+    let command = command();
+    let mut flags = Vec::new();
+    for (key, value) in table {
+        let arg = find(&command, &key).ok_or_else(|| format!("{path}: unknown key `{key}`"))?;
+        flags.push((
+            arg.get_id().to_string(),
+            flag(arg, &key, value).map_err(|err| format!("{path}: {err}"))?,
+        ));
+    }
+    Ok((flags, scenario))
+}
+
+/// The argument of `key`, by its long flag or its id.
+fn find<'a>(command: &'a Command, key: &str) -> Option<&'a Arg> {
+    command.get_arguments().find(|arg| {
+        !matches!(arg.get_id().as_str(), "config" | "runs" | "help")
+            && (arg.get_id() == key || arg.get_long().is_some_and(|long| long.replace('-', "_") == key))
+    })
+}
+
+/// The command line arguments that set `arg` to `value`.
+fn flag(arg: &Arg, key: &str, value: toml::Value) -> Result<Vec<OsString>, String> {
+    let values = match value {
+        toml::Value::Array(values) => values,
+        value => vec![value],
+    };
+    let mut flag = Vec::new();
+    for value in values {
+        let value = match value {
+            toml::Value::String(value) => value,
+            toml::Value::Integer(value) => value.to_string(),
+            toml::Value::Float(value) => value.to_string(),
+            // Switches like `--verbose` are only given if they are set
+            toml::Value::Boolean(value) if !arg.get_action().takes_values() => {
+                if value {
+                    flag.push(format!("--{}", arg.get_long().expect("switches are long flags")).into());
+                }
+                continue;
+            }
+            value => return Err(format!("invalid value for `{key}`: {value}")),
+        };
+        match arg.get_long() {
+            Some(long) => flag.push(format!("--{long}={value}").into()),
+            None if arg.is_positional() => flag.push(value.into()),
+            None => flag.push(format!("-{}{value}", arg.get_short().expect("flags have a name")).into()),
+        }
+    }
+    Ok(flag)
+}
@@ -0,0 +1,388 @@
+//! The HTTP API of `--control-addr`, to change the shaping of a proxy while it runs, e.g. from a test orchestrator:
+//!
+//! - `GET /settings` returns the current values of the shaping flags as JSON, with the keys of the scenario steps
+//! - `PUT /settings` changes some of them for all connections, with a JSON object of scenario step keys without `at`,
+//!   e.g. `{"mean": 300, "drop_prob": 0.05, "bandwidth_limit": "100KB"}`, and returns the new values
+//! - `POST /scenario` plays back a TOML scenario from now on, replacing the scenario that is playing
+//! - `GET /stats` returns the statistics of every running stream, and those of the ended streams summed up by direction
+//!
+//! Connections opened later start with the current values. Every request is answered on a connection of its own.
+
+use crate::{
+    scenario::{
+        Flags,
+        Scenario,
+        Step,
+    },
+    Shaping,
+    MAX_HTTP_HEAD_SIZE,
+};
+use chokepoint::{
+    ChokeSettings,
+    ChokeStats,
+};
+use serde::{
+    Deserialize as _,
+    Serialize,
+};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{
+        AsyncBufReadExt as _,
+        AsyncReadExt as _,
+        AsyncWriteExt as _,
+        BufReader,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    sync::watch,
+    task::JoinHandle,
+    time::Instant,
+};
+
+/// The largest request body the API accepts.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// How often a running stream publishes its statistics, while it emits items.
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The statistics of the streams of a proxy, for the API and the metrics. Only collected if they are enabled, by
+/// [`Publisher`]s every second and when the streams end. The statistics of ended streams are summed up by direction, so
+/// that they don't pile up in long runs.
+#[derive(Clone, Default)]
+pub struct StreamStats(Option<Arc<Mutex<Streams>>>);
+
+#[derive(Default)]
+struct Streams {
+    /// The statistics of the running streams by their labels.
+    running: BTreeMap<String, ChokeStats>,
+    ended: BTreeMap<String, Direction>,
+}
+
+/// The statistics of the streams of a direction summed up, `up` from the clients or `down` to them.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Direction {
+    pub streams: usize,
+    #[serde(flatten)]
+    pub stats: ChokeStats,
+}
+
+/// Publishes the statistics of a stream to [`StreamStats`].
+pub struct Publisher {
+    streams: Option<Arc<Mutex<Streams>>>,
+    next: Option<Instant>,
+}
+
+impl StreamStats {
+    pub fn enabled() -> Self {
+        Self(Some(Arc::default()))
+    }
+
+    /// A publisher for a stream that starts now.
+    pub fn publisher(&self) -> Publisher {
+        Publisher {
+            streams: self.0.clone(),
+            next: None,
+        }
+    }
+
+    /// The statistics of the running streams.
+    pub fn running(&self) -> Vec<ChokeStats> {
+        self.0.as_ref().map_or_else(Vec::new, |streams| {
+            streams.lock().unwrap().running.values().cloned().collect()
+        })
+    }
+
+    /// The statistics of the running and the ended streams summed up by direction.
+    pub fn directions(&self) -> BTreeMap<String, Direction> {
+        let Some(streams) = &self.0 else {
+            return BTreeMap::new();
+        };
+        let streams = streams.lock().unwrap();
+        let mut directions = streams.ended.clone();
+        for stats in streams.running.values() {
+            directions.entry(direction(stats)).or_default().add(stats);
+        }
+        directions
+    }
+
+    /// The statistics of the ended streams summed up by direction.
+    pub fn ended(&self) -> BTreeMap<String, Direction> {
+        self.0
+            .as_ref()
+            .map_or_else(BTreeMap::new, |streams| streams.lock().unwrap().ended.clone())
+    }
+}
+
+/// The direction of a stream of a proxy, labelled with the connection number and the direction, e.g. `3-up`.
+fn direction(stats: &ChokeStats) -> String {
+    let label = stats.label.as_deref().unwrap_or_default();
+    label
+        .rsplit_once('-')
+        .map_or(label, |(_, direction)| direction)
+        .to_string()
+}
+
+impl Direction {
+    fn add(&mut self, stats: &ChokeStats) {
+        let total = &mut self.stats;
+        self.streams += 1;
+        total.received += stats.received;
+        total.emitted += stats.emitted;
+        total.dropped += stats.dropped;
+        total.dropped_by.random += stats.dropped_by.random;
+        total.dropped_by.bandwidth_limit += stats.dropped_by.bandwidth_limit;
+        total.dropped_by.hook += stats.dropped_by.hook;
+        total.dropped_by.replay += stats.dropped_by.replay;
+        total.dropped_by.expired += stats.dropped_by.expired;
+        total.expired += stats.expired;
+        total.corrupted += stats.corrupted;
+        total.duplicated += stats.duplicated;
+        total.coalesced += stats.coalesced;
+        total.marked += stats.marked;
+        total.emitted_bytes += stats.emitted_bytes;
+        total.goodput_bytes += stats.goodput_bytes;
+        total.corrupted_bytes += stats.corrupted_bytes;
+        total.queued += stats.queued;
+        total.queued_bytes += stats.queued_bytes;
+        total.delayed += stats.delayed;
+        total.max_queued = total.max_queued.max(stats.max_queued);
+        total.max_queued_bytes = total.max_queued_bytes.max(stats.max_queued_bytes);
+        total.max_delayed = total.max_delayed.max(stats.max_delayed);
+    }
+}
+
+impl Publisher {
+    /// Publish the statistics of the running stream, unless they were published less than a second ago. They are only
+    /// taken if they are published.
+    pub fn update(&mut self, stats: impl FnOnce() -> ChokeStats) {
+        let Some(streams) = &self.streams else {
+            return;
+        };
+        let now = Instant::now();
+        if self.next.is_some_and(|next| now < next) {
+            return;
+        }
+        self.next = Some(now + PUBLISH_INTERVAL);
+        let stats = stats();
+        let label = stats.label.clone().unwrap_or_default();
+        streams.lock().unwrap().running.insert(label, stats);
+    }
+
+    /// Add the final statistics of the stream to those of the ended streams of its direction.
+    pub fn end(self, stats: &ChokeStats) {
+        let Some(streams) = &self.streams else {
+            return;
+        };
+        let mut streams = streams.lock().unwrap();
+        streams.running.remove(stats.label.as_deref().unwrap_or_default());
+        // Items still queued when the stream ended are not queued anymore
+        let stats = ChokeStats {
+            queued: 0,
+            queued_bytes: 0,
+            delayed: 0,
+            ..stats.clone()
+        };
+        streams.ended.entry(direction(&stats)).or_default().add(&stats);
+    }
+}
+
+/// Changes the flags of the streams of a proxy while it runs, for the API and the hotkeys of `--interactive`.
+pub struct Control {
+    flags: Mutex<Flags>,
+    /// Accumulates the updates, so that streams that start later apply all of them at once.
+    watcher: watch::Sender<ChokeSettings>,
+    stats: StreamStats,
+    scenario: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// Serve the API of `control` on `addr`.
+pub async fn serve(addr: SocketAddr, control: Arc<Control>) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    eprintln!("serving the control API on {addr}");
+    tokio::spawn(async move {
+        loop {
+            let (mut client, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(%err, "failed to accept control connection");
+                    continue;
+                }
+            };
+            let control = control.clone();
+            tokio::spawn(async move {
+                if let Err(err) = control.handle(&mut client).await {
+                    debug!(%err, %peer, "failed to answer control request");
+                }
+            });
+        }
+    });
+}
+
+impl Control {
+    /// Control the streams of `shaping` from now on, starting from `flags`. The scenario of `--scenario` is played back
+    /// by the control instead, so that the changes and the scenario build on each other.
+    pub fn new(shaping: &mut Shaping, flags: Flags, scenario: Option<Scenario>) -> Arc<Self> {
+        shaping.scenario = None;
+        let control = Arc::new(Control {
+            flags: Mutex::new(flags),
+            watcher: shaping.settings.settings_watcher(),
+            stats: shaping.stats.clone(),
+            scenario: Mutex::new(None),
+        });
+        if let Some(scenario) = scenario {
+            control.play(scenario);
+        }
+        control
+    }
+
+    /// The current values of the flags.
+    pub fn flags(&self) -> Flags {
+        *self.flags.lock().unwrap()
+    }
+
+    async fn handle(self: &Arc<Self>, client: &mut TcpStream) -> std::io::Result<()> {
+        let (status, body) = match request(client).await {
+            Ok((method, path, body)) => self.respond(&method, &path, &body),
+            Err(err) if err.kind() == std::io::ErrorKind::InvalidData => ("400 Bad Request", error(err)),
+            Err(err) => return Err(err),
+        };
+        reply(client, status, "application/json", &body).await
+    }
+
+    fn respond(self: &Arc<Self>, method: &str, path: &str, body: &[u8]) -> (&'static str, String) {
+        match (method, path) {
+            ("GET", "/settings") => ("200 OK", json(&*self.flags.lock().unwrap())),
+            ("PUT", "/settings") => match self.change(body) {
+                Ok(flags) => ("200 OK", json(&flags)),
+                Err(err) => ("400 Bad Request", error(err)),
+            },
+            ("POST", "/scenario") => {
+                match std::str::from_utf8(body)
+                    .map_err(|err| err.to_string())
+                    .and_then(Scenario::parse)
+                {
+                    Ok(scenario) => {
+                        self.play(scenario);
+                        ("200 OK", json(&*self.flags.lock().unwrap()))
+                    }
+                    Err(err) => ("400 Bad Request", error(err)),
+                }
+            }
+            ("GET", "/stats") => (
+                "200 OK",
+                json(&serde_json::json!({
+                    "running": self.stats.running(),
+                    "ended": self.stats.ended(),
+                })),
+            ),
+            (_, "/settings" | "/scenario" | "/stats") => ("405 Method Not Allowed", error("method not allowed")),
+            _ => ("404 Not Found", error("not found")),
+        }
+    }
+
+    /// Apply the changes of a `PUT /settings` body, returning the new values of the flags.
+    fn change(&self, body: &[u8]) -> Result<Flags, String> {
+        let mut value = serde_json::from_slice::<serde_json::Value>(body).map_err(|err| err.to_string())?;
+        let Some(object) = value.as_object_mut() else {
+            return Err("expected a JSON object".to_string());
+        };
+        object.insert("at".to_string(), 0.into());
+        let step = Step::deserialize(value).map_err(|err| err.to_string())?;
+        self.apply(&step)
+    }
+
+    /// Change the flags of `step` for all streams, unless they are invalid.
+    pub fn apply(&self, step: &Step) -> Result<Flags, String> {
+        let mut flags = self.flags.lock().unwrap();
+        let mut changed = *flags;
+        let update = changed.apply(step);
+        update.validate().map_err(|err| err.to_string())?;
+        *flags = changed;
+        self.watcher.send_modify(|settings| settings.merge(update));
+        Ok(changed)
+    }
+
+    /// Play back the steps of `scenario` from now on, instead of the scenario that is playing.
+    fn play(self: &Arc<Self>, scenario: Scenario) {
+        let start = Instant::now();
+        let control = self.clone();
+        let playing = tokio::spawn(async move {
+            for step in scenario.steps() {
+                tokio::time::sleep_until(start + std::time::Duration::from_secs_f64(step.at)).await;
+                if let Err(err) = control.apply(step) {
+                    eprintln!("skipping the scenario step at {}s: {err}", step.at);
+                }
+            }
+        });
+        if let Some(previous) = self.scenario.lock().unwrap().replace(playing) {
+            previous.abort();
+        }
+    }
+}
+
+/// Read the method, the path and the body of a request.
+pub async fn request(client: &mut TcpStream) -> std::io::Result<(String, String, Vec<u8>)> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+
+    let mut reader = BufReader::new(client).take(MAX_HTTP_HEAD_SIZE as u64);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(invalid("the request head is incomplete or too large"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().map_err(|_| invalid("invalid content length"))?;
+            }
+        }
+    }
+    if content_length > MAX_BODY_SIZE {
+        return Err(invalid("the request body is too large"));
+    }
+    let mut body = vec![0; content_length];
+    let mut reader = reader.into_inner();
+    reader.read_exact(&mut body).await?;
+
+    let mut request_line = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Err(invalid("malformed request"));
+    };
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+    Ok((method.to_string(), path.to_string(), body))
+}
+
+/// Answer a request with `body` and close the connection.
+pub async fn reply(client: &mut TcpStream, status: &str, content_type: &str, body: &str) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    client.write_all(response.as_bytes()).await?;
+    client.shutdown().await
+}
+
+fn json(value: &impl serde::Serialize) -> String {
+    serde_json::to_string(value).expect("serializes to JSON")
+}
+
+fn error(message: impl ToString) -> String {
+    json(&serde_json::json!({ "error": message.to_string() }))
+}
@@ -0,0 +1,210 @@
+//! The hotkeys of `--interactive`, to change the shaping of a proxy by hand while trying out an app:
+//!
+//! - `↑` / `↓` raise or lower the mean latency by 10ms
+//! - `→` / `←` raise or lower the loss by 1%
+//! - `b` toggles a blackout, which drops everything
+//! - `r` resets the flags to their values at the start
+//! - `h` lists the keys, `q` or `Ctrl-C` quits
+//!
+//! The keys are read from the terminal without waiting for a line, which is only supported on Unix.
+
+use crate::{
+    control::Control,
+    scenario::{
+        Flags,
+        Step,
+    },
+};
+use chokepoint::recorder::Recorder;
+use std::{
+    io::{
+        IsTerminal as _,
+        Read as _,
+    },
+    sync::Arc,
+};
+
+const LATENCY_STEP_MS: f64 = 10.0;
+
+const LOSS_STEP: f64 = 0.01;
+
+const HELP: &str = "keys: ↑/↓ latency ±10ms, →/← loss ±1%, b blackout, r reset, h help, q quit";
+
+enum Key {
+    Up,
+    Down,
+    Right,
+    Left,
+    Char(u8),
+}
+
+/// Read the hotkeys from stdin and apply them to `control`, flushing `recorder` when quitting. The terminal is restored
+/// by [`restore`], when quitting or panicking.
+pub fn spawn(control: Arc<Control>, recorder: Recorder) {
+    if !std::io::stdin().is_terminal() {
+        eprintln!("--interactive needs a terminal on stdin, the hotkeys are disabled");
+        return;
+    }
+    if let Err(err) = terminal::enable_raw_mode() {
+        return eprintln!("failed to read the hotkeys: {err}");
+    }
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore();
+        hook(info);
+    }));
+    eprintln!("{HELP}");
+
+    // Reading stdin blocks, the runtime would wait for it when shutting down
+    std::thread::spawn(move || {
+        let mut hotkeys = Hotkeys {
+            initial: control.flags(),
+            blackout: None,
+            control,
+        };
+        let mut keys = std::io::stdin().lock().bytes().map_while(Result::ok);
+        while let Some(key) = next_key(&mut keys) {
+            match key {
+                // Ctrl-C arrives as a key in raw mode
+                Key::Char(b'q' | 3) => break,
+                key => hotkeys.press(key),
+            }
+        }
+        restore();
+        let _ = recorder.flush();
+        std::process::exit(0);
+    });
+}
+
+/// Restore the terminal, if the hotkeys are read from it.
+pub fn restore() {
+    terminal::restore();
+}
+
+/// The next key, with the arrow keys decoded from their escape sequences.
+fn next_key(bytes: &mut impl Iterator<Item = u8>) -> Option<Key> {
+    let byte = bytes.next()?;
+    if byte != 0x1b {
+        return Some(Key::Char(byte));
+    }
+    match (bytes.next()?, bytes.next()?) {
+        (b'[', b'A') => Some(Key::Up),
+        (b'[', b'B') => Some(Key::Down),
+        (b'[', b'C') => Some(Key::Right),
+        (b'[', b'D') => Some(Key::Left),
+        _ => Some(Key::Char(0x1b)),
+    }
+}
+
+struct Hotkeys {
+    control: Arc<Control>,
+    /// The flags at the start, restored by `r`.
+    initial: Flags,
+    /// The loss before the blackout, while there is one.
+    blackout: Option<f64>,
+}
+
+impl Hotkeys {
+    fn press(&mut self, key: Key) {
+        let flags = self.control.flags();
+        // The loss keys end a blackout, starting from the loss before it
+        let loss = self.blackout.unwrap_or(flags.drop_prob);
+        let step = match key {
+            Key::Up => Step {
+                mean: Some(flags.latency_distribution.mean + LATENCY_STEP_MS),
+                ..Step::default()
+            },
+            Key::Down => Step {
+                mean: Some((flags.latency_distribution.mean - LATENCY_STEP_MS).max(0.0)),
+                ..Step::default()
+            },
+            Key::Right | Key::Left => {
+                self.blackout = None;
+                let step = if let Key::Right = key { LOSS_STEP } else { -LOSS_STEP };
+                Step {
+                    drop_prob: Some((loss + step).clamp(0.0, 1.0)),
+                    ..Step::default()
+                }
+            }
+            Key::Char(b'b') => {
+                let drop_prob = match self.blackout.take() {
+                    Some(loss) => loss,
+                    None => {
+                        self.blackout = Some(loss);
+                        1.0
+                    }
+                };
+                Step {
+                    drop_prob: Some(drop_prob),
+                    ..Step::default()
+                }
+            }
+            Key::Char(b'r') => {
+                self.blackout = None;
+                Step::from(self.initial)
+            }
+            Key::Char(b'h' | b'?') => return eprintln!("{HELP}"),
+            Key::Char(_) => return,
+        };
+        match self.control.apply(&step) {
+            Ok(flags) => eprintln!(
+                "latency {:.0}ms ± {:.0}ms, loss {:.0}%{}",
+                flags.latency_distribution.mean,
+                flags.latency_distribution.stddev,
+                self.blackout.unwrap_or(flags.drop_prob) * 100.0,
+                if self.blackout.is_some() { ", blackout" } else { "" }
+            ),
+            Err(err) => eprintln!("failed to change the settings: {err}"),
+        }
+    }
+}
+
+#[cfg(unix)]
+mod terminal {
+    use std::sync::{
+        Mutex,
+        PoisonError,
+    };
+
+    /// The configuration of the terminal before raw mode, while raw mode is enabled.
+    static ORIGINAL: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+    /// Read keys as they are pressed and don't echo them until [`restore`] is called, Ctrl-C is read as a key too.
+    pub fn enable_raw_mode() -> std::io::Result<()> {
+        // SAFETY: `termios` is plain data that `tcgetattr` fills in
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        // SAFETY: stdin is a terminal and `termios` is valid for writes
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let original = termios;
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        // SAFETY: `termios` is a valid configuration read from the terminal
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        *ORIGINAL.lock().unwrap_or_else(PoisonError::into_inner) = Some(original);
+        Ok(())
+    }
+
+    pub fn restore() {
+        if let Some(original) = ORIGINAL.lock().unwrap_or_else(PoisonError::into_inner).take() {
+            // SAFETY: restores the configuration read in `enable_raw_mode`
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &original) };
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod terminal {
+    pub fn enable_raw_mode() -> std::io::Result<()> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "hotkeys are only supported on Unix",
+        ))
+    }
+
+    pub fn restore() {}
+}
@@ -1,82 +1,367 @@
+// The shaped connections are spawned on tokio's multi threaded runtime, which requires `Send` streams.
+#[cfg(chokepoint_local)]
+compile_error!(
+    "the chokepoint CLI can't be built with `--cfg chokepoint_local`, build the library only: `-p chokepoint`"
+);
+
+use bytes::Bytes;
 use chokepoint::{
-    normal_distribution,
+    recorder::{
+        RecordFormat,
+        Recorder,
+    },
+    test_util::{
+        TestPayload,
+        TestSink,
+    },
+    BandwidthLimit,
     ChokeSettings,
     ChokeSettingsOrder,
     ChokeSink,
+    ChokeStats,
     ChokeStream,
-};
-use chokepoint_test_helpers::{
-    TestPayload,
-    TestSink,
+    Latency,
+    Reorder,
 };
 use chrono::prelude::*;
 use clap::{
+    FromArgMatches as _,
     Parser,
     ValueEnum,
 };
+use control::{
+    Control,
+    StreamStats,
+};
 use futures::{
     stream::StreamExt,
+    Sink,
     SinkExt,
+    Stream,
+};
+use preset::Preset;
+use scenario::{
+    Flags,
+    Playback,
+    Scenario,
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::Arc,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use sweep::Sweep;
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncReadExt as _,
+        AsyncWrite,
+        AsyncWriteExt as _,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+        UdpSocket,
+    },
+    sync::mpsc,
 };
-use std::path::PathBuf;
-use tokio::sync::mpsc;
 use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{
+        Request,
+        Response,
+    },
+    protocol::{
+        frame::coding::CloseCode,
+        CloseFrame,
+    },
+    Error as WsError,
+    Message,
+};
 
 #[macro_use]
 extern crate tracing;
 
+mod config;
+mod control;
+mod hotkeys;
+mod metrics;
+mod plot;
+mod preset;
+mod scenario;
+mod summary;
+mod sweep;
+
+/// How often the statistics are logged with `--verbose`.
+const STATS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(2500);
+
+/// The most bytes read from a proxied connection or stdin at once, i.e. the largest item that is shaped.
+const TCP_CHUNK_SIZE: usize = 16 * 1024;
+
+/// The largest datagram the UDP relay can forward.
+const MAX_DATAGRAM_SIZE: usize = 65_535;
+
+/// How long the UDP relay keeps the session of a client without datagrams from it, like the mapping of a NAT.
+const UDP_SESSION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
 #[derive(Parser)]
 struct Args {
     #[clap(short, long, action)]
     verbose: bool,
 
-    #[clap(help = "Simulate a sink or a stream")]
+    #[clap(
+        long,
+        help = "TOML file with the flags of the run and the steps of a scenario, the flags that are given override its values"
+    )]
+    config: Option<PathBuf>,
+
+    #[clap(
+        help = "Simulate a sink or a stream, shape stdin to stdout, shape the traffic of a TCP, UDP, SOCKS5, HTTP or WebSocket proxy, or summarize earlier runs"
+    )]
     mode: Mode,
 
+    #[clap(
+        help = "Files written with --output by earlier runs, compared side by side in summarize mode",
+        required_if_eq("mode", "summarize")
+    )]
+    runs: Vec<PathBuf>,
+
+    #[clap(
+        long,
+        help = "Address the proxy accepts connections or datagrams on",
+        required_if_eq_any([
+            ("mode", "tcp"),
+            ("mode", "udp"),
+            ("mode", "socks5"),
+            ("mode", "http"),
+            ("mode", "websocket")
+        ])
+    )]
+    listen: Option<SocketAddr>,
+
+    #[clap(
+        long,
+        help = "Address the proxy forwards connections or datagrams to, a ws:// or wss:// URL in websocket mode",
+        required_if_eq_any([("mode", "tcp"), ("mode", "udp"), ("mode", "websocket")])
+    )]
+    upstream: Option<String>,
+
     #[clap(short, default_value = "250", help = "Number of packets to send")]
     n: usize,
 
-    #[clap(short, long, help = "Output file (csv) with packet timing information")]
+    #[clap(
+        long,
+        value_parser = parse_duration,
+        conflicts_with = "n",
+        help = "Send packets for this long instead of -n packets, e.g. 60s, 500ms or 2m. Proxies and pipes stop after it"
+    )]
+    duration: Option<Duration>,
+
+    #[clap(
+        long,
+        value_parser = Sweep::parse,
+        requires = "duration",
+        conflicts_with = "scenario",
+        help = "Step a flag from one value to another over the --duration, e.g. bandwidth_limit=5MB..100KB, and print the statistics of every step"
+    )]
+    sweep: Option<Sweep>,
+
+    #[clap(long, default_value = "10", requires = "sweep", help = "Number of steps of --sweep")]
+    sweep_steps: NonZeroUsize,
+
+    #[clap(
+        short,
+        long,
+        help = "Output file with an event per packet (enqueue time, delay, fate, emit time, size)"
+    )]
     output: Option<PathBuf>,
 
+    #[clap(long, value_parser = parse_format, default_value = "csv", help = "Output format: csv or jsonl")]
+    format: RecordFormat,
+
+    #[clap(
+        long,
+        help = "Address of an HTTP API to read and change the shaping flags of a proxy while it runs, and to read its statistics"
+    )]
+    control_addr: Option<SocketAddr>,
+
+    #[clap(
+        long,
+        help = "Address to serve the statistics of a proxy on, in the Prometheus format at /metrics"
+    )]
+    metrics_addr: Option<SocketAddr>,
+
+    #[clap(
+        short,
+        long,
+        help = "Change the latency and loss of a proxy with the arrow keys while it runs, `b` toggles a blackout, `h` lists the keys"
+    )]
+    interactive: bool,
+
+    #[clap(
+        long,
+        help = "SVG file with charts of the latency and the throughput over time, rendered when a stream, sink or pipe run ends"
+    )]
+    plot: Option<PathBuf>,
+
     #[clap(short = 'r', long, help = "Send rate in packets per second")]
     packet_rate: Option<usize>,
 
     #[clap(short = 's', long, help = "Packet size in bytes", default_value = "1B")]
     packet_size: bytesize::ByteSize,
 
-    #[clap(long, help = "Packet drop probability", default_value = "0.0")]
+    #[clap(
+        long = "drop",
+        alias = "drop-prob",
+        value_parser = parse_probability,
+        help = "Packet drop probability, e.g. 0.05 or 5%",
+        default_value = "0"
+    )]
     drop_prob: f64,
 
+    #[clap(
+        long = "corrupt",
+        alias = "corrupt-prob",
+        value_parser = parse_probability,
+        help = "Packet corruption probability, e.g. 0.05 or 5%",
+        default_value = "0"
+    )]
+    corrupt_prob: f64,
+
+    #[clap(
+        long = "duplicate",
+        alias = "duplicate-prob",
+        value_parser = parse_probability,
+        help = "Packet duplication probability, e.g. 0.05 or 5%",
+        default_value = "0"
+    )]
+    duplicate_prob: f64,
+
+    #[clap(
+        long,
+        value_parser = parse_probability,
+        help = "Probability that a packet is pushed back behind later ones",
+        default_value = "0"
+    )]
+    reorder_prob: f64,
+
+    #[clap(
+        long,
+        help = "Number of later packets a reordered packet is pushed back behind",
+        default_value = "1"
+    )]
+    reorder_gap: usize,
+
+    #[clap(
+        long = "shape-host",
+        help = "Only shape the connections to this host in socks5 and http mode, `*.example.com` for its subdomains. Can be repeated"
+    )]
+    shape_hosts: Vec<String>,
+
     #[clap(long, value_parser = parse_ordering, default_value = "ordered")]
     ordering: ChokeSettingsOrder,
 
+    #[clap(long, help = "Don't consume new packets while packets are queued")]
+    backpressure: bool,
+
     #[clap(long, help = "Bandwidth limit")]
     bandwidth_limit: Option<bytesize::ByteSize>,
 
     #[clap(
         long,
+        value_parser = parse_probability,
         help = "Drop probability when bandwidth limit is reached",
-        default_value = "0.0"
+        default_value = "0"
     )]
     bandwidth_drop_prob: f64,
 
+    #[clap(
+        long,
+        help = "Seed for the random decisions, e.g. from the stats printed by an earlier run"
+    )]
+    seed: Option<u64>,
+
+    #[clap(
+        long,
+        value_parser = Scenario::load,
+        help = "TOML file with steps that change the shaping flags during the run"
+    )]
+    scenario: Option<Scenario>,
+
+    #[clap(
+        long,
+        help = "Shape like a typical network: latency, jitter, bandwidth and loss. Flags that are given override its values"
+    )]
+    preset: Option<Preset>,
+
     #[clap(flatten)]
     latency_distribution: LatencyDistribution,
 }
 
+fn parse_format(s: &str) -> Result<RecordFormat, &'static str> {
+    match s {
+        "csv" => Ok(RecordFormat::Csv),
+        "jsonl" => Ok(RecordFormat::JsonLines),
+        _ => Err("invalid format"),
+    }
+}
+
+/// A probability between 0 and 1, or a percentage like `5%`.
+fn parse_probability(s: &str) -> Result<f64, String> {
+    let probability = match s.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map(|percent| percent / 100.0),
+        None => s.parse::<f64>(),
+    }
+    .map_err(|err| err.to_string())?;
+    if !(0.0..=1.0).contains(&probability) {
+        return Err(format!("{s} is not between 0 and 1 or 0% and 100%"));
+    }
+    Ok(probability)
+}
+
+/// A duration like `60s`, `500ms`, `2m` or `1h`, in seconds without a unit.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (value, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(index) => s.split_at(index),
+        None => (s, "s"),
+    };
+    let value = value.trim().parse::<f64>().map_err(|err| format!("{s}: {err}"))?;
+    let seconds = match unit {
+        "ms" => value / 1000.0,
+        "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(format!("{s}: unknown unit {unit}, expected ms, s, m or h")),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|err| format!("{s}: {err}"))
+}
+
 fn parse_ordering(s: &str) -> Result<ChokeSettingsOrder, &'static str> {
     match s {
         "unordered" => Ok(ChokeSettingsOrder::Unordered),
         "ordered" => Ok(ChokeSettingsOrder::Ordered),
-        "backpressure" => Ok(ChokeSettingsOrder::Backpressure),
+        "partially_ordered" => Ok(ChokeSettingsOrder::PartiallyOrdered),
         _ => Err("invalid ordering"),
     }
 }
 
-#[derive(Debug, clap::Args)]
+#[derive(Debug, Clone, Copy, clap::Args, serde::Serialize)]
 #[group(required = false, multiple = true)]
 struct LatencyDistribution {
+    #[clap(
+        long = "latency-dist",
+        default_value = "normal",
+        help = "Distribution of the latency, all but constant have the given mean and standard deviation"
+    )]
+    #[serde(rename = "latency_dist")]
+    model: LatencyModel,
+
     #[clap(long, default_value = "0.0", help = "Mean latency in ms")]
     mean: f64,
 
@@ -86,71 +371,251 @@ struct LatencyDistribution {
         help = "Standard deviation of latency in ms (aka jitter)"
     )]
     stddev: f64,
+
+    #[clap(
+        long,
+        default_value = "0.0",
+        help = "Shape of the skewed distribution, positive values skew it towards higher latencies"
+    )]
+    skew: f64,
+
+    #[clap(
+        long,
+        help = "Maximum latency in ms [default: mean + 3 stddev, mean + 10 stddev for pareto and lognormal]"
+    )]
+    max_latency: Option<f64>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LatencyModel {
+    Normal,
+    // A skew normal distribution with the mean as location and the standard deviation as scale
+    Skewed,
+    Pareto,
+    Lognormal,
+    Uniform,
+    Constant,
+}
+
+impl LatencyDistribution {
+    /// The latency model of the flags. The parameters of the distributions are derived from the mean and the standard
+    /// deviation, so that switching between them only changes the shape.
+    fn latency(&self) -> Latency {
+        let millis = |millis: f64| std::time::Duration::from_secs_f64(millis.max(0.0) / 1000.0);
+        let (mean, stddev) = (self.mean.max(0.0), self.stddev.max(0.0));
+        let tail = match self.model {
+            LatencyModel::Pareto | LatencyModel::Lognormal => 10.0,
+            _ => 3.0,
+        };
+        let max = millis(self.max_latency.unwrap_or(mean + stddev * tail));
+        match self.model {
+            _ if mean == 0.0 && stddev == 0.0 => Latency::None,
+            // Without a mean, the long tail has nothing to start from
+            LatencyModel::Lognormal | LatencyModel::Pareto if mean == 0.0 => Latency::None,
+            LatencyModel::Constant => Latency::Constant(millis(mean)),
+            LatencyModel::Normal => Latency::Normal {
+                mean: millis(mean),
+                std_dev: millis(stddev),
+                max,
+            },
+            LatencyModel::Skewed => Latency::SkewNormal {
+                location: millis(mean),
+                scale: millis(stddev),
+                shape: self.skew,
+                max,
+            },
+            LatencyModel::Uniform => {
+                let half_width = stddev * 3f64.sqrt();
+                let min = millis(mean - half_width).min(max);
+                Latency::Uniform {
+                    min,
+                    max: millis(mean + half_width).clamp(min, max),
+                }
+            }
+            LatencyModel::Lognormal => {
+                let variation = 1.0 + (stddev / mean).powi(2);
+                Latency::LogNormal {
+                    median: millis(mean / variation.sqrt()),
+                    sigma: variation.ln().sqrt(),
+                    max,
+                }
+            }
+            LatencyModel::Pareto if stddev == 0.0 => Latency::Constant(millis(mean).min(max)),
+            LatencyModel::Pareto => {
+                let shape = 1.0 + (1.0 + (mean / stddev).powi(2)).sqrt();
+                Latency::Pareto {
+                    scale: millis(mean * (shape - 1.0) / shape),
+                    shape,
+                    max,
+                }
+            }
+        }
+    }
 }
 
 #[derive(ValueEnum, Debug, Clone, Copy)]
 enum Mode {
     Stream,
     Sink,
+    // Write what is read from stdin to stdout, shaped in the chunks it is read in
+    Pipe,
+    // Forward connections from `--listen` to `--upstream`, shaping both directions of every connection
+    Tcp,
+    // Relay datagrams from `--listen` to `--upstream` and back, shaping every datagram
+    Udp,
+    // Accept SOCKS5 connections on `--listen` and forward them to the requested targets, shaping both directions
+    Socks5,
+    // Accept HTTP CONNECT tunnels on `--listen` and forward them to the requested targets, shaping both directions
+    Http,
+    // Accept WebSocket connections on `--listen` and relay them to the `--upstream` URL, shaping every message
+    Websocket,
+    // Print the latency, loss, reordering and throughput of the runs recorded in the given files side by side
+    Summarize,
 }
 
 #[tokio::main]
 async fn main() {
-    let args: Args = Args::parse();
+    let (command_line, config_scenario) = config::command_line();
+    let matches = config::command().get_matches_from(command_line);
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    if let (Some(sweep), Some(duration)) = (&args.sweep, args.duration) {
+        args.scenario = Some(sweep.scenario(duration, args.sweep_steps.get()));
+    } else if args.scenario.is_none() {
+        args.scenario = config_scenario;
+    }
+    if let Some(preset) = args.preset {
+        preset.apply(&mut args, &matches);
+    }
 
     if args.verbose {
         tracing_subscriber::fmt()
             .with_env_filter(tracing_subscriber::EnvFilter::builder().parse_lossy("trace"))
+            .with_writer(std::io::stderr)
             .with_span_events(
                 tracing_subscriber::fmt::format::FmtSpan::NEW | tracing_subscriber::fmt::format::FmtSpan::CLOSE,
             )
             .init();
     }
 
+    if let Mode::Summarize = args.mode {
+        if let Err(err) = summary::print(&args.runs) {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let now = Utc::now();
-    let n = args.n;
+    let (n, duration) = (args.n, args.duration);
 
-    let out = match &args.output {
-        Some(path) => {
+    let out = match (&args.output, args.mode) {
+        (Some(path), _) => {
             let file = std::fs::File::create(path).unwrap();
-            Box::new(std::io::BufWriter::new(file)) as Box<dyn std::io::Write>
+            Box::new(std::io::BufWriter::new(file)) as Box<dyn std::io::Write + Send>
         }
-        None => Box::new(std::io::stdout()) as Box<dyn std::io::Write>,
+        // stdout carries the data in pipe mode, the events are only written with `--output`
+        (None, Mode::Pipe) => Box::new(std::io::sink()) as Box<dyn std::io::Write + Send>,
+        (None, _) => Box::new(std::io::stdout()) as Box<dyn std::io::Write + Send>,
     };
+    let mut recorder = Recorder::new(out, args.format).unwrap();
+    if args.plot.is_some() {
+        recorder = recorder.keep_events();
+    }
+    // The steps of the sweep are told apart by the time since the start of the run
+    if args.sweep.is_some() {
+        recorder = recorder.keep_events().with_epoch(Instant::now());
+    }
+    let plot = args.plot.clone();
+    let sweep = args
+        .sweep
+        .clone()
+        .zip(duration)
+        .map(|(sweep, duration)| (sweep, duration, args.sweep_steps.get()));
 
-    match args.mode {
-        Mode::Stream => stream(out, args).await,
-        Mode::Sink => sink(out, args).await,
+    let mode = args.mode;
+    let run = async {
+        match args.mode {
+            Mode::Stream => stream(recorder.clone(), args).await,
+            Mode::Sink => sink(recorder.clone(), args).await,
+            Mode::Pipe => stdio(recorder.clone(), args).await,
+            Mode::Tcp => tcp(recorder.clone(), args).await,
+            Mode::Udp => udp(recorder.clone(), args).await,
+            Mode::Socks5 => socks5(recorder.clone(), args).await,
+            Mode::Http => http(recorder.clone(), args).await,
+            Mode::Websocket => websocket(recorder.clone(), args).await,
+            Mode::Summarize => unreachable!("summarized above"),
+        }
+    };
+    match (mode, duration) {
+        // Streams and sinks stop sending after the duration and run until their packets are through, pipes stop
+        // reading
+        (Mode::Stream | Mode::Sink | Mode::Pipe, _) | (_, None) => run.await,
+        (_, Some(duration)) => {
+            let _ = tokio::time::timeout(duration, run).await;
+        }
+    }
+    hotkeys::restore();
+    recorder.flush().unwrap();
+    if let Some(path) = plot {
+        if let Err(err) = plot::render(&path, &recorder.events()) {
+            eprintln!("failed to plot to {}: {err}", path.display());
+            std::process::exit(1);
+        }
+    }
+    if let Some((sweep, duration, steps)) = sweep {
+        eprint!("{}", sweep.report(duration, steps, &recorder.events()));
     }
 
     let elapsed = (Utc::now() - now).num_milliseconds();
-    let ms_per_packet = elapsed as f64 / n as f64;
-    info!("done in {}ms ms/packet={:.2}", elapsed, ms_per_packet);
+    if duration.is_some() {
+        info!("done in {}ms", elapsed);
+    } else {
+        let ms_per_packet = elapsed as f64 / n as f64;
+        info!("done in {}ms ms/packet={:.2}", elapsed, ms_per_packet);
+    }
+    // The runtime would wait for the read of stdin that was cut off to shut down
+    if let (Mode::Pipe, Some(_)) = (mode, duration) {
+        std::process::exit(0);
+    }
+}
+
+/// The numbers of the packets to send: `n` of them, or as many as are sent within `duration`.
+fn packet_numbers(n: usize, duration: Option<Duration>) -> impl Iterator<Item = usize> {
+    let deadline = duration.map(|duration| Instant::now() + duration);
+    (0..).take_while(move |&i| match deadline {
+        Some(deadline) => Instant::now() < deadline,
+        None => i < n,
+    })
 }
 
-async fn stream(
-    mut out: Box<dyn std::io::Write>,
-    Args {
+fn with_bandwidth_limit(
+    settings: ChokeSettings,
+    bandwidth_limit: Option<bytesize::ByteSize>,
+    drop_ratio: f64,
+) -> ChokeSettings {
+    match bandwidth_limit {
+        Some(limit) => settings.set_bandwidth_limit_with(
+            BandwidthLimit::builder()
+                .bytes_per_sec(limit.as_u64() as usize)
+                .drop_ratio(drop_ratio),
+        ),
+        None => settings,
+    }
+}
+
+async fn stream(recorder: Recorder, args: Args) {
+    let settings = shaping(recorder, &args).settings();
+    let Args {
         n,
-        ordering,
+        duration,
         packet_rate,
         packet_size,
-        latency_distribution: LatencyDistribution { mean, stddev },
-        bandwidth_limit,
-        bandwidth_drop_prob,
         ..
-    }: Args,
-) {
+    } = args;
     let (tx, rx) = mpsc::unbounded_channel();
 
-    let mut stream = ChokeStream::<TestPayload>::new(
-        Box::new(UnboundedReceiverStream::new(rx)),
-        ChokeSettings::default()
-            .set_ordering(Some(ordering))
-            .set_latency_distribution(chokepoint::normal_distribution(mean, stddev, mean + stddev * 3.0))
-            .set_bandwidth_limit(bandwidth_limit.map(|b| b.as_u64() as usize), bandwidth_drop_prob)
-            .set_corrupt_probability(Some(0.0)),
-    );
+    let mut stream = ChokeStream::<TestPayload>::new(Box::new(UnboundedReceiverStream::new(rx)), settings);
 
     tokio::spawn(async move {
         let packet_size = packet_size.as_u64() as usize;
@@ -160,19 +625,22 @@ async fn stream(
 
         const CHUNKED: bool = true;
 
+        let mut sent = 0;
         if CHUNKED {
             let chunk_size = 10;
-            for chunk in (0..n).collect::<Vec<_>>().chunks(chunk_size) {
-                for i in chunk {
-                    tx.send(TestPayload::new(*i, packet_size)).unwrap();
-                }
-                if let Some(delay) = delay {
-                    tokio::time::sleep(delay * chunk_size as u32).await;
+            for i in packet_numbers(n, duration) {
+                tx.send(TestPayload::new(i, packet_size)).unwrap();
+                sent += 1;
+                if sent % chunk_size == 0 {
+                    if let Some(delay) = delay {
+                        tokio::time::sleep(delay * chunk_size as u32).await;
+                    }
                 }
             }
         } else {
-            for i in 0..n {
+            for i in packet_numbers(n, duration) {
                 tx.send(TestPayload::new(i, packet_size)).unwrap();
+                sent += 1;
                 if let Some(delay) = delay {
                     tokio::time::sleep(delay).await;
                 }
@@ -181,50 +649,27 @@ async fn stream(
 
         debug!(
             "sent {} packets in {}µs ({}µs/packet)",
-            n,
+            sent,
             (Utc::now() - now).num_microseconds().unwrap(),
-            (Utc::now() - now).num_microseconds().unwrap() / n as i64
+            (Utc::now() - now).num_microseconds().unwrap() / sent.max(1) as i64
         );
     });
 
-    writeln!(out, "i,received,created,delta").unwrap();
+    while stream.next().await.is_some() {}
 
-    while let Some(packet) = stream.next().await {
-        let now = Utc::now();
-        let delta = now - packet.created;
-        writeln!(
-            out,
-            "{},{},{},{}",
-            packet.i,
-            now.to_rfc3339(),
-            packet.created.to_rfc3339(),
-            delta.num_milliseconds()
-        )
-        .unwrap();
-    }
+    eprintln!("{}", stream.stats());
 }
 
-async fn sink(
-    mut out: Box<dyn std::io::Write>,
-    Args {
+async fn sink(recorder: Recorder, args: Args) {
+    let settings = shaping(recorder, &args).settings();
+    let Args {
         n,
-        ordering,
+        duration,
         packet_rate,
         packet_size,
-        latency_distribution: LatencyDistribution { mean, stddev },
-        bandwidth_limit,
-        bandwidth_drop_prob,
         ..
-    }: Args,
-) {
-    let mut sink = ChokeSink::new(
-        TestSink::default(),
-        ChokeSettings::default()
-            .set_ordering(Some(ordering))
-            .set_bandwidth_limit(bandwidth_limit.map(|b| b.as_u64() as usize), bandwidth_drop_prob)
-            .set_latency_distribution(normal_distribution(mean, stddev, mean + stddev * 3.0))
-            .set_corrupt_probability(Some(0.0)),
-    );
+    } = args;
+    let mut sink = ChokeSink::new(TestSink::default(), settings);
 
     {
         let packet_size = packet_size.as_u64() as usize;
@@ -234,19 +679,22 @@ async fn sink(
 
         const CHUNKED: bool = false;
 
+        let mut sent = 0;
         if CHUNKED {
             let chunk_size = 10;
-            for chunk in (0..n).collect::<Vec<_>>().chunks(chunk_size) {
-                for i in chunk {
-                    sink.send(TestPayload::new(*i, packet_size)).await.unwrap();
-                }
-                if let Some(delay) = delay {
-                    tokio::time::sleep(delay * chunk_size as u32).await;
+            for i in packet_numbers(n, duration) {
+                sink.send(TestPayload::new(i, packet_size)).await.unwrap();
+                sent += 1;
+                if sent % chunk_size == 0 {
+                    if let Some(delay) = delay {
+                        tokio::time::sleep(delay * chunk_size as u32).await;
+                    }
                 }
             }
         } else {
-            for i in 0..n {
+            for i in packet_numbers(n, duration) {
                 sink.send(TestPayload::new(i, packet_size)).await.unwrap();
+                sent += 1;
                 if let Some(delay) = delay {
                     tokio::time::sleep(delay).await;
                 }
@@ -255,26 +703,671 @@ async fn sink(
 
         debug!(
             "sent {} packets in {}µs ({}µs/packet)",
-            n,
+            sent,
             (Utc::now() - now).num_microseconds().unwrap(),
-            (Utc::now() - now).num_microseconds().unwrap() / n as i64
+            (Utc::now() - now).num_microseconds().unwrap() / sent.max(1) as i64
         );
     }
 
     sink.close().await.unwrap();
+    eprintln!("{}", sink.stats());
+}
+
+/// The settings given by the flags, the scenario that changes them during the run, and the statistics of the streams.
+#[derive(Clone)]
+struct Shaping {
+    settings: ChokeSettings,
+    scenario: Option<Playback>,
+    stats: StreamStats,
+}
+
+impl Shaping {
+    /// The settings of a stream that starts now, following the rest of the scenario.
+    fn settings(&self) -> ChokeSettings {
+        match &self.scenario {
+            Some(scenario) => self.settings.clone().set_settings_source(scenario.source()),
+            None => self.settings.clone(),
+        }
+    }
+
+    /// The settings of both directions of the connection `id`. Each direction is shaped on its own, like the up- and
+    /// downlink of a real connection.
+    fn connection(&self, id: usize) -> (ChokeSettings, ChokeSettings) {
+        (
+            self.settings().set_label(format!("{id}-up")),
+            self.settings().set_label(format!("{id}-down")),
+        )
+    }
+}
+
+/// The shaping given by the flags.
+fn shaping(
+    recorder: Recorder,
+    args @ &Args {
+        ordering,
+        backpressure,
+        drop_prob,
+        corrupt_prob,
+        duplicate_prob,
+        reorder_prob,
+        reorder_gap,
+        latency_distribution,
+        bandwidth_limit,
+        bandwidth_drop_prob,
+        seed,
+        verbose,
+        ..
+    }: &Args,
+) -> Shaping {
+    let settings = with_bandwidth_limit(
+        ChokeSettings::default()
+            .set_ordering(Some(ordering))
+            .set_backpressure(Some(backpressure))
+            .set_latency(Some(latency_distribution.latency()))
+            .set_drop_probability(Some(drop_prob))
+            .set_duplicate_probability(Some(duplicate_prob))
+            .set_reorder(Some(
+                Reorder::builder().probability(reorder_prob).gap(reorder_gap).build(),
+            ))
+            .set_corrupt_probability(Some(corrupt_prob))
+            .set_seed(seed)
+            .set_recorder(Some(recorder))
+            .set_stats_interval(verbose.then_some(STATS_INTERVAL)),
+        bandwidth_limit,
+        bandwidth_drop_prob,
+    );
+    Shaping {
+        settings,
+        scenario: args
+            .scenario
+            .as_ref()
+            .map(|scenario| scenario.playback(Flags::new(args))),
+        stats: StreamStats::default(),
+    }
+}
+
+/// The shaping of the connections of a proxy, and the addresses of the proxy. The upstream is only set for the TCP, UDP
+/// and WebSocket proxies. Serves the control API with `--control-addr` and the metrics with `--metrics-addr`, and reads
+/// the hotkeys with `--interactive`.
+async fn proxy_settings(recorder: Recorder, args: Args) -> (Shaping, SocketAddr, Option<String>) {
+    let mut shaping = shaping(recorder.clone(), &args);
+    if args.control_addr.is_some() || args.metrics_addr.is_some() {
+        shaping.stats = StreamStats::enabled();
+    }
+    if args.control_addr.is_some() || args.interactive {
+        let control = Control::new(&mut shaping, Flags::new(&args), args.scenario);
+        if let Some(addr) = args.control_addr {
+            control::serve(addr, control.clone()).await;
+        }
+        if args.interactive {
+            hotkeys::spawn(control, recorder);
+        }
+    }
+    if let Some(addr) = args.metrics_addr {
+        metrics::serve(addr, shaping.stats.clone()).await;
+    }
+    (shaping, args.listen.expect("clap requires --listen"), args.upstream)
+}
+
+/// Write the recorded events every second, the proxies run until they are killed.
+fn flush_periodically(recorder: Recorder) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            if let Err(err) = recorder.flush() {
+                eprintln!("failed to write events: {err}");
+                break;
+            }
+        }
+    });
+}
+
+async fn tcp(recorder: Recorder, args: Args) {
+    let (shaping, listen, upstream) = proxy_settings(recorder.clone(), args).await;
+    let upstream = upstream.expect("clap requires --upstream");
+    flush_periodically(recorder);
+
+    let listener = TcpListener::bind(listen).await.unwrap();
+    eprintln!("forwarding connections from {listen} to {upstream}");
+    for id in 0.. {
+        let (client, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(%err, "failed to accept connection");
+                continue;
+            }
+        };
+        debug!(id, %peer, "accepted connection");
+        let (up, down) = shaping.connection(id);
+        let (upstream, stats) = (upstream.clone(), shaping.stats.clone());
+        tokio::spawn(async move {
+            match TcpStream::connect(&upstream).await {
+                Ok(server) => {
+                    let (up, down) = proxy(client, server, up, down, &stats).await;
+                    eprintln!("{up}\n{down}");
+                }
+                Err(err) => eprintln!("connection {id} from {peer}: failed to connect to {upstream}: {err}"),
+            }
+        });
+    }
+}
+
+/// Forward `client` to `server` and back until both directions are closed.
+async fn proxy(
+    client: TcpStream,
+    server: TcpStream,
+    up: ChokeSettings,
+    down: ChokeSettings,
+    stats: &StreamStats,
+) -> (ChokeStats, ChokeStats) {
+    let (client_read, client_write) = client.into_split();
+    let (server_read, server_write) = server.into_split();
+    tokio::join!(
+        pipe(chunks(client_read), server_write, up, stats),
+        pipe(chunks(server_read), client_write, down, stats)
+    )
+}
+
+/// The chunks read from `reader`, until the end of the input or the first error.
+fn chunks(reader: impl AsyncRead + Unpin) -> impl Stream<Item = Bytes> {
+    futures::stream::unfold(reader, |mut reader| async move {
+        let mut chunk = bytes::BytesMut::with_capacity(TCP_CHUNK_SIZE);
+        match reader.read_buf(&mut chunk).await {
+            Ok(0) => None,
+            Ok(_) => Some((chunk.freeze(), reader)),
+            Err(err) => {
+                debug!(%err, "failed to read");
+                None
+            }
+        }
+    })
+}
+
+/// Write `chunks` to `writer`, shaped according to `settings`, publishing the statistics to `stats`. Stops at the end
+/// of the chunks or the first error of `writer`, and closes the write direction of `writer` then.
+async fn pipe(
+    chunks: impl Stream<Item = Bytes>,
+    mut writer: impl AsyncWrite + Unpin,
+    settings: ChokeSettings,
+    stats: &StreamStats,
+) -> ChokeStats {
+    let mut stream = ChokeStream::new(Box::pin(chunks), settings);
+    let mut publisher = stats.publisher();
+    while let Some(chunk) = stream.next().await {
+        publisher.update(|| stream.stats());
+        if let Err(err) = writer.write_all(&chunk).await {
+            debug!(%err, "failed to write");
+            break;
+        }
+    }
+    // Shutting down doesn't flush stdout
+    let _ = writer.flush().await;
+    let _ = writer.shutdown().await;
+    let stats = stream.stats();
+    publisher.end(&stats);
+    stats
+}
+
+async fn stdio(recorder: Recorder, args: Args) {
+    let settings = shaping(recorder, &args).settings();
+    // With `--duration`, the input ends after it, the items that are queued then are still written
+    let end = async move {
+        match args.duration {
+            Some(duration) => tokio::time::sleep(duration).await,
+            None => std::future::pending().await,
+        }
+    };
+    let input = chunks(tokio::io::stdin()).take_until(end);
+    let stats = pipe(input, tokio::io::stdout(), settings, &StreamStats::default()).await;
+    eprintln!("{stats}");
+}
+
+async fn udp(recorder: Recorder, args: Args) {
+    let (shaping, listen, upstream) = proxy_settings(recorder.clone(), args).await;
+    let upstream = upstream.expect("clap requires --upstream");
+    flush_periodically(recorder);
+    let upstream = tokio::net::lookup_host(&upstream)
+        .await
+        .unwrap()
+        .next()
+        .expect("upstream resolves to an address");
+
+    let socket = Arc::new(UdpSocket::bind(listen).await.unwrap());
+    eprintln!("relaying datagrams from {listen} to {upstream}");
+    // Every client gets a socket of its own towards the upstream, so that the replies can be told apart
+    let mut sessions = HashMap::<SocketAddr, mpsc::UnboundedSender<Bytes>>::new();
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    let mut next_id = 0;
+    loop {
+        let (datagram, peer) = loop {
+            match socket.recv_from(&mut buf).await {
+                Ok((len, peer)) => break (Bytes::copy_from_slice(&buf[..len]), peer),
+                Err(err) => debug!(%err, "failed to receive datagram"),
+            }
+        };
+        // A session ends once its client has been silent for a while, a new one starts with the next datagram
+        let datagram = match sessions.get(&peer) {
+            Some(session) => match session.send(datagram) {
+                Ok(()) => continue,
+                Err(mpsc::error::SendError(datagram)) => datagram,
+            },
+            None => datagram,
+        };
+
+        let id = next_id;
+        next_id += 1;
+        debug!(id, %peer, "new session");
+        sessions.retain(|_, session| !session.is_closed());
+        let (tx, rx) = mpsc::unbounded_channel();
+        tx.send(datagram).unwrap();
+        sessions.insert(peer, tx);
+        let (up, down) = shaping.connection(id);
+        let (socket, stats) = (socket.clone(), shaping.stats.clone());
+        tokio::spawn(async move {
+            match relay(socket, peer, upstream, rx, up, down, &stats).await {
+                Ok((up, down)) => eprintln!("{up}\n{down}"),
+                Err(err) => eprintln!("session {id} from {peer}: failed to connect to {upstream}: {err}"),
+            }
+        });
+    }
+}
+
+/// Relay the datagrams of the client at `peer`, received by the main loop, to `upstream` and the replies back through
+/// `socket`, until the client has been silent for [`UDP_SESSION_TIMEOUT`].
+async fn relay(
+    socket: Arc<UdpSocket>,
+    peer: SocketAddr,
+    upstream: SocketAddr,
+    datagrams: mpsc::UnboundedReceiver<Bytes>,
+    up: ChokeSettings,
+    down: ChokeSettings,
+    stats: &StreamStats,
+) -> std::io::Result<(ChokeStats, ChokeStats)> {
+    let unspecified: SocketAddr = if upstream.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let server = Arc::new(UdpSocket::bind(unspecified).await?);
+    server.connect(upstream).await?;
+
+    let datagrams = futures::stream::unfold(datagrams, |mut datagrams| async move {
+        let datagram = tokio::time::timeout(UDP_SESSION_TIMEOUT, datagrams.recv())
+            .await
+            .ok()??;
+        Some((datagram, datagrams))
+    });
+    let replies = futures::stream::unfold(
+        (server.clone(), vec![0; MAX_DATAGRAM_SIZE]),
+        |(server, mut buf)| async move {
+            loop {
+                match server.recv(&mut buf).await {
+                    Ok(len) => return Some((Bytes::copy_from_slice(&buf[..len]), (server, buf))),
+                    // An ICMP error for an earlier datagram, e.g. because nothing listens upstream (yet)
+                    Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => continue,
+                    Err(err) => {
+                        debug!(%err, "failed to receive reply");
+                        return None;
+                    }
+                }
+            }
+        },
+    );
+    let mut up = ChokeStream::new(Box::pin(datagrams), up);
+    let mut down = ChokeStream::new(Box::pin(replies), down);
+
+    let (mut up_publisher, mut down_publisher) = (stats.publisher(), stats.publisher());
+    let forward = async {
+        while let Some(datagram) = up.next().await {
+            up_publisher.update(|| up.stats());
+            if let Err(err) = server.send(&datagram).await {
+                debug!(%err, "failed to send datagram");
+            }
+        }
+    };
+    let backward = async {
+        while let Some(reply) = down.next().await {
+            down_publisher.update(|| down.stats());
+            if let Err(err) = socket.send_to(&reply, peer).await {
+                debug!(%err, "failed to send reply");
+            }
+        }
+    };
+    // The session ends once the client is gone, replies can't arrive without a request anyway
+    tokio::select! {
+        _ = forward => {}
+        _ = backward => {}
+    }
+    let (up, down) = (up.stats(), down.stats());
+    up_publisher.end(&up);
+    down_publisher.end(&down);
+    Ok((up, down))
+}
 
-    writeln!(out, "i,received,created,delta").unwrap();
-    let items = sink.into_inner().received.into_inner().into_iter().collect::<Vec<_>>();
-
-    for (received, TestPayload { created, i, .. }) in items {
-        let delta = received - created;
-        writeln!(
-            out,
-            "{i},{},{},{}",
-            received.to_rfc3339(),
-            created.to_rfc3339(),
-            delta.num_milliseconds()
+/// The hosts whose connections are shaped by the socks5 and http modes, see `--shape-host`.
+struct ShapedHosts(Vec<String>);
+
+impl ShapedHosts {
+    /// Whether connections to `host` are shaped, all are if no hosts were given.
+    fn contains(&self, host: &str) -> bool {
+        self.0.is_empty()
+            || self.0.iter().any(|pattern| match pattern.strip_prefix("*.") {
+                Some(domain) => host
+                    .to_ascii_lowercase()
+                    .strip_suffix(&domain.to_ascii_lowercase())
+                    .is_some_and(|subdomain| subdomain.ends_with('.')),
+                None => pattern.eq_ignore_ascii_case(host),
+            })
+    }
+
+    /// The settings of both directions of the connection `id` to `host`, which is forwarded unshaped if the host
+    /// doesn't match.
+    fn settings(&self, shaping: &Shaping, host: &str, id: usize) -> (ChokeSettings, ChokeSettings) {
+        if self.contains(host) {
+            return shaping.connection(id);
+        }
+        (
+            ChokeSettings::default().set_label(format!("{id}-up")),
+            ChokeSettings::default().set_label(format!("{id}-down")),
         )
-        .unwrap();
     }
 }
+
+async fn socks5(recorder: Recorder, mut args: Args) {
+    let hosts = Arc::new(ShapedHosts(std::mem::take(&mut args.shape_hosts)));
+    let (shaping, listen, _) = proxy_settings(recorder.clone(), args).await;
+    flush_periodically(recorder);
+
+    let listener = TcpListener::bind(listen).await.unwrap();
+    eprintln!("accepting SOCKS5 connections on {listen}");
+    for id in 0.. {
+        let (mut client, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(%err, "failed to accept connection");
+                continue;
+            }
+        };
+        let (shaping, hosts) = (shaping.clone(), hosts.clone());
+        tokio::spawn(async move {
+            // The handshake is not shaped, like a proxy on the local network
+            let (host, port) = match socks5_request(&mut client).await {
+                Ok(target) => target,
+                Err(err) => return eprintln!("connection {id} from {peer}: {err}"),
+            };
+            let server = match TcpStream::connect((host.as_str(), port)).await {
+                Ok(server) => server,
+                Err(err) => {
+                    let reply = match err.kind() {
+                        std::io::ErrorKind::ConnectionRefused => SOCKS5_CONNECTION_REFUSED,
+                        _ => SOCKS5_HOST_UNREACHABLE,
+                    };
+                    let _ = socks5_reply(&mut client, reply, None).await;
+                    return eprintln!("connection {id} from {peer}: failed to connect to {host}:{port}: {err}");
+                }
+            };
+            if let Err(err) = socks5_reply(&mut client, SOCKS5_SUCCEEDED, server.local_addr().ok()).await {
+                return eprintln!("connection {id} from {peer}: {err}");
+            }
+            eprintln!("connection {id} from {peer} to {host}:{port}");
+            let (up, down) = hosts.settings(&shaping, &host, id);
+            let (up, down) = proxy(client, server, up, down, &shaping.stats).await;
+            eprintln!("{up}\n{down}");
+        });
+    }
+}
+
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_NO_AUTHENTICATION: u8 = 0;
+const SOCKS5_NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const SOCKS5_CONNECT: u8 = 1;
+const SOCKS5_IPV4: u8 = 1;
+const SOCKS5_DOMAIN_NAME: u8 = 3;
+const SOCKS5_IPV6: u8 = 4;
+const SOCKS5_SUCCEEDED: u8 = 0;
+const SOCKS5_HOST_UNREACHABLE: u8 = 4;
+const SOCKS5_CONNECTION_REFUSED: u8 = 5;
+const SOCKS5_COMMAND_NOT_SUPPORTED: u8 = 7;
+const SOCKS5_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 8;
+
+/// Negotiate a SOCKS5 session without authentication (RFC 1928) and read the target of a CONNECT request. Other
+/// commands are rejected.
+async fn socks5_request(client: &mut TcpStream) -> std::io::Result<(String, u16)> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+
+    let [version, methods] = read_array(client).await?;
+    if version != SOCKS5_VERSION {
+        return Err(invalid("not a SOCKS5 client"));
+    }
+    let mut methods = vec![0; methods.into()];
+    client.read_exact(&mut methods).await?;
+    if !methods.contains(&SOCKS5_NO_AUTHENTICATION) {
+        client
+            .write_all(&[SOCKS5_VERSION, SOCKS5_NO_ACCEPTABLE_METHODS])
+            .await?;
+        return Err(invalid("the client requires authentication"));
+    }
+    client.write_all(&[SOCKS5_VERSION, SOCKS5_NO_AUTHENTICATION]).await?;
+
+    let [version, command, _, address_type] = read_array(client).await?;
+    if version != SOCKS5_VERSION {
+        return Err(invalid("not a SOCKS5 request"));
+    }
+    let host = match address_type {
+        SOCKS5_IPV4 => std::net::Ipv4Addr::from(read_array::<4>(client).await?).to_string(),
+        SOCKS5_IPV6 => std::net::Ipv6Addr::from(read_array::<16>(client).await?).to_string(),
+        SOCKS5_DOMAIN_NAME => {
+            let mut name = vec![0; client.read_u8().await?.into()];
+            client.read_exact(&mut name).await?;
+            String::from_utf8(name).map_err(|_| invalid("the domain name is not valid UTF-8"))?
+        }
+        _ => {
+            socks5_reply(client, SOCKS5_ADDRESS_TYPE_NOT_SUPPORTED, None).await?;
+            return Err(invalid("unsupported address type"));
+        }
+    };
+    let port = client.read_u16().await?;
+    if command != SOCKS5_CONNECT {
+        socks5_reply(client, SOCKS5_COMMAND_NOT_SUPPORTED, None).await?;
+        return Err(invalid("only CONNECT is supported"));
+    }
+    Ok((host, port))
+}
+
+/// Answer a SOCKS5 request, with the address the proxy connected from if it succeeded.
+async fn socks5_reply(client: &mut TcpStream, reply: u8, bound: Option<SocketAddr>) -> std::io::Result<()> {
+    let mut message = vec![SOCKS5_VERSION, reply, 0];
+    match bound.unwrap_or_else(|| ([0, 0, 0, 0], 0).into()) {
+        SocketAddr::V4(addr) => {
+            message.push(SOCKS5_IPV4);
+            message.extend(addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            message.push(SOCKS5_IPV6);
+            message.extend(addr.ip().octets());
+        }
+    }
+    message.extend(bound.map_or(0, |addr| addr.port()).to_be_bytes());
+    client.write_all(&message).await
+}
+
+async fn read_array<const N: usize>(client: &mut TcpStream) -> std::io::Result<[u8; N]> {
+    let mut array = [0; N];
+    client.read_exact(&mut array).await?;
+    Ok(array)
+}
+
+/// The most bytes of an HTTP request head the http mode accepts.
+const MAX_HTTP_HEAD_SIZE: usize = 8 * 1024;
+
+async fn http(recorder: Recorder, mut args: Args) {
+    let hosts = Arc::new(ShapedHosts(std::mem::take(&mut args.shape_hosts)));
+    let (shaping, listen, _) = proxy_settings(recorder.clone(), args).await;
+    flush_periodically(recorder);
+
+    let listener = TcpListener::bind(listen).await.unwrap();
+    eprintln!("accepting HTTP CONNECT tunnels on {listen}");
+    for id in 0.. {
+        let (mut client, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(%err, "failed to accept connection");
+                continue;
+            }
+        };
+        let (shaping, hosts) = (shaping.clone(), hosts.clone());
+        tokio::spawn(async move {
+            // The request is not shaped, like a proxy on the local network
+            let (host, port) = match connect_request(&mut client).await {
+                Ok(target) => target,
+                Err(err) => return eprintln!("connection {id} from {peer}: {err}"),
+            };
+            let server = match TcpStream::connect((host.as_str(), port)).await {
+                Ok(server) => server,
+                Err(err) => {
+                    let _ = client.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n").await;
+                    return eprintln!("connection {id} from {peer}: failed to connect to {host}:{port}: {err}");
+                }
+            };
+            if let Err(err) = client.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").await {
+                return eprintln!("connection {id} from {peer}: {err}");
+            }
+            eprintln!("connection {id} from {peer} to {host}:{port}");
+            let (up, down) = hosts.settings(&shaping, &host, id);
+            let (up, down) = proxy(client, server, up, down, &shaping.stats).await;
+            eprintln!("{up}\n{down}");
+        });
+    }
+}
+
+/// Read the head of an HTTP `CONNECT host:port` request and return its target. Other requests are answered with an
+/// error, the tunnel is the only way to reach the targets.
+async fn connect_request(client: &mut TcpStream) -> std::io::Result<(String, u16)> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+
+    // Read byte by byte, so that nothing the client sends after the head is consumed
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() == MAX_HTTP_HEAD_SIZE {
+            client
+                .write_all(b"HTTP/1.1 431 Request Header Fields Too Large\r\n\r\n")
+                .await?;
+            return Err(invalid("the request head is too large"));
+        }
+        head.push(client.read_u8().await?);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        client.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n").await?;
+        return Err(invalid("malformed request"));
+    };
+    if method != "CONNECT" {
+        client
+            .write_all(b"HTTP/1.1 405 Method Not Allowed\r\nAllow: CONNECT\r\n\r\n")
+            .await?;
+        return Err(invalid("only CONNECT is supported"));
+    }
+    let target = target
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host.trim_start_matches('[').trim_end_matches(']'), port.parse().ok()?)));
+    let Some((host, port)) = target else {
+        client.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n").await?;
+        return Err(invalid("the target is not host:port"));
+    };
+    Ok((host.to_string(), port))
+}
+
+async fn websocket(recorder: Recorder, args: Args) {
+    let (shaping, listen, upstream) = proxy_settings(recorder.clone(), args).await;
+    let upstream = upstream.expect("clap requires --upstream");
+    flush_periodically(recorder);
+
+    let listener = TcpListener::bind(listen).await.unwrap();
+    eprintln!("relaying WebSocket connections from {listen} to {upstream}");
+    for id in 0.. {
+        let (client, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(%err, "failed to accept connection");
+                continue;
+            }
+        };
+        debug!(id, %peer, "accepted connection");
+        let (up, down) = shaping.connection(id);
+        let (upstream, stats) = (upstream.clone(), shaping.stats.clone());
+        tokio::spawn(async move {
+            // The handshake is not shaped, like the one of the TCP connection in tcp mode
+            let mut path = String::new();
+            // The callback never fails, and its error type is given by tungstenite
+            #[allow(clippy::result_large_err)]
+            let accepted = tokio_tungstenite::accept_hdr_async(client, |request: &Request, response: Response| {
+                path = request
+                    .uri()
+                    .path_and_query()
+                    .map_or("/", |path| path.as_str())
+                    .to_string();
+                Ok(response)
+            })
+            .await;
+            let mut client = match accepted {
+                Ok(client) => client,
+                Err(err) => return eprintln!("connection {id} from {peer}: {err}"),
+            };
+            // The requested path is appended to the upstream, so that one proxy serves all the endpoints of an app
+            let url = format!("{}{path}", upstream.trim_end_matches('/'));
+            let server = match tokio_tungstenite::connect_async(&url).await {
+                Ok((server, _)) => server,
+                Err(err) => {
+                    let frame = CloseFrame {
+                        code: CloseCode::Error,
+                        reason: "failed to connect to the upstream".into(),
+                    };
+                    let _ = client.close(Some(frame)).await;
+                    return eprintln!("connection {id} from {peer}: failed to connect to {url}: {err}");
+                }
+            };
+            eprintln!("connection {id} from {peer} to {url}");
+            let (client_sink, client_messages) = client.split();
+            let (server_sink, server_messages) = server.split();
+            let (up, down) = tokio::join!(
+                relay_messages(client_messages, server_sink, up, &stats),
+                relay_messages(server_messages, client_sink, down, &stats)
+            );
+            eprintln!("{up}\n{down}");
+        });
+    }
+}
+
+/// Send the messages read from `messages` to `sink`, shaped according to `settings`, publishing the statistics to
+/// `stats`. Close messages are relayed like the others. Stops at the end of the input or the first error of either
+/// side, and closes `sink` then.
+async fn relay_messages(
+    messages: impl Stream<Item = Result<Message, WsError>> + Unpin,
+    mut sink: impl Sink<Message, Error = WsError> + Unpin,
+    settings: ChokeSettings,
+    stats: &StreamStats,
+) -> ChokeStats {
+    let messages = futures::stream::unfold(messages, |mut messages| async move {
+        match messages.next().await? {
+            Ok(message) => Some((message, messages)),
+            Err(err) => {
+                debug!(%err, "failed to read message");
+                None
+            }
+        }
+    });
+    let mut stream = ChokeStream::new(Box::pin(messages), settings);
+    let mut publisher = stats.publisher();
+    while let Some(message) = stream.next().await {
+        publisher.update(|| stream.stats());
+        if let Err(err) = sink.send(message).await {
+            debug!(%err, "failed to send message");
+            break;
+        }
+    }
+    let _ = sink.close().await;
+    let stats = stream.stats();
+    publisher.end(&stats);
+    stats
+}
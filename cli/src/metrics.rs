@@ -0,0 +1,157 @@
+//! The Prometheus endpoint of `--metrics-addr`, serving the statistics of a proxy at `/metrics` while it runs.
+//!
+//! The statistics of all streams are summed up by direction, `up` from the clients and `down` to them, so that the
+//! number of series doesn't grow with every connection. The streams of closed connections keep counting, so the
+//! counters never decrease. Running streams publish their statistics every second, see [`control::Publisher`].
+
+use crate::control::{
+    self,
+    Direction,
+    StreamStats,
+};
+use chokepoint::event::DropReason;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    net::SocketAddr,
+};
+use tokio::net::TcpListener;
+
+/// The content type of the text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+const DROP_REASONS: [(DropReason, &str); 6] = [
+    (DropReason::Random, "random"),
+    (DropReason::BandwidthLimit, "bandwidth_limit"),
+    (DropReason::Hook, "hook"),
+    (DropReason::Replay, "replay"),
+    (DropReason::Expired, "expired"),
+    (DropReason::Reconfigured, "reconfigured"),
+];
+
+/// A metric taken from the statistics of a stream.
+struct Metric {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    value: fn(&Direction) -> usize,
+}
+
+const METRICS: [Metric; 10] = [
+    Metric {
+        name: "chokepoint_streams_total",
+        kind: "counter",
+        help: "Shaped streams, two per connection.",
+        value: |direction| direction.streams,
+    },
+    Metric {
+        name: "chokepoint_received_items_total",
+        kind: "counter",
+        help: "Items read from the connections.",
+        value: |direction| direction.stats.received,
+    },
+    Metric {
+        name: "chokepoint_emitted_items_total",
+        kind: "counter",
+        help: "Items written to the connections, including duplicates.",
+        value: |direction| direction.stats.emitted,
+    },
+    Metric {
+        name: "chokepoint_corrupted_items_total",
+        kind: "counter",
+        help: "Items that were corrupted.",
+        value: |direction| direction.stats.corrupted,
+    },
+    Metric {
+        name: "chokepoint_duplicated_items_total",
+        kind: "counter",
+        help: "Duplicates that were added.",
+        value: |direction| direction.stats.duplicated,
+    },
+    Metric {
+        name: "chokepoint_emitted_bytes_total",
+        kind: "counter",
+        help: "Bytes written to the connections, including duplicates and corrupted items.",
+        value: |direction| direction.stats.emitted_bytes,
+    },
+    Metric {
+        name: "chokepoint_goodput_bytes_total",
+        kind: "counter",
+        help: "Bytes written to the connections, excluding duplicates and corrupted items.",
+        value: |direction| direction.stats.goodput_bytes,
+    },
+    Metric {
+        name: "chokepoint_queued_items",
+        kind: "gauge",
+        help: "Items that are neither written nor discarded yet.",
+        value: |direction| direction.stats.queued,
+    },
+    Metric {
+        name: "chokepoint_queued_bytes",
+        kind: "gauge",
+        help: "Bytes of the items that are neither written nor discarded yet.",
+        value: |direction| direction.stats.queued_bytes,
+    },
+    Metric {
+        name: "chokepoint_delayed_items",
+        kind: "gauge",
+        help: "Queued items that wait for their latency to pass.",
+        value: |direction| direction.stats.delayed,
+    },
+];
+
+/// Serve the statistics of `stats` on `addr`.
+pub async fn serve(addr: SocketAddr, stats: StreamStats) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    eprintln!("serving metrics on http://{addr}/metrics");
+    tokio::spawn(async move {
+        loop {
+            let (mut client, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(%err, "failed to accept metrics connection");
+                    continue;
+                }
+            };
+            let stats = stats.clone();
+            tokio::spawn(async move {
+                let result = match control::request(&mut client).await {
+                    Ok((method, path, _)) if method == "GET" && path == "/metrics" => {
+                        control::reply(&mut client, "200 OK", CONTENT_TYPE, &render(&stats.directions())).await
+                    }
+                    Ok(_) => control::reply(&mut client, "404 Not Found", "text/plain", "not found\n").await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = result {
+                    debug!(%err, %peer, "failed to answer metrics request");
+                }
+            });
+        }
+    });
+}
+
+/// The metrics of the streams of `directions` in the text exposition format.
+fn render(directions: &BTreeMap<String, Direction>) -> String {
+    let mut metrics = String::new();
+    for metric in &METRICS {
+        let _ = writeln!(metrics, "# HELP {} {}", metric.name, metric.help);
+        let _ = writeln!(metrics, "# TYPE {} {}", metric.name, metric.kind);
+        for (name, direction) in directions {
+            let value = (metric.value)(direction);
+            let _ = writeln!(metrics, "{}{{direction=\"{name}\"}} {value}", metric.name);
+        }
+    }
+    let name = "chokepoint_dropped_items_total";
+    let _ = writeln!(metrics, "# HELP {name} Items that were dropped or expired, by reason.");
+    let _ = writeln!(metrics, "# TYPE {name} counter");
+    for (direction_name, direction) in directions {
+        for (reason, reason_name) in DROP_REASONS {
+            let value = direction.stats.dropped_by.get(reason);
+            let _ = writeln!(
+                metrics,
+                "{name}{{direction=\"{direction_name}\",reason=\"{reason_name}\"}} {value}"
+            );
+        }
+    }
+    metrics
+}
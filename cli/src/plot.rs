@@ -0,0 +1,106 @@
+//! Charts of a run for `--plot`, rendered from the events of the recorder when the run ends.
+
+use chokepoint::recorder::{
+    Fate,
+    ItemEvent,
+};
+use plotters::prelude::*;
+use std::{
+    error::Error,
+    path::Path,
+};
+
+const SIZE: (u32, u32) = (1024, 768);
+
+/// The number of intervals the throughput is averaged over.
+const THROUGHPUT_BUCKETS: usize = 100;
+
+/// Render the latency of every item over the time it was taken from the inner stream, with the dropped and expired
+/// items at the bottom, above the throughput of the emitted items over time, to an SVG file.
+pub fn render(path: &Path, events: &[ItemEvent]) -> Result<(), Box<dyn Error>> {
+    let end = events
+        .iter()
+        .map(|event| event.emitted.unwrap_or(event.enqueued).as_secs_f64())
+        .fold(0.0, f64::max)
+        .max(0.001);
+    let latencies = events
+        .iter()
+        .filter_map(|event| {
+            let emitted = event.emitted?;
+            Some((
+                event.enqueued.as_secs_f64(),
+                (emitted - event.enqueued).as_secs_f64() * 1000.0,
+            ))
+        })
+        .collect::<Vec<_>>();
+    let lost = events
+        .iter()
+        .filter(|event| matches!(event.fate, Fate::Dropped | Fate::Expired))
+        .map(|event| event.enqueued.as_secs_f64())
+        .collect::<Vec<_>>();
+    let max_latency = latencies
+        .iter()
+        .map(|&(_, latency)| latency)
+        .fold(0.0, f64::max)
+        .max(1.0);
+
+    let bucket = end / THROUGHPUT_BUCKETS as f64;
+    let mut bytes = vec![0usize; THROUGHPUT_BUCKETS];
+    for event in events {
+        if let Some(emitted) = event.emitted {
+            let index = ((emitted.as_secs_f64() / bucket) as usize).min(THROUGHPUT_BUCKETS - 1);
+            bytes[index] += event.size;
+        }
+    }
+    let throughput = bytes
+        .iter()
+        .enumerate()
+        .map(|(index, &bytes)| ((index as f64 + 0.5) * bucket, bytes as f64 / bucket / 1000.0))
+        .collect::<Vec<_>>();
+    let max_throughput = throughput.iter().map(|&(_, kbps)| kbps).fold(0.0, f64::max).max(1.0);
+
+    let root = SVGBackend::new(path, SIZE).into_drawing_area();
+    root.fill(&WHITE)?;
+    let (upper, lower) = root.split_vertically(SIZE.1 / 2);
+
+    let mut chart = ChartBuilder::on(&upper)
+        .caption("Latency", ("sans-serif", 20))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0.0..end, 0.0..max_latency * 1.05)?;
+    chart
+        .configure_mesh()
+        .x_desc("enqueued (s)")
+        .y_desc("latency (ms)")
+        .draw()?;
+    chart
+        .draw_series(latencies.iter().map(|&point| Circle::new(point, 2, BLUE.filled())))?
+        .label("emitted")
+        .legend(|(x, y)| Circle::new((x, y), 3, BLUE.filled()));
+    chart
+        .draw_series(lost.iter().map(|&at| Cross::new((at, 0.0), 4, RED)))?
+        .label("dropped or expired")
+        .legend(|(x, y)| Cross::new((x, y), 4, RED));
+    chart
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+
+    let mut chart = ChartBuilder::on(&lower)
+        .caption("Throughput", ("sans-serif", 20))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0.0..end, 0.0..max_throughput * 1.05)?;
+    chart
+        .configure_mesh()
+        .x_desc("emitted (s)")
+        .y_desc("throughput (kB/s)")
+        .draw()?;
+    chart.draw_series(LineSeries::new(throughput, &BLUE))?;
+
+    root.present()?;
+    Ok(())
+}
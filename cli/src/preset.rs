@@ -0,0 +1,85 @@
+//! Typical networks for `--preset`, so that quick experiments don't need the numbers of a network at hand.
+
+use crate::Args;
+use bytesize::ByteSize;
+use clap::{
+    parser::ValueSource,
+    ArgMatches,
+    ValueEnum,
+};
+
+/// A network to shape like. The values are for a single direction, as every direction of a proxied connection is
+/// shaped on its own, so the round trip time is twice the latency.
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum Preset {
+    Wifi,
+    Lte,
+    #[value(name = "3g")]
+    ThreeG,
+    Edge,
+    Satellite,
+}
+
+/// The flags a preset sets.
+struct Values {
+    mean: f64,
+    stddev: f64,
+    bandwidth_limit: ByteSize,
+    drop_prob: f64,
+}
+
+impl Preset {
+    fn values(self) -> Values {
+        match self {
+            Preset::Wifi => Values {
+                mean: 5.0,
+                stddev: 3.0,
+                bandwidth_limit: ByteSize::mb(4),
+                drop_prob: 0.001,
+            },
+            Preset::Lte => Values {
+                mean: 35.0,
+                stddev: 10.0,
+                bandwidth_limit: ByteSize::kb(2500),
+                drop_prob: 0.001,
+            },
+            Preset::ThreeG => Values {
+                mean: 100.0,
+                stddev: 30.0,
+                bandwidth_limit: ByteSize::kb(100),
+                drop_prob: 0.005,
+            },
+            Preset::Edge => Values {
+                mean: 300.0,
+                stddev: 80.0,
+                bandwidth_limit: ByteSize::kb(30),
+                drop_prob: 0.01,
+            },
+            // A geostationary satellite, the signal travels ~36,000 km up and down again
+            Preset::Satellite => Values {
+                mean: 300.0,
+                stddev: 20.0,
+                bandwidth_limit: ByteSize::kb(1250),
+                drop_prob: 0.005,
+            },
+        }
+    }
+
+    /// Set the flags of the preset that were not given on the command line.
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) {
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+        let values = self.values();
+        if unset("mean") {
+            args.latency_distribution.mean = values.mean;
+        }
+        if unset("stddev") {
+            args.latency_distribution.stddev = values.stddev;
+        }
+        if unset("bandwidth_limit") {
+            args.bandwidth_limit = Some(values.bandwidth_limit);
+        }
+        if unset("drop_prob") {
+            args.drop_prob = values.drop_prob;
+        }
+    }
+}
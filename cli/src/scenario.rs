@@ -0,0 +1,241 @@
+//! Scenario files for `--scenario`, which change the shaping flags at given times of a run, e.g.
+//!
+//! ```toml
+//! # Congested between 10s and 30s, then back to the flags
+//! [[step]]
+//! at = 10
+//! mean = 300
+//! drop_prob = 0.05
+//! bandwidth_limit = "100KB"
+//!
+//! [[step]]
+//! at = 30
+//! mean = 50
+//! drop_prob = 0.0
+//! bandwidth_limit = "0"
+//! ```
+
+use crate::{
+    with_bandwidth_limit,
+    Args,
+    LatencyDistribution,
+    LatencyModel,
+};
+use bytesize::ByteSize;
+use chokepoint::{
+    source::ScenarioSource,
+    ChokeSettings,
+    Reorder,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(rename = "step", default)]
+    steps: Vec<Step>,
+}
+
+/// The flags that change at `at` seconds into the run, named like the fields of [`Args`], e.g. `drop_prob` for
+/// `--drop`. The others keep their values. A bandwidth limit of zero disables the limit.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub at: f64,
+    pub latency_dist: Option<LatencyModel>,
+    pub mean: Option<f64>,
+    pub stddev: Option<f64>,
+    pub skew: Option<f64>,
+    pub max_latency: Option<f64>,
+    pub drop_prob: Option<f64>,
+    pub corrupt_prob: Option<f64>,
+    pub duplicate_prob: Option<f64>,
+    pub reorder_prob: Option<f64>,
+    pub reorder_gap: Option<usize>,
+    pub bandwidth_limit: Option<ByteSize>,
+    pub bandwidth_drop_prob: Option<f64>,
+}
+
+impl Scenario {
+    pub fn new(steps: Vec<Step>) -> Self {
+        Self { steps }
+    }
+
+    /// Read a scenario file, the value parser of `--scenario`.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|err| format!("failed to read {path}: {err}"))?;
+        Self::parse(&contents)
+    }
+
+    /// Parse the TOML of a scenario.
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let scenario = toml::from_str::<Scenario>(contents).map_err(|err| format!("invalid scenario: {err}"))?;
+        if let Some(step) = scenario.steps.iter().find(|step| !step.at.is_finite() || step.at < 0.0) {
+            return Err(format!("invalid scenario: step at {}s", step.at));
+        }
+        Ok(scenario)
+    }
+
+    /// The settings updates of the steps, starting from `flags`, played back from now on.
+    pub fn playback(&self, mut flags: Flags) -> Playback {
+        let updates = self
+            .steps()
+            .map(|step| (Duration::from_secs_f64(step.at), flags.apply(step)))
+            .collect();
+        Playback {
+            updates,
+            start: Instant::now(),
+        }
+    }
+
+    /// The steps ordered by their time.
+    pub fn steps(&self) -> impl Iterator<Item = &Step> {
+        let mut steps = self.steps.iter().collect::<Vec<_>>();
+        steps.sort_by(|a, b| a.at.total_cmp(&b.at));
+        steps.into_iter()
+    }
+}
+
+/// The current values of the flags that scenario steps change, serialized with the keys of the steps.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Flags {
+    #[serde(flatten)]
+    pub latency_distribution: LatencyDistribution,
+    pub drop_prob: f64,
+    pub corrupt_prob: f64,
+    pub duplicate_prob: f64,
+    pub reorder_prob: f64,
+    pub reorder_gap: usize,
+    pub bandwidth_limit: Option<ByteSize>,
+    pub bandwidth_drop_prob: f64,
+}
+
+impl From<Flags> for Step {
+    /// A step that changes all flags to `flags`, right away.
+    fn from(flags: Flags) -> Self {
+        let latency_distribution = flags.latency_distribution;
+        Step {
+            at: 0.0,
+            latency_dist: Some(latency_distribution.model),
+            mean: Some(latency_distribution.mean),
+            stddev: Some(latency_distribution.stddev),
+            skew: Some(latency_distribution.skew),
+            max_latency: latency_distribution.max_latency,
+            drop_prob: Some(flags.drop_prob),
+            corrupt_prob: Some(flags.corrupt_prob),
+            duplicate_prob: Some(flags.duplicate_prob),
+            reorder_prob: Some(flags.reorder_prob),
+            reorder_gap: Some(flags.reorder_gap),
+            bandwidth_limit: Some(flags.bandwidth_limit.unwrap_or_default()),
+            bandwidth_drop_prob: Some(flags.bandwidth_drop_prob),
+        }
+    }
+}
+
+impl Flags {
+    pub fn new(args: &Args) -> Self {
+        let &Args {
+            latency_distribution,
+            drop_prob,
+            corrupt_prob,
+            duplicate_prob,
+            reorder_prob,
+            reorder_gap,
+            bandwidth_limit,
+            bandwidth_drop_prob,
+            ..
+        } = args;
+        Self {
+            latency_distribution,
+            drop_prob,
+            corrupt_prob,
+            duplicate_prob,
+            reorder_prob,
+            reorder_gap,
+            bandwidth_limit,
+            bandwidth_drop_prob,
+        }
+    }
+
+    /// Change the flags that are set in `step`, returning the settings update that applies them.
+    pub fn apply(&mut self, step: &Step) -> ChokeSettings {
+        let mut update = ChokeSettings::default();
+        // Flags that configure one setting together, e.g. the latency distribution, are changed one by one
+        if step.latency_dist.is_some()
+            || step.mean.is_some()
+            || step.stddev.is_some()
+            || step.skew.is_some()
+            || step.max_latency.is_some()
+        {
+            let latency_distribution = &mut self.latency_distribution;
+            latency_distribution.model = step.latency_dist.unwrap_or(latency_distribution.model);
+            latency_distribution.mean = step.mean.unwrap_or(latency_distribution.mean);
+            latency_distribution.stddev = step.stddev.unwrap_or(latency_distribution.stddev);
+            latency_distribution.skew = step.skew.unwrap_or(latency_distribution.skew);
+            latency_distribution.max_latency = step.max_latency.or(latency_distribution.max_latency);
+            update = update.set_latency(Some(latency_distribution.latency()));
+        }
+        if let Some(drop_prob) = step.drop_prob {
+            self.drop_prob = drop_prob;
+            update = update.set_drop_probability(Some(drop_prob));
+        }
+        if let Some(corrupt_prob) = step.corrupt_prob {
+            self.corrupt_prob = corrupt_prob;
+            update = update.set_corrupt_probability(Some(corrupt_prob));
+        }
+        if let Some(duplicate_prob) = step.duplicate_prob {
+            self.duplicate_prob = duplicate_prob;
+            update = update.set_duplicate_probability(Some(duplicate_prob));
+        }
+        if step.reorder_prob.is_some() || step.reorder_gap.is_some() {
+            self.reorder_prob = step.reorder_prob.unwrap_or(self.reorder_prob);
+            self.reorder_gap = step.reorder_gap.unwrap_or(self.reorder_gap);
+            update = update.set_reorder(Some(
+                Reorder::builder()
+                    .probability(self.reorder_prob)
+                    .gap(self.reorder_gap)
+                    .build(),
+            ));
+        }
+        if step.bandwidth_limit.is_some() || step.bandwidth_drop_prob.is_some() {
+            self.bandwidth_limit = step.bandwidth_limit.or(self.bandwidth_limit);
+            self.bandwidth_drop_prob = step.bandwidth_drop_prob.unwrap_or(self.bandwidth_drop_prob);
+            update = match self.bandwidth_limit.filter(|limit| limit.as_u64() > 0) {
+                Some(limit) => with_bandwidth_limit(update, Some(limit), self.bandwidth_drop_prob),
+                None => update.set_bandwidth_limit(None),
+            };
+        }
+        update
+    }
+}
+
+/// The updates of a scenario on the timeline of the run, shared by the streams of all connections of a proxy.
+#[derive(Clone)]
+pub struct Playback {
+    updates: Vec<(Duration, ChokeSettings)>,
+    start: Instant,
+}
+
+impl Playback {
+    /// The source of a stream that starts now. The updates that are due already are applied at once, so that streams
+    /// of later connections start with the current state of the scenario.
+    pub fn source(&self) -> ScenarioSource {
+        let elapsed = self.start.elapsed();
+        let mut due = ChokeSettings::default();
+        let mut pending = Vec::new();
+        for (at, update) in &self.updates {
+            match at.checked_sub(elapsed) {
+                Some(after) if !after.is_zero() => pending.push((after, update.clone())),
+                _ => due.merge(update.clone()),
+            }
+        }
+        ScenarioSource::new(std::iter::once((Duration::ZERO, due)).chain(pending))
+    }
+}
@@ -0,0 +1,238 @@
+//! The `summarize` mode, comparing the events written with `--output` by earlier runs side by side, e.g. for A/B
+//! experiments. The files can be CSV or JSON Lines.
+
+use chokepoint::recorder::ItemEvent;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+/// The columns of an event row that are summarized, see `chokepoint::recorder`.
+#[derive(Debug, Deserialize)]
+pub struct Event {
+    seq: usize,
+    enqueued_ms: f64,
+    fate: String,
+    emitted_ms: Option<f64>,
+    size: usize,
+    corrupted: bool,
+    label: Option<String>,
+}
+
+/// The statistics of a run.
+#[derive(Debug, Default)]
+struct Summary {
+    /// Items taken from the inner streams, without duplicates.
+    items: usize,
+    lost: usize,
+    duplicated: usize,
+    corrupted: usize,
+    /// Items emitted after an item that was taken later by the same stream.
+    reordered: usize,
+    /// The latencies of the emitted items in ms, sorted.
+    latencies: Vec<f64>,
+    emitted_bytes: usize,
+    /// From the first item taken to the last item emitted, in ms.
+    duration_ms: f64,
+}
+
+/// A row of the comparison: its name and the value of a run.
+type Row = (&'static str, fn(&Summary) -> String);
+
+/// Print the statistics of the runs in `paths`, one column per run.
+pub fn print(paths: &[PathBuf]) -> Result<(), String> {
+    let runs = paths
+        .iter()
+        .map(|path| {
+            let events = read(path).map_err(|err| format!("{}: {err}", path.display()))?;
+            let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+            Ok((name.into_owned(), events))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    print!("{}", table(&runs));
+    Ok(())
+}
+
+/// The statistics of the named runs side by side, one column per run.
+pub fn table(runs: &[(String, Vec<Event>)]) -> String {
+    let summaries = runs.iter().map(|(_, events)| Summary::new(events)).collect::<Vec<_>>();
+    let rows: [Row; 12] = [
+        ("items", |summary| summary.items.to_string()),
+        ("loss", |summary| percent(summary.lost, summary.items)),
+        ("reordered", |summary| percent(summary.reordered, summary.items)),
+        ("duplicated", |summary| percent(summary.duplicated, summary.items)),
+        ("corrupted", |summary| percent(summary.corrupted, summary.items)),
+        ("latency mean", |summary| millis(summary.mean_latency())),
+        ("latency p50", |summary| millis(summary.latency_percentile(50.0))),
+        ("latency p90", |summary| millis(summary.latency_percentile(90.0))),
+        ("latency p99", |summary| millis(summary.latency_percentile(99.0))),
+        ("latency max", |summary| millis(summary.latencies.last().copied())),
+        ("duration", |summary| format!("{:.2}s", summary.duration_ms / 1000.0)),
+        ("throughput", |summary| {
+            let bytes_per_second = summary.emitted_bytes as f64 / (summary.duration_ms / 1000.0).max(0.001);
+            format!("{}/s", bytesize::ByteSize::b(bytes_per_second as u64).display().si())
+        }),
+    ];
+
+    let width = runs
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or_default()
+        .max(12);
+    let mut table = format!("{:<14}", "");
+    for (name, _) in runs {
+        let _ = write!(table, " {name:>width$}");
+    }
+    table.push('\n');
+    for (name, value) in rows {
+        let _ = write!(table, "{name:<14}");
+        for summary in &summaries {
+            let _ = write!(table, " {:>width$}", value(summary));
+        }
+        table.push('\n');
+    }
+    table
+}
+
+fn percent(part: usize, total: usize) -> String {
+    format!("{:.2}%", part as f64 / total.max(1) as f64 * 100.0)
+}
+
+fn millis(latency: Option<f64>) -> String {
+    latency.map_or("-".to_string(), |latency| format!("{latency:.1}ms"))
+}
+
+impl From<&ItemEvent> for Event {
+    fn from(event: &ItemEvent) -> Self {
+        let millis = |time: std::time::Duration| time.as_secs_f64() * 1000.0;
+        Event {
+            seq: event.seq,
+            enqueued_ms: millis(event.enqueued),
+            fate: event.fate.as_str().to_string(),
+            emitted_ms: event.emitted.map(millis),
+            size: event.size,
+            corrupted: event.corrupted,
+            label: event.label.clone(),
+        }
+    }
+}
+
+impl Summary {
+    fn new(events: &[Event]) -> Self {
+        let mut summary = Summary::default();
+        let mut emitted = Vec::new();
+        for event in events {
+            match event.fate.as_str() {
+                "duplicate" => summary.duplicated += 1,
+                "dropped" | "expired" => {
+                    summary.items += 1;
+                    summary.lost += 1;
+                }
+                _ => summary.items += 1,
+            }
+            if event.corrupted && event.fate != "duplicate" {
+                summary.corrupted += 1;
+            }
+            if let Some(emitted_ms) = event.emitted_ms {
+                summary.emitted_bytes += event.size;
+                if event.fate != "duplicate" {
+                    summary.latencies.push(emitted_ms - event.enqueued_ms);
+                    emitted.push((event.label.as_deref(), emitted_ms, event.seq));
+                }
+            }
+        }
+        summary.latencies.sort_by(f64::total_cmp);
+
+        // In the order they were emitted, by stream
+        emitted.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let mut latest = HashMap::new();
+        for (label, _, seq) in emitted {
+            let latest = latest.entry(label).or_insert(seq);
+            if seq < *latest {
+                summary.reordered += 1;
+            }
+            *latest = seq.max(*latest);
+        }
+
+        let start = events
+            .iter()
+            .map(|event| event.enqueued_ms)
+            .fold(f64::INFINITY, f64::min);
+        let end = events
+            .iter()
+            .map(|event| event.emitted_ms.unwrap_or(event.enqueued_ms))
+            .fold(f64::NEG_INFINITY, f64::max);
+        summary.duration_ms = (end - start).max(0.0);
+        summary
+    }
+
+    fn mean_latency(&self) -> Option<f64> {
+        (!self.latencies.is_empty()).then(|| self.latencies.iter().sum::<f64>() / self.latencies.len() as f64)
+    }
+
+    /// The nearest-rank percentile of the latencies.
+    fn latency_percentile(&self, percentile: f64) -> Option<f64> {
+        let rank = (percentile / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies.get(rank.saturating_sub(1)).copied()
+    }
+}
+
+/// Read the events of a CSV or JSON Lines file, told apart by the first line.
+fn read(path: &Path) -> Result<Vec<Event>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let mut lines = contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+    let Some((_, first)) = lines.next() else {
+        return Ok(Vec::new());
+    };
+    if first.starts_with('{') {
+        return std::iter::once((0, first))
+            .chain(lines)
+            .map(|(index, line)| serde_json::from_str(line).map_err(|err| format!("line {}: {err}", index + 1)))
+            .collect();
+    }
+
+    let header = csv_fields(first);
+    lines
+        .map(|(index, line)| {
+            let fields = csv_fields(line);
+            let row = header
+                .iter()
+                .zip(fields)
+                .filter(|(_, value)| !value.is_empty())
+                .map(|(name, value)| {
+                    let value = match name.as_str() {
+                        "fate" | "label" => serde_json::Value::String(value),
+                        _ => serde_json::from_str(&value).unwrap_or(serde_json::Value::String(value)),
+                    };
+                    (name.clone(), value)
+                })
+                .collect::<serde_json::Map<_, _>>();
+            serde_json::from_value(row.into()).map_err(|err| format!("line {}: {err}", index + 1))
+        })
+        .collect()
+}
+
+/// The fields of a CSV line, unquoted.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
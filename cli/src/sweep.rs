@@ -0,0 +1,124 @@
+//! Sweeps for `--sweep`, which step a flag from one value to another over the `--duration` of a run, e.g.
+//! `--sweep bandwidth_limit=5MB..100KB` to find the bandwidth at which a protocol breaks down. The flag changes like in
+//! a scenario, in equal steps at equal intervals, and the statistics of the items taken in every step are printed when
+//! the run ends.
+
+use crate::{
+    parse_probability,
+    scenario::{
+        Scenario,
+        Step,
+    },
+    summary,
+};
+use bytesize::ByteSize;
+use chokepoint::recorder::ItemEvent;
+use std::time::Duration;
+
+/// The keys of the scenario steps that can be swept.
+const KEYS: [&str; 10] = [
+    "mean",
+    "stddev",
+    "skew",
+    "max_latency",
+    "drop_prob",
+    "corrupt_prob",
+    "duplicate_prob",
+    "reorder_prob",
+    "bandwidth_limit",
+    "bandwidth_drop_prob",
+];
+
+#[derive(Debug, Clone)]
+pub struct Sweep {
+    key: &'static str,
+    from: f64,
+    to: f64,
+}
+
+impl Sweep {
+    /// Parse `key=from..to`, the value parser of `--sweep`. The keys are those of the scenario steps.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (key, range) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=from..to, e.g. bandwidth_limit=5MB..100KB, got {s}"))?;
+        let key = KEYS
+            .into_iter()
+            .find(|known| *known == key.trim())
+            .ok_or_else(|| format!("{key} can't be swept, expected one of {}", KEYS.join(", ")))?;
+        let (from, to) = range
+            .split_once("..")
+            .ok_or_else(|| format!("expected a range like from..to, got {range}"))?;
+        let value = |value: &str| -> Result<f64, String> {
+            let value = value.trim();
+            match key {
+                "bandwidth_limit" => value.parse::<ByteSize>().map(|limit| limit.as_u64() as f64),
+                key if key.ends_with("_prob") => parse_probability(value),
+                _ => value.parse::<f64>().map_err(|err| format!("{value}: {err}")),
+            }
+        };
+        Ok(Self {
+            key,
+            from: value(from)?,
+            to: value(to)?,
+        })
+    }
+
+    /// The value of the flag in each of `steps` steps, from `from` to `to`.
+    fn values(&self, steps: usize) -> impl Iterator<Item = f64> + '_ {
+        let intervals = steps.saturating_sub(1).max(1) as f64;
+        (0..steps).map(move |step| self.from + (self.to - self.from) * step as f64 / intervals)
+    }
+
+    /// The scenario that changes the flag at the start of each of `steps` equal parts of `duration`.
+    pub fn scenario(&self, duration: Duration, steps: usize) -> Scenario {
+        let steps = self
+            .values(steps)
+            .enumerate()
+            .map(|(step, value)| {
+                let mut step = Step {
+                    at: duration.as_secs_f64() * step as f64 / steps as f64,
+                    ..Step::default()
+                };
+                match self.key {
+                    "mean" => step.mean = Some(value),
+                    "stddev" => step.stddev = Some(value),
+                    "skew" => step.skew = Some(value),
+                    "max_latency" => step.max_latency = Some(value),
+                    "drop_prob" => step.drop_prob = Some(value),
+                    "corrupt_prob" => step.corrupt_prob = Some(value),
+                    "duplicate_prob" => step.duplicate_prob = Some(value),
+                    "reorder_prob" => step.reorder_prob = Some(value),
+                    "bandwidth_limit" => step.bandwidth_limit = Some(ByteSize::b(value.round() as u64)),
+                    "bandwidth_drop_prob" => step.bandwidth_drop_prob = Some(value),
+                    key => unreachable!("{key} is not in KEYS"),
+                }
+                step
+            })
+            .collect();
+        Scenario::new(steps)
+    }
+
+    /// The statistics of the items taken in each of `steps` equal parts of `duration`, with times relative to the
+    /// start of the sweep, one column per step.
+    pub fn report(&self, duration: Duration, steps: usize, events: &[ItemEvent]) -> String {
+        let mut columns = self
+            .values(steps)
+            .map(|value| (self.format(value), Vec::new()))
+            .collect::<Vec<_>>();
+        for event in events {
+            let step = (event.enqueued.as_secs_f64() / duration.as_secs_f64() * steps as f64) as usize;
+            columns[step.min(steps - 1)].1.push(summary::Event::from(event));
+        }
+        format!("{}\n{}", self.key, summary::table(&columns))
+    }
+
+    fn format(&self, value: f64) -> String {
+        match self.key {
+            "bandwidth_limit" => format!("{}/s", ByteSize::b(value.round() as u64).display().si()),
+            key if key.ends_with("_prob") => format!("{:.2}%", value * 100.0),
+            "skew" => format!("{value:.2}"),
+            _ => format!("{value:.1}ms"),
+        }
+    }
+}
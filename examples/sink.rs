@@ -1,12 +1,12 @@
 use chokepoint::{
     normal_distribution,
+    test_util::{
+        TestPayload,
+        TestSink,
+    },
     ChokeSettings,
     ChokeSink,
 };
-use chokepoint_test_helpers::{
-    TestPayload,
-    TestSink,
-};
 use futures::SinkExt as _;
 
 #[tokio::main]
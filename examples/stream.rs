@@ -23,9 +23,9 @@ async fn main() {
     let mut settings = ChokeSettings::default();
     let settings_tx = settings.settings_updater();
 
-    let mut traffic_shaper = ChokeStream::new(Box::new(ReceiverStream::new(rx)), settings);
+    let mut stream = ChokeStream::new(Box::new(ReceiverStream::new(rx)), settings);
 
-    // You can send new settings to the TrafficShaper at any time (normally you would do this on creation, this is just
+    // You can send new settings to the ChokeStream at any time (normally you would do this on creation, this is just
     // to showcase that).
     settings_tx
         .send(
@@ -33,12 +33,12 @@ async fn main() {
                 .set_latency_distribution(normal_distribution(10.0, 15.0, 100.0))
                 .set_drop_probability(Some(0.3))
                 .set_corrupt_probability(Some(0.0))
-                .set_bandwidth_limit(Some(100), 0.0),
+                .set_bandwidth_limit(Some(100)),
         )
         .await
         .unwrap();
 
-    // Spawn a task to send packets into the TrafficShaper
+    // Spawn a task to send packets into the ChokeStream
     tokio::spawn(async move {
         for i in 0..10usize {
             let mut data = Vec::new();
@@ -50,7 +50,7 @@ async fn main() {
         }
     });
 
-    while let Some(packet) = traffic_shaper.next().await {
+    while let Some(packet) = stream.next().await {
         let now = Utc::now().timestamp_nanos_opt().unwrap();
         let then = Duration::nanoseconds(i64::from_le_bytes(packet[0..8].try_into().unwrap()));
         let i = usize::from_le_bytes(packet[8..16].try_into().unwrap());
@@ -1,6 +1,25 @@
-use crate::time::Instant;
+use crate::{
+    time::Instant,
+    BandwidthLimit,
+    CrossTrafficPattern,
+};
+use rand::{
+    rngs::StdRng,
+    Rng as _,
+    SeedableRng as _,
+};
+use rand_distr::{
+    Distribution as _,
+    Exp,
+};
 use std::{
     collections::VecDeque,
+    sync::{
+        Arc,
+        Mutex,
+        MutexGuard,
+        PoisonError,
+    },
     time::Duration,
 };
 
@@ -21,6 +40,10 @@ impl BandwidthLimiter {
         }
     }
 
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
     pub fn limit_reached(&self) -> bool {
         self.capacity_left() == 0
     }
@@ -29,9 +52,9 @@ impl BandwidthLimiter {
         self.limit.saturating_sub(self.current_burden)
     }
 
-    #[allow(dead_code)]
+    /// When the oldest request leaves the window.
     pub fn deadline(&self) -> Option<Instant> {
-        self.requests.front().map(|(time, _)| *time + self.window)
+        self.requests.iter().map(|(time, _)| *time + self.window).min()
     }
 
     pub fn deadline_duration(&self, now: Instant) -> Option<Duration> {
@@ -43,15 +66,16 @@ impl BandwidthLimiter {
 
     pub fn update_at(&mut self, now: Instant) {
         let cutoff = now - self.window;
-        let n = self.requests.iter().take_while(|(time, _)| *time < cutoff).count();
-        if n == 0 {
-            return;
-        };
-        let remaining = self.requests.split_off(n);
-        for (_, weight) in self.requests.drain(..) {
-            self.current_burden -= weight;
-        }
-        self.requests = remaining;
+        // Requests are not guaranteed to be recorded in chronological order, so we can't stop at the first request
+        // that is still inside the window.
+        let current_burden = &mut self.current_burden;
+        self.requests.retain(|(time, weight)| {
+            let expired = *time <= cutoff;
+            if expired {
+                *current_burden -= weight;
+            }
+            !expired
+        });
     }
 
     pub fn add_request(&mut self, weight: usize) {
@@ -65,9 +89,321 @@ impl BandwidthLimiter {
     }
 }
 
+/// How late an item can be emitted with pacing while still keeping the rate, since timers fire a bit late. Longer
+/// gaps, e.g. while no items are queued, are not made up for by emitting a burst.
+const MAX_PACING_LAG: Duration = Duration::from_millis(2);
+
+/// A [`BandwidthLimit`] along with the bytes emitted so far.
+pub(crate) struct ActiveBandwidthLimit {
+    pub(crate) limit: BandwidthLimit,
+    window: BandwidthLimiter,
+    /// With pacing, when the next item may be emitted.
+    next_send: Option<Instant>,
+    /// The bytes per second in effect: the configured limit, lowered while ramping up or responding to congestion.
+    bytes_per_second: f64,
+    /// When the limit was first used, the start of the [`crate::CrossTraffic`].
+    started: Option<Instant>,
+    /// When the [`crate::SlowStart`] started, with the first use of the limit or after a restart.
+    ramp_started: Option<Instant>,
+    /// The bytes per second allowed by the [`crate::Congestion`] response, lowered while the limit overflows.
+    congested: f64,
+    /// When the current overflow started, reset whenever the limit is lowered.
+    overflow_since: Option<Instant>,
+    last_overflow: Option<Instant>,
+    /// Until when the limit has recovered.
+    recovered_until: Option<Instant>,
+    /// The packets of the [`crate::CrossTraffic`], created when the limit is first used.
+    cross_traffic: Option<CrossTrafficState>,
+}
+
+/// The progress of the [`crate::CrossTraffic`] of a limit.
+struct CrossTrafficState {
+    rng: StdRng,
+    /// When the next packet arrives.
+    next: Instant,
+}
+
+impl ActiveBandwidthLimit {
+    pub(crate) fn new(limit: BandwidthLimit) -> Self {
+        Self {
+            window: BandwidthLimiter::new(limit.bytes_per_second, limit.window),
+            bytes_per_second: limit.bytes_per_second as f64,
+            started: None,
+            ramp_started: None,
+            congested: limit.bytes_per_second as f64,
+            limit,
+            next_send: None,
+            overflow_since: None,
+            last_overflow: None,
+            recovered_until: None,
+            cross_traffic: None,
+        }
+    }
+
+    /// Change the limit. If only the bytes per second changed, the bytes emitted so far are kept and the limit in
+    /// effect is scaled along, otherwise the limit starts over.
+    pub(crate) fn change(&mut self, limit: BandwidthLimit) {
+        if self.limit == limit {
+            return;
+        }
+        let only_rate = BandwidthLimit {
+            bytes_per_second: self.limit.bytes_per_second,
+            ..limit.clone()
+        } == self.limit;
+        if !only_rate || self.limit.bytes_per_second == 0 {
+            *self = Self::new(limit);
+            return;
+        }
+        let ratio = limit.bytes_per_second as f64 / self.limit.bytes_per_second as f64;
+        self.limit = limit;
+        self.congested *= ratio;
+        self.set_bytes_per_second(self.bytes_per_second * ratio);
+    }
+
+    /// The bytes per second in effect, lower than the configured limit while it ramps up or responds to congestion.
+    pub(crate) fn bytes_per_second(&self) -> usize {
+        self.bytes_per_second as usize
+    }
+
+    /// Start the [`crate::SlowStart`] over with the next use of the limit. The bytes emitted so far are kept.
+    pub(crate) fn restart_slow_start(&mut self) {
+        self.ramp_started = None;
+    }
+
+    /// Update the bytes per second in effect at `now`.
+    fn update(&mut self, now: Instant) {
+        self.recover(now);
+        let started = *self.started.get_or_insert(now);
+        let ramp_started = *self.ramp_started.get_or_insert(now);
+        let ramp = self.limit.slow_start.as_ref().map_or(1.0, |slow_start| {
+            slow_start.ratio_after(now.saturating_duration_since(ramp_started))
+        });
+        self.set_bytes_per_second(self.congested.min(self.limit.bytes_per_second as f64 * ramp));
+        self.add_cross_traffic(started, now);
+    }
+
+    /// Account for the packets of the [`crate::CrossTraffic`] that arrived until `now`.
+    fn add_cross_traffic(&mut self, started: Instant, now: Instant) {
+        let Some(cross_traffic) = self.limit.cross_traffic.clone() else {
+            return;
+        };
+        let bytes_per_second = cross_traffic.pattern.bytes_per_second();
+        if bytes_per_second == 0 {
+            return;
+        }
+        let mut state = self.cross_traffic.take().unwrap_or_else(|| CrossTrafficState {
+            rng: StdRng::seed_from_u64(cross_traffic.seed.unwrap_or_else(|| rand::rng().random())),
+            next: started,
+        });
+        // Packets that left the window before `now` don't count anymore
+        if let Some(cutoff) = now.checked_sub(self.limit.window) {
+            if state.next < cutoff {
+                state.next = match &cross_traffic.pattern {
+                    // Keep the phase of the pattern
+                    CrossTrafficPattern::OnOff { on, off, .. } => {
+                        let period = (*on + *off).as_nanos();
+                        let skipped = (cutoff - state.next).as_nanos();
+                        state.next + nanos(skipped - skipped % period)
+                    }
+                    _ => cutoff,
+                };
+            }
+        }
+
+        let gap = cross_traffic.packet_size as f64 / bytes_per_second as f64;
+        let exp = Exp::new(1.0 / gap).expect("positive rate");
+        let gap = Duration::from_secs_f64(gap).max(Duration::from_nanos(1));
+        while state.next <= now {
+            let at = state.next;
+            match &cross_traffic.pattern {
+                CrossTrafficPattern::OnOff { on, off, .. } => {
+                    // The packets arrive during the first `on` of every period since the start
+                    let period = (*on + *off).as_nanos();
+                    let offset = at.saturating_duration_since(started).as_nanos() % period;
+                    if offset >= on.as_nanos() {
+                        state.next = at + nanos(period - offset);
+                        continue;
+                    }
+                    state.next = at + gap;
+                }
+                CrossTrafficPattern::Constant { .. } => state.next = at + gap,
+                CrossTrafficPattern::Poisson { .. } => {
+                    state.next = at + Duration::from_secs_f64(exp.sample(&mut state.rng));
+                }
+            }
+            self.sent(cross_traffic.packet_size, at);
+        }
+        self.cross_traffic = Some(state);
+    }
+
+    /// Account for an item that has to wait for the limit or is dropped because it is reached. With
+    /// [`crate::Congestion`], the limit is lowered once it has overflowed for an interval.
+    pub(crate) fn overflowed(&mut self, now: Instant) {
+        let Some(congestion) = &self.limit.congestion else {
+            return;
+        };
+        // An overflow that follows the previous one within the interval continues it
+        let continued = self
+            .last_overflow
+            .is_some_and(|last| now.saturating_duration_since(last) < congestion.interval);
+        let since = match self.overflow_since {
+            Some(since) if continued => since,
+            _ => now,
+        };
+        self.last_overflow = Some(now);
+        self.overflow_since = Some(since);
+
+        if now.saturating_duration_since(since) >= congestion.interval {
+            let min = self.limit.bytes_per_second as f64 * congestion.min_ratio;
+            self.congested = (self.congested * congestion.backoff).max(min);
+            self.set_bytes_per_second(self.bytes_per_second.min(self.congested));
+            self.overflow_since = Some(now);
+        }
+    }
+
+    /// With [`crate::Congestion`], raise a lowered limit for the time without overflow since the last call.
+    fn recover(&mut self, now: Instant) {
+        let (Some(congestion), Some(last_overflow)) = (&self.limit.congestion, self.last_overflow) else {
+            return;
+        };
+        let configured = self.limit.bytes_per_second as f64;
+        if self.congested >= configured {
+            return;
+        }
+        let from = (last_overflow + congestion.interval).max(self.recovered_until.unwrap_or(last_overflow));
+        if now <= from {
+            return;
+        }
+        self.recovered_until = Some(now);
+        let regained = (now - from).as_secs_f64() * congestion.recovery * configured;
+        self.congested = (self.congested + regained).min(configured);
+    }
+
+    fn set_bytes_per_second(&mut self, bytes_per_second: f64) {
+        self.bytes_per_second = bytes_per_second;
+        self.window.set_limit(bytes_per_second as usize);
+    }
+
+    pub(crate) fn limit_reached(&self, now: Instant) -> bool {
+        if self.limit.pacing {
+            self.paced_until(now).is_some()
+        } else {
+            self.window.limit_reached()
+        }
+    }
+
+    /// With pacing, the time until which no item can be emitted, if it is after `now`.
+    pub(crate) fn paced_until(&self, now: Instant) -> Option<Instant> {
+        self.next_send.filter(|next_send| *next_send > now)
+    }
+
+    /// When the next item can be emitted if the limit is reached, if it is after `now`.
+    pub(crate) fn available_at(&self, now: Instant) -> Option<Instant> {
+        if self.limit.pacing {
+            self.paced_until(now)
+        } else if self.window.limit_reached() {
+            self.window.deadline().filter(|deadline| *deadline > now)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `false` if the limit is reached at `now` and an item must wait.
+    pub(crate) fn can_send(&mut self, now: Instant) -> bool {
+        self.update(now);
+        let can_send = if self.limit.pacing {
+            self.paced_until(now).is_none()
+        } else {
+            self.window.update_at(now);
+            !self.window.limit_reached()
+        };
+        if !can_send {
+            self.overflowed(now);
+        }
+        can_send
+    }
+
+    /// Account for emitting `bytes` at `now`.
+    pub(crate) fn sent(&mut self, bytes: usize, now: Instant) {
+        if self.limit.pacing {
+            let start = self.next_send.map_or(now, |next_send| {
+                next_send.max(now.checked_sub(MAX_PACING_LAG).unwrap_or(now))
+            });
+            let transmission = Duration::from_secs_f64(bytes as f64 / self.bytes_per_second);
+            self.next_send = Some(start + transmission);
+        } else {
+            self.window.add_request_at(bytes, now);
+        }
+    }
+}
+
+fn nanos(nanos: u128) -> Duration {
+    Duration::from_nanos(nanos.try_into().unwrap_or(u64::MAX))
+}
+
+/// A bandwidth limit shared by several [`crate::ChokeStream`]s and [`crate::ChokeSink`]s, so that together they don't
+/// exceed it, e.g. the uplink of a host with many connections. Clones share the limit and the bytes emitted so far, see
+/// [`crate::ChokeSettings::set_shared_bandwidth_limit`]. The streams should use the same clock.
+#[derive(Clone)]
+pub struct SharedBandwidthLimit(Arc<Mutex<ActiveBandwidthLimit>>);
+
+impl SharedBandwidthLimit {
+    pub fn new(limit: impl Into<BandwidthLimit>) -> Self {
+        Self(Arc::new(Mutex::new(ActiveBandwidthLimit::new(limit.into()))))
+    }
+
+    /// The limit that is currently in effect.
+    pub fn limit(&self) -> BandwidthLimit {
+        self.lock().limit.clone()
+    }
+
+    /// Change the limit of all streams sharing it at once. The bytes emitted so far are only kept if nothing but the
+    /// bytes per second changed. A limit of zero disables the shared limit.
+    pub fn set_limit(&self, limit: impl Into<BandwidthLimit>) {
+        self.lock().change(limit.into());
+    }
+
+    /// The bytes per second in effect, lower than the configured limit while it ramps up or responds to congestion, see
+    /// [`crate::SlowStart`] and [`crate::Congestion`].
+    pub fn effective_bytes_per_second(&self) -> usize {
+        self.lock().bytes_per_second()
+    }
+
+    /// Start the [`crate::SlowStart`] of all streams sharing the limit over, e.g. after a blackout.
+    pub fn restart_slow_start(&self) {
+        self.lock().restart_slow_start();
+    }
+
+    /// Locks the limit, `None` if it is disabled by a limit of zero.
+    pub(crate) fn active(&self) -> Option<MutexGuard<'_, ActiveBandwidthLimit>> {
+        Some(self.lock()).filter(|active| active.limit.bytes_per_second > 0)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ActiveBandwidthLimit> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl PartialEq for SharedBandwidthLimit {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl std::fmt::Debug for SharedBandwidthLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SharedBandwidthLimit").field(&self.limit()).finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        Congestion,
+        CrossTraffic,
+        SlowStart,
+    };
 
     #[test]
     fn time_based_capacity_window() {
@@ -95,4 +431,179 @@ mod tests {
         limiter.update_at(now);
         assert_eq!(limiter.capacity_left(), 5);
     }
+
+    #[test]
+    fn capacity_at_deadline() {
+        let mut limiter = BandwidthLimiter::new(10, Duration::from_secs(1));
+        let now = Instant::now();
+        limiter.add_request_at(10, now);
+        assert!(limiter.limit_reached());
+
+        let deadline = limiter.deadline().unwrap();
+        assert_eq!(deadline, now + Duration::from_secs(1));
+        limiter.update_at(deadline);
+        assert_eq!(limiter.capacity_left(), 10);
+        assert_eq!(limiter.deadline(), None);
+    }
+
+    #[test]
+    fn congestion_backs_off_and_recovers() {
+        let congestion = Congestion::builder()
+            .interval(Duration::from_millis(100))
+            .backoff(0.5)
+            .recovery(0.1)
+            .min_ratio(0.2);
+        let mut limit = ActiveBandwidthLimit::new(
+            BandwidthLimit::builder()
+                .bytes_per_sec(1000)
+                .congestion(congestion)
+                .build(),
+        );
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        // Halved after every interval of overflow, down to the minimum
+        for millis in [0, 50] {
+            limit.overflowed(at(millis));
+        }
+        assert_eq!(limit.bytes_per_second(), 1000);
+        limit.overflowed(at(100));
+        assert_eq!(limit.bytes_per_second(), 500);
+        for millis in [150, 200] {
+            limit.overflowed(at(millis));
+        }
+        assert_eq!(limit.bytes_per_second(), 250);
+        for millis in [250, 300] {
+            limit.overflowed(at(millis));
+        }
+        assert_eq!(limit.bytes_per_second(), 200);
+
+        // Overflows further apart than the interval don't add up
+        limit.update(at(400));
+        limit.overflowed(at(600));
+        limit.overflowed(at(750));
+        assert_eq!(limit.bytes_per_second(), 200);
+
+        // Recovers linearly after an interval without overflow
+        limit.update(at(850));
+        assert_eq!(limit.bytes_per_second(), 200);
+        limit.update(at(1850));
+        assert_eq!(limit.bytes_per_second(), 300);
+        limit.update(at(60_000));
+        assert_eq!(limit.bytes_per_second(), 1000);
+    }
+
+    #[test]
+    fn slow_start_ramps_up() {
+        let slow_start = SlowStart::builder()
+            .duration(Duration::from_secs(2))
+            .initial_ratio(0.01);
+        let mut limit = ActiveBandwidthLimit::new(
+            BandwidthLimit::builder()
+                .bytes_per_sec(10_000)
+                .slow_start(slow_start)
+                .build(),
+        );
+        let start = Instant::now();
+
+        // Grows exponentially from the initial ratio, starting with the first use
+        limit.update(start);
+        assert_eq!(limit.bytes_per_second(), 100);
+        limit.update(start + Duration::from_secs(1));
+        assert_eq!(limit.bytes_per_second(), 1000);
+        limit.update(start + Duration::from_secs(2));
+        assert_eq!(limit.bytes_per_second(), 10_000);
+        limit.update(start + Duration::from_secs(10));
+        assert_eq!(limit.bytes_per_second(), 10_000);
+
+        // And again after a restart
+        limit.restart_slow_start();
+        limit.update(start + Duration::from_secs(20));
+        assert_eq!(limit.bytes_per_second(), 100);
+        limit.update(start + Duration::from_secs(21));
+        assert_eq!(limit.bytes_per_second(), 1000);
+    }
+
+    #[test]
+    fn changing_rate_keeps_state() {
+        let slow_start = SlowStart::builder()
+            .duration(Duration::from_secs(2))
+            .initial_ratio(0.01);
+        let limit = BandwidthLimit::builder()
+            .bytes_per_sec(10_000)
+            .slow_start(slow_start)
+            .build();
+        let mut active = ActiveBandwidthLimit::new(limit.clone());
+        let start = Instant::now();
+        active.update(start);
+        active.update(start + Duration::from_secs(1));
+        active.sent(500, start + Duration::from_secs(1));
+        assert_eq!(active.bytes_per_second(), 1000);
+
+        // The ramp and the emitted bytes are kept
+        active.change(BandwidthLimit {
+            bytes_per_second: 20_000,
+            ..limit.clone()
+        });
+        assert_eq!(active.bytes_per_second(), 2000);
+        assert_eq!(active.window.capacity_left(), 1500);
+        active.update(start + Duration::from_secs(2));
+        assert_eq!(active.bytes_per_second(), 20_000);
+
+        // Any other change starts over
+        active.change(BandwidthLimit {
+            window: Duration::from_secs(2),
+            ..limit
+        });
+        assert_eq!(active.bytes_per_second(), 10_000);
+        assert_eq!(active.window.capacity_left(), 10_000);
+    }
+
+    fn cross_traffic_limit(pattern: CrossTrafficPattern) -> ActiveBandwidthLimit {
+        ActiveBandwidthLimit::new(
+            BandwidthLimit::builder()
+                .bytes_per_sec(10_000)
+                .cross_traffic(CrossTraffic::builder().pattern(pattern).packet_size(100).seed(1))
+                .build(),
+        )
+    }
+
+    #[test]
+    fn cross_traffic_uses_capacity() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        // A packet every 20ms, the first one at the start
+        let mut limit = cross_traffic_limit(CrossTrafficPattern::Constant { bytes_per_second: 5000 });
+        assert!(limit.can_send(at(0)));
+        assert_eq!(limit.window.capacity_left(), 9900);
+        assert!(limit.can_send(at(990)));
+        assert_eq!(limit.window.capacity_left(), 5000);
+        // Only the packets within the last window count
+        assert!(limit.can_send(at(10_990)));
+        assert_eq!(limit.window.capacity_left(), 5000);
+
+        // Only during the first 500ms of every second
+        let mut limit = cross_traffic_limit(CrossTrafficPattern::OnOff {
+            bytes_per_second: 5000,
+            on: Duration::from_millis(500),
+            off: Duration::from_millis(500),
+        });
+        assert!(limit.can_send(at(0)));
+        assert!(limit.can_send(at(490)));
+        assert_eq!(limit.window.capacity_left(), 7500);
+        assert!(limit.can_send(at(990)));
+        assert_eq!(limit.window.capacity_left(), 7500);
+        assert!(limit.can_send(at(10_490)));
+        assert_eq!(limit.window.capacity_left(), 7500);
+        assert!(limit.can_send(at(10_990)));
+        assert_eq!(limit.window.capacity_left(), 7500);
+
+        // At the same rate on average
+        let mut limit = cross_traffic_limit(CrossTrafficPattern::Poisson { bytes_per_second: 5000 });
+        assert!(limit.can_send(at(0)));
+        assert!(limit.can_send(at(999)));
+        let used = 10_000 - limit.window.capacity_left();
+        assert!((4000..=6000).contains(&used), "{used}");
+    }
 }
@@ -0,0 +1,323 @@
+//! The source of time used by [`crate::ChokeStream`] and [`crate::ChokeSink`] to delay items and limit the bandwidth.
+//! By default, the [`SystemClock`] is used. A custom [`Clock`] can be configured with
+//! [`crate::ChokeSettings::set_clock`], e.g. to control time in tests.
+
+pub use crate::time::Instant;
+use crate::{
+    latency::MaybeSendSync,
+    time,
+};
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+    },
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc,
+        Mutex,
+        MutexGuard,
+        PoisonError,
+    },
+    task::{
+        Context,
+        Poll,
+        Waker,
+    },
+    time::Duration,
+};
+
+/// A future returned by [`Clock::sleep_until`].
+#[cfg(not(chokepoint_local))]
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A future returned by [`Clock::sleep_until`].
+#[cfg(chokepoint_local)]
+pub type Sleep = Pin<Box<dyn Future<Output = ()>>>;
+
+/// A source of time.
+///
+/// Example of a clock that runs at double speed:
+/// ```rust
+/// # use chokepoint::clock::{Clock, Instant, Sleep, SystemClock};
+/// struct DoubleSpeed(Instant);
+///
+/// impl Clock for DoubleSpeed {
+///     fn now(&self) -> Instant {
+///         self.0 + self.0.elapsed() * 2
+///     }
+///
+///     fn sleep_until(&self, deadline: Instant) -> Sleep {
+///         let remaining = deadline.saturating_duration_since(self.now());
+///         SystemClock.sleep_until(Instant::now() + remaining / 2)
+///     }
+/// }
+/// ```
+pub trait Clock: MaybeSendSync + 'static {
+    /// The current time.
+    fn now(&self) -> Instant;
+
+    /// A future that completes once [`Clock::now`] has reached `deadline`.
+    fn sleep_until(&self, deadline: Instant) -> Sleep;
+}
+
+/// The real time, using the timer of the runtime selected by the crate features (see the crate documentation). With
+/// the `tokio` feature, this respects a paused tokio clock, so tests using `#[tokio::test(start_paused = true)]`
+/// complete instantly.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        time::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Box::pin(time::sleep_until(deadline))
+    }
+}
+
+/// A clock that is shared between settings and the streams they have been applied to.
+#[derive(Clone)]
+pub(crate) struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub(crate) fn new(clock: impl Clock) -> Self {
+        Self(Arc::new(clock))
+    }
+
+    pub(crate) fn system() -> Self {
+        Self::new(SystemClock)
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        self.0.now()
+    }
+
+    pub(crate) fn sleep_until(&self, deadline: Instant) -> Sleep {
+        self.0.sleep_until(deadline)
+    }
+}
+
+impl PartialEq for SharedClock {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl std::fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedClock").finish_non_exhaustive()
+    }
+}
+
+/// Simulated time for discrete-event simulations: instead of waiting, time jumps forward to the earliest deadline
+/// any sleeper of the clock is waiting for. A scenario with minutes of latency completes in milliseconds of wall-clock
+/// time.
+///
+/// A sleeper yields to the executor once before advancing the time, so work that is ready at the current simulated
+/// time (e.g. other tasks that were just woken up) runs first. Everything that waits for time to pass, like the
+/// producer of the items, should use the same clock. With several tasks that are busy at the same simulated time, a
+/// single yield might not be enough: a [`VirtualClock::manual`] clock driven by an executor that knows when all tasks
+/// are idle, like [`crate::sim::Simulation`], is exact.
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{clock::VirtualClock, ChokeSettings, ChokeStream};
+/// # use bytes::Bytes;
+/// # use futures::StreamExt as _;
+/// # use std::time::Duration;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let clock = VirtualClock::new();
+/// let items = futures::stream::iter((0..1000).map(|_| Bytes::from_static(b"item")));
+/// let stream = ChokeStream::new(
+///     items,
+///     ChokeSettings::default()
+///         .set_clock(clock.clone())
+///         .set_latency_distribution(Some(|| Some(Duration::from_secs(600)))),
+/// );
+///
+/// assert_eq!(stream.count().await, 1000);
+/// assert!(clock.elapsed() >= Duration::from_secs(600));
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct VirtualClock {
+    state: Arc<Mutex<VirtualClockState>>,
+}
+
+struct VirtualClockState {
+    start: Instant,
+    now: Instant,
+    /// The wakers of the pending sleepers, by deadline and id.
+    sleepers: BTreeMap<(Instant, u64), Waker>,
+    /// Sleepers that have been woken up but not polled yet. Time doesn't advance until they had a chance to run.
+    woken: BTreeSet<u64>,
+    next_id: u64,
+    /// Whether sleepers advance the time on their own, see [`VirtualClock::manual`].
+    auto_advance: bool,
+}
+
+impl VirtualClock {
+    /// A clock starting at the current real time.
+    pub fn new() -> Self {
+        Self::with_auto_advance(true)
+    }
+
+    /// A clock whose time only advances through [`VirtualClock::advance`] and
+    /// [`VirtualClock::advance_to_next_deadline`], for executors that know when all of their tasks are idle (see
+    /// [`crate::sim::Simulation`]).
+    pub fn manual() -> Self {
+        Self::with_auto_advance(false)
+    }
+
+    fn with_auto_advance(auto_advance: bool) -> Self {
+        let now = time::now();
+        Self {
+            state: Arc::new(Mutex::new(VirtualClockState {
+                start: now,
+                now,
+                sleepers: BTreeMap::new(),
+                woken: BTreeSet::new(),
+                next_id: 0,
+                auto_advance,
+            })),
+        }
+    }
+
+    /// The simulated time that has passed since the clock was created.
+    pub fn elapsed(&self) -> Duration {
+        let state = self.lock();
+        state.now - state.start
+    }
+
+    /// Advance the time by `duration`, waking up all sleepers whose deadline has passed.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.lock();
+        let now = state.now + duration;
+        state.advance_to(now);
+    }
+
+    /// Advance the time to the earliest deadline a sleeper is waiting for and wake it up. Returns `false` if there is
+    /// no sleeper.
+    pub fn advance_to_next_deadline(&self) -> bool {
+        let mut state = self.lock();
+        match state.sleepers.keys().next() {
+            Some(&(deadline, _)) => {
+                state.advance_to(deadline);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VirtualClockState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for VirtualClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VirtualClock")
+            .field("elapsed", &self.elapsed())
+            .finish()
+    }
+}
+
+impl VirtualClockState {
+    fn advance_to(&mut self, now: Instant) {
+        self.now = self.now.max(now);
+        while let Some(entry) = self.sleepers.first_entry() {
+            if entry.key().0 > self.now {
+                break;
+            }
+            let ((_, id), waker) = entry.remove_entry();
+            self.woken.insert(id);
+            waker.wake();
+        }
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.lock().now
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        let id = {
+            let mut state = self.lock();
+            state.next_id += 1;
+            state.next_id
+        };
+        Box::pin(VirtualSleep {
+            clock: self.clone(),
+            deadline,
+            id,
+            yielded: false,
+        })
+    }
+}
+
+struct VirtualSleep {
+    clock: VirtualClock,
+    deadline: Instant,
+    id: u64,
+    yielded: bool,
+}
+
+impl Future for VirtualSleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let key = (self.deadline, self.id);
+        let clock = self.clock.clone();
+        let mut state = clock.lock();
+        if state.now >= self.deadline {
+            state.sleepers.remove(&key);
+            state.woken.remove(&self.id);
+            return Poll::Ready(());
+        }
+        state.sleepers.insert(key, cx.waker().clone());
+        if !state.auto_advance {
+            return Poll::Pending;
+        }
+
+        if !self.yielded {
+            // Give other work that is ready at the current time a chance to run first.
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+
+        // Nothing happened in the meantime, jump to the earliest deadline.
+        self.yielded = false;
+        if !state.woken.is_empty() {
+            return Poll::Pending;
+        }
+        if let Some(&(earliest, _)) = state.sleepers.keys().next() {
+            state.advance_to(earliest);
+        }
+        if state.now >= self.deadline {
+            state.woken.remove(&self.id);
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for VirtualSleep {
+    fn drop(&mut self) {
+        let mut state = self.clock.lock();
+        state.sleepers.remove(&(self.deadline, self.id));
+        state.woken.remove(&self.id);
+    }
+}
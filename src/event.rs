@@ -0,0 +1,116 @@
+//! A stream of structured events for every item of a [`crate::ChokeStream`] or [`crate::ChokeSink`], e.g. to feed
+//! shaping telemetry to an external tool.
+//!
+//! [`crate::ChokeStream::events`] returns a [`ChokeEvents`] stream that receives a [`ChokeEvent`] for every step in the
+//! shaping of every item from then on: when it was taken from the inner stream, the latency added to it, whether it
+//! was corrupted or duplicated and when it was dropped or emitted. Unlike a [`crate::recorder::Recorder`], the events
+//! are not formatted and can be consumed asynchronously, e.g. by another task. Events are buffered until they are
+//! consumed, so a stream that is not consumed grows without bound. Dropping it stops the events.
+//!
+//! Example:
+//! ```rust
+//! # use chokepoint::{event::ChokeEventKind, ChokeSettings, ChokeStream};
+//! # use bytes::Bytes;
+//! # use futures::StreamExt as _;
+//! # #[tokio::main]
+//! # async fn main() {
+//! let items = futures::stream::iter((0..100).map(|_| Bytes::from_static(b"hello")));
+//! let mut stream = ChokeStream::new(items, ChokeSettings::default().set_drop_probability(Some(0.1)));
+//! let events = stream.events();
+//! while stream.next().await.is_some() {}
+//! drop(stream);
+//!
+//! let dropped = events
+//!     .filter(|event| std::future::ready(matches!(event.kind, ChokeEventKind::Dropped(_))))
+//!     .count()
+//!     .await;
+//! assert!(dropped > 0);
+//! # }
+//! ```
+
+use crate::time::Instant;
+use futures::{
+    channel::mpsc,
+    Stream,
+};
+use std::{
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::Duration,
+};
+
+/// A step in the shaping of an item, see the [module documentation](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChokeEvent {
+    /// The position of the item in the inner stream, starting at 0. A duplicate has the same position as the original.
+    pub seq: usize,
+    pub kind: ChokeEventKind,
+    /// When the step happened.
+    pub at: Instant,
+}
+
+/// What happened to an item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChokeEventKind {
+    /// The item was taken from the inner stream. Followed by the other events of the item.
+    Enqueued,
+    /// A latency was added to the item. It can be emitted later because of the ordering or the bandwidth limit.
+    Delayed(Duration),
+    Dropped(DropReason),
+    Corrupted,
+    /// A duplicate of the item was added.
+    Duplicated,
+    /// The item was emitted. Duplicates are emitted as well.
+    Emitted,
+}
+
+/// Why an item was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// Randomly, see [`crate::ChokeSettings::set_drop_probability`].
+    Random,
+    /// By a bandwidth limit, see [`crate::BandwidthLimit::drop_ratio`]. Includes items that can't be marked with ECN.
+    BandwidthLimit,
+    /// By a [`crate::hook::DecisionHook`].
+    Hook,
+    /// By a replayed decision, see [`crate::replay`].
+    Replay,
+    /// The item waited longer than its time to live, see [`crate::ChokeSettings::set_item_ttl`].
+    Expired,
+    /// The item was queued when the ordering changed, see [`crate::ChokeSettings::set_ordering`].
+    Reconfigured,
+}
+
+/// The events of a stream, see the [module documentation](self). Ends once the stream is dropped.
+#[derive(Debug)]
+pub struct ChokeEvents(mpsc::UnboundedReceiver<ChokeEvent>);
+
+impl Stream for ChokeEvents {
+    type Item = ChokeEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
+/// The senders of the [`ChokeEvents`] streams of a shaper.
+#[derive(Debug, Default)]
+pub(crate) struct EventSenders(Vec<mpsc::UnboundedSender<ChokeEvent>>);
+
+impl EventSenders {
+    /// A new stream that receives the events sent from now on.
+    pub(crate) fn subscribe(&mut self) -> ChokeEvents {
+        let (tx, rx) = mpsc::unbounded();
+        self.0.push(tx);
+        ChokeEvents(rx)
+    }
+
+    /// Send an event to every stream, forgetting the streams that were dropped.
+    pub(crate) fn send(&mut self, seq: usize, kind: ChokeEventKind, at: Instant) {
+        let event = ChokeEvent { seq, kind, at };
+        self.0.retain(|tx| tx.unbounded_send(event).is_ok());
+    }
+}
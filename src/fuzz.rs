@@ -0,0 +1,281 @@
+//! [`Arbitrary`] implementations for the serializable settings, enabled with the `arbitrary` feature, so that fuzz
+//! targets can configure a [`crate::ChokeStream`] from fuzzer bytes, see [`ChokeSettingsSnapshot`].
+//!
+//! Only valid values are generated: probabilities between 0 and 1, non-zero windows, finite shapes and so on. The
+//! values are bounded to keep simulations short, like the strategies of the `proptest` feature: latencies and delays up
+//! to 500ms, bandwidth limits of at least 1 KB per second and MTUs of at least 64 bytes. Cross traffic leaves at least
+//! a tenth of the bandwidth limit to the items. Once the bytes run out, fields take their lowest values, i.e. empty
+//! input produces settings that don't shape items at all.
+
+use crate::{
+    BandwidthLimit,
+    Burst,
+    ChokeSettingsOrder,
+    ChokeSettingsSnapshot,
+    Coalescing,
+    Congestion,
+    CrossTraffic,
+    CrossTrafficPattern,
+    Latency,
+    Nagle,
+    Reorder,
+    ReorderWindow,
+    SlowStart,
+};
+use arbitrary::{
+    Arbitrary,
+    Result,
+    Unstructured,
+};
+use std::time::Duration;
+
+/// The maximum latency, delay or interval.
+const MAX_DELAY: Duration = Duration::from_millis(500);
+
+const MIN_BANDWIDTH: usize = 1_000;
+
+const MAX_BANDWIDTH: usize = 100_000_000;
+
+/// Every packet of cross traffic is simulated, so it is kept low.
+const MAX_CROSS_TRAFFIC: usize = 1_000_000;
+
+/// A number between `min` and `max`.
+fn float(u: &mut Unstructured<'_>, min: f64, max: f64) -> Result<f64> {
+    let fraction = f64::from(u.arbitrary::<u32>()?) / f64::from(u32::MAX);
+    Ok(min + fraction * (max - min))
+}
+
+fn probability(u: &mut Unstructured<'_>) -> Result<f64> {
+    float(u, 0.0, 1.0)
+}
+
+/// A duration between `min` and `max`, in whole microseconds.
+fn duration(u: &mut Unstructured<'_>, min: Duration, max: Duration) -> Result<Duration> {
+    let micros = u.int_in_range(min.as_micros() as u64..=max.as_micros() as u64)?;
+    Ok(Duration::from_micros(micros))
+}
+
+fn delay(u: &mut Unstructured<'_>) -> Result<Duration> {
+    duration(u, Duration::ZERO, MAX_DELAY)
+}
+
+fn non_zero_delay(u: &mut Unstructured<'_>) -> Result<Duration> {
+    duration(u, Duration::from_micros(1), MAX_DELAY)
+}
+
+/// Present if the next byte is odd.
+fn optional<'a, T>(u: &mut Unstructured<'a>, f: impl FnOnce(&mut Unstructured<'a>) -> Result<T>) -> Result<Option<T>> {
+    if u.arbitrary::<bool>()? {
+        f(u).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Latency {
+    /// A latency model other than [`Latency::Custom`].
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=6u8)? {
+            0 => Latency::None,
+            1 => Latency::Constant(delay(u)?),
+            2 => Latency::Normal {
+                mean: delay(u)?,
+                std_dev: duration(u, Duration::ZERO, MAX_DELAY / 4)?,
+                max: delay(u)?,
+            },
+            3 => Latency::SkewNormal {
+                location: delay(u)?,
+                scale: duration(u, Duration::ZERO, MAX_DELAY / 4)?,
+                shape: float(u, -5.0, 5.0)?,
+                max: delay(u)?,
+            },
+            4 => {
+                let min = delay(u)?;
+                Latency::Uniform {
+                    min,
+                    max: duration(u, min, MAX_DELAY)?,
+                }
+            }
+            5 => Latency::LogNormal {
+                median: delay(u)?,
+                sigma: float(u, 0.0, 1.5)?,
+                max: delay(u)?,
+            },
+            _ => Latency::Pareto {
+                scale: delay(u)?,
+                shape: float(u, 0.5, 5.0)?,
+                max: delay(u)?,
+            },
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for ChokeSettingsOrder {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(*u.choose(&[
+            ChokeSettingsOrder::Ordered,
+            ChokeSettingsOrder::Unordered,
+            ChokeSettingsOrder::PartiallyOrdered,
+        ])?)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Congestion {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Congestion {
+            interval: non_zero_delay(u)?,
+            backoff: float(u, 0.1, 0.99)?,
+            recovery: float(u, 0.0, 10.0)?,
+            min_ratio: float(u, 0.01, 1.0)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for SlowStart {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(SlowStart {
+            duration: delay(u)?,
+            initial_ratio: float(u, 0.01, 1.0)?,
+        })
+    }
+}
+
+/// Cross traffic of up to `max` bytes per second.
+fn cross_traffic(u: &mut Unstructured<'_>, max: usize) -> Result<CrossTraffic> {
+    let bytes_per_second = u.int_in_range(0..=max)?;
+    let pattern = match u.int_in_range(0..=2u8)? {
+        0 => CrossTrafficPattern::Constant { bytes_per_second },
+        1 => CrossTrafficPattern::OnOff {
+            bytes_per_second,
+            on: non_zero_delay(u)?,
+            off: delay(u)?,
+        },
+        _ => CrossTrafficPattern::Poisson { bytes_per_second },
+    };
+    Ok(CrossTraffic {
+        pattern,
+        packet_size: u.int_in_range(500..=1500)?,
+        seed: Some(u.arbitrary()?),
+    })
+}
+
+impl<'a> Arbitrary<'a> for BandwidthLimit {
+    /// A limit between 1 KB and 100 MB per second. Cross traffic is up to 1 MB per second and leaves at least a tenth
+    /// of the limit to the items.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let bytes_per_second = u.int_in_range(MIN_BANDWIDTH..=MAX_BANDWIDTH)?;
+        let max_cross_traffic = (bytes_per_second / 10 * 9).min(MAX_CROSS_TRAFFIC);
+        Ok(BandwidthLimit {
+            bytes_per_second,
+            window: duration(u, Duration::from_millis(1), Duration::from_secs(2))?,
+            drop_ratio: probability(u)?,
+            only_drop_when_full: u.arbitrary()?,
+            pacing: u.arbitrary()?,
+            congestion: u.arbitrary()?,
+            slow_start: u.arbitrary()?,
+            cross_traffic: optional(u, |u| cross_traffic(u, max_cross_traffic))?,
+            ecn: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Coalescing {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Coalescing {
+            max_bytes: u.int_in_range(1..=65_536)?,
+            max_delay: delay(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Nagle {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Nagle {
+            min_bytes: u.int_in_range(0..=65_536)?,
+            max_delay: delay(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Burst {
+    /// A size, an interval or both.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let size = u.int_in_range(1..=64)?;
+        let interval = non_zero_delay(u)?;
+        Ok(match u.int_in_range(0..=2u8)? {
+            0 => Burst {
+                size: Some(size),
+                interval: None,
+            },
+            1 => Burst {
+                size: None,
+                interval: Some(interval),
+            },
+            _ => Burst {
+                size: Some(size),
+                interval: Some(interval),
+            },
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for ReorderWindow {
+    /// A maximum distance, a maximum time or both.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let max_distance = u.int_in_range(0..=16)?;
+        let max_time = delay(u)?;
+        Ok(match u.int_in_range(0..=2u8)? {
+            0 => ReorderWindow {
+                max_distance: Some(max_distance),
+                max_time: None,
+            },
+            1 => ReorderWindow {
+                max_distance: None,
+                max_time: Some(max_time),
+            },
+            _ => ReorderWindow {
+                max_distance: Some(max_distance),
+                max_time: Some(max_time),
+            },
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Reorder {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Reorder {
+            probability: probability(u)?,
+            gap: u.int_in_range(1..=16)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for ChokeSettingsSnapshot {
+    /// Valid settings without latency distribution functions or decision hooks. Convert them with
+    /// [`crate::ChokeSettings::from`] to configure a stream.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let latency = u.arbitrary::<Option<Latency>>()?.filter(Latency::is_some);
+        Ok(ChokeSettingsSnapshot {
+            latency_distribution: latency.is_some(),
+            latency,
+            drop_probability: probability(u)?,
+            corrupt_probability: probability(u)?,
+            duplicate_probability: probability(u)?,
+            bandwidth_limit: u.arbitrary()?,
+            shared_bandwidth_limit: u.arbitrary()?,
+            ordering: u.arbitrary()?,
+            backpressure: u.arbitrary()?,
+            poll_budget: u.int_in_range(1..=64)?,
+            mtu: optional(u, |u| u.int_in_range(64..=9_000))?,
+            coalescing: u.arbitrary()?,
+            nagle: u.arbitrary()?,
+            burst: u.arbitrary()?,
+            item_ttl: optional(u, |u| duration(u, Duration::ZERO, MAX_DELAY * 4))?,
+            initial_delay: u.arbitrary::<Option<Latency>>()?.filter(Latency::is_some),
+            reorder_window: u.arbitrary()?,
+            reorder: u.arbitrary()?,
+            decision_hooks: 0,
+            seed: u.arbitrary()?,
+        })
+    }
+}
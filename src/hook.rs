@@ -0,0 +1,126 @@
+//! Custom hooks into the decision made for every item, e.g. for a loss model that is not built in.
+//!
+//! The [`crate::ChokeStream`] decides the fate of every item taken from the inner stream: whether it is dropped, the
+//! latency added to it and whether it is corrupted, pushed back or duplicated. Hooks added with
+//! [`crate::ChokeSettings::add_decision_hook`] are called afterwards, in the order in which they were added, and can
+//! change that [`Decision`] based on the item and their own state. They don't replace the built-in steps: the ordering,
+//! the bandwidth limit and all other steps after the decision apply to the result.
+//!
+//! Decisions recorded with a [`crate::replay::DecisionRecorder`] include the changes made by the decision hooks.
+//! Replayed decisions are not passed to the decision hooks again.
+//!
+//! Example:
+//! ```rust
+//! # use chokepoint::{replay::Decision, hook::{DecisionHook, HookItem}, ChokeSettings, ChokeStream};
+//! # use bytes::Bytes;
+//! # use futures::StreamExt as _;
+//! # #[tokio::main]
+//! # async fn main() {
+//! /// Loses items in bursts: once an item is lost, the next one is lost as well with a probability of 70%.
+//! struct BurstLoss {
+//!     losing: bool,
+//! }
+//!
+//! impl DecisionHook for BurstLoss {
+//!     fn process(&mut self, _item: &HookItem<'_>, decision: &mut Decision) {
+//!         self.losing = rand::random_bool(if self.losing { 0.7 } else { 0.05 });
+//!         if self.losing {
+//!             *decision = Decision::Drop;
+//!         }
+//!     }
+//! }
+//!
+//! let items = futures::stream::iter((0..100).map(|_| Bytes::from_static(b"hello")));
+//! let settings = ChokeSettings::default().add_decision_hook(BurstLoss { losing: false });
+//! let mut stream = ChokeStream::new(items, settings);
+//! while stream.next().await.is_some() {}
+//! assert_eq!(stream.stats().dropped + stream.stats().emitted, 100);
+//! # }
+//! ```
+
+use crate::{
+    latency::MaybeSendSync,
+    replay::Decision,
+    time::Instant,
+};
+#[cfg(not(chokepoint_local))]
+use std::sync::{
+    Arc,
+    Mutex,
+    PoisonError,
+};
+#[cfg(chokepoint_local)]
+use std::{
+    cell::RefCell,
+    rc::Rc,
+};
+
+/// A custom step in the shaping of every item, see the [module documentation](self).
+pub trait DecisionHook: MaybeSendSync {
+    /// Called for every item taken from the inner stream with the decision made so far, which can be changed.
+    fn process(&mut self, item: &HookItem<'_>, decision: &mut Decision);
+}
+
+/// What a [`DecisionHook`] knows about an item.
+#[derive(Debug, Clone, Copy)]
+pub struct HookItem<'a> {
+    /// The position of the item in the inner stream, starting at 0.
+    pub seq: usize,
+    /// The size of the item in bytes, see [`crate::ChokeItem::byte_len`].
+    pub size: usize,
+    /// The payload of the item, if available, see [`crate::ChokeItem::payload`].
+    pub payload: Option<&'a [u8]>,
+    /// When the item was taken from the inner stream.
+    pub now: Instant,
+}
+
+/// A hook that is shared between settings and the streams they have been applied to.
+#[cfg(not(chokepoint_local))]
+#[derive(Clone)]
+pub(crate) struct Hook(Arc<Mutex<dyn DecisionHook>>);
+
+/// A hook that is shared between settings and the streams they have been applied to.
+#[cfg(chokepoint_local)]
+#[derive(Clone)]
+pub(crate) struct Hook(Rc<RefCell<dyn DecisionHook>>);
+
+impl Hook {
+    #[cfg(not(chokepoint_local))]
+    pub(crate) fn new(hook: impl DecisionHook + 'static) -> Self {
+        Self(Arc::new(Mutex::new(hook)))
+    }
+
+    #[cfg(chokepoint_local)]
+    pub(crate) fn new(hook: impl DecisionHook + 'static) -> Self {
+        Self(Rc::new(RefCell::new(hook)))
+    }
+
+    #[cfg(not(chokepoint_local))]
+    pub(crate) fn process(&self, item: &HookItem<'_>, decision: &mut Decision) {
+        let mut hook = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        hook.process(item, decision);
+    }
+
+    #[cfg(chokepoint_local)]
+    pub(crate) fn process(&self, item: &HookItem<'_>, decision: &mut Decision) {
+        self.0.borrow_mut().process(item, decision);
+    }
+}
+
+impl PartialEq for Hook {
+    #[cfg(not(chokepoint_local))]
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    #[cfg(chokepoint_local)]
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl std::fmt::Debug for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("dyn DecisionHook")
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc,
+        Mutex,
+        MutexGuard,
+        PoisonError,
+    },
+    task::{
+        Context,
+        Poll,
+        Waker,
+    },
+};
+
+/// The future returned by [`crate::ChokeStream::idle`]. Resolves once the stream reports that no items are in flight
+/// and the inner stream has no more items at the moment, or once the stream is dropped.
+///
+/// Only reports made after the future was first polled count, so items sent to the inner stream before are taken into
+/// account. The future wakes up the task polling the stream to get a report, but the stream has to be polled by
+/// another task for the future to resolve.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Idle {
+    state: Arc<Mutex<IdleState>>,
+    /// The number of reports when the future was first polled.
+    since: Option<u64>,
+}
+
+impl Future for Idle {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let state = self.state.clone();
+        let mut state = lock(&state);
+        if state.dropped {
+            return Poll::Ready(());
+        }
+        match self.since {
+            Some(since) if state.reports > since && state.idle => return Poll::Ready(()),
+            Some(_) => {}
+            None => {
+                self.since = Some(state.reports);
+                if let Some(stream) = &state.stream {
+                    stream.wake_by_ref();
+                }
+            }
+        }
+        if !state.waiting.iter().any(|waker| waker.will_wake(cx.waker())) {
+            state.waiting.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+#[derive(Debug, Default)]
+struct IdleState {
+    /// The number of reports so far.
+    reports: u64,
+    /// Whether the stream was idle at the last report.
+    idle: bool,
+    /// The task polling the stream, woken up for another report.
+    stream: Option<Waker>,
+    /// The tasks waiting for an idle report.
+    waiting: Vec<Waker>,
+    /// Whether the stream was dropped.
+    dropped: bool,
+}
+
+/// Lets a stream report whether it is idle to its [`Idle`] futures.
+#[derive(Debug, Default)]
+pub(crate) struct IdleReporter(Arc<Mutex<IdleState>>);
+
+impl IdleReporter {
+    pub(crate) fn idle(&self) -> Idle {
+        Idle {
+            state: self.0.clone(),
+            since: None,
+        }
+    }
+
+    /// Report the state of the stream at the end of a poll.
+    pub(crate) fn report(&self, idle: bool, cx: &Context<'_>) {
+        let mut state = lock(&self.0);
+        if !state.stream.as_ref().is_some_and(|stream| stream.will_wake(cx.waker())) {
+            state.stream = Some(cx.waker().clone());
+        }
+        state.set(idle);
+    }
+}
+
+impl Drop for IdleReporter {
+    /// Nothing is in flight anymore once the stream is dropped.
+    fn drop(&mut self) {
+        let mut state = lock(&self.0);
+        state.stream = None;
+        state.dropped = true;
+        state.set(true);
+    }
+}
+
+impl IdleState {
+    fn set(&mut self, idle: bool) {
+        self.reports += 1;
+        self.idle = idle;
+        if idle {
+            self.waiting.drain(..).for_each(Waker::wake);
+        }
+    }
+}
+
+fn lock(state: &Mutex<IdleState>) -> MutexGuard<'_, IdleState> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -2,17 +2,52 @@ use bytes::{
     Bytes,
     BytesMut,
 };
-use rand::Rng;
+use rand::{
+    Rng,
+    RngCore,
+};
 
-/// A trait for payloads that can be used with the TrafficShaper.
+/// A trait for items that can be shaped by a [`crate::ChokeStream`] or [`crate::ChokeSink`].
 pub trait ChokeItem: Unpin + Sized + 'static {
     fn byte_len(&self) -> usize;
 
     fn corrupt(&mut self);
 
+    /// Like [`ChokeItem::corrupt`] but with the random number generator of the stream, so that the corruption is
+    /// reproducible with [`crate::ChokeSettings::set_seed`]. Defaults to [`ChokeItem::corrupt`].
+    fn corrupt_with(&mut self, rng: &mut dyn RngCore) {
+        let _ = rng;
+        self.corrupt();
+    }
+
     fn duplicate(&mut self) -> Option<Self> {
         None
     }
+
+    /// The bytes of the item, if it has a byte representation. Used to write the item to a pcap file, see
+    /// [`crate::pcap`].
+    fn payload(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Split the item into fragments of at most `mtu` bytes, see [`crate::ChokeSettings::set_mtu`]. Only called for
+    /// items larger than `mtu`. Defaults to not splitting the item.
+    fn split(self, mtu: usize) -> Vec<Self> {
+        let _ = mtu;
+        vec![self]
+    }
+
+    /// Append `other` to the item, see [`crate::ChokeSettings::set_coalescing`]. Returns `other` if the items can't be
+    /// merged, which is the default.
+    fn coalesce(&mut self, other: Self) -> Result<(), Self> {
+        Err(other)
+    }
+
+    /// Set the ECN field of the item to Congestion Experienced (CE), see [`crate::BandwidthLimitBuilder::ecn`].
+    /// Returns `false` if the item is not ECN-capable, in which case it is dropped instead, which is the default.
+    fn mark_ecn(&mut self) -> bool {
+        false
+    }
 }
 
 impl ChokeItem for Bytes {
@@ -21,7 +56,11 @@ impl ChokeItem for Bytes {
     }
 
     fn corrupt(&mut self) {
-        let index = rand::rng().random_range(0..self.len());
+        self.corrupt_with(&mut rand::rng());
+    }
+
+    fn corrupt_with(&mut self, rng: &mut dyn RngCore) {
+        let index = rng.random_range(0..self.len());
         let mut packet_modified = BytesMut::from(self.to_owned());
         packet_modified[index] ^= 0xFF; // Corrupt one byte
         *self = packet_modified.freeze();
@@ -30,12 +69,33 @@ impl ChokeItem for Bytes {
     fn duplicate(&mut self) -> Option<Self> {
         Some(self.clone())
     }
+
+    fn payload(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+
+    fn split(mut self, mtu: usize) -> Vec<Self> {
+        let mut fragments = Vec::with_capacity(self.len().div_ceil(mtu));
+        while self.len() > mtu {
+            fragments.push(self.split_to(mtu));
+        }
+        fragments.push(self);
+        fragments
+    }
+
+    fn coalesce(&mut self, other: Self) -> Result<(), Self> {
+        let mut merged = BytesMut::with_capacity(self.len() + other.len());
+        merged.extend_from_slice(self);
+        merged.extend_from_slice(&other);
+        *self = merged.freeze();
+        Ok(())
+    }
 }
 
 impl<T, E> ChokeItem for Result<T, E>
 where
     T: ChokeItem,
-    E: Unpin + Send + Sync + 'static,
+    E: Unpin + 'static,
 {
     fn byte_len(&self) -> usize {
         self.as_ref().map_or(0, |payload| payload.byte_len())
@@ -47,9 +107,37 @@ where
         }
     }
 
+    fn corrupt_with(&mut self, rng: &mut dyn RngCore) {
+        if let Ok(payload) = self {
+            payload.corrupt_with(rng);
+        }
+    }
+
     fn duplicate(&mut self) -> Option<Self> {
         self.as_mut().ok().and_then(|payload| payload.duplicate().map(Ok))
     }
+
+    fn payload(&self) -> Option<&[u8]> {
+        self.as_ref().ok().and_then(|payload| payload.payload())
+    }
+
+    fn split(self, mtu: usize) -> Vec<Self> {
+        match self {
+            Ok(payload) => payload.split(mtu).into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        }
+    }
+
+    fn coalesce(&mut self, other: Self) -> Result<(), Self> {
+        match (self, other) {
+            (Ok(payload), Ok(other)) => payload.coalesce(other).map_err(Ok),
+            (_, other) => Err(other),
+        }
+    }
+
+    fn mark_ecn(&mut self) -> bool {
+        self.as_mut().is_ok_and(|payload| payload.mark_ecn())
+    }
 }
 
 impl<T> ChokeItem for Option<T>
@@ -66,7 +154,247 @@ where
         }
     }
 
+    fn corrupt_with(&mut self, rng: &mut dyn RngCore) {
+        if let Some(payload) = self {
+            payload.corrupt_with(rng);
+        }
+    }
+
     fn duplicate(&mut self) -> Option<Self> {
         self.as_mut().and_then(|payload| payload.duplicate().map(Some))
     }
+
+    fn payload(&self) -> Option<&[u8]> {
+        self.as_ref().and_then(|payload| payload.payload())
+    }
+
+    fn split(self, mtu: usize) -> Vec<Self> {
+        match self {
+            Some(payload) => payload.split(mtu).into_iter().map(Some).collect(),
+            None => vec![None],
+        }
+    }
+
+    fn coalesce(&mut self, other: Self) -> Result<(), Self> {
+        match (self, other) {
+            (Some(payload), Some(other)) => payload.coalesce(other).map_err(Some),
+            (_, other) => Err(other),
+        }
+    }
+
+    fn mark_ecn(&mut self) -> bool {
+        self.as_mut().is_some_and(|payload| payload.mark_ecn())
+    }
+}
+
+/// An item with metadata, e.g. for routing or tracking, that rides along untouched: fragments and duplicates carry a
+/// clone of it. Items with metadata are not coalesced, as that would lose the metadata of the appended item.
+impl<T, M> ChokeItem for (T, M)
+where
+    T: ChokeItem,
+    M: Clone + Unpin + 'static,
+{
+    fn byte_len(&self) -> usize {
+        self.0.byte_len()
+    }
+
+    fn corrupt(&mut self) {
+        self.0.corrupt();
+    }
+
+    fn corrupt_with(&mut self, rng: &mut dyn RngCore) {
+        self.0.corrupt_with(rng);
+    }
+
+    fn duplicate(&mut self) -> Option<Self> {
+        self.0.duplicate().map(|payload| (payload, self.1.clone()))
+    }
+
+    fn payload(&self) -> Option<&[u8]> {
+        self.0.payload()
+    }
+
+    fn split(self, mtu: usize) -> Vec<Self> {
+        let (payload, metadata) = self;
+        payload
+            .split(mtu)
+            .into_iter()
+            .map(|fragment| (fragment, metadata.clone()))
+            .collect()
+    }
+
+    fn mark_ecn(&mut self) -> bool {
+        self.0.mark_ecn()
+    }
+}
+
+/// Text, e.g. the lines of `tokio_util::codec::LinesCodec`.
+impl ChokeItem for String {
+    fn byte_len(&self) -> usize {
+        self.len()
+    }
+
+    fn corrupt(&mut self) {
+        self.corrupt_with(&mut rand::rng());
+    }
+
+    /// Corrupts a byte and decodes the text lossily, so that invalid UTF-8 shows up as replacement characters.
+    fn corrupt_with(&mut self, rng: &mut dyn RngCore) {
+        *self = corrupt_text(self, rng);
+    }
+
+    fn duplicate(&mut self) -> Option<Self> {
+        Some(self.clone())
+    }
+
+    fn payload(&self) -> Option<&[u8]> {
+        Some(self.as_bytes())
+    }
+
+    /// Splits at character boundaries. A character larger than `mtu` becomes a fragment of its own.
+    fn split(mut self, mtu: usize) -> Vec<Self> {
+        let mut fragments = Vec::with_capacity(self.len().div_ceil(mtu));
+        while self.len() > mtu {
+            let mut at = (1..=mtu).rev().find(|at| self.is_char_boundary(*at)).unwrap_or(0);
+            if at == 0 {
+                at = self.chars().next().map_or(self.len(), char::len_utf8);
+            }
+            if at == self.len() {
+                break;
+            }
+            let rest = self.split_off(at);
+            fragments.push(std::mem::replace(&mut self, rest));
+        }
+        fragments.push(self);
+        fragments
+    }
+
+    fn coalesce(&mut self, other: Self) -> Result<(), Self> {
+        self.push_str(&other);
+        Ok(())
+    }
+}
+
+/// Flips a random byte of the text and decodes it lossily.
+fn corrupt_text(text: &str, rng: &mut dyn RngCore) -> String {
+    if text.is_empty() {
+        return String::new();
+    }
+    let mut bytes = text.as_bytes().to_vec();
+    let index = rng.random_range(0..bytes.len());
+    bytes[index] ^= 0xFF;
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Corrupts the bytes, unless they are empty.
+#[cfg(any(feature = "http", feature = "tungstenite"))]
+fn corrupt_bytes(bytes: &mut Bytes, rng: &mut dyn RngCore) {
+    if !bytes.is_empty() {
+        bytes.corrupt_with(rng);
+    }
+}
+
+/// WebSocket messages, enabled with the `tungstenite` feature. The size is the size of the payload. Text messages are
+/// corrupted like a [`String`], the payload of binary, ping and pong messages like [`Bytes`] and close messages are not
+/// corrupted. Messages are neither split nor merged, as they are the unit a WebSocket delivers.
+#[cfg(feature = "tungstenite")]
+impl ChokeItem for tungstenite::Message {
+    fn byte_len(&self) -> usize {
+        self.len()
+    }
+
+    fn corrupt(&mut self) {
+        self.corrupt_with(&mut rand::rng());
+    }
+
+    fn corrupt_with(&mut self, rng: &mut dyn RngCore) {
+        use tungstenite::{
+            protocol::frame::Frame,
+            Message,
+        };
+
+        match self {
+            Message::Text(text) => *text = corrupt_text(text.as_str(), rng).into(),
+            Message::Binary(data) | Message::Ping(data) | Message::Pong(data) => corrupt_bytes(data, rng),
+            Message::Close(_) => {}
+            Message::Frame(frame) => {
+                let header = frame.header().clone();
+                let mut payload = Bytes::copy_from_slice(frame.payload());
+                corrupt_bytes(&mut payload, rng);
+                *frame = Frame::from_payload(header, payload);
+            }
+        }
+    }
+
+    fn duplicate(&mut self) -> Option<Self> {
+        Some(self.clone())
+    }
+
+    fn payload(&self) -> Option<&[u8]> {
+        use tungstenite::Message;
+
+        match self {
+            Message::Text(text) => Some(text.as_bytes()),
+            Message::Binary(data) | Message::Ping(data) | Message::Pong(data) => Some(data),
+            Message::Close(_) => None,
+            Message::Frame(frame) => Some(frame.payload()),
+        }
+    }
+}
+
+/// The size of the head of an HTTP/1.1 message with the given start line and headers, including the empty line.
+#[cfg(feature = "http")]
+fn http_head_len(start_line: usize, headers: &http::HeaderMap) -> usize {
+    let headers = headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + ": ".len() + value.len() + "\r\n".len())
+        .sum::<usize>();
+    start_line + "\r\n".len() + headers + "\r\n".len()
+}
+
+/// HTTP requests, enabled with the `http` feature. The size is the size of the request in HTTP/1.1 encoding, only the
+/// body is corrupted.
+#[cfg(feature = "http")]
+impl ChokeItem for http::Request<Bytes> {
+    fn byte_len(&self) -> usize {
+        // E.g. `GET /index.html HTTP/1.1`
+        let start_line = self.method().as_str().len() + 1 + self.uri().to_string().len() + " HTTP/1.1".len();
+        http_head_len(start_line, self.headers()) + self.body().len()
+    }
+
+    fn corrupt(&mut self) {
+        self.corrupt_with(&mut rand::rng());
+    }
+
+    fn corrupt_with(&mut self, rng: &mut dyn RngCore) {
+        corrupt_bytes(self.body_mut(), rng);
+    }
+
+    fn duplicate(&mut self) -> Option<Self> {
+        Some(self.clone())
+    }
+}
+
+/// HTTP responses, enabled with the `http` feature. The size is the size of the response in HTTP/1.1 encoding, only
+/// the body is corrupted.
+#[cfg(feature = "http")]
+impl ChokeItem for http::Response<Bytes> {
+    fn byte_len(&self) -> usize {
+        // E.g. `HTTP/1.1 200 OK`
+        let reason = self.status().canonical_reason().unwrap_or_default();
+        let start_line = "HTTP/1.1 ".len() + 3 + 1 + reason.len();
+        http_head_len(start_line, self.headers()) + self.body().len()
+    }
+
+    fn corrupt(&mut self) {
+        self.corrupt_with(&mut rand::rng());
+    }
+
+    fn corrupt_with(&mut self, rng: &mut dyn RngCore) {
+        corrupt_bytes(self.body_mut(), rng);
+    }
+
+    fn duplicate(&mut self) -> Option<Self> {
+        Some(self.clone())
+    }
 }
@@ -0,0 +1,318 @@
+//! A receive-side jitter buffer, e.g. to evaluate real-time audio or video pipelines end to end with and without one.
+//!
+//! A [`JitterBuffer`] wraps a stream of [`Tagged`] items, typically the output of a [`crate::ChokeStream`] shaping a
+//! [`crate::tagged::tag`]ged stream, and plays them out like a real-time media receiver would: every item is held until
+//! its playout time and the items are emitted in the order of their ids. An item that is missing when the next one is
+//! due is skipped, and items that arrive after their playout time or after a later item was played out are dropped.
+//!
+//! Without a [`JitterBufferConfigBuilder::interval`], the playout time of an item is its arrival plus the target delay,
+//! which removes reordering but keeps the jitter. With an interval, the items are played out at the fixed rate they
+//! were sent at, starting a target delay after the first item arrived, which also removes the jitter.
+//!
+//! Example:
+//! ```rust
+//! # use chokepoint::{jitter::{JitterBuffer, JitterBufferConfig}, tagged::tag, ChokeSettings, ChokeSettingsOrder, ChokeStream};
+//! # use bytes::Bytes;
+//! # use futures::StreamExt as _;
+//! # use std::time::Duration;
+//! # #[tokio::main(flavor = "current_thread", start_paused = true)]
+//! # async fn main() {
+//! // Audio frames sent every 20ms
+//! let frames = futures::stream::iter(0..50).then(|_| async {
+//!     tokio::time::sleep(Duration::from_millis(20)).await;
+//!     Bytes::from_static(b"frame")
+//! })
+//! .boxed();
+//! let shaped = ChokeStream::new(
+//!     tag(frames),
+//!     ChokeSettings::default()
+//!         .set_ordering(Some(ChokeSettingsOrder::Unordered))
+//!         .set_latency_distribution(Some(|| Some(Duration::from_nanos(rand::random_range(10_000_000..50_000_000))))),
+//! );
+//! let mut buffer = JitterBuffer::new(
+//!     shaped,
+//!     JitterBufferConfig::builder()
+//!         .target_delay(Duration::from_millis(60))
+//!         .interval(Duration::from_millis(20)),
+//! );
+//! let mut previous = None;
+//! while let Some(frame) = buffer.next().await {
+//!     // In order and without gaps
+//!     assert_eq!(frame.id, previous.map_or(0, |id| id + 1));
+//!     previous = Some(frame.id);
+//! }
+//! assert_eq!(buffer.stats().emitted, 50);
+//! # }
+//! ```
+
+use crate::{
+    clock::{
+        Clock,
+        SharedClock,
+    },
+    tagged::Tagged,
+    time::{
+        Instant,
+        Timer,
+    },
+};
+use futures::Stream;
+use std::{
+    collections::BTreeMap,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::Duration,
+};
+
+/// The configuration of a [`JitterBuffer`], see [`JitterBufferConfig::builder`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct JitterBufferConfig {
+    pub(crate) target_delay: Duration,
+    pub(crate) max_buffer: usize,
+    pub(crate) interval: Option<Duration>,
+    pub(crate) clock: SharedClock,
+}
+
+/// Builder for [`JitterBufferConfig`], see [`JitterBufferConfig::builder`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct JitterBufferConfigBuilder {
+    config: JitterBufferConfig,
+}
+
+impl JitterBufferConfigBuilder {
+    /// How long an item is held after it arrived, or after the first item arrived if an interval is set. Defaults to
+    /// 60ms.
+    pub fn target_delay(mut self, target_delay: Duration) -> Self {
+        self.config.target_delay = target_delay;
+        self
+    }
+
+    /// The maximum number of buffered items. If another item arrives, the first buffered item is played out right
+    /// away. Defaults to 64.
+    pub fn max_buffer(mut self, max_buffer: usize) -> Self {
+        self.config.max_buffer = max_buffer;
+        self
+    }
+
+    /// The time between two consecutive ids at the sender, e.g. 20ms for audio frames. If set, the items are played
+    /// out at this rate instead of a target delay after their arrival.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.config.interval = Some(interval);
+        self
+    }
+
+    /// Set the [`Clock`] used to hold the items. Should be the clock of the shaped stream. Defaults to the
+    /// [`crate::clock::SystemClock`].
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.config.clock = SharedClock::new(clock);
+        self
+    }
+
+    pub fn build(self) -> JitterBufferConfig {
+        self.config
+    }
+}
+
+impl From<JitterBufferConfigBuilder> for JitterBufferConfig {
+    fn from(builder: JitterBufferConfigBuilder) -> Self {
+        builder.build()
+    }
+}
+
+impl Default for JitterBufferConfig {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl JitterBufferConfig {
+    pub fn builder() -> JitterBufferConfigBuilder {
+        JitterBufferConfigBuilder {
+            config: JitterBufferConfig {
+                target_delay: Duration::from_millis(60),
+                max_buffer: 64,
+                interval: None,
+                clock: SharedClock::system(),
+            },
+        }
+    }
+
+    /// How long an item is held.
+    pub fn target_delay(&self) -> Duration {
+        self.target_delay
+    }
+
+    /// The maximum number of buffered items.
+    pub fn max_buffer(&self) -> usize {
+        self.max_buffer
+    }
+
+    /// The time between two consecutive ids at the sender, if the items are played out at a fixed rate.
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+}
+
+/// The counters of a [`JitterBuffer`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct JitterBufferStats {
+    /// The number of items taken from the inner stream.
+    pub received: usize,
+    /// The number of items played out.
+    pub emitted: usize,
+    /// The number of ids that were skipped because they were missing when a later item was played out.
+    pub lost: usize,
+    /// The number of items that were dropped because they arrived after their playout time or after a later item was
+    /// played out.
+    pub late: usize,
+    /// The number of items that were dropped because an item with the same id was buffered already.
+    pub duplicates: usize,
+    /// The number of items that were played out early because the buffer was full.
+    pub overflows: usize,
+}
+
+/// Plays out the items of a stream of [`Tagged`] items in order, see the [module documentation](self).
+#[pin_project]
+pub struct JitterBuffer<S, T> {
+    #[pin]
+    stream: S,
+    config: JitterBufferConfig,
+    /// The buffered items by id, with their playout time.
+    buffer: BTreeMap<usize, (Instant, T)>,
+    /// The id after the last played out item.
+    next_id: Option<usize>,
+    /// With an interval, the id and playout time of the first item, the playout times of all other ids follow.
+    origin: Option<(usize, Instant)>,
+    stats: JitterBufferStats,
+    timer: Timer,
+    /// Whether the inner stream has ended, it must not be polled again afterwards.
+    stream_ended: bool,
+}
+
+impl<S, T> JitterBuffer<S, T>
+where
+    S: Stream<Item = Tagged<T>>,
+{
+    pub fn new(stream: S, config: impl Into<JitterBufferConfig>) -> Self {
+        Self {
+            stream,
+            config: config.into(),
+            buffer: BTreeMap::new(),
+            next_id: None,
+            origin: None,
+            stats: JitterBufferStats::default(),
+            timer: Timer::new(),
+            stream_ended: false,
+        }
+    }
+}
+
+impl<S, T> JitterBuffer<S, T> {
+    pub fn config(&self) -> &JitterBufferConfig {
+        &self.config
+    }
+
+    /// Returns the counters of the items processed so far.
+    pub fn stats(&self) -> JitterBufferStats {
+        self.stats
+    }
+
+    /// The number of items that are buffered.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Consumes the `JitterBuffer`, returning the inner stream and the buffered items in order.
+    pub fn into_parts(self) -> (S, Vec<Tagged<T>>) {
+        let items = self
+            .buffer
+            .into_iter()
+            .map(|(id, (_, item))| Tagged { id, item })
+            .collect();
+        (self.stream, items)
+    }
+}
+
+impl<S, T> Stream for JitterBuffer<S, T>
+where
+    S: Stream<Item = Tagged<T>>,
+{
+    type Item = Tagged<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        let now = this.config.clock.now();
+
+        while !*this.stream_ended {
+            match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Tagged { id, item })) => {
+                    this.stats.received += 1;
+                    if this.next_id.is_some_and(|next_id| id < next_id) {
+                        this.stats.late += 1;
+                        continue;
+                    }
+                    if this.buffer.contains_key(&id) {
+                        this.stats.duplicates += 1;
+                        continue;
+                    }
+
+                    let playout = match this.config.interval {
+                        Some(interval) => {
+                            let (origin_id, origin) =
+                                *this.origin.get_or_insert_with(|| (id, now + this.config.target_delay));
+                            let offset = |ids: usize| interval.saturating_mul(ids.try_into().unwrap_or(u32::MAX));
+                            if id >= origin_id {
+                                origin + offset(id - origin_id)
+                            } else {
+                                origin.checked_sub(offset(origin_id - id)).unwrap_or(now)
+                            }
+                        }
+                        None => now + this.config.target_delay,
+                    };
+                    if playout < now {
+                        this.stats.late += 1;
+                        continue;
+                    }
+                    this.buffer.insert(id, (playout, item));
+
+                    if this.buffer.len() > this.config.max_buffer {
+                        // Play out the first item right away to make room
+                        if let Some((_, (playout, _))) = this.buffer.iter_mut().next() {
+                            *playout = now;
+                        }
+                        this.stats.overflows += 1;
+                    }
+                }
+                Poll::Ready(None) => *this.stream_ended = true,
+                Poll::Pending => break,
+            }
+        }
+
+        let Some((&id, &(playout, _))) = this.buffer.iter().next() else {
+            return if *this.stream_ended {
+                Poll::Ready(None)
+            } else {
+                Poll::Pending
+            };
+        };
+        if playout <= now {
+            let (_, item) = this.buffer.remove(&id).expect("first buffered item");
+            if let Some(next_id) = *this.next_id {
+                this.stats.lost += id - next_id;
+            }
+            *this.next_id = Some(id + 1);
+            this.stats.emitted += 1;
+            return Poll::Ready(Some(Tagged { id, item }));
+        }
+
+        // Wait for the playout time of the first item, a missing earlier id might still arrive in the meantime
+        this.timer.reset(&this.config.clock, playout);
+        if this.timer.poll(cx).is_ready() {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
@@ -1,11 +1,66 @@
+use rand::{
+    Rng as _,
+    RngCore,
+};
 use rand_distr::{
     Distribution as _,
+    LogNormal,
     Normal,
+    Pareto,
     SkewNormal,
 };
+#[cfg(not(chokepoint_local))]
+use std::sync::{
+    Arc,
+    Mutex,
+    PoisonError,
+};
 use std::time::Duration;
+#[cfg(chokepoint_local)]
+use std::{
+    cell::RefCell,
+    rc::Rc,
+};
+
+/// The thread safety required from latency distribution functions: `Send + Sync` by default. Building with
+/// `RUSTFLAGS='--cfg chokepoint_local'` lifts this requirement, so that functions can capture e.g. an `Rc` on single
+/// threaded runtimes and wasm. Note that the settings and streams are not `Send` then.
+#[cfg(not(chokepoint_local))]
+pub trait MaybeSendSync: Send + Sync {}
+
+#[cfg(not(chokepoint_local))]
+impl<T: Send + Sync + ?Sized> MaybeSendSync for T {}
+
+/// The thread safety required from latency distribution functions: `Send + Sync` by default. Building with
+/// `RUSTFLAGS='--cfg chokepoint_local'` lifts this requirement, so that functions can capture e.g. an `Rc` on single
+/// threaded runtimes and wasm. Note that the settings and streams are not `Send` then.
+#[cfg(chokepoint_local)]
+pub trait MaybeSendSync {}
+
+#[cfg(chokepoint_local)]
+impl<T: ?Sized> MaybeSendSync for T {}
+
+/// Like [`MaybeSendSync`] for values that are only used by a single stream at a time, e.g. a
+/// [`crate::source::SettingsSource`]: `Send` by default, no requirement with
+/// `--cfg chokepoint_local`.
+#[cfg(not(chokepoint_local))]
+pub trait MaybeSend: Send {}
+
+#[cfg(not(chokepoint_local))]
+impl<T: Send + ?Sized> MaybeSend for T {}
+
+/// Like [`MaybeSendSync`] for values that are only used by a single stream at a time, e.g. a
+/// [`crate::source::SettingsSource`]: `Send` by default, no requirement with
+/// `--cfg chokepoint_local`.
+#[cfg(chokepoint_local)]
+pub trait MaybeSend {}
+
+#[cfg(chokepoint_local)]
+impl<T: ?Sized> MaybeSend for T {}
 
 /// Uses [`rand_distr::Normal`] to generate a normal distribution.
+///
+/// Panics if `std_dev` is negative or not finite.
 pub fn normal_distribution(
     mean: f64,
     std_dev: f64,
@@ -19,6 +74,8 @@ pub fn normal_distribution(
 }
 
 /// Uses [`rand_distr::SkewNormal`] to generate a skewed distribution.
+///
+/// Panics if `scale` is negative or any parameter is not finite.
 pub fn skewed_distribution(
     location: f64,
     scale: f64,
@@ -31,3 +88,221 @@ pub fn skewed_distribution(
         (latency > 0).then(|| std::time::Duration::from_millis(latency))
     })
 }
+
+/// The latency added to every item, see [`crate::ChokeSettings::set_latency`].
+///
+/// The built-in models draw from the random number generator of the stream, so the latencies are reproduced with the
+/// same seed, and can be cloned, compared and serialized. Sampled latencies are clamped to `0..=max` and a latency of
+/// zero adds no delay. Models with invalid parameters, which [`crate::ChokeSettings::validate`] rejects, don't panic
+/// when applied anyway but add no latency.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Latency {
+    /// No latency is added.
+    #[default]
+    None,
+    /// The same latency for every item.
+    Constant(Duration),
+    /// A normal distribution, see [`rand_distr::Normal`].
+    Normal {
+        mean: Duration,
+        std_dev: Duration,
+        max: Duration,
+    },
+    /// A skew normal distribution, see [`rand_distr::SkewNormal`]. The shape must be finite. Without a scale, every
+    /// item gets the location, like a normal distribution without a standard deviation.
+    SkewNormal {
+        location: Duration,
+        scale: Duration,
+        shape: f64,
+        max: Duration,
+    },
+    /// A uniform distribution between `min` and `max`, which must not be smaller than `min`. Otherwise the bounds are
+    /// swapped.
+    Uniform { min: Duration, max: Duration },
+    /// A log-normal distribution, see [`rand_distr::LogNormal`], whose long tail models the occasional very slow
+    /// item. Half of the items get less than the median. `sigma` is the standard deviation of the logarithm and must
+    /// be finite and not negative.
+    LogNormal {
+        median: Duration,
+        sigma: f64,
+        max: Duration,
+    },
+    /// A Pareto distribution, see [`rand_distr::Pareto`]. Every item gets at least the scale, and the smaller the
+    /// shape, the heavier the tail. The shape must be finite and positive.
+    Pareto { scale: Duration, shape: f64, max: Duration },
+    /// A custom distribution function, see [`Latency::custom`]. It can't be serialized.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(LatencyFn),
+}
+
+impl Latency {
+    /// A custom distribution function. It produces an optional [`Duration`] that represents the latency to be added to
+    /// the item. If the function returns `None`, no latency will be added. Clones share the function.
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: FnMut() -> Option<Duration> + MaybeSendSync + 'static,
+    {
+        Self::Custom(LatencyFn::new(f))
+    }
+
+    /// Returns `true` unless this is [`Latency::None`].
+    pub fn is_some(&self) -> bool {
+        !matches!(self, Latency::None)
+    }
+
+    /// Returns `true` if the parameters that are not durations are in range and a uniform distribution is not empty.
+    pub(crate) fn is_valid(&self) -> bool {
+        match self {
+            Latency::SkewNormal { shape, .. } => shape.is_finite(),
+            Latency::Uniform { min, max } => min <= max,
+            Latency::LogNormal { sigma, .. } => sigma.is_finite() && *sigma >= 0.0,
+            Latency::Pareto { shape, .. } => shape.is_finite() && *shape > 0.0,
+            _ => true,
+        }
+    }
+
+    /// Appends the parameters of a built-in model to the bytes of a [`crate::ChokeSettingsSnapshot::fingerprint`].
+    pub(crate) fn fingerprint(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Latency::Constant(latency) => {
+                bytes.push(1);
+                bytes.extend(latency.as_nanos().to_le_bytes());
+            }
+            Latency::Normal { mean, std_dev, max } => {
+                bytes.push(2);
+                for parameter in [mean, std_dev, max] {
+                    bytes.extend(parameter.as_nanos().to_le_bytes());
+                }
+            }
+            Latency::SkewNormal {
+                location,
+                scale,
+                shape,
+                max,
+            } => {
+                bytes.push(3);
+                bytes.extend(location.as_nanos().to_le_bytes());
+                bytes.extend(scale.as_nanos().to_le_bytes());
+                bytes.extend(shape.to_bits().to_le_bytes());
+                bytes.extend(max.as_nanos().to_le_bytes());
+            }
+            Latency::Uniform { min, max } => {
+                bytes.push(4);
+                bytes.extend(min.as_nanos().to_le_bytes());
+                bytes.extend(max.as_nanos().to_le_bytes());
+            }
+            Latency::LogNormal { median, sigma, max } => {
+                bytes.push(5);
+                bytes.extend(median.as_nanos().to_le_bytes());
+                bytes.extend(sigma.to_bits().to_le_bytes());
+                bytes.extend(max.as_nanos().to_le_bytes());
+            }
+            Latency::Pareto { scale, shape, max } => {
+                bytes.push(6);
+                bytes.extend(scale.as_nanos().to_le_bytes());
+                bytes.extend(shape.to_bits().to_le_bytes());
+                bytes.extend(max.as_nanos().to_le_bytes());
+            }
+            Latency::None | Latency::Custom(_) => {}
+        }
+    }
+
+    /// Draw the latency of an item.
+    pub(crate) fn sample(&self, rng: &mut dyn RngCore) -> Option<Duration> {
+        let clamped = |latency: f64, max: Duration| Duration::from_secs_f64(latency.clamp(0.0, max.as_secs_f64()));
+        let latency = match self {
+            Latency::None => None,
+            Latency::Constant(latency) => Some(*latency),
+            Latency::Normal { mean, std_dev, max } => {
+                let normal = Normal::new(mean.as_secs_f64(), std_dev.as_secs_f64()).expect("finite parameters");
+                Some(clamped(normal.sample(rng), *max))
+            }
+            Latency::Uniform { min, max } => Some(rng.random_range(*min.min(max)..=*min.max(max))),
+            // Rejected by `ChokeSettings::validate`, which streams don't require
+            _ if !self.is_valid() => None,
+            Latency::SkewNormal {
+                location, scale, max, ..
+            } if scale.is_zero() => Some(clamped(location.as_secs_f64(), *max)),
+            Latency::SkewNormal {
+                location,
+                scale,
+                shape,
+                max,
+            } => SkewNormal::new(location.as_secs_f64(), scale.as_secs_f64(), *shape)
+                .ok()
+                .map(|skew_normal| clamped(skew_normal.sample(rng), *max)),
+            // The logarithm of a zero median and a zero scale are not defined, every sample would be zero
+            Latency::LogNormal { median, .. } | Latency::Pareto { scale: median, .. } if median.is_zero() => None,
+            Latency::LogNormal { median, sigma, max } => LogNormal::new(median.as_secs_f64().ln(), *sigma)
+                .ok()
+                .map(|log_normal| clamped(log_normal.sample(rng), *max)),
+            Latency::Pareto { scale, shape, max } => Pareto::new(scale.as_secs_f64(), *shape)
+                .ok()
+                .map(|pareto| clamped(pareto.sample(rng), *max)),
+            Latency::Custom(f) => return f.sample(),
+        };
+        latency.filter(|latency| !latency.is_zero())
+    }
+}
+
+/// A latency distribution function that is shared between settings and the streams they have been applied to, see
+/// [`Latency::custom`].
+#[cfg(not(chokepoint_local))]
+#[derive(Clone)]
+pub struct LatencyFn(Arc<Mutex<dyn FnMut() -> Option<Duration> + Send + Sync>>);
+
+/// A latency distribution function that is shared between settings and the streams they have been applied to, see
+/// [`Latency::custom`].
+#[cfg(chokepoint_local)]
+#[derive(Clone)]
+pub struct LatencyFn(Rc<RefCell<dyn FnMut() -> Option<Duration>>>);
+
+impl LatencyFn {
+    #[cfg(not(chokepoint_local))]
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: FnMut() -> Option<Duration> + MaybeSendSync + 'static,
+    {
+        Self(Arc::new(Mutex::new(f)))
+    }
+
+    #[cfg(chokepoint_local)]
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: FnMut() -> Option<Duration> + MaybeSendSync + 'static,
+    {
+        Self(Rc::new(RefCell::new(f)))
+    }
+
+    #[cfg(not(chokepoint_local))]
+    pub(crate) fn sample(&self) -> Option<Duration> {
+        let mut f = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        f()
+    }
+
+    #[cfg(chokepoint_local)]
+    pub(crate) fn sample(&self) -> Option<Duration> {
+        let mut f = self.0.borrow_mut();
+        f()
+    }
+}
+
+impl std::fmt::Debug for LatencyFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("fn() -> Option<Duration>")
+    }
+}
+
+impl PartialEq for LatencyFn {
+    #[cfg(not(chokepoint_local))]
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    #[cfg(chokepoint_local)]
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
@@ -5,14 +5,109 @@
 //! A library for simulating "traffic shaping" in Rust based on a generic `futures::Stream` and `futures::Sink`
 //! transformer that can be used to modify the delivery of items. The main purpose is to simulate various network
 //! conditions such as:
-//! - Delay (using a user provided function)
+//! - Delay, from a constant, normal or skew normal distribution or a user provided function, and a connection setup
+//!   delay before the first item
 //! - Packet loss
-//! - Packet reordering
+//! - Packet reordering, through random delays or explicitly by a number of positions
 //! - Packet corruption
 //! - Packet duplication
-//! - Bandwidth limiting
+//! - Bandwidth limiting, optionally with evenly paced emissions, shared by several streams, responding to congestion or
+//!   competing with cross traffic
+//! - Fragmentation of items larger than an MTU
+//! - Coalescing of consecutive items
+//! - Nagle-style holding of small items
+//! - Release of items in bursts
 //!
-//! See [`TrafficShaper`] for more information and an example.
+//! See [`ChokeStream`] for more information and an example, and [`ChokeSink`] to shape the items sent to a sink.
+//!
+//! ## Runtimes
+//!
+//! By default, tokio's timer is used. To use chokepoint with another runtime such as async-std or smol, disable the
+//! default features and enable the `futures-timer` feature.
+//!
+//! With tokio, the paused clock of `#[tokio::test(start_paused = true)]` is respected: delays are skipped as soon as
+//! the runtime is idle, so tests with large latencies complete instantly and deterministically.
+//!
+//! ## Simulations
+//!
+//! The [`sim`] module runs scenarios on virtual time with seeded randomness, e.g. to send thousands of items through a
+//! lossy, slow link in a test and assert on the outcome. Runs with the same seed are reproducible.
+//!
+//! ## Multi-hop paths
+//!
+//! [`ChokeStream::chain`] adds another hop with its own settings, e.g. to simulate an access link, a backbone and the
+//! access link of the peer. The hops of a [`ChokeChain`] share a single task and timer, which costs less than nesting
+//! streams.
+//!
+//! ## Live updates
+//!
+//! The settings of a running stream can be changed through a channel, a file that is reloaded when it changes or a
+//! scenario that applies settings at fixed times, see the [`source`] module. For long soak tests, the latency, drop
+//! probability and bandwidth can drift in a bounded random walk, see [`source::DriftSource`].
+//!
+//! ## Record and replay
+//!
+//! The decisions made for every item (drop, delay, corruption, duplication) can be recorded and replayed in a later
+//! run, see the [`replay`] module.
+//!
+//! ## PCAP export
+//!
+//! Emitted and dropped items with a byte payload can be written to a pcapng file for inspection with Wireshark, see
+//! the [`pcap`] module.
+//!
+//! ## Event recording
+//!
+//! A [`recorder::Recorder`] writes one row per item (enqueue time, applied delay, fate, emit time, size) as CSV or JSON
+//! Lines, e.g. to graph the shaping behavior, see the [`recorder`] module. For telemetry that is consumed
+//! asynchronously, [`ChokeStream::events`] returns a stream of structured events per item, see the [`event`] module.
+//!
+//! ## Sequence tagging
+//!
+//! [`tagged::tag`] wraps the items of a stream in a [`tagged::Tagged`] with an increasing id, and
+//! [`tagged::SequenceAnalysis`] computes lost ids, duplicates and reorder distances from the output, so tests don't
+//! need payloads that embed their own counters.
+//!
+//! ## Jitter buffer
+//!
+//! A [`jitter::JitterBuffer`] re-sequences and plays out a shaped stream of tagged items like a real-time media
+//! receiver would, so audio or video pipelines can be evaluated end to end with and without one, see the [`jitter`]
+//! module.
+//!
+//! ## turmoil
+//!
+//! The `turmoil` feature adds adapters to shape the UDP traffic of a [turmoil](https://docs.rs/turmoil) simulation,
+//! see the [`turmoil`](crate::turmoil) module.
+//!
+//! ## Protocol items
+//!
+//! Besides `Bytes`, `String` implements [`ChokeItem`], e.g. for the lines of `tokio_util::codec::LinesCodec`. The
+//! `tungstenite` feature implements it for WebSocket messages and the `http` feature for `http::Request<Bytes>` and
+//! `http::Response<Bytes>`, so that the messages of these protocols can be shaped without wrapping them. With the
+//! `serde` feature, `SerdeItem` shapes any serializable message as its JSON encoding: corrupting it flips a byte of
+//! the encoding and decodes it again, yielding a decode error if the encoding became invalid.
+//!
+//! ## Test utilities
+//!
+//! The `test-util` feature adds a payload that records when it was created and a sink that records when it received
+//! every payload, for testing code that uses chokepoint, along with assertions for the loss and reordering of tagged
+//! output, see the `chokepoint::test_util` module.
+//!
+//! ## Property testing
+//!
+//! The `proptest` feature adds [proptest](https://docs.rs/proptest) strategies that generate valid random settings and
+//! scenarios, e.g. to check that a protocol never deadlocks under any shaping configuration, see the
+//! `chokepoint::strategy` module.
+//!
+//! ## Fuzzing
+//!
+//! The `arbitrary` feature implements [`arbitrary::Arbitrary`](https://docs.rs/arbitrary) for [`ChokeSettingsSnapshot`]
+//! and the types it consists of, generating valid settings only. A fuzz target can configure a stream from the fuzzer
+//! bytes with `ChokeSettings::from(ChokeSettingsSnapshot::arbitrary(&mut unstructured)?)` and drive a protocol
+//! implementation through it.
+//!
+//! ## WebAssembly
+//!
+//! WebAssembly (wasm32-unknown-unknown) is supported, timers are provided by [wasmtimer](https://crates.io/crates/wasmtimer) there. Enable the `wasm` feature and build with `RUSTFLAGS='--cfg getrandom_backend="wasm_js"'` so that random numbers can be generated in the browser. The tests can be run with `wasm-pack test --node -- --features wasm`. Latency distribution functions, decision hooks, clocks and settings sources have to be `Send + Sync` unless building with `RUSTFLAGS='--cfg chokepoint_local'`, e.g. to capture an `Rc` on single threaded runtimes and wasm. The settings and streams are not `Send` then, which is why this is not a feature.
 //!
 //! ## chokepoint command line tool
 //!
@@ -33,7 +128,9 @@
 //!   -n <N>
 //!           Number of packets to send [default: 250]
 //!   -o, --output <OUTPUT>
-//!           Output file (csv) with packet timing information
+//!           Output file with an event per packet (enqueue time, delay, fate, emit time, size)
+//!       --format <FORMAT>
+//!           Output format: csv or jsonl [default: csv]
 //!   -r, --packet-rate <PACKET_RATE>
 //!           Send rate in packets per second
 //!   -s, --packet-size <PACKET_SIZE>
@@ -42,6 +139,8 @@
 //!           [default: ordered]
 //!   -l, --bandwidth-limit <BANDWIDTH_LIMIT>
 //!           Bandwidth limit
+//!       --seed <SEED>
+//!           Seed for the random decisions, e.g. from the stats printed by an earlier run
 //!       --mean <MEAN>
 //!           Mean latency in ms [default: 0.0]
 //!       --stddev <STDDEV>
@@ -49,6 +148,8 @@
 //!   -h, --help
 //!           Print help
 //! ```
+//!
+//! After a run, the stats are printed to stderr, including the seed that reproduces the random decisions with `--seed`.
 
 #[macro_use]
 extern crate tracing;
@@ -57,18 +158,88 @@ extern crate tracing;
 extern crate pin_project;
 
 pub mod bandwidth_limiter;
+pub mod clock;
+pub mod event;
+#[cfg(feature = "arbitrary")]
+mod fuzz;
+pub mod hook;
+mod idle;
 mod item;
+pub mod jitter;
 mod latency;
+pub mod pcap;
+pub mod recorder;
+pub mod replay;
+#[cfg(feature = "serde")]
+mod serde_item;
 mod settings;
+pub mod sim;
 mod sink;
+pub mod source;
+mod stats;
+#[cfg(feature = "proptest")]
+pub mod strategy;
 mod stream;
+pub mod tagged;
+#[cfg(feature = "test-util")]
+pub mod test_util;
 pub(crate) mod time;
+mod transport;
+#[cfg(all(feature = "turmoil", not(target_arch = "wasm32")))]
+pub mod turmoil;
 
+pub use bandwidth_limiter::SharedBandwidthLimit;
+pub use idle::Idle;
 pub use item::ChokeItem;
 pub use latency::*;
+#[cfg(feature = "serde")]
+pub use serde_item::{
+    SerdeItem,
+    SerdeItemError,
+};
 pub use settings::{
+    BandwidthLimit,
+    BandwidthLimitBuilder,
+    Burst,
+    BurstBuilder,
     ChokeSettings,
+    ChokeSettingsError,
+    ChokeSettingsField,
     ChokeSettingsOrder,
+    ChokeSettingsSnapshot,
+    Coalescing,
+    CoalescingBuilder,
+    Congestion,
+    CongestionBuilder,
+    CrossTraffic,
+    CrossTrafficBuilder,
+    CrossTrafficPattern,
+    Nagle,
+    NagleBuilder,
+    Reorder,
+    ReorderBuilder,
+    ReorderWindow,
+    ReorderWindowBuilder,
+    SlowStart,
+    SlowStartBuilder,
+    DEFAULT_POLL_BUDGET,
+};
+pub use sink::{
+    ChokeSink,
+    ChokeSinkError,
+};
+pub use stats::{
+    ChokeStats,
+    DropCounts,
+    DurationHistogram,
+    TimingStats,
+};
+pub use stream::{
+    ChokeChain,
+    ChokeStream,
+    ChokeStreamExt,
+    EmitInfo,
+    Shaped,
+    ShapedStream,
 };
-pub use sink::ChokeSink;
-pub use stream::ChokeStream;
+pub use transport::ChokeTransport;
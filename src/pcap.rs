@@ -0,0 +1,254 @@
+//! Writing the traffic of a [`crate::ChokeStream`] or [`crate::ChokeSink`] to a
+//! [pcapng](https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-02.html) file, e.g. to inspect it with
+//! Wireshark.
+//!
+//! A [`PcapWriter`] attached with [`crate::ChokeSettings::set_pcap_writer`] records every emitted item with the time it
+//! was emitted. Dropped items are recorded at the time they were dropped, with the packet comment `dropped`
+//! (filter with `frame.comment == "dropped"`). Only items that expose their bytes through
+//! [`crate::ChokeItem::payload`] are written.
+//!
+//! Example:
+//! ```rust
+//! # use chokepoint::{pcap::PcapWriter, ChokeSettings};
+//! # fn main() -> std::io::Result<()> {
+//! # let path = std::env::temp_dir().join("chokepoint-doc.pcapng");
+//! let pcap = PcapWriter::new(std::io::BufWriter::new(std::fs::File::create(&path)?))?;
+//! let settings = ChokeSettings::default()
+//!     .set_drop_probability(Some(0.1))
+//!     .set_pcap_writer(Some(pcap.clone()));
+//! // ... run the stream, then
+//! pcap.flush()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::time::{
+    Instant,
+    SystemTime,
+    UNIX_EPOCH,
+};
+use std::{
+    io::{
+        self,
+        Write,
+    },
+    sync::{
+        Arc,
+        Mutex,
+        MutexGuard,
+        PoisonError,
+    },
+};
+
+/// Link type for payloads without a known protocol, see <https://www.tcpdump.org/linktypes.html>.
+pub const LINKTYPE_USER0: u16 = 147;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const IF_TSRESOL: u16 = 9;
+
+/// Writes the items of the streams it is attached to as pcapng, see the [module documentation](self). Clones share the
+/// same output.
+#[derive(Clone)]
+pub struct PcapWriter(Arc<Mutex<PcapState>>);
+
+struct PcapState {
+    output: Box<dyn Write + Send>,
+    /// Maps the instants of the stream's clock to wall-clock time, set by the first packet.
+    epoch: Option<(Instant, SystemTime)>,
+    /// The first write error, packets are not written anymore afterwards.
+    error: Option<io::Error>,
+}
+
+impl PcapWriter {
+    /// Write a pcapng file with the [`LINKTYPE_USER0`] link type to `output`.
+    pub fn new(output: impl Write + Send + 'static) -> io::Result<Self> {
+        Self::with_link_type(output, LINKTYPE_USER0)
+    }
+
+    /// Write a pcapng file with the given link type to `output`, e.g. 101 (`LINKTYPE_RAW`) if the items are IP
+    /// packets.
+    pub fn with_link_type(output: impl Write + Send + 'static, link_type: u16) -> io::Result<Self> {
+        let mut output: Box<dyn Write + Send> = Box::new(output);
+        // Section header: byte order magic, version 1.0, unknown section length
+        let mut body = Vec::new();
+        body.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend(1u16.to_le_bytes());
+        body.extend(0u16.to_le_bytes());
+        body.extend((-1i64).to_le_bytes());
+        write_block(&mut output, SECTION_HEADER_BLOCK, &body)?;
+
+        // Interface description: link type, reserved, no snap length, nanosecond timestamps
+        let mut body = Vec::new();
+        body.extend(link_type.to_le_bytes());
+        body.extend(0u16.to_le_bytes());
+        body.extend(0u32.to_le_bytes());
+        write_option(&mut body, IF_TSRESOL, &[9]);
+        write_option(&mut body, OPT_ENDOFOPT, &[]);
+        write_block(&mut output, INTERFACE_DESCRIPTION_BLOCK, &body)?;
+
+        Ok(Self(Arc::new(Mutex::new(PcapState {
+            output,
+            epoch: None,
+            error: None,
+        }))))
+    }
+
+    /// Flush the output. Returns the first error that occurred while writing packets, if any.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut state = self.lock();
+        if let Some(err) = state.error.take() {
+            return Err(err);
+        }
+        state.output.flush()
+    }
+
+    pub(crate) fn emitted(&self, payload: &[u8], at: Instant) {
+        self.write_packet(payload, at, None);
+    }
+
+    pub(crate) fn dropped(&self, payload: &[u8], at: Instant) {
+        self.write_packet(payload, at, Some("dropped"));
+    }
+
+    fn write_packet(&self, payload: &[u8], at: Instant, comment: Option<&str>) {
+        let mut state = self.lock();
+        if state.error.is_some() {
+            return;
+        }
+
+        let (epoch, wall_clock) = *state.epoch.get_or_insert_with(|| (at, SystemTime::now()));
+        let timestamp = (wall_clock + at.saturating_duration_since(epoch))
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+
+        let mut body = Vec::with_capacity(payload.len() + 64);
+        body.extend(0u32.to_le_bytes()); // interface
+        body.extend(((timestamp >> 32) as u32).to_le_bytes());
+        body.extend((timestamp as u32).to_le_bytes());
+        body.extend((payload.len() as u32).to_le_bytes()); // captured length
+        body.extend((payload.len() as u32).to_le_bytes()); // original length
+        body.extend(payload);
+        pad(&mut body);
+        if let Some(comment) = comment {
+            write_option(&mut body, OPT_COMMENT, comment.as_bytes());
+            write_option(&mut body, OPT_ENDOFOPT, &[]);
+        }
+
+        if let Err(err) = write_block(&mut state.output, ENHANCED_PACKET_BLOCK, &body) {
+            warn!(%err, "failed to write pcap packet");
+            state.error = Some(err);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PcapState> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl PartialEq for PcapWriter {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl std::fmt::Debug for PcapWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PcapWriter").finish_non_exhaustive()
+    }
+}
+
+/// Write a block with its type and (repeated) total length around `body`, which must be padded to 32 bits.
+fn write_block(output: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total_length = (body.len() + 12) as u32;
+    output.write_all(&block_type.to_le_bytes())?;
+    output.write_all(&total_length.to_le_bytes())?;
+    output.write_all(body)?;
+    output.write_all(&total_length.to_le_bytes())
+}
+
+fn write_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend(code.to_le_bytes());
+    body.extend((value.len() as u16).to_le_bytes());
+    body.extend(value);
+    pad(body);
+}
+
+fn pad(body: &mut Vec<u8>) {
+    body.resize(body.len().next_multiple_of(4), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Split the output into (block type, body) pairs, checking the framing.
+    fn blocks(mut data: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let u32_at = |data: &[u8], i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        let mut blocks = Vec::new();
+        while !data.is_empty() {
+            let length = u32_at(data, 4) as usize;
+            assert_eq!(length % 4, 0);
+            assert_eq!(u32_at(data, length - 4) as usize, length);
+            blocks.push((u32_at(data, 0), data[8..length - 4].to_vec()));
+            data = &data[length..];
+        }
+        blocks
+    }
+
+    #[test]
+    fn writes_pcapng_blocks() {
+        let buffer = Buffer::default();
+        let pcap = PcapWriter::new(buffer.clone()).unwrap();
+        let start = Instant::now();
+        pcap.emitted(b"hello", start);
+        pcap.dropped(b"abcd", start + Duration::from_millis(1500));
+        pcap.flush().unwrap();
+
+        let data = buffer.0.lock().unwrap().clone();
+        let blocks = blocks(&data);
+        let types = blocks.iter().map(|(block_type, _)| *block_type).collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                SECTION_HEADER_BLOCK,
+                INTERFACE_DESCRIPTION_BLOCK,
+                ENHANCED_PACKET_BLOCK,
+                ENHANCED_PACKET_BLOCK
+            ]
+        );
+
+        let timestamp = |body: &[u8]| {
+            let high = u32::from_le_bytes(body[4..8].try_into().unwrap()) as u64;
+            let low = u32::from_le_bytes(body[8..12].try_into().unwrap()) as u64;
+            (high << 32) | low
+        };
+        let (emitted, dropped) = (&blocks[2].1, &blocks[3].1);
+        assert_eq!(timestamp(dropped) - timestamp(emitted), 1_500_000_000);
+
+        assert_eq!(&emitted[20..25], b"hello");
+        assert_eq!(emitted.len(), 20 + 8);
+        assert_eq!(&dropped[20..24], b"abcd");
+        assert_eq!(&dropped[24..28], &[1, 0, 7, 0]);
+        assert_eq!(&dropped[28..35], b"dropped");
+    }
+}
@@ -0,0 +1,418 @@
+//! Recording an event for every item of a [`crate::ChokeStream`] or [`crate::ChokeSink`], e.g. to graph the shaping
+//! behavior.
+//!
+//! A [`Recorder`] attached with [`crate::ChokeSettings::set_recorder`] writes one row per item as CSV or JSON Lines:
+//! when the item was taken from the inner stream, the latency that was applied to it, its [`Fate`], when it was
+//! emitted, its size in bytes and the label of the shaper, see [`crate::ChokeSettings::set_label`]. Times are in
+//! milliseconds since the first item was taken, or since the epoch given with [`Recorder::with_epoch`]. Dropped and
+//! expired items are written when they are discarded, all others when they are emitted, so the rows are ordered by the
+//! time they were written. Items merged by [`crate::ChokeSettings::set_coalescing`] have a row each. Items that are
+//! still queued when the stream is dropped are not written.
+//!
+//! Example:
+//! ```rust
+//! # use chokepoint::{recorder::Recorder, ChokeSettings};
+//! # fn main() -> std::io::Result<()> {
+//! # let path = std::env::temp_dir().join("chokepoint-doc.csv");
+//! let recorder = Recorder::csv(std::io::BufWriter::new(std::fs::File::create(&path)?))?;
+//! let settings = ChokeSettings::default()
+//!     .set_drop_probability(Some(0.1))
+//!     .set_recorder(Some(recorder.clone()));
+//! // ... run the stream, then
+//! recorder.flush()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The CSV output starts with a header:
+//! ```text
+//! seq,enqueued_ms,delay_ms,fate,emitted_ms,size,corrupted,label
+//! 0,0.000,12.500,emitted,12.512,1200,false,uplink
+//! 1,0.020,,dropped,,1200,false,uplink
+//! ```
+
+use crate::time::Instant;
+use std::{
+    io::{
+        self,
+        Write,
+    },
+    sync::{
+        Arc,
+        Mutex,
+        MutexGuard,
+        PoisonError,
+    },
+    time::Duration,
+};
+
+/// The output format of a [`Recorder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// Comma-separated values with a header line. Empty cells for values that are not set.
+    Csv,
+    /// One JSON object per line, `null` for values that are not set.
+    JsonLines,
+}
+
+/// What happened to an item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fate {
+    Emitted,
+    /// The item is the duplicate of another item and was emitted.
+    Duplicate,
+    /// The item was dropped, randomly or by the bandwidth limit.
+    Dropped,
+    /// The item was discarded because it waited longer than its time to live, see
+    /// [`crate::ChokeSettings::set_item_ttl`].
+    Expired,
+}
+
+impl Fate {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Fate::Emitted => "emitted",
+            Fate::Duplicate => "duplicate",
+            Fate::Dropped => "dropped",
+            Fate::Expired => "expired",
+        }
+    }
+}
+
+/// A single row written by a [`Recorder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemEvent {
+    /// The position of the item in the inner stream, starting at 0. A duplicate has the same position as the
+    /// original.
+    pub seq: usize,
+    /// When the item was taken from the inner stream.
+    pub enqueued: Duration,
+    /// The latency that was applied to the item. The item can be emitted later because of the ordering or the
+    /// bandwidth limit.
+    pub delay: Option<Duration>,
+    pub fate: Fate,
+    /// When the item was emitted, `None` if it was dropped or expired.
+    pub emitted: Option<Duration>,
+    /// The size of the item in bytes, see [`crate::ChokeItem::byte_len`].
+    pub size: usize,
+    pub corrupted: bool,
+    /// The label of the shaper, see [`crate::ChokeSettings::set_label`].
+    pub label: Option<String>,
+}
+
+/// What the shaper knows about an item taken from the inner stream, kept along with the queued item.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ItemInfo {
+    pub(crate) seq: usize,
+    pub(crate) enqueued: Instant,
+    pub(crate) delay: Option<Duration>,
+    pub(crate) corrupted: bool,
+}
+
+/// Writes an [`ItemEvent`] for every item of the streams it is attached to, see the [module documentation](self).
+/// Clones share the same output.
+#[derive(Clone)]
+pub struct Recorder(Arc<Mutex<RecorderState>>);
+
+struct RecorderState {
+    output: Box<dyn Write + Send>,
+    format: RecordFormat,
+    /// The instant the times are relative to, set by the first item.
+    epoch: Option<Instant>,
+    /// The first write error, events are not written anymore afterwards.
+    error: Option<io::Error>,
+    /// The events written so far, if they are kept, see [`Recorder::keep_events`].
+    events: Option<Vec<ItemEvent>>,
+}
+
+impl Recorder {
+    /// Write the events to `output` in the given format.
+    pub fn new(output: impl Write + Send + 'static, format: RecordFormat) -> io::Result<Self> {
+        let mut output: Box<dyn Write + Send> = Box::new(output);
+        if format == RecordFormat::Csv {
+            writeln!(output, "seq,enqueued_ms,delay_ms,fate,emitted_ms,size,corrupted,label")?;
+        }
+        Ok(Self(Arc::new(Mutex::new(RecorderState {
+            output,
+            format,
+            epoch: None,
+            error: None,
+            events: None,
+        }))))
+    }
+
+    /// Write the events to `output` as CSV, see [`RecordFormat::Csv`].
+    pub fn csv(output: impl Write + Send + 'static) -> io::Result<Self> {
+        Self::new(output, RecordFormat::Csv)
+    }
+
+    /// Write the events to `output` as JSON Lines, see [`RecordFormat::JsonLines`].
+    pub fn json_lines(output: impl Write + Send + 'static) -> io::Result<Self> {
+        Self::new(output, RecordFormat::JsonLines)
+    }
+
+    /// Also keep the events in memory, e.g. to plot a run when it ends. They are kept even if writing them fails.
+    pub fn keep_events(self) -> Self {
+        self.lock().events.get_or_insert_with(Vec::new);
+        self
+    }
+
+    /// Make the times relative to `epoch` instead of the first item, e.g. to line the events up with the steps of a
+    /// scenario that starts before the first item.
+    pub fn with_epoch(self, epoch: Instant) -> Self {
+        self.lock().epoch = Some(epoch);
+        self
+    }
+
+    /// The events recorded so far, empty unless they are kept, see [`Recorder::keep_events`].
+    pub fn events(&self) -> Vec<ItemEvent> {
+        self.lock().events.clone().unwrap_or_default()
+    }
+
+    /// Flush the output. Returns the first error that occurred while writing events, if any.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut state = self.lock();
+        if let Some(err) = state.error.take() {
+            return Err(err);
+        }
+        state.output.flush()
+    }
+
+    /// Called when an item is taken from the inner stream, so that times are relative to the first item even if
+    /// another item is written first.
+    pub(crate) fn enqueued(&self, at: Instant) {
+        self.lock().epoch.get_or_insert(at);
+    }
+
+    /// Write the event of an item that was emitted at `emitted`, or dropped if `None`, by the shaper with `label`.
+    pub(crate) fn record(
+        &self,
+        info: &ItemInfo,
+        fate: Fate,
+        emitted: Option<Instant>,
+        size: usize,
+        label: Option<&str>,
+    ) {
+        let mut state = self.lock();
+        if state.error.is_some() && state.events.is_none() {
+            return;
+        }
+        let epoch = *state.epoch.get_or_insert(info.enqueued);
+        let event = ItemEvent {
+            seq: info.seq,
+            enqueued: info.enqueued.saturating_duration_since(epoch),
+            delay: info.delay,
+            fate,
+            emitted: emitted.map(|emitted| emitted.saturating_duration_since(epoch)),
+            size,
+            corrupted: info.corrupted,
+            label: label.map(str::to_owned),
+        };
+
+        if state.error.is_none() {
+            let line = match state.format {
+                RecordFormat::Csv => event.to_csv(),
+                RecordFormat::JsonLines => event.to_json(),
+            };
+            if let Err(err) = writeln!(state.output, "{line}") {
+                warn!(%err, "failed to write item event");
+                state.error = Some(err);
+            }
+        }
+        if let Some(events) = &mut state.events {
+            events.push(event);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RecorderState> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl PartialEq for Recorder {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder")
+            .field("format", &self.lock().format)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ItemEvent {
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            self.seq,
+            millis(self.enqueued),
+            self.delay.map(millis).unwrap_or_default(),
+            self.fate.as_str(),
+            self.emitted.map(millis).unwrap_or_default(),
+            self.size,
+            self.corrupted,
+            self.label.as_deref().map(csv_field).unwrap_or_default()
+        )
+    }
+
+    fn to_json(&self) -> String {
+        let optional = |value: Option<Duration>| value.map(millis).unwrap_or_else(|| "null".to_string());
+        format!(
+            r#"{{"seq":{},"enqueued_ms":{},"delay_ms":{},"fate":"{}","emitted_ms":{},"size":{},"corrupted":{},"label":{}}}"#,
+            self.seq,
+            millis(self.enqueued),
+            optional(self.delay),
+            self.fate.as_str(),
+            optional(self.emitted),
+            self.size,
+            self.corrupted,
+            self.label
+                .as_deref()
+                .map(json_string)
+                .unwrap_or_else(|| "null".to_string())
+        )
+    }
+}
+
+/// Milliseconds with microsecond precision.
+fn millis(duration: Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64() * 1000.0)
+}
+
+/// Quotes `value` if it contains a separator, a quote or a line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// `value` as a JSON string literal.
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn record(recorder: &Recorder) {
+        let start = Instant::now();
+        recorder.enqueued(start);
+        let dropped = ItemInfo {
+            seq: 1,
+            enqueued: start + Duration::from_micros(20),
+            delay: None,
+            corrupted: false,
+        };
+        recorder.record(&dropped, Fate::Dropped, None, 3, None);
+        let emitted = ItemInfo {
+            seq: 0,
+            enqueued: start,
+            delay: Some(Duration::from_micros(12_500)),
+            corrupted: true,
+        };
+        recorder.record(
+            &emitted,
+            Fate::Emitted,
+            Some(start + Duration::from_micros(12_512)),
+            1200,
+            Some("link \"a\", b"),
+        );
+        recorder.flush().unwrap();
+    }
+
+    #[test]
+    fn writes_csv() {
+        let buffer = Buffer::default();
+        record(&Recorder::csv(buffer.clone()).unwrap());
+        assert_eq!(
+            String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap(),
+            "seq,enqueued_ms,delay_ms,fate,emitted_ms,size,corrupted,label\n\
+             1,0.020,,dropped,,3,false,\n\
+             0,0.000,12.500,emitted,12.512,1200,true,\"link \"\"a\"\", b\"\n"
+        );
+    }
+
+    #[test]
+    fn keeps_events() {
+        let recorder = Recorder::csv(io::sink()).unwrap();
+        record(&recorder);
+        assert!(recorder.events().is_empty());
+
+        let recorder = Recorder::csv(io::sink()).unwrap().keep_events();
+        record(&recorder);
+        let events = recorder.events();
+        assert_eq!(
+            events.iter().map(|event| (event.seq, event.fate)).collect::<Vec<_>>(),
+            [(1, Fate::Dropped), (0, Fate::Emitted)]
+        );
+        assert_eq!(events[1].emitted, Some(Duration::from_micros(12_512)));
+        assert_eq!(events[1].label.as_deref(), Some("link \"a\", b"));
+    }
+
+    #[test]
+    fn times_are_relative_to_the_epoch() {
+        let epoch = Instant::now();
+        let recorder = Recorder::csv(io::sink()).unwrap().keep_events().with_epoch(epoch);
+        let info = ItemInfo {
+            seq: 0,
+            enqueued: epoch + Duration::from_millis(300),
+            delay: None,
+            corrupted: false,
+        };
+        recorder.enqueued(info.enqueued);
+        recorder.record(
+            &info,
+            Fate::Emitted,
+            Some(info.enqueued + Duration::from_millis(20)),
+            1,
+            None,
+        );
+        let event = &recorder.events()[0];
+        assert_eq!(event.enqueued, Duration::from_millis(300));
+        assert_eq!(event.emitted, Some(Duration::from_millis(320)));
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let buffer = Buffer::default();
+        record(&Recorder::json_lines(buffer.clone()).unwrap());
+        assert_eq!(
+            String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap(),
+            "{\"seq\":1,\"enqueued_ms\":0.020,\"delay_ms\":null,\"fate\":\"dropped\",\"emitted_ms\":null,\"size\":3,\"corrupted\":false,\"label\":null}\n\
+             {\"seq\":0,\"enqueued_ms\":0.000,\"delay_ms\":12.500,\"fate\":\"emitted\",\"emitted_ms\":12.512,\"size\":1200,\"corrupted\":true,\"label\":\"link \\\"a\\\", b\"}\n"
+        );
+    }
+}
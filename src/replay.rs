@@ -0,0 +1,304 @@
+//! Recording the decisions a [`crate::ChokeStream`] makes for every item and replaying them in a later run.
+//!
+//! A [`DecisionRecorder`] attached with [`crate::ChokeSettings::set_decision_recorder`] collects one [`Decision`] per
+//! item taken from the inner stream: whether it was dropped, its delay, how it was corrupted, whether it was marked
+//! with ECN, whether it was pushed back and whether it was duplicated. The resulting [`DecisionTrace`] can be stored in
+//! a compact text format and applied to a new run with [`crate::ChokeSettings::set_decision_replay`], which reproduces
+//! the fate of every item without any randomness. This way a rare failure under random settings can be captured once
+//! and then debugged deterministically.
+//!
+//! Example:
+//! ```rust
+//! # use chokepoint::{replay::{DecisionRecorder, DecisionTrace}, ChokeSettings, ChokeStream};
+//! # use bytes::Bytes;
+//! # use futures::StreamExt as _;
+//! # #[tokio::main]
+//! # async fn main() {
+//! let items = || futures::stream::iter((0..100u8).map(|i| Bytes::from(vec![i])));
+//!
+//! let recorder = DecisionRecorder::new();
+//! let settings = ChokeSettings::default()
+//!     .set_drop_probability(Some(0.1))
+//!     .set_corrupt_probability(Some(0.1))
+//!     .set_decision_recorder(Some(recorder.clone()));
+//! let output = ChokeStream::new(items(), settings).collect::<Vec<_>>().await;
+//!
+//! // e.g. write it to a file
+//! let trace = recorder.trace().to_string();
+//!
+//! let trace: DecisionTrace = trace.parse().unwrap();
+//! let settings = ChokeSettings::default().set_decision_replay(Some(trace));
+//! assert_eq!(ChokeStream::new(items(), settings).collect::<Vec<_>>().await, output);
+//! # }
+//! ```
+
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    sync::{
+        Arc,
+        Mutex,
+        PoisonError,
+    },
+    time::Duration,
+};
+
+/// The fate of a single item taken from the inner stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Decision {
+    /// The item was dropped, randomly, by the bandwidth limit or by a [`crate::hook::DecisionHook`].
+    Drop,
+    /// The item was queued to be emitted.
+    Deliver {
+        /// The latency added to the item.
+        delay: Option<Duration>,
+        /// If the item was corrupted, the seed of the random number generator passed to
+        /// [`crate::ChokeItem::corrupt_with`].
+        corrupt: Option<u64>,
+        /// Whether the item was marked with Congestion Experienced instead of being dropped, see
+        /// [`crate::BandwidthLimitBuilder::ecn`]. An item that is not ECN-capable is dropped.
+        #[cfg_attr(feature = "serde", serde(default))]
+        ecn: bool,
+        /// Whether the item was pushed back, see [`crate::ChokeSettings::set_reorder`].
+        #[cfg_attr(feature = "serde", serde(default))]
+        reorder: bool,
+        /// Whether a duplicate of the item was emitted as well.
+        duplicate: bool,
+    },
+}
+
+/// A sequence of [`Decision`]s, one per item taken from the inner stream.
+///
+/// Its text representation (`Display` / `FromStr`) has one line per decision: `-` for a dropped item, otherwise the
+/// delay in nanoseconds (`_` for none), optionally followed by ` c<seed>` for a corrupted, ` e` for an ECN marked,
+/// ` r` for a pushed back and ` +` for a duplicated item.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecisionTrace {
+    decisions: VecDeque<Decision>,
+}
+
+impl DecisionTrace {
+    pub fn decisions(&self) -> impl Iterator<Item = &Decision> {
+        self.decisions.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.decisions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decisions.is_empty()
+    }
+
+    pub(crate) fn pop_front(&mut self) -> Option<Decision> {
+        self.decisions.pop_front()
+    }
+}
+
+impl From<Vec<Decision>> for DecisionTrace {
+    fn from(decisions: Vec<Decision>) -> Self {
+        Self {
+            decisions: decisions.into(),
+        }
+    }
+}
+
+impl FromIterator<Decision> for DecisionTrace {
+    fn from_iter<I: IntoIterator<Item = Decision>>(iter: I) -> Self {
+        Self {
+            decisions: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl IntoIterator for DecisionTrace {
+    type Item = Decision;
+    type IntoIter = std::collections::vec_deque::IntoIter<Decision>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.decisions.into_iter()
+    }
+}
+
+impl std::fmt::Display for DecisionTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for decision in &self.decisions {
+            match decision {
+                Decision::Drop => writeln!(f, "-")?,
+                Decision::Deliver {
+                    delay,
+                    corrupt,
+                    ecn,
+                    reorder,
+                    duplicate,
+                } => {
+                    match delay {
+                        Some(delay) => write!(f, "{}", delay.as_nanos())?,
+                        None => write!(f, "_")?,
+                    }
+                    if let Some(seed) = corrupt {
+                        write!(f, " c{seed}")?;
+                    }
+                    if *ecn {
+                        write!(f, " e")?;
+                    }
+                    if *reorder {
+                        write!(f, " r")?;
+                    }
+                    if *duplicate {
+                        write!(f, " +")?;
+                    }
+                    writeln!(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error returned when parsing a [`DecisionTrace`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecisionTraceParseError {
+    /// The line that could not be parsed, starting at 1.
+    pub line: usize,
+}
+
+impl std::fmt::Display for DecisionTraceParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid decision in line {}", self.line)
+    }
+}
+
+impl std::error::Error for DecisionTraceParseError {}
+
+impl FromStr for DecisionTrace {
+    type Err = DecisionTraceParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn parse_line(line: &str) -> Option<Decision> {
+            if line == "-" {
+                return Some(Decision::Drop);
+            }
+            let mut parts = line.split(' ');
+            let delay = match parts.next()? {
+                "_" => None,
+                nanos => Some(Duration::from_nanos(nanos.parse().ok()?)),
+            };
+            let mut corrupt = None;
+            let mut ecn = false;
+            let mut reorder = false;
+            let mut duplicate = false;
+            for part in parts {
+                match part.strip_prefix('c') {
+                    Some(seed) if corrupt.is_none() && !ecn && !reorder && !duplicate => {
+                        corrupt = Some(seed.parse().ok()?)
+                    }
+                    None if part == "e" && !ecn && !reorder && !duplicate => ecn = true,
+                    None if part == "r" && !reorder && !duplicate => reorder = true,
+                    None if part == "+" && !duplicate => duplicate = true,
+                    _ => return None,
+                }
+            }
+            Some(Decision::Deliver {
+                delay,
+                corrupt,
+                ecn,
+                reorder,
+                duplicate,
+            })
+        }
+
+        s.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| parse_line(line.trim()).ok_or(DecisionTraceParseError { line: i + 1 }))
+            .collect()
+    }
+}
+
+/// Collects the decisions of the streams it is attached to, see [`crate::ChokeSettings::set_decision_recorder`].
+/// Clones share the recorded trace.
+#[derive(Debug, Default, Clone)]
+pub struct DecisionRecorder(Arc<Mutex<DecisionTrace>>);
+
+impl DecisionRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The decisions recorded so far.
+    pub fn trace(&self) -> DecisionTrace {
+        self.lock().clone()
+    }
+
+    /// Take the decisions recorded so far, leaving an empty trace.
+    pub fn take(&self) -> DecisionTrace {
+        std::mem::take(&mut *self.lock())
+    }
+
+    pub(crate) fn record(&self, decision: Decision) {
+        self.lock().decisions.push_back(decision);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, DecisionTrace> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl PartialEq for DecisionRecorder {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Whether decisions are recorded or replayed, see [`crate::ChokeSettings::set_decision_recorder`] and
+/// [`crate::ChokeSettings::set_decision_replay`].
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum DecisionMode {
+    Record(DecisionRecorder),
+    Replay(DecisionTrace),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_round_trip() {
+        let trace = DecisionTrace::from(vec![
+            Decision::Drop,
+            Decision::Deliver {
+                delay: None,
+                corrupt: None,
+                ecn: false,
+                reorder: false,
+                duplicate: false,
+            },
+            Decision::Deliver {
+                delay: Some(Duration::from_millis(15)),
+                corrupt: Some(42),
+                ecn: true,
+                reorder: true,
+                duplicate: true,
+            },
+            Decision::Deliver {
+                delay: Some(Duration::from_nanos(1)),
+                corrupt: None,
+                ecn: false,
+                reorder: false,
+                duplicate: true,
+            },
+        ]);
+
+        let text = trace.to_string();
+        assert_eq!(text, "-\n_\n15000000 c42 e r +\n1 +\n");
+        assert_eq!(text.parse::<DecisionTrace>(), Ok(trace));
+
+        assert_eq!(
+            "-\n10 x\n".parse::<DecisionTrace>(),
+            Err(DecisionTraceParseError { line: 2 })
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use crate::ChokeItem;
+use bytes::Bytes;
+use rand::RngCore;
+use serde::{
+    de::DeserializeOwned,
+    Serialize,
+};
+
+/// A structured message that is shaped as its JSON encoding, enabled with the `serde` feature.
+///
+/// The size of the item is the size of the encoding. Corrupting the item flips a byte of the encoding and decodes it
+/// again, like a receiver would, so that the value either changes or the item carries a [`SerdeItemError`] instead.
+/// Duplicates are decoded from the encoding as well, so `T` doesn't need to be [`Clone`].
+///
+/// ```
+/// use chokepoint::{
+///     ChokeItem as _,
+///     SerdeItem,
+/// };
+///
+/// let mut item = SerdeItem::new(vec![1, 2, 3]).unwrap();
+/// assert_eq!(item.byte_len(), "[1,2,3]".len());
+/// assert_eq!(item.value(), Ok(&vec![1, 2, 3]));
+///
+/// item.corrupt();
+/// assert_ne!(item.value(), Ok(&vec![1, 2, 3]));
+/// ```
+#[derive(Debug)]
+pub struct SerdeItem<T> {
+    value: Result<T, SerdeItemError>,
+    encoded: Bytes,
+}
+
+impl<T> SerdeItem<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Encodes the value. Fails if `T`'s implementation of [`Serialize`] fails.
+    pub fn new(value: T) -> Result<Self, SerdeItemError> {
+        let encoded = serde_json::to_vec(&value).map_err(SerdeItemError::new)?;
+        Ok(Self {
+            value: Ok(value),
+            encoded: encoded.into(),
+        })
+    }
+
+    /// Decodes an item from its encoding, e.g. to shape items read from the network.
+    pub fn decode(encoded: Bytes) -> Self {
+        Self {
+            value: serde_json::from_slice(&encoded).map_err(SerdeItemError::new),
+            encoded,
+        }
+    }
+
+    /// The value, or the error of decoding the corrupted encoding.
+    pub fn value(&self) -> Result<&T, &SerdeItemError> {
+        self.value.as_ref()
+    }
+
+    /// Returns the value, or the error of decoding the corrupted encoding.
+    pub fn into_value(self) -> Result<T, SerdeItemError> {
+        self.value
+    }
+
+    /// The JSON encoding of the value.
+    pub fn encoded(&self) -> &Bytes {
+        &self.encoded
+    }
+}
+
+impl<T> ChokeItem for SerdeItem<T>
+where
+    T: Serialize + DeserializeOwned + Unpin + 'static,
+{
+    fn byte_len(&self) -> usize {
+        self.encoded.len()
+    }
+
+    fn corrupt(&mut self) {
+        self.corrupt_with(&mut rand::rng());
+    }
+
+    fn corrupt_with(&mut self, rng: &mut dyn RngCore) {
+        if self.encoded.is_empty() {
+            return;
+        }
+        let mut encoded = self.encoded.clone();
+        encoded.corrupt_with(rng);
+        *self = Self::decode(encoded);
+    }
+
+    fn duplicate(&mut self) -> Option<Self> {
+        Some(match &self.value {
+            Ok(_) => Self::decode(self.encoded.clone()),
+            Err(err) => Self {
+                value: Err(err.clone()),
+                encoded: self.encoded.clone(),
+            },
+        })
+    }
+
+    fn payload(&self) -> Option<&[u8]> {
+        Some(&self.encoded)
+    }
+}
+
+/// Error of encoding or decoding a [`SerdeItem`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerdeItemError {
+    message: String,
+}
+
+impl SerdeItemError {
+    fn new(err: serde_json::Error) -> Self {
+        Self {
+            message: err.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for SerdeItemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid item: {}", self.message)
+    }
+}
+
+impl std::error::Error for SerdeItemError {}
@@ -1,64 +1,1348 @@
-use crate::bandwidth_limiter::BandwidthLimiter;
+use crate::{
+    bandwidth_limiter::SharedBandwidthLimit,
+    clock::{
+        Clock,
+        SharedClock,
+    },
+    hook::{
+        DecisionHook,
+        Hook,
+    },
+    latency::{
+        Latency,
+        MaybeSendSync,
+    },
+    pcap::PcapWriter,
+    recorder::Recorder,
+    replay::{
+        DecisionMode,
+        DecisionRecorder,
+        DecisionTrace,
+    },
+    source::{
+        BoxedSource,
+        SettingsSource,
+    },
+};
 use std::time::Duration;
-use tokio::sync::mpsc;
+use tokio::sync::{
+    mpsc,
+    watch,
+};
+
+/// The default of [`ChokeSettings::set_poll_budget`].
+pub const DEFAULT_POLL_BUDGET: usize = 64;
 
 /// Settings for the [`crate::ChokeStream`] and [`crate::ChokeSink`].
+///
+/// Every field is optional so that settings can be used for partial updates: a field that is not set leaves the
+/// current value of the stream untouched when the settings are applied. Use [`ChokeSettings::reset`] to explicitly
+/// restore a field to its default value, [`ChokeSettings::merge`] to combine two updates and [`ChokeSettings::diff`] to
+/// compute the update that turns one configuration into another.
+///
+/// Settings can be cloned to configure multiple streams the same way. Clones share the latency distribution function
+/// (and its internal state, if any) as well as a [`ChokeSettings::settings_watcher`], so a single watcher updates all
+/// streams that were created from clones of the same settings. A [`ChokeSettings::settings_updater`] and a
+/// [`ChokeSettings::set_settings_source`] are not cloned since only one stream can receive from them.
 // Uses double options to allow for partial updates. See `ChokeStream::apply_settings`.
 #[derive(Default)]
-#[allow(clippy::type_complexity)]
 pub struct ChokeSettings {
     pub(crate) settings_rx: Option<mpsc::Receiver<ChokeSettings>>,
-    pub(crate) latency_distribution: Option<Option<Box<dyn FnMut() -> Option<Duration> + Send + Sync>>>,
+    pub(crate) settings_watch: Option<watch::Receiver<ChokeSettings>>,
+    pub(crate) settings_source: Option<BoxedSource>,
+    pub(crate) latency: Option<Latency>,
     pub(crate) drop_probability: Option<f64>,
     pub(crate) corrupt_probability: Option<f64>,
     pub(crate) duplicate_probability: Option<f64>,
     pub(crate) bandwidth_limit: Option<Option<BandwidthLimit>>,
+    pub(crate) shared_bandwidth_limit: Option<Option<SharedBandwidthLimit>>,
     pub(crate) ordering: Option<ChokeSettingsOrder>,
+    pub(crate) backpressure: Option<bool>,
+    pub(crate) poll_budget: Option<usize>,
+    pub(crate) mtu: Option<Option<usize>>,
+    pub(crate) coalescing: Option<Option<Coalescing>>,
+    pub(crate) nagle: Option<Option<Nagle>>,
+    pub(crate) burst: Option<Option<Burst>>,
+    pub(crate) item_ttl: Option<Option<Duration>>,
+    pub(crate) initial_delay: Option<Option<Latency>>,
+    pub(crate) reorder_window: Option<Option<ReorderWindow>>,
+    pub(crate) reorder: Option<Option<Reorder>>,
+    pub(crate) decision_hooks: Option<Vec<Hook>>,
+    pub(crate) clock: Option<SharedClock>,
+    pub(crate) seed: Option<Option<u64>>,
+    pub(crate) decisions: Option<Option<DecisionMode>>,
+    pub(crate) pcap: Option<Option<PcapWriter>>,
+    pub(crate) recorder: Option<Option<Recorder>>,
+    pub(crate) stats_interval: Option<Option<Duration>>,
+    pub(crate) label: Option<Option<String>>,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ChokeSettingsOrder {
+    /// Consume items as fast as possible from the inner stream. If items are delayed, their order might be changed.
+    Unordered,
+    /// Consume items as fast as possible from the inner stream, but ensure ordering. This is done by adjusting the
+    /// delay of each item and might potentially block until a delayed item is ready.
+    #[default]
+    Ordered,
+    /// Like [`ChokeSettingsOrder::Ordered`] for delayed items, but items without a delay are not blocked by delayed
+    /// items that are still waiting and pass them. The order is only preserved among the delayed items and among the
+    /// items without a delay.
+    PartiallyOrdered,
+}
+
+/// Identifies a single field of [`ChokeSettings`], see [`ChokeSettings::reset`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChokeSettingsField {
+    LatencyDistribution,
+    DropProbability,
+    CorruptProbability,
+    DuplicateProbability,
+    BandwidthLimit,
+    SharedBandwidthLimit,
+    Ordering,
+    Backpressure,
+    PollBudget,
+    Mtu,
+    Coalescing,
+    Nagle,
+    Burst,
+    ItemTtl,
+    InitialDelay,
+    ReorderWindow,
+    Reorder,
+    DecisionHooks,
+    Clock,
+    Seed,
+    Decisions,
+    Pcap,
+    Recorder,
+    StatsInterval,
+    Label,
+}
+
+/// A bandwidth limit, see [`ChokeSettings::set_bandwidth_limit_with`].
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{BandwidthLimit, ChokeSettings};
+/// # use std::time::Duration;
+/// let settings = ChokeSettings::default().set_bandwidth_limit_with(
+///     BandwidthLimit::builder()
+///         .bytes_per_sec(100_000)
+///         .drop_ratio(0.1)
+///         .only_drop_when_full(true)
+///         .window(Duration::from_millis(500)),
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BandwidthLimit {
+    pub(crate) bytes_per_second: usize,
+    pub(crate) window: Duration,
+    pub(crate) drop_ratio: f64,
+    pub(crate) only_drop_when_full: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) pacing: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) congestion: Option<Congestion>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) slow_start: Option<SlowStart>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) cross_traffic: Option<CrossTraffic>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) ecn: bool,
+}
+
+/// Builder for a [`BandwidthLimit`], see [`BandwidthLimit::builder`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BandwidthLimitBuilder {
+    limit: BandwidthLimit,
+}
+
+impl BandwidthLimitBuilder {
+    /// The number of bytes that can be emitted per second. A limit of zero disables bandwidth limiting.
+    pub fn bytes_per_sec(mut self, bytes_per_second: usize) -> Self {
+        self.limit.bytes_per_second = bytes_per_second;
+        self
+    }
+
+    /// The probability of dropping an item (0.0 to 1.0) instead of delaying it. Defaults to 0.0.
+    pub fn drop_ratio(mut self, drop_ratio: f64) -> Self {
+        self.limit.drop_ratio = drop_ratio;
+        self
+    }
+
+    /// If `true` (the default), items are only dropped while the limit is reached. Otherwise, the drop ratio applies to
+    /// every item.
+    pub fn only_drop_when_full(mut self, only_drop_when_full: bool) -> Self {
+        self.limit.only_drop_when_full = only_drop_when_full;
+        self
+    }
+
+    /// The sliding window over which the emitted bytes are accounted. Defaults to one second.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.limit.window = window;
+        self
+    }
+
+    /// If `true`, items are spaced evenly so that they leave at a steady rate matching the limit, like the pacing of
+    /// the `fq` qdisc: after an item, the next one is emitted once the time to transmit its bytes at the limit has
+    /// passed. Otherwise (the default), items are emitted as fast as possible until the window is full, i.e. in bursts.
+    pub fn pacing(mut self, pacing: bool) -> Self {
+        self.limit.pacing = pacing;
+        self
+    }
+
+    /// Lower the limit while it overflows and recover afterwards, see [`Congestion`]. Defaults to a fixed limit.
+    pub fn congestion(mut self, congestion: impl Into<Congestion>) -> Self {
+        self.limit.congestion = Some(congestion.into());
+        self
+    }
+
+    /// Start with a fraction of the limit and ramp up to it, see [`SlowStart`]. Defaults to the full limit right away.
+    pub fn slow_start(mut self, slow_start: impl Into<SlowStart>) -> Self {
+        self.limit.slow_start = Some(slow_start.into());
+        self
+    }
+
+    /// Synthetic competing load that uses up part of the limit, see [`CrossTraffic`]. Defaults to no load.
+    pub fn cross_traffic(mut self, cross_traffic: impl Into<CrossTraffic>) -> Self {
+        self.limit.cross_traffic = Some(cross_traffic.into());
+        self
+    }
+
+    /// If `true`, items that would be dropped by the drop ratio are marked with Congestion Experienced by
+    /// [`crate::ChokeItem::mark_ecn`] and delivered instead, like an AQM with ECN. Items that are not ECN-capable are
+    /// still dropped. Defaults to `false`.
+    pub fn ecn(mut self, ecn: bool) -> Self {
+        self.limit.ecn = ecn;
+        self
+    }
+
+    pub fn build(self) -> BandwidthLimit {
+        self.limit
+    }
+}
+
+impl From<BandwidthLimitBuilder> for BandwidthLimit {
+    fn from(builder: BandwidthLimitBuilder) -> Self {
+        builder.build()
+    }
+}
+
+impl BandwidthLimit {
+    pub fn builder() -> BandwidthLimitBuilder {
+        BandwidthLimitBuilder {
+            limit: BandwidthLimit {
+                bytes_per_second: 0,
+                window: Duration::from_millis(1000),
+                drop_ratio: 0.0,
+                only_drop_when_full: true,
+                pacing: false,
+                congestion: None,
+                slow_start: None,
+                cross_traffic: None,
+                ecn: false,
+            },
+        }
+    }
+
+    /// The number of bytes that can be emitted per second.
+    pub fn bytes_per_second(&self) -> usize {
+        self.bytes_per_second
+    }
+
+    /// The sliding window over which the emitted bytes are accounted.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// The probability of dropping an item while the limit is reached.
+    pub fn drop_ratio(&self) -> f64 {
+        self.drop_ratio
+    }
+
+    /// Whether items are only dropped while the limit is reached.
+    pub fn only_drop_when_full(&self) -> bool {
+        self.only_drop_when_full
+    }
+
+    /// Whether items are spaced evenly instead of being emitted in bursts.
+    pub fn pacing(&self) -> bool {
+        self.pacing
+    }
+
+    /// How the limit responds to congestion, `None` if it is fixed.
+    pub fn congestion(&self) -> Option<&Congestion> {
+        self.congestion.as_ref()
+    }
+
+    /// How the limit ramps up, `None` if the full limit applies right away.
+    pub fn slow_start(&self) -> Option<&SlowStart> {
+        self.slow_start.as_ref()
+    }
+
+    /// The synthetic competing load, `None` if the items have the limit to themselves.
+    pub fn cross_traffic(&self) -> Option<&CrossTraffic> {
+        self.cross_traffic.as_ref()
+    }
+
+    /// Whether items are marked with Congestion Experienced instead of being dropped.
+    pub fn ecn(&self) -> bool {
+        self.ecn
+    }
+}
+
+/// When the packets of [`CrossTraffic`] arrive.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CrossTrafficPattern {
+    /// Packets at a fixed rate.
+    Constant { bytes_per_second: usize },
+    /// Packets at a fixed rate for `on`, then none for `off`, repeatedly, starting with `on`.
+    OnOff {
+        bytes_per_second: usize,
+        on: Duration,
+        off: Duration,
+    },
+    /// Packets with exponentially distributed gaps, i.e. a Poisson process, at the given mean rate.
+    Poisson { bytes_per_second: usize },
+}
+
+impl Default for CrossTrafficPattern {
+    fn default() -> Self {
+        CrossTrafficPattern::Constant { bytes_per_second: 0 }
+    }
+}
+
+impl CrossTrafficPattern {
+    /// The rate while packets arrive.
+    pub fn bytes_per_second(&self) -> usize {
+        match self {
+            CrossTrafficPattern::Constant { bytes_per_second }
+            | CrossTrafficPattern::OnOff { bytes_per_second, .. }
+            | CrossTrafficPattern::Poisson { bytes_per_second } => *bytes_per_second,
+        }
+    }
+}
+
+/// Synthetic competing load on a bandwidth limit, so that the bandwidth available to the items fluctuates like on a
+/// link shared with other applications, see [`BandwidthLimitBuilder::cross_traffic`].
+///
+/// Packets of the cross traffic arrive according to the [`CrossTrafficPattern`] from when the limit is first used, and
+/// count against the limit like emitted items do. They are not emitted and don't wait for the limit, so cross traffic
+/// at or above the limit leaves no bandwidth for the items. With a [`crate::SharedBandwidthLimit`], the cross traffic
+/// competes with all streams sharing it.
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{BandwidthLimit, ChokeSettings, CrossTraffic, CrossTrafficPattern};
+/// # use std::time::Duration;
+/// let settings = ChokeSettings::default().set_bandwidth_limit_with(
+///     BandwidthLimit::builder().bytes_per_sec(1_000_000).pacing(true).cross_traffic(
+///         CrossTraffic::builder()
+///             .pattern(CrossTrafficPattern::OnOff {
+///                 bytes_per_second: 600_000,
+///                 on: Duration::from_secs(2),
+///                 off: Duration::from_secs(3),
+///             })
+///             .packet_size(1500),
+///     ),
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CrossTraffic {
+    pub(crate) pattern: CrossTrafficPattern,
+    pub(crate) packet_size: usize,
+    pub(crate) seed: Option<u64>,
+}
+
+/// Builder for [`CrossTraffic`], see [`CrossTraffic::builder`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrossTrafficBuilder {
+    cross_traffic: CrossTraffic,
+}
+
+impl CrossTrafficBuilder {
+    /// When the packets arrive. Defaults to no packets.
+    pub fn pattern(mut self, pattern: CrossTrafficPattern) -> Self {
+        self.cross_traffic.pattern = pattern;
+        self
+    }
+
+    /// The size of every packet in bytes. Defaults to 1200.
+    pub fn packet_size(mut self, packet_size: usize) -> Self {
+        self.cross_traffic.packet_size = packet_size;
+        self
+    }
+
+    /// The seed for the gaps of a [`CrossTrafficPattern::Poisson`] pattern. Defaults to a random seed.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.cross_traffic.seed = Some(seed);
+        self
+    }
+
+    pub fn build(self) -> CrossTraffic {
+        self.cross_traffic
+    }
+}
+
+impl From<CrossTrafficBuilder> for CrossTraffic {
+    fn from(builder: CrossTrafficBuilder) -> Self {
+        builder.build()
+    }
+}
+
+impl From<CrossTrafficPattern> for CrossTraffic {
+    fn from(pattern: CrossTrafficPattern) -> Self {
+        CrossTraffic::builder().pattern(pattern).build()
+    }
+}
+
+impl CrossTraffic {
+    pub fn builder() -> CrossTrafficBuilder {
+        CrossTrafficBuilder {
+            cross_traffic: CrossTraffic {
+                pattern: CrossTrafficPattern::default(),
+                packet_size: 1200,
+                seed: None,
+            },
+        }
+    }
+
+    /// When the packets arrive.
+    pub fn pattern(&self) -> &CrossTrafficPattern {
+        &self.pattern
+    }
+
+    /// The size of every packet in bytes.
+    pub fn packet_size(&self) -> usize {
+        self.packet_size
+    }
+
+    /// The seed for the gaps of a Poisson pattern, if set.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Appends the parameters to the bytes of a [`ChokeSettingsSnapshot::fingerprint`].
+    fn fingerprint(&self, bytes: &mut Vec<u8>) {
+        bytes.push(4);
+        match &self.pattern {
+            CrossTrafficPattern::Constant { bytes_per_second } => {
+                bytes.push(0);
+                bytes.extend((*bytes_per_second as u64).to_le_bytes());
+            }
+            CrossTrafficPattern::OnOff {
+                bytes_per_second,
+                on,
+                off,
+            } => {
+                bytes.push(1);
+                bytes.extend((*bytes_per_second as u64).to_le_bytes());
+                bytes.extend(on.as_nanos().to_le_bytes());
+                bytes.extend(off.as_nanos().to_le_bytes());
+            }
+            CrossTrafficPattern::Poisson { bytes_per_second } => {
+                bytes.push(2);
+                bytes.extend((*bytes_per_second as u64).to_le_bytes());
+            }
+        }
+        bytes.extend((self.packet_size as u64).to_le_bytes());
+        bytes.extend(self.seed.unwrap_or_default().to_le_bytes());
+    }
+
+    fn is_valid(&self) -> bool {
+        let on = match &self.pattern {
+            CrossTrafficPattern::OnOff { on, .. } => !on.is_zero(),
+            _ => true,
+        };
+        self.packet_size > 0 && on
+    }
+}
+
+/// A bandwidth limit that starts low and ramps up, like TCP slow start or the establishment of a cellular bearer, see
+/// [`BandwidthLimitBuilder::slow_start`].
+///
+/// The limit starts at the initial ratio of the configured limit when it is first used and grows exponentially, i.e. by
+/// the same factor in equal times, until it reaches the configured limit after the duration. The ramp starts over
+/// whenever the limit is applied again with different settings or after it was disabled with a limit of zero, and when
+/// restarted with [`crate::ChokeStream::restart_slow_start`] or [`crate::SharedBandwidthLimit::restart_slow_start`],
+/// e.g. after a blackout.
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{BandwidthLimit, ChokeSettings, SlowStart};
+/// # use std::time::Duration;
+/// let settings = ChokeSettings::default().set_bandwidth_limit_with(
+///     BandwidthLimit::builder().bytes_per_sec(1_000_000).slow_start(
+///         SlowStart::builder()
+///             .duration(Duration::from_secs(2))
+///             .initial_ratio(0.01),
+///     ),
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SlowStart {
+    pub(crate) duration: Duration,
+    pub(crate) initial_ratio: f64,
+}
+
+/// Builder for [`SlowStart`], see [`SlowStart::builder`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowStartBuilder {
+    slow_start: SlowStart,
+}
+
+impl SlowStartBuilder {
+    /// How long it takes to reach the configured limit. Defaults to one second.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.slow_start.duration = duration;
+        self
+    }
+
+    /// The share of the configured limit (above 0.0, up to 1.0) to start with. Defaults to 0.1.
+    pub fn initial_ratio(mut self, initial_ratio: f64) -> Self {
+        self.slow_start.initial_ratio = initial_ratio;
+        self
+    }
+
+    pub fn build(self) -> SlowStart {
+        self.slow_start
+    }
+}
+
+impl From<SlowStartBuilder> for SlowStart {
+    fn from(builder: SlowStartBuilder) -> Self {
+        builder.build()
+    }
+}
+
+impl Default for SlowStart {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl SlowStart {
+    pub fn builder() -> SlowStartBuilder {
+        SlowStartBuilder {
+            slow_start: SlowStart {
+                duration: Duration::from_secs(1),
+                initial_ratio: 0.1,
+            },
+        }
+    }
+
+    /// How long it takes to reach the configured limit.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The share of the configured limit to start with.
+    pub fn initial_ratio(&self) -> f64 {
+        self.initial_ratio
+    }
+
+    /// The share of the configured limit that applies `elapsed` after the start.
+    pub(crate) fn ratio_after(&self, elapsed: Duration) -> f64 {
+        if elapsed >= self.duration {
+            return 1.0;
+        }
+        self.initial_ratio
+            .powf(1.0 - elapsed.as_secs_f64() / self.duration.as_secs_f64())
+    }
+
+    /// Appends the parameters to the bytes of a [`ChokeSettingsSnapshot::fingerprint`].
+    fn fingerprint(&self, bytes: &mut Vec<u8>) {
+        bytes.push(3);
+        bytes.extend(self.duration.as_nanos().to_le_bytes());
+        bytes.extend(self.initial_ratio.to_bits().to_le_bytes());
+    }
+
+    fn is_valid(&self) -> bool {
+        self.initial_ratio > 0.0 && self.initial_ratio <= 1.0
+    }
+}
+
+/// A bandwidth limit that responds to congestion, like a path whose capacity is governed by competing
+/// congestion-controlled traffic, see [`BandwidthLimitBuilder::congestion`].
+///
+/// The limit overflows while items have to wait for it or are dropped because it is reached. Once it has overflowed
+/// for an interval, the limit in effect is multiplied by the backoff factor, e.g. halved, and again after every further
+/// interval of overflow, down to a minimum. After an interval without overflow, it grows linearly back to the
+/// configured limit.
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{BandwidthLimit, ChokeSettings, Congestion};
+/// # use std::time::Duration;
+/// let settings = ChokeSettings::default().set_bandwidth_limit_with(
+///     BandwidthLimit::builder().bytes_per_sec(1_000_000).congestion(
+///         Congestion::builder()
+///             .interval(Duration::from_millis(50))
+///             .backoff(0.5)
+///             .recovery(0.2),
+///     ),
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Congestion {
+    pub(crate) interval: Duration,
+    pub(crate) backoff: f64,
+    pub(crate) recovery: f64,
+    pub(crate) min_ratio: f64,
+}
+
+/// Builder for [`Congestion`], see [`Congestion::builder`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CongestionBuilder {
+    congestion: Congestion,
+}
+
+impl CongestionBuilder {
+    /// How long the limit has to overflow before it is lowered, and how long it must not overflow before it recovers,
+    /// e.g. a round-trip time of the competing traffic. Defaults to 100ms.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.congestion.interval = interval;
+        self
+    }
+
+    /// The factor (between 0.0 and 1.0) the limit is multiplied with on sustained overflow. Defaults to 0.5.
+    pub fn backoff(mut self, backoff: f64) -> Self {
+        self.congestion.backoff = backoff;
+        self
+    }
+
+    /// The share of the configured limit that is regained per second without overflow. Defaults to 0.1, i.e. the limit
+    /// recovers from half of the configured limit within 5 seconds.
+    pub fn recovery(mut self, recovery: f64) -> Self {
+        self.congestion.recovery = recovery;
+        self
+    }
+
+    /// The share of the configured limit (above 0.0, up to 1.0) the limit is never lowered below. Defaults to 0.1.
+    pub fn min_ratio(mut self, min_ratio: f64) -> Self {
+        self.congestion.min_ratio = min_ratio;
+        self
+    }
+
+    pub fn build(self) -> Congestion {
+        self.congestion
+    }
+}
+
+impl From<CongestionBuilder> for Congestion {
+    fn from(builder: CongestionBuilder) -> Self {
+        builder.build()
+    }
+}
+
+impl Default for Congestion {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl Congestion {
+    pub fn builder() -> CongestionBuilder {
+        CongestionBuilder {
+            congestion: Congestion {
+                interval: Duration::from_millis(100),
+                backoff: 0.5,
+                recovery: 0.1,
+                min_ratio: 0.1,
+            },
+        }
+    }
+
+    /// How long the limit has to overflow before it is lowered.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The factor the limit is multiplied with on sustained overflow.
+    pub fn backoff(&self) -> f64 {
+        self.backoff
+    }
+
+    /// The share of the configured limit that is regained per second without overflow.
+    pub fn recovery(&self) -> f64 {
+        self.recovery
+    }
+
+    /// The share of the configured limit the limit is never lowered below.
+    pub fn min_ratio(&self) -> f64 {
+        self.min_ratio
+    }
+
+    /// Appends the parameters to the bytes of a [`ChokeSettingsSnapshot::fingerprint`].
+    fn fingerprint(&self, bytes: &mut Vec<u8>) {
+        bytes.push(2);
+        bytes.extend(self.interval.as_nanos().to_le_bytes());
+        for parameter in [self.backoff, self.recovery, self.min_ratio] {
+            bytes.extend(parameter.to_bits().to_le_bytes());
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        !self.interval.is_zero()
+            && self.backoff > 0.0
+            && self.backoff < 1.0
+            && self.recovery.is_finite()
+            && self.recovery >= 0.0
+            && self.min_ratio > 0.0
+            && self.min_ratio <= 1.0
+    }
+}
+
+/// Merging consecutive items before they are emitted, see [`ChokeSettings::set_coalescing`].
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{ChokeSettings, Coalescing};
+/// # use std::time::Duration;
+/// let settings = ChokeSettings::default().set_coalescing(Some(
+///     Coalescing::builder()
+///         .max_bytes(64 * 1024)
+///         .max_delay(Duration::from_millis(5))
+///         .build(),
+/// ));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Coalescing {
+    pub(crate) max_bytes: usize,
+    pub(crate) max_delay: Duration,
+}
+
+/// Builder for [`Coalescing`], see [`Coalescing::builder`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoalescingBuilder {
+    coalescing: Coalescing,
+}
+
+impl CoalescingBuilder {
+    /// The maximum size of a merged item in bytes. Items are not merged if the result would be larger. Defaults to
+    /// 64 KiB.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.coalescing.max_bytes = max_bytes;
+        self
+    }
+
+    /// How long an item is held back waiting for more items to merge it with. Defaults to zero, i.e. only items that
+    /// are due at the same time are merged.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.coalescing.max_delay = max_delay;
+        self
+    }
+
+    pub fn build(self) -> Coalescing {
+        self.coalescing
+    }
+}
+
+impl From<CoalescingBuilder> for Coalescing {
+    fn from(builder: CoalescingBuilder) -> Self {
+        builder.build()
+    }
+}
+
+impl Coalescing {
+    pub fn builder() -> CoalescingBuilder {
+        CoalescingBuilder {
+            coalescing: Coalescing {
+                max_bytes: 64 * 1024,
+                max_delay: Duration::ZERO,
+            },
+        }
+    }
+
+    /// The maximum size of a merged item in bytes.
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// How long an item is held back waiting for more items.
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+}
+
+/// Holding back small items waiting for more data, see [`ChokeSettings::set_nagle`].
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{ChokeSettings, Nagle};
+/// # use std::time::Duration;
+/// let settings = ChokeSettings::default().set_nagle(Some(
+///     Nagle::builder()
+///         .min_bytes(1460)
+///         .max_delay(Duration::from_millis(40))
+///         .build(),
+/// ));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Nagle {
+    pub(crate) min_bytes: usize,
+    pub(crate) max_delay: Duration,
+}
+
+/// Builder for [`Nagle`], see [`Nagle::builder`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct NagleBuilder {
+    nagle: Nagle,
+}
+
+impl NagleBuilder {
+    /// Held items are released as soon as they add up to at least this many bytes. Defaults to 1460, the maximum
+    /// segment size of TCP over Ethernet.
+    pub fn min_bytes(mut self, min_bytes: usize) -> Self {
+        self.nagle.min_bytes = min_bytes;
+        self
+    }
+
+    /// How long the first held item waits for more data before the held items are released anyway. Defaults to
+    /// 200ms, a common delayed ACK timeout.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.nagle.max_delay = max_delay;
+        self
+    }
+
+    pub fn build(self) -> Nagle {
+        self.nagle
+    }
+}
+
+impl From<NagleBuilder> for Nagle {
+    fn from(builder: NagleBuilder) -> Self {
+        builder.build()
+    }
+}
+
+impl Nagle {
+    pub fn builder() -> NagleBuilder {
+        NagleBuilder {
+            nagle: Nagle {
+                min_bytes: 1460,
+                max_delay: Duration::from_millis(200),
+            },
+        }
+    }
+
+    /// The number of bytes that releases the held items.
+    pub fn min_bytes(&self) -> usize {
+        self.min_bytes
+    }
+
+    /// How long the first held item waits for more data.
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+}
+
+/// Releasing items in bursts, see [`ChokeSettings::set_burst`].
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{Burst, ChokeSettings};
+/// # use std::time::Duration;
+/// let settings = ChokeSettings::default().set_burst(Some(
+///     Burst::builder()
+///         .size(16)
+///         .interval(Duration::from_millis(20))
+///         .build(),
+/// ));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Burst {
+    pub(crate) size: Option<usize>,
+    pub(crate) interval: Option<Duration>,
+}
+
+/// Builder for [`Burst`], see [`Burst::builder`]. At least one of the size and the interval must be set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BurstBuilder {
+    burst: Burst,
+}
+
+impl BurstBuilder {
+    /// Held items are released as soon as this many items are held.
+    pub fn size(mut self, size: usize) -> Self {
+        self.burst.size = Some(size);
+        self
+    }
+
+    /// Held items are released once the first of them has been held for this long.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.burst.interval = Some(interval);
+        self
+    }
+
+    pub fn build(self) -> Burst {
+        self.burst
+    }
+}
+
+impl From<BurstBuilder> for Burst {
+    fn from(builder: BurstBuilder) -> Self {
+        builder.build()
+    }
+}
+
+impl Burst {
+    pub fn builder() -> BurstBuilder {
+        BurstBuilder {
+            burst: Burst {
+                size: None,
+                interval: None,
+            },
+        }
+    }
+
+    /// The number of items that releases the held items.
+    pub fn size(&self) -> Option<usize> {
+        self.size
+    }
+
+    /// How long the first held item waits before the held items are released.
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+}
+
+/// A bound on how far items are reordered, see [`ChokeSettings::set_reorder_window`].
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{ChokeSettings, ChokeSettingsOrder, ReorderWindow};
+/// # use std::time::Duration;
+/// let settings = ChokeSettings::default()
+///     .set_ordering(Some(ChokeSettingsOrder::Unordered))
+///     .set_reorder_window(Some(
+///         ReorderWindow::builder()
+///             .max_distance(3)
+///             .max_time(Duration::from_millis(10))
+///             .build(),
+///     ));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReorderWindow {
+    pub(crate) max_distance: Option<usize>,
+    pub(crate) max_time: Option<Duration>,
+}
+
+/// Builder for [`ReorderWindow`], see [`ReorderWindow::builder`]. At least one of the bounds must be set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReorderWindowBuilder {
+    window: ReorderWindow,
+}
+
+impl ReorderWindowBuilder {
+    /// An item is overtaken by at most this many later items. Zero disables reordering.
+    pub fn max_distance(mut self, max_distance: usize) -> Self {
+        self.window.max_distance = Some(max_distance);
+        self
+    }
+
+    /// An item is only overtaken by items that were taken from the inner stream at most this long after it.
+    pub fn max_time(mut self, max_time: Duration) -> Self {
+        self.window.max_time = Some(max_time);
+        self
+    }
+
+    pub fn build(self) -> ReorderWindow {
+        self.window
+    }
+}
+
+impl From<ReorderWindowBuilder> for ReorderWindow {
+    fn from(builder: ReorderWindowBuilder) -> Self {
+        builder.build()
+    }
+}
+
+impl ReorderWindow {
+    pub fn builder() -> ReorderWindowBuilder {
+        ReorderWindowBuilder {
+            window: ReorderWindow {
+                max_distance: None,
+                max_time: None,
+            },
+        }
+    }
+
+    /// The maximum number of later items that overtake an item.
+    pub fn max_distance(&self) -> Option<usize> {
+        self.max_distance
+    }
+
+    /// How much later an item can be taken from the inner stream and still overtake another item.
+    pub fn max_time(&self) -> Option<Duration> {
+        self.max_time
+    }
 }
 
-#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
-pub enum ChokeSettingsOrder {
-    /// Consume items as fast as possible from the inner stream. If items are delayed, their order might be changed.
-    Unordered,
-    /// Consume items as fast as possible from the inner stream, but ensure ordering. This is done by adjusting the
-    /// delay of each item and might potentially block until a delayed item is ready.
-    #[default]
-    Ordered,
-    /// `Backpressure` works by not consuming from the inner stream until the currently queued item has been processed.
-    /// Without backpressure, the [`crate::ChokeStream`] will consume items as fast as possible.
-    Backpressure,
+/// Explicit reordering of randomly selected items, see [`ChokeSettings::set_reorder`].
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{ChokeSettings, Reorder};
+/// let settings = ChokeSettings::default().set_reorder(Some(Reorder::builder().probability(0.25).gap(3).build()));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reorder {
+    pub(crate) probability: f64,
+    pub(crate) gap: usize,
 }
 
-pub(crate) struct BandwidthLimit {
-    pub(crate) window: BandwidthLimiter,
-    pub(crate) drop_ratio: f64,
+/// Builder for [`Reorder`], see [`Reorder::builder`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReorderBuilder {
+    reorder: Reorder,
+}
+
+impl ReorderBuilder {
+    /// The probability that an item is pushed back (0.0 to 1.0). Defaults to 0.0.
+    pub fn probability(mut self, probability: f64) -> Self {
+        self.reorder.probability = probability;
+        self
+    }
+
+    /// The number of positions a selected item is pushed back, i.e. how many later items are emitted before it.
+    /// Defaults to 1.
+    pub fn gap(mut self, gap: usize) -> Self {
+        self.reorder.gap = gap;
+        self
+    }
+
+    pub fn build(self) -> Reorder {
+        self.reorder
+    }
+}
+
+impl From<ReorderBuilder> for Reorder {
+    fn from(builder: ReorderBuilder) -> Self {
+        builder.build()
+    }
+}
+
+impl Reorder {
+    pub fn builder() -> ReorderBuilder {
+        ReorderBuilder {
+            reorder: Reorder {
+                probability: 0.0,
+                gap: 1,
+            },
+        }
+    }
+
+    /// The probability that an item is pushed back.
+    pub fn probability(&self) -> f64 {
+        self.probability
+    }
+
+    /// The number of positions a selected item is pushed back.
+    pub fn gap(&self) -> usize {
+        self.gap
+    }
+}
+
+/// The configuration a [`crate::ChokeStream`] or [`crate::ChokeSink`] is currently using, with all partial updates
+/// applied. See [`crate::ChokeStream::current_settings`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChokeSettingsSnapshot {
+    /// Whether a latency distribution is set. The distribution itself is an opaque function.
+    pub latency_distribution: bool,
+    /// The built-in latency model, see [`ChokeSettings::set_latency`]. `None` without latency or with a custom
+    /// function.
+    pub latency: Option<Latency>,
+    pub drop_probability: f64,
+    pub corrupt_probability: f64,
+    pub duplicate_probability: f64,
+    pub bandwidth_limit: Option<BandwidthLimit>,
+    /// The limit of the shared bandwidth limit, see [`ChokeSettings::set_shared_bandwidth_limit`].
+    pub shared_bandwidth_limit: Option<BandwidthLimit>,
+    pub ordering: ChokeSettingsOrder,
+    pub backpressure: bool,
+    /// The maximum number of items taken from the inner stream per poll, see [`ChokeSettings::set_poll_budget`].
+    pub poll_budget: usize,
+    /// The size above which items are split into fragments, see [`ChokeSettings::set_mtu`].
+    pub mtu: Option<usize>,
+    pub coalescing: Option<Coalescing>,
+    pub nagle: Option<Nagle>,
+    pub burst: Option<Burst>,
+    /// How long an item may wait before it is discarded, see [`ChokeSettings::set_item_ttl`].
+    pub item_ttl: Option<Duration>,
+    /// The connection setup time before the first item, see [`ChokeSettings::set_initial_delay`]. `None` without one
+    /// or with a custom function.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub initial_delay: Option<Latency>,
+    pub reorder_window: Option<ReorderWindow>,
+    pub reorder: Option<Reorder>,
+    /// The number of custom decision hooks, see [`ChokeSettings::add_decision_hook`].
+    pub decision_hooks: usize,
+    /// The seed of the random number generator. Chosen randomly unless set with [`ChokeSettings::set_seed`].
+    pub seed: u64,
+}
+
+impl ChokeSettingsSnapshot {
+    /// A hash of the settings, excluding the seed, that is stable across runs and platforms. Useful to check that a run
+    /// is reproduced with the same configuration. Latency distributions are opaque functions, so only whether one is
+    /// set is part of the fingerprint. The poll budget does not change how items are shaped and is not part of it
+    /// either.
+    pub fn fingerprint(&self) -> u64 {
+        let mut bytes = Vec::new();
+        bytes.push(self.latency_distribution as u8);
+        for probability in [
+            self.drop_probability,
+            self.corrupt_probability,
+            self.duplicate_probability,
+        ] {
+            bytes.extend(probability.to_bits().to_le_bytes());
+        }
+        if let Some(limit) = &self.bandwidth_limit {
+            bytes.push(1);
+            bytes.extend((limit.bytes_per_second as u64).to_le_bytes());
+            bytes.extend(limit.window.as_nanos().to_le_bytes());
+            bytes.extend(limit.drop_ratio.to_bits().to_le_bytes());
+            bytes.push(limit.only_drop_when_full as u8);
+            // Only appended if set, so that fingerprints of limits without pacing stay the same
+            if limit.pacing {
+                bytes.push(1);
+            }
+            if let Some(congestion) = &limit.congestion {
+                congestion.fingerprint(&mut bytes);
+            }
+            if let Some(slow_start) = &limit.slow_start {
+                slow_start.fingerprint(&mut bytes);
+            }
+            if let Some(cross_traffic) = &limit.cross_traffic {
+                cross_traffic.fingerprint(&mut bytes);
+            }
+            if limit.ecn {
+                bytes.push(5);
+            }
+        } else {
+            bytes.push(0);
+        }
+        bytes.push(self.ordering as u8);
+        bytes.push(self.backpressure as u8);
+        // Only appended if set, so that fingerprints of settings without these options stay the same. Every option
+        // starts with a tag of its own, so that options with the same kind of parameters are kept apart.
+        if let Some(mtu) = self.mtu {
+            bytes.push(6);
+            bytes.extend((mtu as u64).to_le_bytes());
+        }
+        if let Some(coalescing) = &self.coalescing {
+            bytes.push(7);
+            bytes.extend((coalescing.max_bytes as u64).to_le_bytes());
+            bytes.extend(coalescing.max_delay.as_nanos().to_le_bytes());
+        }
+        if let Some(nagle) = &self.nagle {
+            bytes.push(8);
+            bytes.extend((nagle.min_bytes as u64).to_le_bytes());
+            bytes.extend(nagle.max_delay.as_nanos().to_le_bytes());
+        }
+        if let Some(burst) = &self.burst {
+            bytes.push(9);
+            bytes.extend((burst.size.unwrap_or(0) as u64).to_le_bytes());
+            bytes.extend(burst.interval.unwrap_or_default().as_nanos().to_le_bytes());
+        }
+        if let Some(item_ttl) = self.item_ttl {
+            bytes.push(10);
+            bytes.extend(item_ttl.as_nanos().to_le_bytes());
+        }
+        if let Some(initial_delay) = &self.initial_delay {
+            bytes.push(4);
+            initial_delay.fingerprint(&mut bytes);
+        }
+        if let Some(window) = &self.reorder_window {
+            bytes.push(11);
+            bytes.extend((window.max_distance.map_or(u64::MAX, |distance| distance as u64)).to_le_bytes());
+            bytes.extend(window.max_time.map_or(u128::MAX, |time| time.as_nanos()).to_le_bytes());
+        }
+        if let Some(reorder) = &self.reorder {
+            bytes.push(12);
+            bytes.extend(reorder.probability.to_bits().to_le_bytes());
+            bytes.extend((reorder.gap as u64).to_le_bytes());
+        }
+        if let Some(latency) = &self.latency {
+            bytes.push(13);
+            latency.fingerprint(&mut bytes);
+        }
+        if self.decision_hooks > 0 {
+            bytes.push(14);
+            bytes.extend((self.decision_hooks as u64).to_le_bytes());
+        }
+        if let Some(limit) = &self.shared_bandwidth_limit {
+            bytes.push(15);
+            bytes.extend((limit.bytes_per_second as u64).to_le_bytes());
+            bytes.extend(limit.window.as_nanos().to_le_bytes());
+            bytes.extend(limit.drop_ratio.to_bits().to_le_bytes());
+            bytes.push(limit.only_drop_when_full as u8);
+            bytes.push(limit.pacing as u8);
+            if let Some(congestion) = &limit.congestion {
+                congestion.fingerprint(&mut bytes);
+            }
+            if let Some(slow_start) = &limit.slow_start {
+                slow_start.fingerprint(&mut bytes);
+            }
+            if let Some(cross_traffic) = &limit.cross_traffic {
+                cross_traffic.fingerprint(&mut bytes);
+            }
+            if limit.ecn {
+                bytes.push(5);
+            }
+        }
+
+        // FNV-1a
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    }
+}
+
+impl From<ChokeSettingsSnapshot> for ChokeSettings {
+    /// Settings that restore the snapshot when applied, e.g. to reproduce the configuration of a stream from its
+    /// serialized snapshot. Every field is set. Latency distribution functions and decision hooks can't be restored and
+    /// are left unset, a shared bandwidth limit is restored as a new limit that is not shared with other streams.
+    fn from(snapshot: ChokeSettingsSnapshot) -> Self {
+        let settings = ChokeSettings::default()
+            .set_latency(Some(snapshot.latency.unwrap_or_default()))
+            .set_drop_probability(Some(snapshot.drop_probability))
+            .set_corrupt_probability(Some(snapshot.corrupt_probability))
+            .set_duplicate_probability(Some(snapshot.duplicate_probability))
+            .set_shared_bandwidth_limit(snapshot.shared_bandwidth_limit.map(SharedBandwidthLimit::new))
+            .set_ordering(Some(snapshot.ordering))
+            .set_backpressure(Some(snapshot.backpressure))
+            .set_poll_budget(Some(snapshot.poll_budget))
+            .set_mtu(snapshot.mtu)
+            .set_coalescing(snapshot.coalescing)
+            .set_nagle(snapshot.nagle)
+            .set_burst(snapshot.burst)
+            .set_item_ttl(snapshot.item_ttl)
+            .set_initial_delay(snapshot.initial_delay)
+            .set_reorder_window(snapshot.reorder_window)
+            .set_reorder(snapshot.reorder)
+            .set_seed(Some(snapshot.seed));
+        match snapshot.bandwidth_limit {
+            Some(limit) => settings.set_bandwidth_limit_with(limit),
+            None => settings.set_bandwidth_limit(None),
+        }
+    }
+}
+
+/// Error returned by [`ChokeSettings::validate`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChokeSettingsError {
+    /// A probability (or the drop ratio of the bandwidth limit) is outside of `0.0..=1.0`.
+    InvalidProbability { field: ChokeSettingsField, value: f64 },
+    /// A bandwidth limit of zero disables bandwidth limiting, so its drop ratio would never be applied.
+    ZeroBandwidthWithDropRatio(f64),
+    /// The bandwidth limit is accounted over an empty window.
+    ZeroBandwidthWindow,
+    /// Items can't be split into fragments of zero bytes.
+    ZeroMtu,
+    /// Items can't be merged into items of zero bytes.
+    ZeroCoalescingSize,
+    /// A burst needs a size or an interval, and neither can be zero.
+    InvalidBurst,
+    /// A reorder window needs a maximum distance or time.
+    EmptyReorderWindow,
+    /// Pushing items back by zero positions does not reorder them.
+    ZeroReorderGap,
+    /// A poll budget of zero would never take items from the inner stream.
+    ZeroPollBudget,
+    /// The shape of a skew normal latency distribution is not finite.
+    InvalidLatency,
+    /// The interval of a [`Congestion`] is zero, its backoff is not within `0.0..1.0` (exclusive), its recovery is
+    /// negative or its minimum is not within `0.0..=1.0` (excluding 0.0).
+    InvalidCongestion,
+    /// The initial ratio of a [`SlowStart`] is not within `0.0..=1.0` (excluding 0.0).
+    InvalidSlowStart,
+    /// The packets of [`CrossTraffic`] are empty or its on/off pattern is never on.
+    InvalidCrossTraffic,
 }
 
-impl std::fmt::Debug for BandwidthLimit {
+impl std::fmt::Display for ChokeSettingsError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("BandwithLimit")
-            .field("window", &"fn() -> Duration")
-            .field("drop_ratio", &self.drop_ratio)
-            .finish()
+        match self {
+            ChokeSettingsError::InvalidProbability { field, value } => {
+                write!(f, "{field:?}: probability {value} is not within 0.0..=1.0")
+            }
+            ChokeSettingsError::ZeroBandwidthWithDropRatio(drop_ratio) => {
+                write!(
+                    f,
+                    "bandwidth limit of zero disables limiting but drop ratio {drop_ratio} is set"
+                )
+            }
+            ChokeSettingsError::ZeroBandwidthWindow => write!(f, "bandwidth limit window must not be zero"),
+            ChokeSettingsError::ZeroMtu => write!(f, "MTU must not be zero"),
+            ChokeSettingsError::ZeroCoalescingSize => write!(f, "maximum size of coalesced items must not be zero"),
+            ChokeSettingsError::InvalidBurst => write!(f, "burst needs a non-zero size or interval"),
+            ChokeSettingsError::EmptyReorderWindow => write!(f, "reorder window needs a maximum distance or time"),
+            ChokeSettingsError::ZeroReorderGap => write!(f, "reorder gap must not be zero"),
+            ChokeSettingsError::ZeroPollBudget => write!(f, "poll budget must not be zero"),
+            ChokeSettingsError::InvalidLatency => write!(f, "latency distribution shape must be finite"),
+            ChokeSettingsError::InvalidCongestion => write!(f, "congestion response parameters are out of range"),
+            ChokeSettingsError::InvalidSlowStart => write!(f, "slow start must start above zero"),
+            ChokeSettingsError::InvalidCrossTraffic => write!(f, "cross traffic needs non-empty packets and on time"),
+        }
+    }
+}
+
+impl std::error::Error for ChokeSettingsError {}
+
+impl Clone for ChokeSettings {
+    fn clone(&self) -> Self {
+        ChokeSettings {
+            settings_rx: None,
+            settings_watch: self.settings_watch.clone(),
+            ..self.to_update()
+        }
     }
 }
 
 impl std::fmt::Debug for ChokeSettings {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ChokeSettings")
-            .field(
-                "latency_distribution",
-                if self.latency_distribution.is_some() {
-                    &"Some"
-                } else {
-                    &"None"
-                },
-            )
+            .field("latency", &self.latency)
             .field("drop_probability", &self.drop_probability)
             .field("corrupt_probability", &self.corrupt_probability)
             .field("duplicate_probability", &self.duplicate_probability)
-            .field("bandwidth_limiter", &self.bandwidth_limit)
+            .field("bandwidth_limit", &self.bandwidth_limit)
+            .field("shared_bandwidth_limit", &self.shared_bandwidth_limit)
             .field("ordering", &self.ordering)
+            .field("backpressure", &self.backpressure)
+            .field("poll_budget", &self.poll_budget)
+            .field("mtu", &self.mtu)
+            .field("coalescing", &self.coalescing)
+            .field("nagle", &self.nagle)
+            .field("burst", &self.burst)
+            .field("item_ttl", &self.item_ttl)
+            .field("initial_delay", &self.initial_delay)
+            .field("reorder_window", &self.reorder_window)
+            .field("reorder", &self.reorder)
+            .field("decision_hooks", &self.decision_hooks)
+            .field("clock", &self.clock)
+            .field("seed", &self.seed)
+            .field("decisions", &self.decisions)
+            .field("pcap", &self.pcap)
+            .field("recorder", &self.recorder)
+            .field("stats_interval", &self.stats_interval)
+            .field("label", &self.label)
             .finish()
     }
 }
@@ -72,33 +1356,65 @@ impl ChokeSettings {
         settings_tx
     }
 
-    /// Set the bandwidth limit in bytes per second.
-    pub fn set_bandwidth_limit(mut self, bytes_per_seconds: Option<usize>, drop_ratio: f64) -> Self {
-        match bytes_per_seconds {
-            Some(bytes_per_seconds) if bytes_per_seconds > 0 => {
-                self.bandwidth_limit = Some(Some(BandwidthLimit {
-                    window: BandwidthLimiter::new(bytes_per_seconds, Duration::from_millis(1000)),
-                    drop_ratio,
-                }));
-            }
-            _ => {
-                self.bandwidth_limit = Some(None);
-            }
-        }
+    /// Like [`ChokeSettings::settings_updater`] but based on a [`watch::Sender`]: sending never blocks and the
+    /// [`crate::ChokeStream`] / [`crate::ChokeSink`] applies the most recent value the next time it is polled, skipping
+    /// intermediate states.
+    ///
+    /// [`watch::Sender::send_replace`] replaces the pending update, use
+    /// `sender.send_modify(|settings| settings.merge(update))` to accumulate partial updates instead.
+    pub fn settings_watcher(&mut self) -> watch::Sender<ChokeSettings> {
+        let (settings_tx, settings_rx) = watch::channel(ChokeSettings::default());
+        self.settings_watch = Some(settings_rx);
+        settings_tx
+    }
+
+    /// Live update the configuration with the updates produced by `source`, e.g. a
+    /// [`crate::source::ScenarioSource`], see [`crate::source`]. Replaces the source the stream is using, if any. Like
+    /// a [`ChokeSettings::settings_updater`], the source is not cloned with the settings.
+    pub fn set_settings_source(mut self, source: impl SettingsSource) -> Self {
+        self.settings_source = Some(BoxedSource::new(source));
+        self
+    }
+
+    /// Set the bandwidth limit in bytes per second. `None` or a limit of zero disables bandwidth limiting. Items
+    /// exceeding the limit are delayed, use [`ChokeSettings::set_bandwidth_limit_with`] to configure dropping.
+    pub fn set_bandwidth_limit(mut self, bytes_per_seconds: Option<usize>) -> Self {
+        self.bandwidth_limit = Some(
+            bytes_per_seconds.map(|bytes_per_second| BandwidthLimit::builder().bytes_per_sec(bytes_per_second).build()),
+        );
+        self
+    }
+
+    /// Set a bandwidth limit with advanced options such as the drop ratio, see [`BandwidthLimit::builder`].
+    pub fn set_bandwidth_limit_with(mut self, limit: impl Into<BandwidthLimit>) -> Self {
+        self.bandwidth_limit = Some(Some(limit.into()));
+        self
+    }
+
+    /// Set a bandwidth limit that is shared with other streams and sinks, e.g. with all connections of a proxy, so
+    /// that together they don't exceed it. It applies in addition to the limit of the stream itself: an item is only
+    /// emitted once both have capacity. Use [`SharedBandwidthLimit::set_limit`] to change the limit of all streams at
+    /// once. `None` (the default) disables it.
+    pub fn set_shared_bandwidth_limit(mut self, limit: Option<SharedBandwidthLimit>) -> Self {
+        self.shared_bandwidth_limit = Some(limit);
         self
     }
 
     /// Set the latency distribution function. It produces an optional [`Duration`] that represents the latency to be
-    /// added to the packet. If the function returns `None`, no latency will be added.
+    /// added to the packet. If the function returns `None`, no latency will be added. Shorthand for
+    /// [`ChokeSettings::set_latency`] with [`Latency::custom`].
     pub fn set_latency_distribution<F>(mut self, f: Option<F>) -> Self
     where
-        F: FnMut() -> Option<Duration> + Send + Sync + 'static,
+        F: FnMut() -> Option<Duration> + MaybeSendSync + 'static,
     {
-        if let Some(f) = f {
-            self.latency_distribution = Some(Some(Box::new(f)));
-        } else {
-            self.latency_distribution = Some(None);
-        }
+        self.latency = Some(f.map_or(Latency::None, Latency::custom));
+        self
+    }
+
+    /// Set the latency added to every item, e.g. [`Latency::Normal`]. Unlike custom functions, the built-in models are
+    /// reproduced with the seed. Defaults to [`Latency::None`].
+    pub fn set_latency(mut self, latency: Option<Latency>) -> Self {
+        self.latency = latency;
         self
     }
 
@@ -125,4 +1441,798 @@ impl ChokeSettings {
         self.ordering = ordering;
         self
     }
+
+    /// Enable backpressure. With backpressure, no items are consumed from the inner stream until the currently queued
+    /// items have been processed. Without backpressure (the default), the [`crate::ChokeStream`] will consume items as
+    /// fast as possible. Backpressure can be combined with any ordering.
+    pub fn set_backpressure(mut self, backpressure: Option<bool>) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// The maximum number of items the [`crate::ChokeStream`] takes from the inner stream in a single poll. Once it is
+    /// used up, the task wakes itself and yields, so that an inner stream that is always ready, e.g. a channel that is
+    /// filled faster than it is drained, does not starve other tasks on the executor. Defaults to
+    /// [`DEFAULT_POLL_BUDGET`].
+    pub fn set_poll_budget(mut self, poll_budget: Option<usize>) -> Self {
+        self.poll_budget = poll_budget;
+        self
+    }
+
+    /// Split items larger than `mtu` bytes into fragments of at most `mtu` bytes with [`crate::ChokeItem::split`]. Each
+    /// fragment is shaped independently, so some fragments of an item may be dropped or delayed more than others.
+    /// `None` (the default) disables fragmentation.
+    pub fn set_mtu(mut self, mtu: Option<usize>) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    /// Merge consecutive items into one with [`crate::ChokeItem::coalesce`] before they are emitted, like GSO or a
+    /// middlebox that aggregates small packets. Items that can't be merged are emitted on their own. `None` (the
+    /// default) disables coalescing.
+    pub fn set_coalescing(mut self, coalescing: Option<Coalescing>) -> Self {
+        self.coalescing = Some(coalescing);
+        self
+    }
+
+    /// Hold back items that are due until they add up to a number of bytes or the first of them has waited for a
+    /// while, then release them at once, like Nagle's algorithm aggregates small writes. The items are not merged,
+    /// combine this with [`ChokeSettings::set_coalescing`] for that. `None` (the default) disables holding items.
+    pub fn set_nagle(mut self, nagle: Option<Nagle>) -> Self {
+        self.nagle = Some(nagle);
+        self
+    }
+
+    /// Hold back items that are due and release them in bursts, once a number of items is held or the first of them
+    /// has waited for an interval, like wireless frame aggregation or middleboxes that cause on/off delivery patterns.
+    /// The items are not merged. `None` (the default) disables bursts.
+    pub fn set_burst(mut self, burst: Option<Burst>) -> Self {
+        self.burst = Some(burst);
+        self
+    }
+
+    /// Discard items that have waited longer than `ttl` since they were taken from the inner stream, through their
+    /// delay as well as queueing behind other items or the bandwidth limit, like real-time systems where late packets
+    /// are worthless. Discarded items are counted in [`crate::ChokeStats::expired`]. `None` (the default) keeps items
+    /// until they are emitted.
+    pub fn set_item_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.item_ttl = Some(ttl);
+        self
+    }
+
+    /// Hold back the first item for a connection setup time drawn from `delay`, e.g. for DNS resolution and the
+    /// handshakes of TCP and TLS, so that the time to the first byte is realistic. The setup starts when the first item
+    /// is taken from the inner stream. Items taken until it is over are delayed by the remaining setup time in addition
+    /// to their latency, later items are not affected. Changing it after the first item has no effect. `None` (the
+    /// default) emits the first item without a setup time.
+    pub fn set_initial_delay(mut self, delay: Option<Latency>) -> Self {
+        self.initial_delay = Some(delay);
+        self
+    }
+
+    /// Bound how far items are reordered with [`ChokeSettingsOrder::Unordered`]: an item is overtaken by a limited
+    /// number of later items or only by items that follow it closely, like reordering in real networks is constrained.
+    /// Items that would be reordered further are delayed until just after the item they would overtake. `None` (the
+    /// default) allows arbitrary reordering. Has no effect with the other orderings.
+    pub fn set_reorder_window(mut self, window: Option<ReorderWindow>) -> Self {
+        self.reorder_window = Some(window);
+        self
+    }
+
+    /// Push randomly selected items back by a number of positions, like netem's `reorder`: a selected item is held
+    /// until as many later items have been emitted, or the inner stream has ended. Unlike reordering through random
+    /// delays, this controls precisely how often and how far items are reordered. `None` (the default) disables it.
+    pub fn set_reorder(mut self, reorder: Option<Reorder>) -> Self {
+        self.reorder = Some(reorder);
+        self
+    }
+
+    /// Add a custom hook that can change the decision made for every item, see [`crate::hook`]. Hooks are called in
+    /// the order in which they were added. When the settings are applied, their hooks replace the ones of the stream,
+    /// use [`ChokeSettings::reset`] with [`ChokeSettingsField::DecisionHooks`] to remove all hooks. Clones share the
+    /// hooks and their state.
+    pub fn add_decision_hook(mut self, hook: impl DecisionHook + 'static) -> Self {
+        self.decision_hooks.get_or_insert_with(Vec::new).push(Hook::new(hook));
+        self
+    }
+
+    /// Set the [`Clock`] used to delay items and to limit the bandwidth. Defaults to the
+    /// [`crate::clock::SystemClock`].
+    pub fn set_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Some(SharedClock::new(clock));
+        self
+    }
+
+    /// Seed the random number generator that decides which items are dropped, corrupted and duplicated, making these
+    /// decisions reproducible. `None` (the default) seeds it randomly. Applying a seed restarts the random sequence.
+    ///
+    /// Latency distribution functions use their own source of randomness.
+    pub fn set_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Record the decision made for every item (drop, delay, corruption, duplication) with `recorder`, see
+    /// [`crate::replay`]. `None` stops recording or replaying.
+    pub fn set_decision_recorder(mut self, recorder: Option<DecisionRecorder>) -> Self {
+        self.decisions = Some(recorder.map(DecisionMode::Record));
+        self
+    }
+
+    /// Decide the fate of the items according to a recorded `trace` instead of the probabilities, the latency
+    /// distribution and the bandwidth limit's drop ratio. Once the trace is exhausted, decisions are made as usual
+    /// again. `None` stops recording or replaying.
+    pub fn set_decision_replay(mut self, trace: Option<DecisionTrace>) -> Self {
+        self.decisions = Some(trace.map(DecisionMode::Replay));
+        self
+    }
+
+    /// Write the emitted and dropped items to a pcapng file, see [`crate::pcap`]. `None` stops writing.
+    pub fn set_pcap_writer(mut self, writer: Option<PcapWriter>) -> Self {
+        self.pcap = Some(writer);
+        self
+    }
+
+    /// Write an event for every item, e.g. as CSV, see [`crate::recorder`]. `None` stops writing.
+    pub fn set_recorder(mut self, recorder: Option<Recorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Log the statistics at debug level every `interval`, with the rates of the emitted items and bytes since the
+    /// previous report, see [`crate::ChokeStats`]. `None` (the default) doesn't log them.
+    pub fn set_stats_interval(mut self, interval: Option<Duration>) -> Self {
+        self.stats_interval = Some(interval);
+        self
+    }
+
+    /// Name the shaper, e.g. after the connection or link it simulates, so that the output of several shapers can be
+    /// told apart: the label is a field of a span around the tracing events of the shaper, part of its
+    /// [`crate::ChokeStats`] and a column written by a [`crate::recorder::Recorder`]. Use [`ChokeSettings::reset`]
+    /// with [`ChokeSettingsField::Label`] to remove it.
+    pub fn set_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(Some(label.into()));
+        self
+    }
+
+    /// Check that the settings are consistent, e.g. that all probabilities are within `0.0..=1.0`.
+    pub fn validate(&self) -> Result<(), ChokeSettingsError> {
+        let probabilities = [
+            (ChokeSettingsField::DropProbability, self.drop_probability),
+            (ChokeSettingsField::CorruptProbability, self.corrupt_probability),
+            (ChokeSettingsField::DuplicateProbability, self.duplicate_probability),
+        ];
+        for (field, probability) in probabilities {
+            if let Some(value) = probability.filter(|p| !(0.0..=1.0).contains(p)) {
+                return Err(ChokeSettingsError::InvalidProbability { field, value });
+            }
+        }
+
+        let limits = [
+            (
+                ChokeSettingsField::BandwidthLimit,
+                self.bandwidth_limit.clone().flatten(),
+            ),
+            (
+                ChokeSettingsField::SharedBandwidthLimit,
+                self.shared_bandwidth_limit
+                    .as_ref()
+                    .and_then(|shared| shared.as_ref().map(SharedBandwidthLimit::limit)),
+            ),
+        ];
+        for (field, limit) in limits {
+            let Some(limit) = limit else {
+                continue;
+            };
+            if !(0.0..=1.0).contains(&limit.drop_ratio) {
+                return Err(ChokeSettingsError::InvalidProbability {
+                    field,
+                    value: limit.drop_ratio,
+                });
+            }
+            if limit.bytes_per_second == 0 && limit.drop_ratio > 0.0 {
+                return Err(ChokeSettingsError::ZeroBandwidthWithDropRatio(limit.drop_ratio));
+            }
+            if limit.window.is_zero() {
+                return Err(ChokeSettingsError::ZeroBandwidthWindow);
+            }
+            if limit
+                .congestion
+                .as_ref()
+                .is_some_and(|congestion| !congestion.is_valid())
+            {
+                return Err(ChokeSettingsError::InvalidCongestion);
+            }
+            if limit
+                .slow_start
+                .as_ref()
+                .is_some_and(|slow_start| !slow_start.is_valid())
+            {
+                return Err(ChokeSettingsError::InvalidSlowStart);
+            }
+            if limit
+                .cross_traffic
+                .as_ref()
+                .is_some_and(|cross_traffic| !cross_traffic.is_valid())
+            {
+                return Err(ChokeSettingsError::InvalidCrossTraffic);
+            }
+        }
+
+        let initial_delay = self.initial_delay.as_ref().and_then(Option::as_ref);
+        if [self.latency.as_ref(), initial_delay]
+            .into_iter()
+            .flatten()
+            .any(|latency| !latency.is_valid())
+        {
+            return Err(ChokeSettingsError::InvalidLatency);
+        }
+        if self.poll_budget == Some(0) {
+            return Err(ChokeSettingsError::ZeroPollBudget);
+        }
+        if self.mtu == Some(Some(0)) {
+            return Err(ChokeSettingsError::ZeroMtu);
+        }
+        if let Some(Some(coalescing)) = &self.coalescing {
+            if coalescing.max_bytes == 0 {
+                return Err(ChokeSettingsError::ZeroCoalescingSize);
+            }
+        }
+        if let Some(Some(reorder)) = &self.reorder {
+            if !(0.0..=1.0).contains(&reorder.probability) {
+                return Err(ChokeSettingsError::InvalidProbability {
+                    field: ChokeSettingsField::Reorder,
+                    value: reorder.probability,
+                });
+            }
+            if reorder.gap == 0 {
+                return Err(ChokeSettingsError::ZeroReorderGap);
+            }
+        }
+        if let Some(Some(window)) = &self.reorder_window {
+            if window.max_distance.is_none() && window.max_time.is_none() {
+                return Err(ChokeSettingsError::EmptyReorderWindow);
+            }
+        }
+        if let Some(Some(burst)) = &self.burst {
+            let empty = burst.size.is_none() && burst.interval.is_none();
+            if empty || burst.size == Some(0) || burst.interval.is_some_and(|interval| interval.is_zero()) {
+                return Err(ChokeSettingsError::InvalidBurst);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finish configuring the settings, returning an error if they are invalid. See [`ChokeSettings::validate`].
+    pub fn build(self) -> Result<Self, ChokeSettingsError> {
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Explicitly set `field` back to its default value. Unlike leaving a field unset (which keeps whatever the stream
+    /// is currently using), applying these settings will restore the default.
+    pub fn reset(mut self, field: ChokeSettingsField) -> Self {
+        match field {
+            ChokeSettingsField::LatencyDistribution => self.latency = Some(Latency::None),
+            ChokeSettingsField::DropProbability => self.drop_probability = Some(0.0),
+            ChokeSettingsField::CorruptProbability => self.corrupt_probability = Some(0.0),
+            ChokeSettingsField::DuplicateProbability => self.duplicate_probability = Some(0.0),
+            ChokeSettingsField::BandwidthLimit => self.bandwidth_limit = Some(None),
+            ChokeSettingsField::SharedBandwidthLimit => self.shared_bandwidth_limit = Some(None),
+            ChokeSettingsField::Ordering => self.ordering = Some(ChokeSettingsOrder::default()),
+            ChokeSettingsField::Backpressure => self.backpressure = Some(false),
+            ChokeSettingsField::PollBudget => self.poll_budget = Some(DEFAULT_POLL_BUDGET),
+            ChokeSettingsField::Mtu => self.mtu = Some(None),
+            ChokeSettingsField::Coalescing => self.coalescing = Some(None),
+            ChokeSettingsField::Nagle => self.nagle = Some(None),
+            ChokeSettingsField::Burst => self.burst = Some(None),
+            ChokeSettingsField::ItemTtl => self.item_ttl = Some(None),
+            ChokeSettingsField::InitialDelay => self.initial_delay = Some(None),
+            ChokeSettingsField::ReorderWindow => self.reorder_window = Some(None),
+            ChokeSettingsField::Reorder => self.reorder = Some(None),
+            ChokeSettingsField::DecisionHooks => self.decision_hooks = Some(Vec::new()),
+            ChokeSettingsField::Clock => self.clock = Some(SharedClock::system()),
+            ChokeSettingsField::Seed => self.seed = Some(None),
+            ChokeSettingsField::Decisions => self.decisions = Some(None),
+            ChokeSettingsField::Pcap => self.pcap = Some(None),
+            ChokeSettingsField::Recorder => self.recorder = Some(None),
+            ChokeSettingsField::StatsInterval => self.stats_interval = Some(None),
+            ChokeSettingsField::Label => self.label = Some(None),
+        }
+        self
+    }
+
+    /// Merge `other` into `self`. Every field that is set in `other` overrides the value in `self`, fields that are
+    /// not set in `other` are kept. Applying the merged settings is equivalent to applying `self` and then `other`.
+    pub fn merge(&mut self, other: ChokeSettings) {
+        if other.settings_rx.is_some() {
+            self.settings_rx = other.settings_rx;
+        }
+        if other.settings_watch.is_some() {
+            self.settings_watch = other.settings_watch;
+        }
+        if other.settings_source.is_some() {
+            self.settings_source = other.settings_source;
+        }
+        if other.latency.is_some() {
+            self.latency = other.latency;
+        }
+        if other.drop_probability.is_some() {
+            self.drop_probability = other.drop_probability;
+        }
+        if other.corrupt_probability.is_some() {
+            self.corrupt_probability = other.corrupt_probability;
+        }
+        if other.duplicate_probability.is_some() {
+            self.duplicate_probability = other.duplicate_probability;
+        }
+        if other.bandwidth_limit.is_some() {
+            self.bandwidth_limit = other.bandwidth_limit;
+        }
+        if other.shared_bandwidth_limit.is_some() {
+            self.shared_bandwidth_limit = other.shared_bandwidth_limit;
+        }
+        if other.ordering.is_some() {
+            self.ordering = other.ordering;
+        }
+        if other.backpressure.is_some() {
+            self.backpressure = other.backpressure;
+        }
+        if other.poll_budget.is_some() {
+            self.poll_budget = other.poll_budget;
+        }
+        if other.mtu.is_some() {
+            self.mtu = other.mtu;
+        }
+        if other.coalescing.is_some() {
+            self.coalescing = other.coalescing;
+        }
+        if other.nagle.is_some() {
+            self.nagle = other.nagle;
+        }
+        if other.burst.is_some() {
+            self.burst = other.burst;
+        }
+        if other.item_ttl.is_some() {
+            self.item_ttl = other.item_ttl;
+        }
+        if other.initial_delay.is_some() {
+            self.initial_delay = other.initial_delay;
+        }
+        if other.reorder_window.is_some() {
+            self.reorder_window = other.reorder_window;
+        }
+        if other.reorder.is_some() {
+            self.reorder = other.reorder;
+        }
+        if other.decision_hooks.is_some() {
+            self.decision_hooks = other.decision_hooks;
+        }
+        if other.clock.is_some() {
+            self.clock = other.clock;
+        }
+        if other.seed.is_some() {
+            self.seed = other.seed;
+        }
+        if other.decisions.is_some() {
+            self.decisions = other.decisions;
+        }
+        if other.pcap.is_some() {
+            self.pcap = other.pcap;
+        }
+        if other.recorder.is_some() {
+            self.recorder = other.recorder;
+        }
+        if other.stats_interval.is_some() {
+            self.stats_interval = other.stats_interval;
+        }
+        if other.label.is_some() {
+            self.label = other.label;
+        }
+    }
+
+    /// Copy all configuration values, leaving out the settings updaters.
+    pub(crate) fn to_update(&self) -> ChokeSettings {
+        ChokeSettings {
+            settings_rx: None,
+            settings_watch: None,
+            settings_source: None,
+            latency: self.latency.clone(),
+            drop_probability: self.drop_probability,
+            corrupt_probability: self.corrupt_probability,
+            duplicate_probability: self.duplicate_probability,
+            bandwidth_limit: self.bandwidth_limit.clone(),
+            shared_bandwidth_limit: self.shared_bandwidth_limit.clone(),
+            ordering: self.ordering,
+            backpressure: self.backpressure,
+            poll_budget: self.poll_budget,
+            mtu: self.mtu,
+            coalescing: self.coalescing.clone(),
+            nagle: self.nagle.clone(),
+            burst: self.burst.clone(),
+            item_ttl: self.item_ttl,
+            initial_delay: self.initial_delay.clone(),
+            reorder_window: self.reorder_window.clone(),
+            reorder: self.reorder.clone(),
+            decision_hooks: self.decision_hooks.clone(),
+            clock: self.clock.clone(),
+            seed: self.seed,
+            decisions: self.decisions.clone(),
+            pcap: self.pcap.clone(),
+            recorder: self.recorder.clone(),
+            stats_interval: self.stats_interval,
+            label: self.label.clone(),
+        }
+    }
+
+    /// Returns `true` if no field is set, i.e. applying these settings would not change anything.
+    pub fn is_empty(&self) -> bool {
+        self.settings_rx.is_none()
+            && self.settings_watch.is_none()
+            && self.settings_source.is_none()
+            && self.latency.is_none()
+            && self.drop_probability.is_none()
+            && self.corrupt_probability.is_none()
+            && self.duplicate_probability.is_none()
+            && self.bandwidth_limit.is_none()
+            && self.shared_bandwidth_limit.is_none()
+            && self.ordering.is_none()
+            && self.backpressure.is_none()
+            && self.poll_budget.is_none()
+            && self.mtu.is_none()
+            && self.coalescing.is_none()
+            && self.nagle.is_none()
+            && self.burst.is_none()
+            && self.item_ttl.is_none()
+            && self.initial_delay.is_none()
+            && self.reorder_window.is_none()
+            && self.reorder.is_none()
+            && self.decision_hooks.is_none()
+            && self.clock.is_none()
+            && self.seed.is_none()
+            && self.decisions.is_none()
+            && self.pcap.is_none()
+            && self.recorder.is_none()
+            && self.stats_interval.is_none()
+            && self.label.is_none()
+    }
+
+    /// Compute the partial update that turns `base` into `self`: the result only contains the fields that are set in
+    /// `self` and differ from `base`. Latency distributions and clocks are compared by identity, so a distribution only
+    /// counts as unchanged if both settings share the same function. Settings updaters are never part of the diff.
+    pub fn diff(&self, base: &ChokeSettings) -> ChokeSettings {
+        fn changed<T: PartialEq + Clone>(value: &Option<T>, base: &Option<T>) -> Option<T> {
+            value.as_ref().filter(|value| base.as_ref() != Some(*value)).cloned()
+        }
+
+        ChokeSettings {
+            settings_rx: None,
+            settings_watch: None,
+            settings_source: None,
+            latency: changed(&self.latency, &base.latency),
+            drop_probability: changed(&self.drop_probability, &base.drop_probability),
+            corrupt_probability: changed(&self.corrupt_probability, &base.corrupt_probability),
+            duplicate_probability: changed(&self.duplicate_probability, &base.duplicate_probability),
+            bandwidth_limit: changed(&self.bandwidth_limit, &base.bandwidth_limit),
+            shared_bandwidth_limit: changed(&self.shared_bandwidth_limit, &base.shared_bandwidth_limit),
+            ordering: changed(&self.ordering, &base.ordering),
+            backpressure: changed(&self.backpressure, &base.backpressure),
+            poll_budget: changed(&self.poll_budget, &base.poll_budget),
+            mtu: changed(&self.mtu, &base.mtu),
+            coalescing: changed(&self.coalescing, &base.coalescing),
+            nagle: changed(&self.nagle, &base.nagle),
+            burst: changed(&self.burst, &base.burst),
+            item_ttl: changed(&self.item_ttl, &base.item_ttl),
+            initial_delay: changed(&self.initial_delay, &base.initial_delay),
+            reorder_window: changed(&self.reorder_window, &base.reorder_window),
+            reorder: changed(&self.reorder, &base.reorder),
+            decision_hooks: changed(&self.decision_hooks, &base.decision_hooks),
+            clock: changed(&self.clock, &base.clock),
+            seed: changed(&self.seed, &base.seed),
+            decisions: changed(&self.decisions, &base.decisions),
+            pcap: changed(&self.pcap, &base.pcap),
+            recorder: changed(&self.recorder, &base.recorder),
+            stats_interval: changed(&self.stats_interval, &base.stats_interval),
+            label: changed(&self.label, &base.label),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_overrides_set_fields_only() {
+        let mut settings = ChokeSettings::default()
+            .set_drop_probability(Some(0.1))
+            .set_ordering(Some(ChokeSettingsOrder::Unordered));
+        settings.merge(
+            ChokeSettings::default()
+                .set_drop_probability(Some(0.2))
+                .set_bandwidth_limit(Some(100)),
+        );
+
+        assert_eq!(settings.drop_probability, Some(0.2));
+        assert_eq!(settings.ordering, Some(ChokeSettingsOrder::Unordered));
+        assert!(matches!(settings.bandwidth_limit, Some(Some(_))));
+        assert_eq!(settings.corrupt_probability, None);
+    }
+
+    #[test]
+    fn reset_is_an_explicit_update() {
+        let mut settings = ChokeSettings::default()
+            .set_drop_probability(Some(0.1))
+            .set_bandwidth_limit(Some(100))
+            .set_latency_distribution(Some(|| None));
+        settings.merge(
+            ChokeSettings::default()
+                .reset(ChokeSettingsField::DropProbability)
+                .reset(ChokeSettingsField::BandwidthLimit)
+                .reset(ChokeSettingsField::LatencyDistribution)
+                .reset(ChokeSettingsField::Seed),
+        );
+
+        assert_eq!(settings.drop_probability, Some(0.0));
+        assert_eq!(settings.bandwidth_limit, Some(None));
+        assert_eq!(settings.latency, Some(Latency::None));
+        assert_eq!(settings.seed, Some(None));
+    }
+
+    #[test]
+    fn validate_rejects_invalid_settings() {
+        assert!(ChokeSettings::default().set_drop_probability(Some(0.5)).build().is_ok());
+        assert_eq!(
+            ChokeSettings::default().set_corrupt_probability(Some(1.5)).validate(),
+            Err(ChokeSettingsError::InvalidProbability {
+                field: ChokeSettingsField::CorruptProbability,
+                value: 1.5
+            })
+        );
+        assert!(ChokeSettings::default()
+            .set_duplicate_probability(Some(f64::NAN))
+            .validate()
+            .is_err());
+        assert_eq!(
+            ChokeSettings::default()
+                .set_bandwidth_limit_with(BandwidthLimit::builder().bytes_per_sec(0).drop_ratio(0.5))
+                .validate(),
+            Err(ChokeSettingsError::ZeroBandwidthWithDropRatio(0.5))
+        );
+        assert!(ChokeSettings::default().set_bandwidth_limit(Some(0)).validate().is_ok());
+        assert_eq!(
+            ChokeSettings::default().set_mtu(Some(0)).validate(),
+            Err(ChokeSettingsError::ZeroMtu)
+        );
+        assert_eq!(
+            ChokeSettings::default()
+                .set_coalescing(Some(Coalescing::builder().max_bytes(0).build()))
+                .validate(),
+            Err(ChokeSettingsError::ZeroCoalescingSize)
+        );
+        assert_eq!(
+            ChokeSettings::default()
+                .set_burst(Some(Burst::builder().build()))
+                .validate(),
+            Err(ChokeSettingsError::InvalidBurst)
+        );
+        assert!(ChokeSettings::default()
+            .set_burst(Some(Burst::builder().size(4).build()))
+            .validate()
+            .is_ok());
+        assert_eq!(
+            ChokeSettings::default()
+                .set_reorder_window(Some(ReorderWindow::builder().build()))
+                .validate(),
+            Err(ChokeSettingsError::EmptyReorderWindow)
+        );
+        assert_eq!(
+            ChokeSettings::default()
+                .set_reorder(Some(Reorder::builder().probability(0.5).gap(0).build()))
+                .validate(),
+            Err(ChokeSettingsError::ZeroReorderGap)
+        );
+        assert_eq!(
+            ChokeSettings::default().set_poll_budget(Some(0)).validate(),
+            Err(ChokeSettingsError::ZeroPollBudget)
+        );
+        let max = Duration::from_millis(100);
+        for latency in [
+            Latency::SkewNormal {
+                location: Duration::from_millis(10),
+                scale: Duration::from_millis(5),
+                shape: f64::NAN,
+                max,
+            },
+            Latency::Uniform {
+                min: Duration::from_millis(20),
+                max: Duration::from_millis(10),
+            },
+            Latency::LogNormal {
+                median: Duration::from_millis(10),
+                sigma: -1.0,
+                max,
+            },
+            Latency::Pareto {
+                scale: Duration::from_millis(10),
+                shape: 0.0,
+                max,
+            },
+        ] {
+            assert_eq!(
+                ChokeSettings::default().set_latency(Some(latency)).validate(),
+                Err(ChokeSettingsError::InvalidLatency)
+            );
+        }
+        let congested = |congestion| BandwidthLimit::builder().bytes_per_sec(1000).congestion(congestion);
+        assert!(ChokeSettings::default()
+            .set_bandwidth_limit_with(congested(Congestion::builder()))
+            .validate()
+            .is_ok());
+        for congestion in [
+            Congestion::builder().backoff(1.0),
+            Congestion::builder().min_ratio(0.0),
+            Congestion::builder().interval(Duration::ZERO),
+        ] {
+            assert_eq!(
+                ChokeSettings::default()
+                    .set_bandwidth_limit_with(congested(congestion))
+                    .validate(),
+                Err(ChokeSettingsError::InvalidCongestion)
+            );
+        }
+        assert_eq!(
+            ChokeSettings::default()
+                .set_bandwidth_limit_with(
+                    BandwidthLimit::builder()
+                        .bytes_per_sec(1000)
+                        .slow_start(SlowStart::builder().initial_ratio(0.0))
+                )
+                .validate(),
+            Err(ChokeSettingsError::InvalidSlowStart)
+        );
+        assert_eq!(
+            ChokeSettings::default()
+                .set_bandwidth_limit_with(
+                    BandwidthLimit::builder()
+                        .bytes_per_sec(1000)
+                        .cross_traffic(CrossTraffic::builder().packet_size(0))
+                )
+                .validate(),
+            Err(ChokeSettingsError::InvalidCrossTraffic)
+        );
+    }
+
+    #[test]
+    fn diff_contains_changed_fields() {
+        let base = ChokeSettings::default()
+            .set_drop_probability(Some(0.1))
+            .set_corrupt_probability(Some(0.1))
+            .set_bandwidth_limit(Some(100));
+        let target = ChokeSettings::default()
+            .set_drop_probability(Some(0.1))
+            .set_corrupt_probability(Some(0.2))
+            .set_bandwidth_limit(None)
+            .set_ordering(Some(ChokeSettingsOrder::Unordered));
+
+        let diff = target.diff(&base);
+        assert_eq!(diff.drop_probability, None);
+        assert_eq!(diff.corrupt_probability, Some(0.2));
+        assert_eq!(diff.bandwidth_limit, Some(None));
+        assert_eq!(diff.ordering, Some(ChokeSettingsOrder::Unordered));
+
+        let mut merged = base;
+        merged.merge(diff);
+        assert!(target.diff(&merged).is_empty());
+    }
+
+    #[test]
+    fn fingerprint_is_stable() {
+        // Must not change between releases, fingerprints of earlier runs are compared against it.
+        const FINGERPRINT: u64 = 0x2435_5fab_e421_9942;
+
+        let snapshot = ChokeSettingsSnapshot {
+            latency_distribution: true,
+            latency: None,
+            drop_probability: 0.1,
+            corrupt_probability: 0.0,
+            duplicate_probability: 0.0,
+            bandwidth_limit: Some(BandwidthLimit::builder().bytes_per_sec(1000).build()),
+            shared_bandwidth_limit: None,
+            ordering: ChokeSettingsOrder::Ordered,
+            backpressure: false,
+            poll_budget: DEFAULT_POLL_BUDGET,
+            mtu: None,
+            coalescing: None,
+            nagle: None,
+            burst: None,
+            item_ttl: None,
+            initial_delay: None,
+            reorder_window: None,
+            reorder: None,
+            decision_hooks: 0,
+            seed: 1,
+        };
+        assert_eq!(snapshot.fingerprint(), FINGERPRINT);
+
+        let reseeded = ChokeSettingsSnapshot {
+            seed: 2,
+            ..snapshot.clone()
+        };
+        assert_eq!(reseeded.fingerprint(), FINGERPRINT);
+
+        let changed = ChokeSettingsSnapshot {
+            drop_probability: 0.2,
+            ..snapshot.clone()
+        };
+        assert_ne!(changed.fingerprint(), FINGERPRINT);
+
+        let fragmented = ChokeSettingsSnapshot {
+            mtu: Some(1500),
+            ..snapshot.clone()
+        };
+        assert_ne!(fragmented.fingerprint(), FINGERPRINT);
+
+        let coalesced = ChokeSettingsSnapshot {
+            coalescing: Some(Coalescing::builder().build()),
+            ..snapshot
+        };
+        assert_ne!(coalesced.fingerprint(), FINGERPRINT);
+    }
+
+    #[test]
+    fn fingerprint_keeps_options_apart() {
+        let snapshot = ChokeSettingsSnapshot {
+            latency_distribution: false,
+            latency: None,
+            drop_probability: 0.0,
+            corrupt_probability: 0.0,
+            duplicate_probability: 0.0,
+            bandwidth_limit: None,
+            shared_bandwidth_limit: None,
+            ordering: ChokeSettingsOrder::Ordered,
+            backpressure: false,
+            poll_budget: DEFAULT_POLL_BUDGET,
+            mtu: None,
+            coalescing: None,
+            nagle: None,
+            burst: None,
+            item_ttl: None,
+            initial_delay: None,
+            reorder_window: None,
+            reorder: None,
+            decision_hooks: 0,
+            seed: 1,
+        };
+        let coalesced = ChokeSettingsSnapshot {
+            coalescing: Some(
+                Coalescing::builder()
+                    .max_bytes(1000)
+                    .max_delay(Duration::from_millis(10))
+                    .build(),
+            ),
+            ..snapshot.clone()
+        };
+        let held = ChokeSettingsSnapshot {
+            nagle: Some(
+                Nagle::builder()
+                    .min_bytes(1000)
+                    .max_delay(Duration::from_millis(10))
+                    .build(),
+            ),
+            ..snapshot.clone()
+        };
+        assert_ne!(coalesced.fingerprint(), held.fingerprint());
+
+        let fragmented = ChokeSettingsSnapshot {
+            mtu: Some(2),
+            ..snapshot.clone()
+        };
+        let hooked = ChokeSettingsSnapshot {
+            decision_hooks: 2,
+            ..snapshot
+        };
+        assert_ne!(fragmented.fingerprint(), hooked.fingerprint());
+    }
 }
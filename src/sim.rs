@@ -0,0 +1,174 @@
+//! Reproducible simulations: send items through a scenario on virtual time with seeded randomness and assert on the
+//! outcome.
+//!
+//! A [`Simulation`] bundles a [`VirtualClock`], so that delays take no real time, with a seed from which the random
+//! decisions of the streams (and, via [`Simulation::rng`], those of latency distributions) are derived. It drives
+//! streams until they have ended, advancing the time only once all of them are idle, and reports when each item was
+//! emitted along with the final [`ChokeStats`]. Running the same scenario with the same seed produces the same report.
+//!
+//! Example:
+//! ```rust
+//! # use chokepoint::{sim::Simulation, ChokeSettings};
+//! # use bytes::Bytes;
+//! # use rand::Rng as _;
+//! # use std::time::Duration;
+//! let mut sim = Simulation::new(42);
+//! let mut rng = sim.rng();
+//! let scenario = ChokeSettings::default()
+//!     .set_drop_probability(Some(0.05))
+//!     .set_latency_distribution(Some(move || Some(Duration::from_millis(rng.random_range(10..100)))));
+//!
+//! let report = sim.send((0..10_000).map(|_| Bytes::from_static(b"hello")), scenario);
+//! assert_eq!(report.stats.received, 10_000);
+//! assert_eq!(report.items.len(), 10_000 - report.stats.dropped);
+//! assert!(report.elapsed < Duration::from_millis(100));
+//! ```
+
+use crate::{
+    clock::VirtualClock,
+    ChokeItem,
+    ChokeSettings,
+    ChokeStats,
+    ChokeStream,
+};
+use futures::{
+    task::ArcWake,
+    Stream,
+    StreamExt as _,
+};
+use rand::{
+    rngs::StdRng,
+    Rng as _,
+    SeedableRng as _,
+};
+use std::{
+    future::Future,
+    pin::pin,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
+    task::{
+        Context,
+        Poll,
+    },
+    time::Duration,
+};
+
+/// A deterministic simulation, see the [module documentation](self).
+#[derive(Debug)]
+pub struct Simulation {
+    seed: u64,
+    rng: StdRng,
+    clock: VirtualClock,
+}
+
+/// The outcome of running a stream in a [`Simulation`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationReport<T> {
+    /// The emitted items, each with the simulated time at which it was emitted, relative to the start of the run.
+    pub items: Vec<(Duration, T)>,
+    /// The counters of the stream once it has ended.
+    pub stats: ChokeStats,
+    /// The simulated time until the stream has ended.
+    pub elapsed: Duration,
+}
+
+impl Simulation {
+    /// A simulation whose randomness is derived from `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            clock: VirtualClock::manual(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The clock of the simulation. Producers that wait for time to pass must use it as well.
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    /// A random number generator derived from the seed, e.g. for latency distribution functions. Each call returns a
+    /// different generator.
+    pub fn rng(&mut self) -> StdRng {
+        StdRng::seed_from_u64(self.rng.random())
+    }
+
+    /// Settings that use the clock of the simulation and a seed derived from the simulation's seed. Streams of the
+    /// simulation must be created with them, merged with the settings of the scenario.
+    pub fn settings(&mut self) -> ChokeSettings {
+        ChokeSettings::default()
+            .set_clock(self.clock.clone())
+            .set_seed(Some(self.rng.random()))
+    }
+
+    /// Send `items` through a [`ChokeStream`] configured by `scenario` and wait until every item has been emitted or
+    /// dropped. All items are available at the start of the run.
+    pub fn send<T>(&mut self, items: impl IntoIterator<Item = T>, scenario: ChokeSettings) -> SimulationReport<T>
+    where
+        T: ChokeItem,
+    {
+        let mut settings = self.settings();
+        settings.merge(scenario);
+        let items = futures::stream::iter(items.into_iter().collect::<Vec<_>>());
+        self.run(vec![ChokeStream::new(items, settings)]).remove(0)
+    }
+
+    /// Poll `streams` concurrently until all of them have ended, returning a report per stream. The streams must have
+    /// been created with [`Simulation::settings`].
+    ///
+    /// Panics if the streams stall, i.e. they are waiting for something other than the clock of the simulation.
+    pub fn run<T, S>(&self, streams: Vec<ChokeStream<T, S>>) -> Vec<SimulationReport<T>>
+    where
+        T: ChokeItem,
+        S: Stream<Item = T>,
+    {
+        let start = self.clock.elapsed();
+        let runs = streams.into_iter().map(|stream| async move {
+            let mut stream = pin!(stream);
+            let mut items = Vec::new();
+            while let Some(item) = stream.next().await {
+                items.push((self.clock.elapsed() - start, item));
+            }
+            SimulationReport {
+                items,
+                stats: stream.stats(),
+                elapsed: self.clock.elapsed() - start,
+            }
+        });
+        self.block_on(futures::future::join_all(runs))
+    }
+
+    /// Poll `future` to completion, advancing the clock to the next deadline whenever nothing else is left to do.
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let woken = Arc::new(WakeFlag(AtomicBool::new(false)));
+        let waker = futures::task::waker(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            if !woken.0.swap(false, Ordering::SeqCst) && !self.clock.advance_to_next_deadline() {
+                panic!("simulation stalled: the streams are neither ready nor waiting for the clock");
+            }
+        }
+    }
+}
+
+/// Records whether the future driven by [`Simulation::block_on`] has been woken up.
+struct WakeFlag(AtomicBool);
+
+impl ArcWake for WakeFlag {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.store(true, Ordering::SeqCst);
+    }
+}
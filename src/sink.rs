@@ -1,10 +1,14 @@
 use crate::{
+    event::ChokeEvents,
     item::ChokeItem,
     ChokeSettings,
-    ChokeSettingsOrder,
+    ChokeSettingsSnapshot,
+    ChokeStats,
     ChokeStream,
+    TimingStats,
 };
 use futures::{
+    channel::mpsc,
     Sink,
     SinkExt,
     StreamExt,
@@ -12,28 +16,65 @@ use futures::{
 use std::{
     pin::Pin,
     task::{
+        ready,
         Context,
         Poll,
     },
 };
-use tokio::sync::mpsc;
-use tokio_stream::wrappers::UnboundedReceiverStream;
 
 const VERBOSE: bool = false;
 
+/// Error returned by [`ChokeSink`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChokeSinkError<E> {
+    /// The inner sink returned an error.
+    Inner(E),
+    /// An item was sent after the sink has been closed.
+    Closed,
+}
+
+impl<E> ChokeSinkError<E> {
+    /// Returns the error of the inner sink, if any.
+    pub fn into_inner(self) -> Option<E> {
+        match self {
+            ChokeSinkError::Inner(err) => Some(err),
+            ChokeSinkError::Closed => None,
+        }
+    }
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for ChokeSinkError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChokeSinkError::Inner(err) => write!(f, "inner sink error: {err}"),
+            ChokeSinkError::Closed => write!(f, "sink is closed"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for ChokeSinkError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChokeSinkError::Inner(err) => Some(err),
+            ChokeSinkError::Closed => None,
+        }
+    }
+}
+
 /// A [`futures::Sink`] that uses an underlaying [`ChokeStream`] to control how items are forwarded to the inner sink.
+///
+/// The items are shaped as `T` and mapped by `F` right before they are forwarded, see [`ChokeSink::with_map`].
 #[allow(clippy::type_complexity)]
 #[pin_project]
-pub struct ChokeSink<Si, T>
-where
-    Si: Sink<T> + Unpin,
-{
+pub struct ChokeSink<Si, T, F = fn(T) -> T> {
     /// The inner sink that gets written to.
     sink: Si,
     /// The choke stream that controls how items are forwarded to the inner sink.
-    choke_stream: ChokeStream<T>,
-    sender: mpsc::UnboundedSender<T>,
-    backpressure: bool,
+    choke_stream: ChokeStream<T, mpsc::UnboundedReceiver<T>>,
+    /// Feeds the choke stream, `None` once the sink is closed.
+    sender: Option<mpsc::UnboundedSender<T>>,
+    /// Maps the shaped items to the items of the inner sink.
+    map: F,
 }
 
 impl<Si, T> ChokeSink<Si, T>
@@ -42,44 +83,167 @@ where
     T: ChokeItem,
 {
     pub fn new(sink: Si, settings: ChokeSettings) -> Self {
-        let (tx, rx) = mpsc::unbounded_channel();
-        let stream = Box::new(UnboundedReceiverStream::new(rx));
+        Self::with_map(sink, settings, std::convert::identity)
+    }
+}
+
+impl<Si, T, U, F> ChokeSink<Si, T, F>
+where
+    Si: Sink<U> + Unpin,
+    T: ChokeItem,
+    F: FnMut(T) -> U + Unpin,
+{
+    /// Shapes the items sent to the sink as `T` and forwards them to a sink of `U`s, mapped by `map`. Unlike mapping
+    /// with [`SinkExt::with`] in front of a [`ChokeSink`], items are only mapped once they leave the choke stream, so
+    /// dropped items are never mapped and backpressure applies to the shaped items.
+    ///
+    /// ```
+    /// # use chokepoint::{ChokeSettings, ChokeSink};
+    /// # use futures::SinkExt as _;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let (tx, rx) = futures::channel::mpsc::unbounded::<String>();
+    /// let mut sink = ChokeSink::with_map(tx, ChokeSettings::default(), |line: bytes::Bytes| {
+    ///     String::from_utf8_lossy(&line).into_owned()
+    /// });
+    /// sink.send(bytes::Bytes::from_static(b"hello")).await.unwrap();
+    /// # }
+    /// ```
+    pub fn with_map(sink: Si, settings: ChokeSettings, map: F) -> Self {
+        let (tx, rx) = mpsc::unbounded();
         Self {
             sink,
-            sender: tx,
-            backpressure: settings.ordering.unwrap_or_default() == ChokeSettingsOrder::Backpressure,
-            choke_stream: ChokeStream::new(stream, settings),
+            sender: Some(tx),
+            choke_stream: ChokeStream::new(rx, settings),
+            map,
         }
     }
 
+    /// Returns the settings that are currently in effect, see [`ChokeStream::current_settings`].
+    pub fn current_settings(&self) -> ChokeSettingsSnapshot {
+        self.choke_stream.current_settings()
+    }
+
+    /// Returns the counters of the items processed so far, see [`ChokeStream::stats`].
+    pub fn stats(&self) -> ChokeStats {
+        self.choke_stream.stats()
+    }
+
+    /// Returns the distributions of the delays of the items forwarded so far and of the gaps between them, see
+    /// [`ChokeStream::timing_stats`].
+    pub fn timing_stats(&self) -> &TimingStats {
+        self.choke_stream.timing_stats()
+    }
+
+    /// Forwards items to the inner sink until no items are queued or delayed, i.e. until every item sent so far has
+    /// been forwarded or dropped, and flushes the inner sink. Unlike [`SinkExt::close`], more items can be sent
+    /// afterwards.
+    pub async fn idle(&mut self) -> Result<(), ChokeSinkError<Si::Error>> {
+        futures::future::poll_fn(|cx| {
+            loop {
+                ready!(self.sink.poll_ready_unpin(cx)).map_err(ChokeSinkError::Inner)?;
+                match self.choke_stream.poll_next_unpin(cx) {
+                    Poll::Ready(Some(item)) => self
+                        .sink
+                        .start_send_unpin((self.map)(item))
+                        .map_err(ChokeSinkError::Inner)?,
+                    Poll::Ready(None) => break,
+                    // Waiting for a delayed item
+                    Poll::Pending if !self.choke_stream.is_idle() => return Poll::Pending,
+                    Poll::Pending => break,
+                }
+            }
+            self.sink.poll_flush_unpin(cx).map_err(ChokeSinkError::Inner)
+        })
+        .await
+    }
+
+    /// Returns a stream of the events of every item from now on, see [`ChokeStream::events`].
+    pub fn events(&mut self) -> ChokeEvents {
+        self.choke_stream.events()
+    }
+
+    /// Returns the bytes per second the bandwidth limit currently allows, see
+    /// [`ChokeStream::effective_bandwidth_limit`].
+    pub fn effective_bandwidth_limit(&self) -> Option<usize> {
+        self.choke_stream.effective_bandwidth_limit()
+    }
+
+    /// Start the slow start of the bandwidth limit over, see [`ChokeStream::restart_slow_start`].
+    pub fn restart_slow_start(&mut self) {
+        self.choke_stream.restart_slow_start();
+    }
+
+    /// Returns a reference to the inner sink.
+    pub fn get_ref(&self) -> &Si {
+        &self.sink
+    }
+
+    /// Returns a mutable reference to the inner sink. Writing to the inner sink directly bypasses the shaping.
+    pub fn get_mut(&mut self) -> &mut Si {
+        &mut self.sink
+    }
+
     pub fn into_inner(self) -> Si {
         self.sink
     }
+
+    /// Forward the next item that the choke stream has ready to the inner sink. Returns `Poll::Pending` if no item is
+    /// ready (yet) and `false` once the choke stream has ended, which only happens after the sink was closed.
+    fn poll_forward(&mut self, cx: &mut Context<'_>) -> Poll<Result<bool, ChokeSinkError<Si::Error>>> {
+        ready!(self.sink.poll_ready_unpin(cx)).map_err(ChokeSinkError::Inner)?;
+        match ready!(self.choke_stream.poll_next_unpin(cx)) {
+            Some(item) => Poll::Ready(
+                self.sink
+                    .start_send_unpin((self.map)(item))
+                    .map(|_| true)
+                    .map_err(ChokeSinkError::Inner),
+            ),
+            None => Poll::Ready(Ok(false)),
+        }
+    }
 }
 
-impl<Si, T> Sink<T> for ChokeSink<Si, T>
+impl<Si, T, U, F> Sink<T> for ChokeSink<Si, T, F>
 where
-    Si: Sink<T> + Unpin + 'static,
-    T: ChokeItem + Send + 'static,
+    Si: Sink<U> + Unpin,
+    T: ChokeItem,
+    F: FnMut(T) -> U + Unpin,
 {
-    type Error = Si::Error;
+    type Error = ChokeSinkError<Si::Error>;
 
     fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         if VERBOSE {
-            debug!(backpressure = %self.backpressure, pending = %self.choke_stream.pending(), "poll_ready");
+            debug!(backpressure = %self.choke_stream.backpressure(), pending = %self.choke_stream.pending(), "poll_ready");
+        }
+        if self.sender.is_none() {
+            return Poll::Ready(Err(ChokeSinkError::Closed));
         }
-        if self.backpressure && self.choke_stream.pending() {
-            return Poll::Pending;
+        if self.choke_stream.backpressure() {
+            // Drive the choke stream until the item in flight has been forwarded or dropped.
+            loop {
+                match self.poll_forward(cx) {
+                    Poll::Ready(Ok(true)) => continue,
+                    Poll::Ready(Ok(false)) => break,
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending if self.choke_stream.pending() => return Poll::Pending,
+                    Poll::Pending => break,
+                }
+            }
         }
-        self.sink.poll_ready_unpin(cx)
+        self.sink.poll_ready_unpin(cx).map_err(ChokeSinkError::Inner)
     }
 
     fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
         if VERBOSE {
             debug!(pending = %self.choke_stream.pending(), "start_send");
         }
-        self.sender.send(item).expect("the stream owns the receiver");
-        Ok(())
+        // Sending only fails if the choke stream, which owns the receiver, was dropped. This can't happen while the
+        // sink is alive.
+        self.sender
+            .as_ref()
+            .and_then(|sender| sender.unbounded_send(item).ok())
+            .ok_or(ChokeSinkError::Closed)
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
@@ -87,25 +251,14 @@ where
             debug!(pending = %self.choke_stream.pending(), "poll_flush");
         }
 
-        match self.choke_stream.poll_next_unpin(cx) {
-            Poll::Ready(Some(item)) => {
-                if VERBOSE {
-                    debug!(pending = %self.choke_stream.pending(), "poll_flush: got item");
-                }
-                if let Err(err) = self.sink.start_send_unpin(item) {
-                    return Poll::Ready(Err(err));
-                }
+        // Forward everything that is ready now, delayed items will be forwarded by a later flush.
+        while let Poll::Ready(true) = self.poll_forward(cx)? {
+            if VERBOSE {
+                debug!(pending = %self.choke_stream.pending(), "poll_flush: forwarded item");
             }
-            Poll::Pending => {
-                if self.choke_stream.has_dropped_item() {
-                    self.choke_stream.reset_dropped_item();
-                    return Poll::Ready(Ok(()));
-                }
-            }
-            _ => {}
         }
 
-        self.sink.poll_flush_unpin(cx)
+        self.sink.poll_flush_unpin(cx).map_err(ChokeSinkError::Inner)
     }
 
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
@@ -113,13 +266,11 @@ where
             debug!(pending = %self.choke_stream.pending(), "poll_close");
         }
 
-        if self.choke_stream.pending() {
-            if let Poll::Ready(Err(err)) = self.poll_flush(cx) {
-                return Poll::Ready(Err(err));
-            };
-            Poll::Pending
-        } else {
-            self.sink.poll_close_unpin(cx)
-        }
+        // Closing the channel ends the choke stream once every item has been emitted or dropped. Until then, the choke
+        // stream has a timer registered for the next delayed item.
+        self.sender.take();
+        while ready!(self.poll_forward(cx))? {}
+
+        self.sink.poll_close_unpin(cx).map_err(ChokeSinkError::Inner)
     }
 }
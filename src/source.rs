@@ -0,0 +1,568 @@
+//! Driving the settings of a [`crate::ChokeStream`] or [`crate::ChokeSink`] from any control plane.
+//!
+//! A [`SettingsSource`] produces partial settings updates, which are applied in the order in which they are produced,
+//! like the updates sent through a [`ChokeSettings::settings_updater`]. Attach one with
+//! [`ChokeSettings::set_settings_source`]. The stream is woken up when the source has a new update, so it is applied
+//! even while the stream is waiting for its inner stream or for a delayed item.
+//!
+//! The following sources are included:
+//! - [`mpsc::Receiver`], receiving updates from a channel
+//! - [`WatchSource`], applying the most recent value of a [`watch`] channel
+//! - [`FileSource`], reading the settings from a file whenever it changes
+//! - [`ScenarioSource`], applying settings at fixed times, e.g. to simulate a link that degrades and recovers
+//! - [`DriftSource`], letting the latency, drop probability or bandwidth drift randomly
+//!
+//! Example:
+//! ```rust
+//! # use chokepoint::{source::ScenarioSource, ChokeSettings, ChokeStream};
+//! # use bytes::Bytes;
+//! # use futures::StreamExt as _;
+//! # use std::time::Duration;
+//! # #[tokio::main(flavor = "current_thread", start_paused = true)]
+//! # async fn main() {
+//! // Lossy between 1s and 3s
+//! let scenario = ScenarioSource::new([
+//!     (Duration::from_secs(1), ChokeSettings::default().set_drop_probability(Some(0.5))),
+//!     (Duration::from_secs(3), ChokeSettings::default().set_drop_probability(Some(0.0))),
+//! ]);
+//! let items = futures::stream::iter(0..40).then(|_| async {
+//!     tokio::time::sleep(Duration::from_millis(100)).await;
+//!     Bytes::from_static(b"hello")
+//! });
+//! let settings = ChokeSettings::default().set_settings_source(scenario);
+//! let output = ChokeStream::new(Box::pin(items), settings).collect::<Vec<_>>().await;
+//! assert!(output.len() < 40);
+//! # }
+//! ```
+
+use crate::{
+    clock::{
+        Clock,
+        SharedClock,
+    },
+    latency::MaybeSend,
+    time::{
+        Instant,
+        Timer,
+    },
+    BandwidthLimit,
+    ChokeSettings,
+    Latency,
+};
+use rand::{
+    rngs::StdRng,
+    Rng as _,
+    SeedableRng as _,
+};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    ops::RangeInclusive,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        Mutex,
+        PoisonError,
+    },
+    task::{
+        Context,
+        Poll,
+    },
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+use tokio::sync::{
+    mpsc,
+    watch,
+};
+
+/// Produces settings updates for a stream, see the [module documentation](self).
+pub trait SettingsSource: MaybeSend + 'static {
+    /// Poll for the next settings update. Returns `Poll::Ready(None)` once the source will not produce any more
+    /// updates, the stream keeps its current settings then. Like [`futures::Stream::poll_next`], the task must be woken
+    /// up once an update is available after returning `Poll::Pending`.
+    fn poll_next_settings(&mut self, cx: &mut Context<'_>) -> Poll<Option<ChokeSettings>>;
+
+    /// Wait for the next settings update, `None` once the source will not produce any more updates.
+    fn next_settings(&mut self) -> impl Future<Output = Option<ChokeSettings>> + '_
+    where
+        Self: Sized,
+    {
+        futures::future::poll_fn(move |cx| self.poll_next_settings(cx))
+    }
+}
+
+impl SettingsSource for mpsc::Receiver<ChokeSettings> {
+    fn poll_next_settings(&mut self, cx: &mut Context<'_>) -> Poll<Option<ChokeSettings>> {
+        self.poll_recv(cx)
+    }
+}
+
+impl SettingsSource for mpsc::UnboundedReceiver<ChokeSettings> {
+    fn poll_next_settings(&mut self, cx: &mut Context<'_>) -> Poll<Option<ChokeSettings>> {
+        self.poll_recv(cx)
+    }
+}
+
+type Changed = (Result<(), watch::error::RecvError>, watch::Receiver<ChokeSettings>);
+
+#[cfg(not(chokepoint_local))]
+type ChangedFuture = Pin<Box<dyn Future<Output = Changed> + Send>>;
+
+#[cfg(chokepoint_local)]
+type ChangedFuture = Pin<Box<dyn Future<Output = Changed>>>;
+
+/// Applies the most recent value of a [`watch`] channel, skipping intermediate values. Unlike
+/// [`ChokeSettings::settings_watcher`], the value the channel was created with is applied as well.
+pub struct WatchSource {
+    changed: ChangedFuture,
+}
+
+impl WatchSource {
+    pub fn new(mut rx: watch::Receiver<ChokeSettings>) -> Self {
+        rx.mark_changed();
+        Self {
+            changed: Self::changed(rx),
+        }
+    }
+
+    /// Like [`WatchSource::new`] but skips the value the channel holds, see [`ChokeSettings::settings_watcher`].
+    pub(crate) fn changes(rx: watch::Receiver<ChokeSettings>) -> Self {
+        Self {
+            changed: Self::changed(rx),
+        }
+    }
+
+    fn changed(mut rx: watch::Receiver<ChokeSettings>) -> ChangedFuture {
+        Box::pin(async move {
+            let result = rx.changed().await;
+            (result, rx)
+        })
+    }
+}
+
+impl From<watch::Receiver<ChokeSettings>> for WatchSource {
+    fn from(rx: watch::Receiver<ChokeSettings>) -> Self {
+        Self::new(rx)
+    }
+}
+
+impl SettingsSource for WatchSource {
+    fn poll_next_settings(&mut self, cx: &mut Context<'_>) -> Poll<Option<ChokeSettings>> {
+        let (result, mut rx) = std::task::ready!(self.changed.as_mut().poll(cx));
+        if result.is_err() {
+            // Keep returning `None`, the sender was dropped
+            self.changed = Self::changed(rx);
+            return Poll::Ready(None);
+        }
+        let settings = rx.borrow_and_update().to_update();
+        self.changed = Self::changed(rx);
+        Poll::Ready(Some(settings))
+    }
+}
+
+impl std::fmt::Debug for WatchSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WatchSource").finish_non_exhaustive()
+    }
+}
+
+/// Reads the settings from a file whenever its modification time changes, e.g. to tune a running test by editing a
+/// file. The contents are parsed by a function, so any format can be used, e.g. JSON with the `serde` feature.
+///
+/// The file is checked once when the source is first polled and then after every interval. A file that is missing or
+/// can't be read or parsed is skipped with a warning until it changes again. The file is checked and read with
+/// blocking calls while the stream is polled, so it should be a small local file.
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{source::FileSource, ChokeSettings};
+/// # use std::time::Duration;
+/// let source = FileSource::new("drop-probability.txt", |contents: &str| {
+///     let probability = contents.trim().parse::<f64>()?;
+///     Ok::<_, std::num::ParseFloatError>(ChokeSettings::default().set_drop_probability(Some(probability)))
+/// })
+/// .interval(Duration::from_millis(100));
+/// let settings = ChokeSettings::default().set_settings_source(source);
+/// ```
+pub struct FileSource<F> {
+    path: PathBuf,
+    parse: F,
+    interval: Duration,
+    clock: SharedClock,
+    timer: Timer,
+    /// When the file is checked next, `None` until the source is first polled.
+    next_check: Option<Instant>,
+    /// The modification time of the file when it was last read.
+    modified: Option<SystemTime>,
+}
+
+impl<F> FileSource<F> {
+    /// Read the settings from the file at `path` with `parse`.
+    pub fn new(path: impl Into<PathBuf>, parse: F) -> Self {
+        Self {
+            path: path.into(),
+            parse,
+            interval: Duration::from_secs(1),
+            clock: SharedClock::system(),
+            timer: Timer::new(),
+            next_check: None,
+            modified: None,
+        }
+    }
+
+    /// How often the modification time of the file is checked. Defaults to one second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the interval is zero.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "file check interval must not be zero");
+        self.interval = interval;
+        self
+    }
+
+    /// Set the [`Clock`] used to wait for the next check. Defaults to the [`crate::clock::SystemClock`].
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+
+    /// Read the file if it changed since it was last read.
+    fn read_if_changed<E>(&mut self) -> Option<ChokeSettings>
+    where
+        F: FnMut(&str) -> Result<ChokeSettings, E>,
+        E: std::fmt::Display,
+    {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()?;
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+        let path = self.path.display();
+        let contents = std::fs::read_to_string(&self.path)
+            .inspect_err(|err| warn!(%path, %err, "failed to read settings file"))
+            .ok()?;
+        (self.parse)(&contents)
+            .inspect_err(|err| warn!(%path, %err, "failed to parse settings file"))
+            .ok()
+    }
+}
+
+impl<F, E> SettingsSource for FileSource<F>
+where
+    F: FnMut(&str) -> Result<ChokeSettings, E> + MaybeSend + 'static,
+    E: std::fmt::Display,
+{
+    fn poll_next_settings(&mut self, cx: &mut Context<'_>) -> Poll<Option<ChokeSettings>> {
+        let now = self.clock.now();
+        let mut next_check = *self.next_check.get_or_insert(now);
+        if next_check <= now {
+            next_check = now + self.interval;
+            self.next_check = Some(next_check);
+            if let Some(settings) = self.read_if_changed() {
+                return Poll::Ready(Some(settings));
+            }
+        }
+        self.timer.wake_at(&self.clock, next_check, cx);
+        Poll::Pending
+    }
+}
+
+impl<F> std::fmt::Debug for FileSource<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileSource")
+            .field("path", &self.path)
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Applies settings at fixed times since it was first polled, i.e. since the stream was first polled.
+pub struct ScenarioSource {
+    /// The remaining steps, in order.
+    steps: VecDeque<(Duration, ChokeSettings)>,
+    clock: SharedClock,
+    timer: Timer,
+    start: Option<Instant>,
+}
+
+impl ScenarioSource {
+    /// Apply the settings of every step once its time has passed. Steps are applied in the order of their times, steps
+    /// with the same time in the given order.
+    pub fn new(steps: impl IntoIterator<Item = (Duration, ChokeSettings)>) -> Self {
+        let mut steps = steps.into_iter().collect::<Vec<_>>();
+        steps.sort_by_key(|(at, _)| *at);
+        Self {
+            steps: steps.into(),
+            clock: SharedClock::system(),
+            timer: Timer::new(),
+            start: None,
+        }
+    }
+
+    /// Set the [`Clock`] the times are measured with. Should be the clock of the stream. Defaults to the
+    /// [`crate::clock::SystemClock`].
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+}
+
+impl SettingsSource for ScenarioSource {
+    fn poll_next_settings(&mut self, cx: &mut Context<'_>) -> Poll<Option<ChokeSettings>> {
+        let now = self.clock.now();
+        let start = *self.start.get_or_insert(now);
+        let Some((at, _)) = self.steps.front() else {
+            return Poll::Ready(None);
+        };
+        let due = start + *at;
+        if due > now {
+            self.timer.wake_at(&self.clock, due, cx);
+            return Poll::Pending;
+        }
+        Poll::Ready(self.steps.pop_front().map(|(_, settings)| settings))
+    }
+}
+
+impl std::fmt::Debug for ScenarioSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScenarioSource")
+            .field("steps", &self.steps)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Lets selected parameters drift in a bounded random walk, creating organic-looking network variation for long soak
+/// tests without writing a [`ScenarioSource`] by hand.
+///
+/// The walks start in the middle of their ranges, which is applied when the source is first polled. Every interval,
+/// each parameter moves by a random amount of at most its step in either direction, clamped to its range.
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{source::DriftSource, ChokeSettings};
+/// # use std::time::Duration;
+/// let drift = DriftSource::new(Duration::from_secs(1))
+///     .latency(Duration::from_millis(20)..=Duration::from_millis(200), Duration::from_millis(10))
+///     .drop_probability(0.0..=0.05, 0.005)
+///     .bandwidth(100_000..=1_000_000, 50_000)
+///     .seed(7);
+/// let settings = ChokeSettings::default().set_settings_source(drift);
+/// ```
+pub struct DriftSource {
+    interval: Duration,
+    /// The mean latency in seconds.
+    latency: Option<Walk>,
+    /// The standard deviation of the latency around the mean, a constant latency if `None`.
+    jitter: Option<Duration>,
+    drop_probability: Option<Walk>,
+    bandwidth: Option<Walk>,
+    /// The limit whose bytes per second drift.
+    bandwidth_limit: BandwidthLimit,
+    rng: StdRng,
+    clock: SharedClock,
+    timer: Timer,
+    /// When the parameters move next, `None` until the source is first polled.
+    next_step: Option<Instant>,
+}
+
+impl DriftSource {
+    /// Move the selected parameters every `interval`. No parameters are selected by default.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            latency: None,
+            jitter: None,
+            drop_probability: None,
+            bandwidth: None,
+            bandwidth_limit: BandwidthLimit::builder().build(),
+            rng: StdRng::seed_from_u64(rand::rng().random()),
+            clock: SharedClock::system(),
+            timer: Timer::new(),
+            next_step: None,
+        }
+    }
+
+    /// Let the mean latency drift within `range` by at most `step` per interval. The latency is constant unless a
+    /// [`DriftSource::jitter`] is set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is empty.
+    pub fn latency(mut self, range: RangeInclusive<Duration>, step: Duration) -> Self {
+        let (min, max) = range.into_inner();
+        self.latency = Some(Walk::new(min.as_secs_f64()..=max.as_secs_f64(), step.as_secs_f64()));
+        self
+    }
+
+    /// Spread the latency in a normal distribution with this standard deviation around the drifting mean, capped at
+    /// four standard deviations above it.
+    pub fn jitter(mut self, std_dev: Duration) -> Self {
+        self.jitter = Some(std_dev);
+        self
+    }
+
+    /// Let the drop probability drift within `range` by at most `step` per interval.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is empty or not within `0.0..=1.0`.
+    pub fn drop_probability(mut self, range: RangeInclusive<f64>, step: f64) -> Self {
+        assert!(
+            *range.start() >= 0.0 && *range.end() <= 1.0,
+            "drop probability range must be within 0.0..=1.0"
+        );
+        self.drop_probability = Some(Walk::new(range, step));
+        self
+    }
+
+    /// Let a bandwidth limit drift within `range` bytes per second by at most `step` per interval. Changing the bytes
+    /// per second keeps the bytes emitted so far.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is empty.
+    pub fn bandwidth(self, range: RangeInclusive<usize>, step: usize) -> Self {
+        self.bandwidth_with(BandwidthLimit::builder(), range, step)
+    }
+
+    /// Like [`DriftSource::bandwidth`], for a limit with further options, e.g. pacing. The bytes per second of `limit`
+    /// are replaced.
+    pub fn bandwidth_with(
+        mut self,
+        limit: impl Into<BandwidthLimit>,
+        range: RangeInclusive<usize>,
+        step: usize,
+    ) -> Self {
+        let (min, max) = range.into_inner();
+        self.bandwidth = Some(Walk::new(min as f64..=max as f64, step as f64));
+        self.bandwidth_limit = limit.into();
+        self
+    }
+
+    /// The seed of the random walks, to reproduce them. Defaults to a random seed.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Set the [`Clock`] the interval is measured with. Should be the clock of the stream. Defaults to the
+    /// [`crate::clock::SystemClock`].
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+
+    /// The settings for the current position of the walks.
+    fn settings(&self) -> ChokeSettings {
+        let mut settings = ChokeSettings::default();
+        if let Some(latency) = &self.latency {
+            let mean = Duration::from_secs_f64(latency.value);
+            settings = settings.set_latency(Some(match self.jitter {
+                Some(std_dev) => Latency::Normal {
+                    mean,
+                    std_dev,
+                    max: mean + std_dev * 4,
+                },
+                None => Latency::Constant(mean),
+            }));
+        }
+        if let Some(drop_probability) = &self.drop_probability {
+            settings = settings.set_drop_probability(Some(drop_probability.value));
+        }
+        if let Some(bandwidth) = &self.bandwidth {
+            settings = settings.set_bandwidth_limit_with(BandwidthLimit {
+                bytes_per_second: bandwidth.value.round() as usize,
+                ..self.bandwidth_limit.clone()
+            });
+        }
+        settings
+    }
+}
+
+impl SettingsSource for DriftSource {
+    fn poll_next_settings(&mut self, cx: &mut Context<'_>) -> Poll<Option<ChokeSettings>> {
+        if self.latency.is_none() && self.drop_probability.is_none() && self.bandwidth.is_none() {
+            return Poll::Ready(None);
+        }
+        let now = self.clock.now();
+        match self.next_step {
+            Some(next_step) if next_step > now => {
+                self.timer.wake_at(&self.clock, next_step, cx);
+                return Poll::Pending;
+            }
+            Some(next_step) => {
+                let walks = [&mut self.latency, &mut self.drop_probability, &mut self.bandwidth];
+                for walk in walks.into_iter().flatten() {
+                    walk.step(&mut self.rng);
+                }
+                self.next_step = Some(next_step + self.interval);
+            }
+            None => self.next_step = Some(now + self.interval),
+        }
+        Poll::Ready(Some(self.settings()))
+    }
+}
+
+impl std::fmt::Debug for DriftSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DriftSource")
+            .field("interval", &self.interval)
+            .field("latency", &self.latency)
+            .field("jitter", &self.jitter)
+            .field("drop_probability", &self.drop_probability)
+            .field("bandwidth", &self.bandwidth)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A bounded random walk of a single parameter.
+#[derive(Debug, Clone)]
+struct Walk {
+    value: f64,
+    min: f64,
+    max: f64,
+    step: f64,
+}
+
+impl Walk {
+    fn new(range: RangeInclusive<f64>, step: f64) -> Self {
+        let (min, max) = range.into_inner();
+        assert!(min <= max, "drift range must not be empty");
+        Self {
+            value: (min + max) / 2.0,
+            min,
+            max,
+            step: step.abs(),
+        }
+    }
+
+    fn step(&mut self, rng: &mut StdRng) {
+        self.value = (self.value + rng.random_range(-self.step..=self.step)).clamp(self.min, self.max);
+    }
+}
+
+/// A settings source attached to settings. Sources are only used by a single stream, the mutex makes the settings
+/// `Sync` anyway, so that they can be sent through a [`watch`] channel.
+pub(crate) struct BoxedSource(Mutex<Box<dyn SettingsSource>>);
+
+impl BoxedSource {
+    pub(crate) fn new(source: impl SettingsSource) -> Self {
+        Self(Mutex::new(Box::new(source)))
+    }
+
+    pub(crate) fn into_inner(self) -> Box<dyn SettingsSource> {
+        self.0.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl std::fmt::Debug for BoxedSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("dyn SettingsSource")
+    }
+}
@@ -0,0 +1,295 @@
+use crate::event::DropReason;
+use std::time::Duration;
+
+/// Counters of a [`crate::ChokeStream`] or [`crate::ChokeSink`] since it was created and the occupancy of its queues,
+/// see [`crate::ChokeStream::stats`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChokeStats {
+    /// The label of the shaper, see [`crate::ChokeSettings::set_label`].
+    pub label: Option<String>,
+    /// Items taken from the inner stream, counting each fragment of a split item, see
+    /// [`crate::ChokeSettings::set_mtu`].
+    pub received: usize,
+    /// Items emitted, including duplicates.
+    pub emitted: usize,
+    /// Items dropped, randomly, by the bandwidth limit, a hook, a replayed decision or because the ordering changed
+    /// while they were queued, see `dropped_by`.
+    pub dropped: usize,
+    /// The dropped and expired items by the reason they were discarded for.
+    pub dropped_by: DropCounts,
+    /// Items discarded because they waited longer than their time to live, see
+    /// [`crate::ChokeSettings::set_item_ttl`].
+    pub expired: usize,
+    /// Items that were corrupted.
+    pub corrupted: usize,
+    /// Duplicates that were added.
+    pub duplicated: usize,
+    /// Items that were merged into a preceding item, see [`crate::ChokeSettings::set_coalescing`].
+    pub coalesced: usize,
+    /// Items marked with Congestion Experienced instead of being dropped, see [`crate::BandwidthLimitBuilder::ecn`].
+    pub marked: usize,
+    /// Bytes emitted, including duplicates and corrupted items, i.e. the throughput.
+    pub emitted_bytes: usize,
+    /// Bytes emitted excluding duplicates and corrupted items, i.e. the goodput.
+    pub goodput_bytes: usize,
+    /// Bytes of the corrupted items that were emitted, including their duplicates.
+    pub corrupted_bytes: usize,
+    /// Items taken from the inner stream that are neither emitted nor discarded yet, including items that are delayed,
+    /// held back or waiting for the bandwidth limit, and duplicates.
+    pub queued: usize,
+    /// The size of the `queued` items in bytes.
+    pub queued_bytes: usize,
+    /// The `queued` items that wait for their latency to pass.
+    pub delayed: usize,
+    /// The most items that were queued at once.
+    pub max_queued: usize,
+    /// The most bytes that were queued at once.
+    pub max_queued_bytes: usize,
+    /// The most items that were delayed at once.
+    pub max_delayed: usize,
+    /// The seed of the random number generator, see [`crate::ChokeSettings::set_seed`].
+    pub seed: u64,
+    /// The fingerprint of the settings, see [`crate::ChokeSettingsSnapshot::fingerprint`].
+    pub settings_fingerprint: u64,
+}
+
+impl std::fmt::Display for ChokeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(label) = &self.label {
+            write!(f, "label={label} ")?;
+        }
+        write!(
+            f,
+            "received={} emitted={} dropped={} expired={} corrupted={} duplicated={} coalesced={} marked={} emitted_bytes={} goodput_bytes={} corrupted_bytes={} queued={} queued_bytes={} delayed={} max_queued={} max_queued_bytes={} max_delayed={} seed={} settings={:016x}",
+            self.received,
+            self.emitted,
+            self.dropped,
+            self.expired,
+            self.corrupted,
+            self.duplicated,
+            self.coalesced,
+            self.marked,
+            self.emitted_bytes,
+            self.goodput_bytes,
+            self.corrupted_bytes,
+            self.queued,
+            self.queued_bytes,
+            self.delayed,
+            self.max_queued,
+            self.max_queued_bytes,
+            self.max_delayed,
+            self.seed,
+            self.settings_fingerprint
+        )
+    }
+}
+
+/// The number of items discarded for every [`DropReason`], see [`ChokeStats::dropped_by`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DropCounts {
+    pub random: usize,
+    pub bandwidth_limit: usize,
+    pub hook: usize,
+    pub replay: usize,
+    /// Same as [`ChokeStats::expired`], these items are not counted in [`ChokeStats::dropped`].
+    pub expired: usize,
+    /// Items that were queued when the ordering changed, see [`DropReason::Reconfigured`].
+    pub reconfigured: usize,
+}
+
+impl DropCounts {
+    /// The number of items discarded for `reason`.
+    pub fn get(&self, reason: DropReason) -> usize {
+        match reason {
+            DropReason::Random => self.random,
+            DropReason::BandwidthLimit => self.bandwidth_limit,
+            DropReason::Hook => self.hook,
+            DropReason::Replay => self.replay,
+            DropReason::Expired => self.expired,
+            DropReason::Reconfigured => self.reconfigured,
+        }
+    }
+
+    pub(crate) fn count(&mut self, reason: DropReason) {
+        let counter = match reason {
+            DropReason::Random => &mut self.random,
+            DropReason::BandwidthLimit => &mut self.bandwidth_limit,
+            DropReason::Hook => &mut self.hook,
+            DropReason::Replay => &mut self.replay,
+            DropReason::Expired => &mut self.expired,
+            DropReason::Reconfigured => &mut self.reconfigured,
+        };
+        *counter += 1;
+    }
+}
+
+/// The distributions of the delays of the items of a [`crate::ChokeStream`] or [`crate::ChokeSink`] and of the gaps
+/// between them since it was created, see [`crate::ChokeStream::timing_stats`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimingStats {
+    /// The latency added to the items, zero for items without latency.
+    pub delay: DurationHistogram,
+    /// The time from taking the items from the inner stream until emitting them, which includes the latency as well as
+    /// the time the items waited for the bandwidth limit, ordering, bursts and so on.
+    pub sojourn: DurationHistogram,
+    /// The part of the sojourn time beyond the latency, i.e. how much longer the items took than configured because
+    /// queues built up.
+    pub waiting: DurationHistogram,
+    /// The gaps between the items taken from the inner stream, before they are split into fragments.
+    pub inter_arrival: DurationHistogram,
+    /// The gaps between the emitted items. Compared to `inter_arrival`, this shows the burstiness the shaping added or
+    /// removed, e.g. the bursts of [`crate::ChokeSettings::set_burst`] or the pacing of a bandwidth limit.
+    pub inter_departure: DurationHistogram,
+}
+
+/// Sub-buckets per power of two, the durations are rounded up by less than 1/64th.
+const SUB_BUCKETS: u64 = 64;
+
+/// A histogram of durations with a resolution of a microsecond and a relative error below 2%, e.g. to assert that 95%
+/// of the items are delayed by less than 200ms:
+///
+/// ```
+/// # use chokepoint::DurationHistogram;
+/// # use std::time::Duration;
+/// let mut histogram = DurationHistogram::default();
+/// for millis in 1..=100 {
+///     histogram.record(Duration::from_millis(millis));
+/// }
+/// assert!(histogram.p95().unwrap() < Duration::from_millis(200));
+/// assert_eq!(histogram.max(), Some(Duration::from_millis(100)));
+/// ```
+#[derive(Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DurationHistogram {
+    /// The number of durations in every bucket, up to the bucket of the longest duration.
+    buckets: Vec<usize>,
+    count: usize,
+    max: Duration,
+}
+
+impl DurationHistogram {
+    pub fn record(&mut self, duration: Duration) {
+        let index = bucket(duration.as_micros().try_into().unwrap_or(u64::MAX));
+        if self.buckets.len() <= index {
+            self.buckets.resize(index + 1, 0);
+        }
+        self.buckets[index] += 1;
+        self.count += 1;
+        self.max = self.max.max(duration);
+    }
+
+    /// The number of recorded durations.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The duration that `percentile` percent of the recorded durations don't exceed, rounded up to the bucket it falls
+    /// into. `None` if nothing was recorded.
+    ///
+    /// Panics if `percentile` is not between 0 and 100.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        assert!((0.0..=100.0).contains(&percentile), "invalid percentile {percentile}");
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((percentile / 100.0 * self.count as f64).ceil() as usize).max(1);
+        let mut seen = 0;
+        let index = self
+            .buckets
+            .iter()
+            .position(|count| {
+                seen += count;
+                seen >= rank
+            })
+            .unwrap_or(self.buckets.len() - 1);
+        Some(Duration::from_micros(bucket_max(index)).min(self.max))
+    }
+
+    pub fn p50(&self) -> Option<Duration> {
+        self.percentile(50.0)
+    }
+
+    pub fn p90(&self) -> Option<Duration> {
+        self.percentile(90.0)
+    }
+
+    pub fn p95(&self) -> Option<Duration> {
+        self.percentile(95.0)
+    }
+
+    pub fn p99(&self) -> Option<Duration> {
+        self.percentile(99.0)
+    }
+
+    /// The longest recorded duration, exactly.
+    pub fn max(&self) -> Option<Duration> {
+        (self.count > 0).then_some(self.max)
+    }
+}
+
+impl std::fmt::Debug for DurationHistogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DurationHistogram")
+            .field("count", &self.count)
+            .field("p50", &self.p50())
+            .field("p90", &self.p90())
+            .field("p99", &self.p99())
+            .field("max", &self.max())
+            .finish()
+    }
+}
+
+/// The bucket of a duration in microseconds: durations below `2 * SUB_BUCKETS` have a bucket of their own, every
+/// following power of two is divided into `SUB_BUCKETS` buckets.
+fn bucket(micros: u64) -> usize {
+    if micros < 2 * SUB_BUCKETS {
+        return micros as usize;
+    }
+    let shift = u64::from(63 - micros.leading_zeros()) - SUB_BUCKETS.trailing_zeros() as u64;
+    (shift * SUB_BUCKETS + (micros >> shift)) as usize
+}
+
+/// The longest duration in microseconds that falls into the bucket.
+fn bucket_max(index: usize) -> u64 {
+    let index = index as u64;
+    if index < 2 * SUB_BUCKETS {
+        return index;
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let mantissa = index - shift * SUB_BUCKETS;
+    // The last bucket ends at `u64::MAX`
+    u64::try_from(((u128::from(mantissa) + 1) << shift) - 1).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_are_contiguous() {
+        for micros in (0..100_000).chain([u64::MAX / 3, u64::MAX - 1, u64::MAX]) {
+            let index = bucket(micros);
+            assert!(bucket_max(index) >= micros, "{micros}");
+            assert!(index == 0 || bucket_max(index - 1) < micros, "{micros}");
+            assert!(bucket_max(index) - micros <= micros / SUB_BUCKETS, "{micros}");
+        }
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut histogram = DurationHistogram::default();
+        assert_eq!((histogram.p50(), histogram.max()), (None, None));
+        for micros in 1..=1000 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        assert_eq!(histogram.count(), 1000);
+        assert_eq!(histogram.percentile(0.0), Some(Duration::from_micros(1)));
+        assert_eq!(histogram.p50(), Some(Duration::from_micros(503)));
+        assert_eq!(histogram.p99(), Some(Duration::from_micros(991)));
+        assert_eq!(histogram.max(), Some(Duration::from_micros(1000)));
+        assert_eq!(histogram.percentile(100.0), histogram.max());
+    }
+}
@@ -0,0 +1,368 @@
+//! [proptest](https://docs.rs/proptest) strategies for valid settings and scenarios, enabled with the `proptest`
+//! feature, e.g. to check that a protocol never deadlocks under any shaping configuration.
+//!
+//! [`settings`] generates [`ChokeSettings`] that pass [`ChokeSettings::validate`], with every field either unset or set
+//! to a random value, and [`scenario`] a [`ScenarioSource`] that applies such settings at random times.
+//! [`ChokeSettings`], [`Latency`], [`BandwidthLimit`] and [`ChokeSettingsOrder`] implement [`Arbitrary`], so they can
+//! be used with `any`.
+//!
+//! The generated values are bounded to keep simulations short: latencies and delays up to [`MAX_DELAY`], bandwidth
+//! limits of at least [`MIN_BANDWIDTH`] bytes per second and MTUs of at least 64 bytes. Probabilities cover the whole
+//! range, so every item might be dropped. Settings always have a seed, so that a failing case is reproducible.
+//!
+//! Example:
+//! ```rust
+//! # use chokepoint::{strategy, ChokeSettings, ChokeStream};
+//! # use bytes::Bytes;
+//! # use futures::StreamExt as _;
+//! # use proptest::prelude::*;
+//! proptest!(|(settings in any::<ChokeSettings>(), steps in strategy::scenario())| {
+//!     let runtime = tokio::runtime::Builder::new_current_thread().enable_time().start_paused(true).build().unwrap();
+//!     let items = futures::stream::iter((0..10).map(|_| Bytes::from_static(b"hello")));
+//!     let settings = settings.set_settings_source(steps);
+//!     let output = runtime.block_on(ChokeStream::new(items, settings).collect::<Vec<_>>());
+//!     // At most every item and a duplicate of it
+//!     prop_assert!(output.len() <= 20);
+//! });
+//! ```
+
+use crate::{
+    source::ScenarioSource,
+    BandwidthLimit,
+    Burst,
+    ChokeSettings,
+    ChokeSettingsOrder,
+    Coalescing,
+    Congestion,
+    CrossTraffic,
+    CrossTrafficPattern,
+    Latency,
+    Nagle,
+    Reorder,
+    ReorderWindow,
+    SlowStart,
+};
+use proptest::{
+    arbitrary::Arbitrary,
+    option,
+    prelude::*,
+    strategy::BoxedStrategy,
+};
+use std::time::Duration;
+
+/// The maximum latency, delay or interval of generated settings.
+pub const MAX_DELAY: Duration = Duration::from_millis(500);
+
+/// The minimum bandwidth limit of generated settings in bytes per second.
+pub const MIN_BANDWIDTH: usize = 1_000;
+
+/// The maximum bandwidth limit of generated settings in bytes per second.
+const MAX_BANDWIDTH: usize = 100_000_000;
+
+/// The maximum cross traffic of generated settings in bytes per second. Every packet of cross traffic is simulated.
+const MAX_CROSS_TRAFFIC: usize = 1_000_000;
+
+/// A probability between 0.0 and 1.0, with the bounds more likely than by chance.
+pub fn probability() -> impl Strategy<Value = f64> {
+    prop_oneof![1 => Just(0.0), 1 => Just(1.0), 8 => 0.0..=1.0]
+}
+
+/// A duration up to `max`, in whole microseconds.
+pub fn duration(max: Duration) -> impl Strategy<Value = Duration> {
+    (0..=max.as_micros() as u64).prop_map(Duration::from_micros)
+}
+
+/// A non-zero duration up to `max`, in whole microseconds.
+fn non_zero_duration(max: Duration) -> impl Strategy<Value = Duration> {
+    (1..=max.as_micros() as u64).prop_map(Duration::from_micros)
+}
+
+/// A latency model other than [`Latency::Custom`], up to [`MAX_DELAY`].
+pub fn latency() -> impl Strategy<Value = Latency> {
+    prop_oneof![
+        Just(Latency::None),
+        duration(MAX_DELAY).prop_map(Latency::Constant),
+        (duration(MAX_DELAY), duration(MAX_DELAY / 4), duration(MAX_DELAY))
+            .prop_map(|(mean, std_dev, max)| Latency::Normal { mean, std_dev, max }),
+        (
+            duration(MAX_DELAY),
+            duration(MAX_DELAY / 4),
+            -5.0..=5.0,
+            duration(MAX_DELAY)
+        )
+            .prop_map(|(location, scale, shape, max)| Latency::SkewNormal {
+                location,
+                scale,
+                shape,
+                max,
+            }),
+        (duration(MAX_DELAY), duration(MAX_DELAY)).prop_map(|(a, b)| Latency::Uniform {
+            min: a.min(b),
+            max: a.max(b),
+        }),
+        (duration(MAX_DELAY), 0.0..=1.5, duration(MAX_DELAY)).prop_map(|(median, sigma, max)| Latency::LogNormal {
+            median,
+            sigma,
+            max
+        }),
+        (duration(MAX_DELAY), 0.5..=5.0, duration(MAX_DELAY)).prop_map(|(scale, shape, max)| Latency::Pareto {
+            scale,
+            shape,
+            max
+        }),
+    ]
+}
+
+/// Any of the orderings.
+pub fn ordering() -> impl Strategy<Value = ChokeSettingsOrder> {
+    prop_oneof![
+        Just(ChokeSettingsOrder::Unordered),
+        Just(ChokeSettingsOrder::Ordered),
+        Just(ChokeSettingsOrder::PartiallyOrdered),
+    ]
+}
+
+fn congestion() -> impl Strategy<Value = Congestion> {
+    (non_zero_duration(MAX_DELAY), 0.1..0.99, 0.0..=10.0, 0.01..=1.0).prop_map(
+        |(interval, backoff, recovery, min_ratio)| {
+            Congestion::builder()
+                .interval(interval)
+                .backoff(backoff)
+                .recovery(recovery)
+                .min_ratio(min_ratio)
+                .build()
+        },
+    )
+}
+
+fn slow_start() -> impl Strategy<Value = SlowStart> {
+    (duration(MAX_DELAY), 0.01..=1.0).prop_map(|(duration, initial_ratio)| {
+        SlowStart::builder()
+            .duration(duration)
+            .initial_ratio(initial_ratio)
+            .build()
+    })
+}
+
+/// Cross traffic of up to `max` bytes per second.
+fn cross_traffic(max: usize) -> impl Strategy<Value = CrossTraffic> {
+    let bytes_per_second = 0..=max;
+    let pattern = prop_oneof![
+        bytes_per_second
+            .clone()
+            .prop_map(|bytes_per_second| CrossTrafficPattern::Constant { bytes_per_second }),
+        (
+            bytes_per_second.clone(),
+            non_zero_duration(MAX_DELAY),
+            duration(MAX_DELAY)
+        )
+            .prop_map(|(bytes_per_second, on, off)| CrossTrafficPattern::OnOff {
+                bytes_per_second,
+                on,
+                off
+            }),
+        bytes_per_second.prop_map(|bytes_per_second| CrossTrafficPattern::Poisson { bytes_per_second }),
+    ];
+    (pattern, 500..=1500usize, any::<u64>()).prop_map(|(pattern, packet_size, seed)| {
+        CrossTraffic::builder()
+            .pattern(pattern)
+            .packet_size(packet_size)
+            .seed(seed)
+            .build()
+    })
+}
+
+/// A bandwidth limit between [`MIN_BANDWIDTH`] and 100 MB per second, with or without the optional behaviors of
+/// [`BandwidthLimit::builder`]. Cross traffic is up to 1 MB per second and leaves at least a tenth of the limit to
+/// the items, so that they are not starved.
+pub fn bandwidth_limit() -> impl Strategy<Value = BandwidthLimit> {
+    (MIN_BANDWIDTH..=MAX_BANDWIDTH)
+        .prop_flat_map(|bytes_per_second| {
+            (
+                (
+                    Just(bytes_per_second),
+                    (1_000..=2_000_000u64).prop_map(Duration::from_micros),
+                    probability(),
+                    any::<bool>(),
+                    any::<bool>(),
+                    any::<bool>(),
+                ),
+                (
+                    option::of(congestion()),
+                    option::of(slow_start()),
+                    option::of(cross_traffic((bytes_per_second / 10 * 9).min(MAX_CROSS_TRAFFIC))),
+                ),
+            )
+        })
+        .prop_map(
+            |((bytes_per_second, window, drop_ratio, only_drop_when_full, pacing, ecn), extensions)| {
+                let (congestion, slow_start, cross_traffic) = extensions;
+                let mut builder = BandwidthLimit::builder()
+                    .bytes_per_sec(bytes_per_second)
+                    .window(window)
+                    .drop_ratio(drop_ratio)
+                    .only_drop_when_full(only_drop_when_full)
+                    .pacing(pacing)
+                    .ecn(ecn);
+                if let Some(congestion) = congestion {
+                    builder = builder.congestion(congestion);
+                }
+                if let Some(slow_start) = slow_start {
+                    builder = builder.slow_start(slow_start);
+                }
+                if let Some(cross_traffic) = cross_traffic {
+                    builder = builder.cross_traffic(cross_traffic);
+                }
+                builder.build()
+            },
+        )
+}
+
+fn coalescing() -> impl Strategy<Value = Coalescing> {
+    (1..=65_536usize, duration(MAX_DELAY))
+        .prop_map(|(max_bytes, max_delay)| Coalescing::builder().max_bytes(max_bytes).max_delay(max_delay).build())
+}
+
+fn nagle() -> impl Strategy<Value = Nagle> {
+    (0..=65_536usize, duration(MAX_DELAY))
+        .prop_map(|(min_bytes, max_delay)| Nagle::builder().min_bytes(min_bytes).max_delay(max_delay).build())
+}
+
+fn burst() -> impl Strategy<Value = Burst> {
+    prop_oneof![
+        (1..=64usize).prop_map(|size| Burst::builder().size(size).build()),
+        non_zero_duration(MAX_DELAY).prop_map(|interval| Burst::builder().interval(interval).build()),
+        (1..=64usize, non_zero_duration(MAX_DELAY))
+            .prop_map(|(size, interval)| Burst::builder().size(size).interval(interval).build()),
+    ]
+}
+
+fn reorder_window() -> impl Strategy<Value = ReorderWindow> {
+    prop_oneof![
+        (0..=16usize).prop_map(|max_distance| ReorderWindow::builder().max_distance(max_distance).build()),
+        duration(MAX_DELAY).prop_map(|max_time| ReorderWindow::builder().max_time(max_time).build()),
+        (0..=16usize, duration(MAX_DELAY)).prop_map(|(max_distance, max_time)| {
+            ReorderWindow::builder()
+                .max_distance(max_distance)
+                .max_time(max_time)
+                .build()
+        }),
+    ]
+}
+
+fn reorder() -> impl Strategy<Value = Reorder> {
+    (probability(), 1..=16usize)
+        .prop_map(|(probability, gap)| Reorder::builder().probability(probability).gap(gap).build())
+}
+
+/// Valid settings with every field unset or set to a random value, see the [module documentation](self). The seed is
+/// always set. Settings sources, decision hooks, clocks and recorders are left unset.
+pub fn settings() -> impl Strategy<Value = ChokeSettings> {
+    let link = (
+        option::of(latency()),
+        option::of(probability()),
+        option::of(probability()),
+        option::of(probability()),
+        option::of(option::of(bandwidth_limit())),
+        option::of(ordering()),
+        option::of(any::<bool>()),
+        option::of(1..=64usize),
+    );
+    let items = (
+        option::of(option::of(64..=9_000usize)),
+        option::of(option::of(coalescing())),
+        option::of(option::of(nagle())),
+        option::of(option::of(burst())),
+        option::of(option::of(duration(MAX_DELAY * 4))),
+        option::of(option::of(latency())),
+        option::of(option::of(reorder_window())),
+        option::of(option::of(reorder())),
+    );
+    (link, items, any::<u64>()).prop_map(|(link, items, seed)| {
+        let (latency, drop, corrupt, duplicate, bandwidth_limit, ordering, backpressure, poll_budget) = link;
+        let (mtu, coalescing, nagle, burst, item_ttl, initial_delay, reorder_window, reorder) = items;
+        let mut settings = ChokeSettings::default()
+            .set_latency(latency)
+            .set_drop_probability(drop)
+            .set_corrupt_probability(corrupt)
+            .set_duplicate_probability(duplicate)
+            .set_ordering(ordering)
+            .set_backpressure(backpressure)
+            .set_poll_budget(poll_budget)
+            .set_seed(Some(seed));
+        // The setters of features that can be disabled always set the field, so they are only called to set or disable
+        // it
+        if let Some(limit) = bandwidth_limit {
+            settings = match limit {
+                Some(limit) => settings.set_bandwidth_limit_with(limit),
+                None => settings.set_bandwidth_limit(None),
+            };
+        }
+        if let Some(mtu) = mtu {
+            settings = settings.set_mtu(mtu);
+        }
+        if let Some(coalescing) = coalescing {
+            settings = settings.set_coalescing(coalescing);
+        }
+        if let Some(nagle) = nagle {
+            settings = settings.set_nagle(nagle);
+        }
+        if let Some(burst) = burst {
+            settings = settings.set_burst(burst);
+        }
+        if let Some(ttl) = item_ttl {
+            settings = settings.set_item_ttl(ttl);
+        }
+        if let Some(delay) = initial_delay {
+            settings = settings.set_initial_delay(delay);
+        }
+        if let Some(window) = reorder_window {
+            settings = settings.set_reorder_window(window);
+        }
+        if let Some(reorder) = reorder {
+            settings = settings.set_reorder(reorder);
+        }
+        settings
+    })
+}
+
+/// A scenario of up to 8 steps within 10 seconds, each applying random [`settings`].
+pub fn scenario() -> impl Strategy<Value = ScenarioSource> {
+    prop::collection::vec((duration(Duration::from_secs(10)), settings()), 0..=8).prop_map(ScenarioSource::new)
+}
+
+impl Arbitrary for ChokeSettings {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        settings().boxed()
+    }
+}
+
+impl Arbitrary for Latency {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        latency().boxed()
+    }
+}
+
+impl Arbitrary for BandwidthLimit {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        bandwidth_limit().boxed()
+    }
+}
+
+impl Arbitrary for ChokeSettingsOrder {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        ordering().boxed()
+    }
+}
@@ -1,21 +1,67 @@
 use crate::{
+    bandwidth_limiter::{
+        ActiveBandwidthLimit,
+        SharedBandwidthLimit,
+    },
+    clock::SharedClock,
+    event::{
+        ChokeEventKind,
+        ChokeEvents,
+        DropReason,
+        EventSenders,
+    },
+    hook::{
+        Hook,
+        HookItem,
+    },
+    idle::{
+        Idle,
+        IdleReporter,
+    },
     item::ChokeItem,
-    settings::BandwidthLimit,
+    latency::{
+        Latency,
+        MaybeSend,
+    },
+    pcap::PcapWriter,
+    recorder::{
+        Fate,
+        ItemInfo,
+        Recorder,
+    },
+    replay::{
+        Decision,
+        DecisionMode,
+    },
+    settings::{
+        Burst,
+        Coalescing,
+        Nagle,
+        Reorder,
+        ReorderWindow,
+        DEFAULT_POLL_BUDGET,
+    },
+    source::{
+        SettingsSource,
+        WatchSource,
+    },
     time::{
-        tokio_time::{
-            interval,
-            Interval,
-        },
+        self,
         Instant,
+        Timer,
     },
     ChokeSettings,
     ChokeSettingsOrder,
+    ChokeSettingsSnapshot,
+    ChokeStats,
+    TimingStats,
 };
-use futures::{
-    Stream,
-    StreamExt,
+use futures::Stream;
+use rand::{
+    rngs::StdRng,
+    Rng,
+    SeedableRng,
 };
-use rand::Rng;
 use std::{
     collections::{
         BTreeMap,
@@ -23,12 +69,14 @@ use std::{
     },
     pin::Pin,
     task::{
+        ready,
         Context,
         Poll,
     },
     time::Duration,
 };
 use tokio::sync::mpsc;
+use tracing::Span;
 
 const VERBOSE: bool = false;
 
@@ -36,7 +84,7 @@ const VERBOSE: bool = false;
 ///
 /// Example:
 /// ```rust
-/// # use chokepoint::{normal_distribution, ChokeStream, ChokeSettings};
+/// # use chokepoint::{normal_distribution, BandwidthLimit, ChokeStream, ChokeSettings};
 /// # use bytes::Bytes;
 /// # use chrono::{prelude::*, Duration};
 /// # use futures::stream::StreamExt;
@@ -48,7 +96,7 @@ const VERBOSE: bool = false;
 /// # async fn main() {
 /// let (tx, rx) = mpsc::unbounded_channel();
 ///
-/// let mut traffic_shaper = ChokeStream::new(
+/// let mut stream = ChokeStream::new(
 ///     Box::new(UnboundedReceiverStream::new(rx)),
 ///     ChokeSettings::default()
 ///         // Set the latency distribution in milliseconds
@@ -60,7 +108,9 @@ const VERBOSE: bool = false;
 ///         // Set other parameters as needed
 ///         .set_drop_probability(Some(0.0))
 ///         .set_corrupt_probability(Some(0.0))
-///         .set_bandwidth_limit(Some(100 /* bytes per second */), 0.1 /* drop prob */),
+///         .set_bandwidth_limit(Some(100 /* bytes per second */))
+///         // Or, to also drop items when the bandwidth limit is reached
+///         .set_bandwidth_limit_with(BandwidthLimit::builder().bytes_per_sec(100).drop_ratio(0.1)),
 /// );
 ///
 /// // Spawn a task to send packets into the ChokeStream
@@ -76,7 +126,7 @@ const VERBOSE: bool = false;
 /// });
 ///
 /// // Consume packets from the stream
-/// while let Some(packet) = traffic_shaper.next().await {
+/// while let Some(packet) = stream.next().await {
 ///     let now = Utc::now().timestamp_nanos_opt().unwrap();
 ///     let then = Duration::nanoseconds(i64::from_le_bytes(packet[0..8].try_into().unwrap()));
 ///     let i = usize::from_le_bytes(packet[8..16].try_into().unwrap());
@@ -86,98 +136,1439 @@ const VERBOSE: bool = false;
 /// # }
 /// ```
 #[pin_project]
-pub struct ChokeStream<T> {
-    stream: Box<dyn Stream<Item = T> + Unpin>,
-    queue: Queue<T>,
-    latency_distribution: Option<Box<dyn FnMut() -> Option<Duration> + Send + Sync>>,
+pub struct ChokeStream<T, S = Box<dyn Stream<Item = T> + Unpin>> {
+    /// The inner stream, pinned structurally so that it does not need to be `Unpin`.
+    #[pin]
+    stream: S,
+    shaper: Shaper<T>,
+    timer: Timer,
+    debug_timer: Timer,
+}
+
+/// The state of a [`ChokeStream`] or of a hop of a [`ChokeChain`], besides the inner stream and the timers.
+struct Shaper<T> {
+    queue: Queue<Queued<T>>,
+    latency: Latency,
     drop_probability: f64,
     corrupt_probability: f64,
     duplicate_probability: f64,
-    bandwidth_limit: Option<BandwidthLimit>,
-    timer: Interval,
+    bandwidth_limit: Option<ActiveBandwidthLimit>,
+    shared_bandwidth_limit: Option<SharedBandwidthLimit>,
     ordering: ChokeSettingsOrder,
+    backpressure: bool,
+    poll_budget: usize,
+    mtu: Option<usize>,
+    coalescing: Option<Coalescing>,
+    /// The items that are merged into one before they are emitted, see [`ChokeSettings::set_coalescing`].
+    batch: Option<Batch<T>>,
+    nagle: Option<Nagle>,
+    /// The items that are held back, see [`ChokeSettings::set_nagle`].
+    held: Held<T>,
+    burst: Option<Burst>,
+    /// The items that are held back to be released in a burst, see [`ChokeSettings::set_burst`].
+    bursting: Held<T>,
+    item_ttl: Option<Duration>,
+    initial_delay: Latency,
+    /// When the connection setup is over, set by the first item, see [`ChokeSettings::set_initial_delay`].
+    setup_until: Option<Instant>,
+    reorder_window: Option<ReorderWindow>,
+    /// The deadlines of the recent items, see [`ChokeSettings::set_reorder_window`].
+    reorder_bound: ReorderBound,
+    reorder: Option<Reorder>,
+    decision_hooks: Vec<Hook>,
+    /// The items that were pushed back, with the number of later items that are still emitted before them, see
+    /// [`ChokeSettings::set_reorder`].
+    reordering: VecDeque<(usize, Queued<T>)>,
     settings_rx: Option<mpsc::Receiver<ChokeSettings>>,
-    has_dropped_item: bool,
-    total_packets: usize,
-    dropped_packets: usize,
-    packets_per_second: usize,
-    debug_timer: Interval,
+    settings_watch: Option<WatchSource>,
+    settings_source: Option<Box<dyn SettingsSource>>,
+    stats: ChokeStats,
+    timing_stats: TimingStats,
+    /// When the last item was taken from the inner stream, see [`TimingStats::inter_arrival`].
+    last_arrival: Option<Instant>,
+    /// When the last item was emitted, see [`TimingStats::inter_departure`].
+    last_departure: Option<Instant>,
+    /// Decides which items are dropped, corrupted and duplicated.
+    rng: StdRng,
+    /// The seed of `rng`, chosen randomly unless configured so that every run can be reproduced.
+    seed: u64,
+    decisions: Option<DecisionMode>,
+    pcap: Option<PcapWriter>,
+    recorder: Option<Recorder>,
+    events: EventSenders,
+    /// How often the statistics are logged, see [`ChokeSettings::set_stats_interval`].
+    stats_interval: Option<Duration>,
+    /// When the statistics were logged last and what they were then, to compute the rates since.
+    reported: (Instant, ChokeStats),
+    /// The earliest deadline registered while emitting, when the shaper needs to be polled again.
+    next_wakeup: Option<Instant>,
+    /// Whether the inner stream has ended, it must not be polled again afterwards.
+    stream_ended: bool,
+    /// Whether the inner stream returned `Poll::Pending` when it was last polled.
+    stream_pending: bool,
+    /// Reports to the futures returned by [`ChokeStream::idle`], created by the first call.
+    idle: Option<IdleReporter>,
+    clock: SharedClock,
+    /// See [`ChokeSettings::set_label`].
+    label: Option<String>,
+    /// Entered while the shaper is polled, with the label as a field.
+    span: Span,
+    /// Maps every item right before it is emitted, see [`ChokeStream::set_on_emit`].
+    on_emit: Option<OnEmit<T>>,
+    /// The info of the item emitted last, see [`ChokeStream::shaped`].
+    emitted: Option<EmitInfo>,
 }
 
-impl<T> ChokeStream<T> {
-    pub fn new(stream: Box<dyn Stream<Item = T> + Unpin>, settings: ChokeSettings) -> Self {
+#[cfg(not(chokepoint_local))]
+type OnEmit<T> = Box<dyn FnMut(T, EmitInfo) -> T + Send>;
+
+#[cfg(chokepoint_local)]
+type OnEmit<T> = Box<dyn FnMut(T, EmitInfo) -> T>;
+
+/// When and how an item was shaped, passed to the function set with [`ChokeStream::set_on_emit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmitInfo {
+    /// The position of the item in the inner stream, starting at 0. A duplicate has the same position as the original.
+    pub seq: usize,
+    /// When the item was taken from the inner stream.
+    pub enqueued: Instant,
+    /// The latency added to the item, if any. The item can be emitted later because of the ordering or the bandwidth
+    /// limit.
+    pub delay: Option<Duration>,
+    /// When the item is emitted.
+    pub emitted: Instant,
+    /// Whether the item is the duplicate of another item.
+    pub duplicate: bool,
+}
+
+impl EmitInfo {
+    /// The time the item spent in the shaper, i.e. its one-way delay through the simulated network.
+    pub fn one_way_delay(&self) -> Duration {
+        self.emitted - self.enqueued
+    }
+}
+
+impl<T, S> ChokeStream<T, S>
+where
+    T: ChokeItem,
+    S: Stream<Item = T>,
+{
+    pub fn new(stream: S, settings: ChokeSettings) -> Self {
+        if VERBOSE {
+            debug!(?settings, "creating new ChokeStream");
+        }
+        ChokeStream {
+            stream,
+            shaper: Shaper::new(settings),
+            timer: Timer::new(),
+            debug_timer: Timer::new(),
+        }
+    }
+
+    /// Yield every item along with when it was taken from the inner stream, the latency added to it and when it was
+    /// emitted, e.g. to measure the one-way delay without timestamps in the items. See [`ShapedStream`].
+    pub fn shaped(self) -> ShapedStream<T, S> {
+        ShapedStream { stream: self }
+    }
+
+    /// Shape the items emitted by this stream once more with `settings`, e.g. to simulate a path over several links.
+    /// See [`ChokeChain`].
+    pub fn chain(self, settings: ChokeSettings) -> ChokeChain<T, S> {
+        ChokeChain {
+            stream: self.stream,
+            hops: vec![self.shaper, Shaper::new(settings)],
+            timer: Timer::new(),
+            debug_timer: Timer::new(),
+        }
+    }
+}
+
+impl<T: ChokeItem> Shaper<T> {
+    fn new(settings: ChokeSettings) -> Self {
+        let ordering = settings.ordering.unwrap_or_default();
+        let seed = rand::rng().random();
+        let mut shaper = Shaper {
+            queue: Queue::queue_for_ordering(ordering),
+            latency: Latency::None,
+            drop_probability: 0.0,
+            corrupt_probability: 0.0,
+            duplicate_probability: 0.0,
+            bandwidth_limit: None,
+            shared_bandwidth_limit: None,
+            ordering,
+            backpressure: false,
+            poll_budget: DEFAULT_POLL_BUDGET,
+            mtu: None,
+            coalescing: None,
+            batch: None,
+            nagle: None,
+            held: Held::default(),
+            burst: None,
+            bursting: Held::default(),
+            item_ttl: None,
+            initial_delay: Latency::None,
+            setup_until: None,
+            reorder_window: None,
+            reorder_bound: ReorderBound::default(),
+            reorder: None,
+            decision_hooks: Vec::new(),
+            reordering: VecDeque::new(),
+            settings_rx: None,
+            settings_watch: None,
+            settings_source: None,
+            stats: ChokeStats::default(),
+            timing_stats: TimingStats::default(),
+            last_arrival: None,
+            last_departure: None,
+            rng: StdRng::seed_from_u64(seed),
+            seed,
+            decisions: None,
+            pcap: None,
+            recorder: None,
+            events: EventSenders::default(),
+            stats_interval: None,
+            reported: (time::now(), ChokeStats::default()),
+            next_wakeup: None,
+            stream_ended: false,
+            stream_pending: false,
+            idle: None,
+            clock: SharedClock::system(),
+            label: None,
+            span: Span::none(),
+            on_emit: None,
+            emitted: None,
+        };
+        shaper.apply_settings(settings);
+        shaper
+    }
+}
+
+impl<T, S> ChokeStream<T, S> {
+    pub fn apply_settings(&mut self, settings: ChokeSettings)
+    where
+        T: ChokeItem,
+    {
+        self.shaper.apply_settings(settings);
+    }
+
+    /// Returns the settings that are currently in effect. Settings sent through a
+    /// [`ChokeSettings::settings_updater`] are picked up the next time the stream is polled and are only reflected here
+    /// afterwards.
+    pub fn current_settings(&self) -> ChokeSettingsSnapshot {
+        self.shaper.current_settings()
+    }
+
+    /// Consumes the `ChokeStream`, returning the inner stream and the items that were taken from it but not emitted
+    /// yet, in the order in which they would have been emitted. Delayed items are returned without waiting for their
+    /// delay to pass.
+    pub fn into_parts(self) -> (S, Vec<T>) {
+        (self.stream, self.shaper.into_items().collect())
+    }
+
+    /// Returns the counters of the items processed so far, along with the seed and a fingerprint of the current
+    /// settings needed to reproduce the run.
+    pub fn stats(&self) -> ChokeStats {
+        self.shaper.stats()
+    }
+
+    /// Returns the distributions of the latency added to the items emitted so far, of the time they spent in the
+    /// stream, e.g. for assertions like "95% of the items are delayed by less than 200ms", and of the gaps between the
+    /// items taken from the inner stream and between the emitted items.
+    pub fn timing_stats(&self) -> &TimingStats {
+        &self.shaper.timing_stats
+    }
+
+    /// Returns a future that resolves once no items are queued or delayed and the inner stream has no more items at
+    /// the moment, i.e. once everything in flight has been emitted or dropped. The stream has to be polled by another
+    /// task meanwhile, see [`Idle`].
+    pub fn idle(&mut self) -> Idle {
+        self.shaper.idle.get_or_insert_with(IdleReporter::default).idle()
+    }
+
+    /// Returns a stream of the events of every item from now on, see the [`crate::event`] module.
+    pub fn events(&mut self) -> ChokeEvents {
+        self.shaper.events.subscribe()
+    }
+
+    /// The bytes per second the bandwidth limit currently allows, lower than the configured limit while it ramps up or
+    /// responds to congestion, see [`crate::SlowStart`] and [`crate::Congestion`]. `None` without a bandwidth limit.
+    pub fn effective_bandwidth_limit(&self) -> Option<usize> {
+        self.shaper
+            .bandwidth_limit
+            .as_ref()
+            .map(ActiveBandwidthLimit::bytes_per_second)
+    }
+
+    /// Start the [`crate::SlowStart`] of the bandwidth limit over, e.g. after a blackout. See
+    /// [`SharedBandwidthLimit::restart_slow_start`] for a shared limit.
+    pub fn restart_slow_start(&mut self) {
+        if let Some(limit) = &mut self.shaper.bandwidth_limit {
+            limit.restart_slow_start();
+        }
+    }
+
+    /// Calls `f` with every item right before it is emitted, along with when it was taken from the inner stream and
+    /// the latency added to it, and emits the item `f` returns instead, e.g. to stamp items with the simulated network
+    /// timing. Coalesced items are passed once, with the info of the first item merged into them.
+    ///
+    /// ```
+    /// # use bytes::Bytes;
+    /// # use chokepoint::{ChokeSettings, ChokeStream, Latency};
+    /// # use futures::StreamExt as _;
+    /// # use std::time::Duration;
+    /// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+    /// # async fn main() {
+    /// // Items with the time they spent in the network, see the `ChokeItem` implementation for tuples
+    /// let items = futures::stream::iter([Bytes::from("a"), Bytes::from("b")].map(|item| (item, Duration::ZERO)));
+    /// let settings = ChokeSettings::default().set_latency(Some(Latency::Constant(Duration::from_millis(10))));
+    /// let mut stream = ChokeStream::new(items, settings);
+    /// stream.set_on_emit(|(item, _), info| (item, info.emitted - info.enqueued));
+    /// let output = stream.collect::<Vec<_>>().await;
+    /// assert!(output.iter().all(|(_, elapsed)| *elapsed == Duration::from_millis(10)));
+    /// # }
+    /// ```
+    pub fn set_on_emit<F>(&mut self, f: F)
+    where
+        F: FnMut(T, EmitInfo) -> T + MaybeSend + 'static,
+    {
+        self.shaper.on_emit = Some(Box::new(f));
+    }
+
+    pub(crate) fn pending(&self) -> bool {
+        self.shaper.pending()
+    }
+
+    pub(crate) fn backpressure(&self) -> bool {
+        self.shaper.backpressure
+    }
+
+    pub(crate) fn is_idle(&self) -> bool {
+        self.shaper.is_idle()
+    }
+}
+
+impl<T, S> ChokeStream<T, S>
+where
+    T: ChokeItem,
+{
+    /// Emits the items that were already taken from the inner stream as fast as their delays and the bandwidth limit
+    /// allow, without taking new items from the inner stream. Resolves once the internal queues are empty.
+    pub async fn drain(&mut self) -> Vec<T> {
+        self.shaper.drain().await
+    }
+}
+
+impl<T> Shaper<T> {
+    fn apply_settings(&mut self, settings: ChokeSettings)
+    where
+        T: ChokeItem,
+    {
+        debug!(?settings, "applying settings");
+
+        if let Some(settings_rx) = settings.settings_rx {
+            self.settings_rx = Some(settings_rx);
+        }
+        if let Some(settings_watch) = settings.settings_watch {
+            self.settings_watch = Some(WatchSource::changes(settings_watch));
+        }
+        if let Some(settings_source) = settings.settings_source {
+            self.settings_source = Some(settings_source.into_inner());
+        }
+        if let Some(latency) = settings.latency {
+            self.latency = latency;
+        }
+        if let Some(drop_probability) = settings.drop_probability {
+            self.drop_probability = drop_probability;
+        }
+        if let Some(corrupt_probability) = settings.corrupt_probability {
+            self.corrupt_probability = corrupt_probability;
+        }
+        if let Some(duplicate_probability) = settings.duplicate_probability {
+            self.duplicate_probability = duplicate_probability;
+        }
+        if let Some(ordering) = settings.ordering.filter(|ordering| *ordering != self.ordering) {
+            self.ordering = ordering;
+            // The queues of the orderings keep their items differently, the queued items are dropped
+            let replaced = std::mem::replace(&mut self.queue, Queue::queue_for_ordering(ordering));
+            let now = self.clock.now();
+            for queued in replaced.into_items() {
+                self.remove_queued(queued.item.byte_len());
+                self.discard_dropped(&queued.item, queued.info.seq, DropReason::Reconfigured, now);
+            }
+        }
+        if let Some(backpressure) = settings.backpressure {
+            self.backpressure = backpressure;
+        }
+        if let Some(poll_budget) = settings.poll_budget {
+            self.poll_budget = poll_budget;
+        }
+        if let Some(mtu) = settings.mtu {
+            self.mtu = mtu;
+        }
+        if let Some(coalescing) = settings.coalescing {
+            self.coalescing = coalescing;
+        }
+        if let Some(nagle) = settings.nagle {
+            self.nagle = nagle;
+        }
+        if let Some(burst) = settings.burst {
+            self.burst = burst;
+        }
+        if let Some(item_ttl) = settings.item_ttl {
+            self.item_ttl = item_ttl;
+        }
+        if let Some(initial_delay) = settings.initial_delay {
+            self.initial_delay = initial_delay.unwrap_or_default();
+        }
+        if let Some(reorder_window) = settings.reorder_window {
+            self.reorder_window = reorder_window;
+            self.reorder_bound = ReorderBound::default();
+        }
+        if let Some(reorder) = settings.reorder {
+            self.reorder = reorder;
+        }
+        if let Some(decision_hooks) = settings.decision_hooks {
+            self.decision_hooks = decision_hooks;
+        }
+        if let Some(clock) = settings.clock.filter(|clock| *clock != self.clock) {
+            self.clock = clock;
+            self.reported.0 = self.clock.now();
+        }
+        if let Some(seed) = settings.seed {
+            self.seed = seed.unwrap_or_else(|| rand::rng().random());
+            self.rng = StdRng::seed_from_u64(self.seed);
+        }
+        if let Some(decisions) = settings.decisions {
+            self.decisions = decisions;
+        }
+        if let Some(pcap) = settings.pcap {
+            self.pcap = pcap;
+        }
+        if let Some(recorder) = settings.recorder {
+            self.recorder = recorder;
+        }
+        if let Some(stats_interval) = settings.stats_interval {
+            self.stats_interval = stats_interval.filter(|interval| !interval.is_zero());
+            self.reported = (self.clock.now(), self.stats.clone());
+        }
+        if let Some(label) = settings.label {
+            self.span = match &label {
+                Some(label) => debug_span!("chokepoint", %label),
+                None => Span::none(),
+            };
+            self.label = label;
+        }
+        if let Some(bandwidth_limit) = settings.bandwidth_limit {
+            let bandwidth_limit = bandwidth_limit.filter(|limit| limit.bytes_per_second > 0);
+            // Keep the state of the current limiter if only the bytes per second changed.
+            match (&mut self.bandwidth_limit, bandwidth_limit) {
+                (Some(active), Some(limit)) => active.change(limit),
+                (active, limit) => *active = limit.map(ActiveBandwidthLimit::new),
+            }
+        }
+        if let Some(shared_bandwidth_limit) = settings.shared_bandwidth_limit {
+            self.shared_bandwidth_limit = shared_bandwidth_limit;
+        }
+    }
+
+    fn current_settings(&self) -> ChokeSettingsSnapshot {
+        ChokeSettingsSnapshot {
+            latency_distribution: self.latency.is_some(),
+            latency: match &self.latency {
+                Latency::None | Latency::Custom(_) => None,
+                latency => Some(latency.clone()),
+            },
+            drop_probability: self.drop_probability,
+            corrupt_probability: self.corrupt_probability,
+            duplicate_probability: self.duplicate_probability,
+            bandwidth_limit: self.bandwidth_limit.as_ref().map(|limit| limit.limit.clone()),
+            shared_bandwidth_limit: self.shared_bandwidth_limit.as_ref().map(SharedBandwidthLimit::limit),
+            ordering: self.ordering,
+            backpressure: self.backpressure,
+            poll_budget: self.poll_budget,
+            mtu: self.mtu,
+            coalescing: self.coalescing.clone(),
+            nagle: self.nagle.clone(),
+            burst: self.burst.clone(),
+            item_ttl: self.item_ttl,
+            initial_delay: match &self.initial_delay {
+                Latency::None | Latency::Custom(_) => None,
+                initial_delay => Some(initial_delay.clone()),
+            },
+            reorder_window: self.reorder_window.clone(),
+            reorder: self.reorder.clone(),
+            decision_hooks: self.decision_hooks.len(),
+            seed: self.seed,
+        }
+    }
+
+    fn pending(&self) -> bool {
+        self.queue.pending()
+    }
+
+    fn report_idle(&self, idle: bool, cx: &Context<'_>) {
+        if let Some(reporter) = &self.idle {
+            reporter.report(idle, cx);
+        }
+    }
+
+    fn stats(&self) -> ChokeStats {
+        ChokeStats {
+            seed: self.seed,
+            settings_fingerprint: self.current_settings().fingerprint(),
+            delayed: self.queue.delayed(),
+            label: self.label.clone(),
+            ..self.stats.clone()
+        }
+    }
+
+    /// The items that were taken from the inner stream but not emitted yet, in the order in which they would have been
+    /// emitted.
+    fn into_items(self) -> impl Iterator<Item = T> {
+        let batch = self.batch.map(|batch| batch.item);
+        let items = self
+            .bursting
+            .into_items()
+            .chain(self.held.into_items())
+            .chain(self.queue.into_items())
+            .chain(self.reordering.into_iter().map(|(_, queued)| queued))
+            .map(|queued| queued.item);
+        batch.into_iter().chain(items)
+    }
+
+    /// The number of items that were taken from the inner stream and are emitted later, including the ones held back
+    /// and pushed back. A batch is emitted as a single item.
+    fn in_flight(&self) -> usize {
+        self.queue.len()
+            + usize::from(self.batch.is_some())
+            + self.held.len()
+            + self.bursting.len()
+            + self.reordering.len()
+    }
+
+    /// Whether no items are in flight and the inner stream had no more items when it was last polled, see
+    /// [`ChokeStream::idle`].
+    fn is_idle(&self) -> bool {
+        (self.stream_ended || self.stream_pending)
+            && !self.queue.pending()
+            && self.batch.is_none()
+            && self.held.is_empty()
+            && self.bursting.is_empty()
+            && self.reordering.is_empty()
+    }
+
+    /// Whether items are taken from the inner stream: until it has ended and, with backpressure, while no item is in
+    /// flight.
+    fn accepts_items(&self) -> bool {
+        !self.stream_ended && (!self.backpressure || !self.queue.pending())
+    }
+
+    /// Pick up settings sent through a [`ChokeSettings::settings_updater`] or [`ChokeSettings::settings_watcher`], or
+    /// produced by a [`ChokeSettings::set_settings_source`]. The channels and the source wake up the task once they
+    /// have another update.
+    fn update_settings(&mut self, cx: &mut Context<'_>)
+    where
+        T: ChokeItem,
+    {
+        while let Some(Poll::Ready(new_settings)) = self.settings_rx.as_mut().map(|rx| rx.poll_recv(cx)) {
+            match new_settings {
+                Some(new_settings) => {
+                    debug!(?new_settings, "settings changed");
+                    self.apply_settings(new_settings);
+                }
+                None => self.settings_rx = None,
+            }
+        }
+
+        while let Some(mut source) = self.settings_source.take() {
+            match source.poll_next_settings(cx) {
+                Poll::Ready(Some(new_settings)) => {
+                    self.settings_source = Some(source);
+                    debug!(?new_settings, "settings changed");
+                    // Can replace the source
+                    self.apply_settings(new_settings);
+                }
+                // The source has ended and is dropped
+                Poll::Ready(None) => {}
+                Poll::Pending => {
+                    self.settings_source = Some(source);
+                    break;
+                }
+            }
+        }
+
+        while let Some(Poll::Ready(new_settings)) = self.settings_watch.as_mut().map(|s| s.poll_next_settings(cx)) {
+            match new_settings {
+                Some(new_settings) => {
+                    debug!(?new_settings, "settings changed");
+                    self.apply_settings(new_settings);
+                }
+                None => self.settings_watch = None,
+            }
+        }
+    }
+
+    /// When the statistics are logged next, if at all.
+    fn stats_deadline(&self) -> Option<Instant> {
+        self.stats_interval.map(|interval| self.reported.0 + interval)
+    }
+
+    /// Log the statistics if they are due.
+    fn log_stats(&mut self, now: Instant) {
+        if self.stats_deadline().is_none_or(|deadline| now < deadline) {
+            return;
+        }
+        let (since, reported) = std::mem::replace(&mut self.reported, (now, self.stats.clone()));
+        let elapsed = (now - since).as_secs_f64();
+        debug!(
+            queued = self.stats.queued,
+            delayed = self.queue.delayed(),
+            items_per_second = (self.stats.emitted - reported.emitted) as f64 / elapsed,
+            bytes_per_second = (self.stats.emitted_bytes - reported.emitted_bytes) as f64 / elapsed,
+            emitted = self.stats.emitted,
+            dropped = self.stats.dropped,
+            sojourn_p50 = ?self.timing_stats.sojourn.p50(),
+            sojourn_p99 = ?self.timing_stats.sojourn.p99(),
+            ordering = ?self.ordering,
+            backpressure = %self.backpressure,
+            "stats"
+        );
+    }
+
+    /// Register that the shaper needs to be polled again at `deadline`.
+    fn wake_at(&mut self, deadline: Instant) {
+        self.next_wakeup = Some(self.next_wakeup.map_or(deadline, |wakeup| wakeup.min(deadline)));
+    }
+
+    /// Decide the fate of an item taken from the inner stream at `now`, or why it is dropped.
+    fn decide(&mut self, now: Instant) -> Result<Decision, DropReason> {
+        let mut shared = self
+            .shared_bandwidth_limit
+            .as_ref()
+            .and_then(SharedBandwidthLimit::active);
+        let mut ecn = false;
+        let bandwidth_drop = [self.bandwidth_limit.as_mut(), shared.as_deref_mut()]
+            .into_iter()
+            .flatten()
+            .any(|limit| {
+                let full = limit.limit_reached(now);
+                let dropped =
+                    (!limit.limit.only_drop_when_full || full) && chance(&mut self.rng, limit.limit.drop_ratio);
+                if dropped && full {
+                    limit.overflowed(now);
+                }
+                // Marked instead, unless another limit drops the item
+                ecn |= dropped && limit.limit.ecn;
+                dropped && !limit.limit.ecn
+            });
+        drop(shared);
+
+        // Simulate packet loss
+        if bandwidth_drop || chance(&mut self.rng, self.drop_probability) {
+            if VERBOSE {
+                debug!("dropped packet bandwith_drop={bandwidth_drop}");
+            }
+            return Err(if bandwidth_drop {
+                DropReason::BandwidthLimit
+            } else {
+                DropReason::Random
+            });
+        }
+
+        // Simulate packet corruption. The seed makes the corruption reproducible when the decision is replayed.
+        let corrupt = chance(&mut self.rng, self.corrupt_probability).then(|| self.rng.random());
+
+        // Simulate latency using the configured model
+        let delay = self.latency.sample(&mut self.rng);
+
+        let duplicate = chance(&mut self.rng, self.duplicate_probability);
+
+        let reorder = self
+            .reorder
+            .as_ref()
+            .is_some_and(|reorder| chance(&mut self.rng, reorder.probability));
+
+        Ok(Decision::Deliver {
+            delay,
+            corrupt,
+            ecn,
+            reorder,
+            duplicate,
+        })
+    }
+}
+
+/// Returns `true` with the given probability. Nothing is drawn for a probability of zero, so that features that are
+/// not used neither cost a random number per item nor change the decisions for a seed.
+fn chance(rng: &mut StdRng, probability: f64) -> bool {
+    probability > 0.0 && rng.random::<f64>() < probability
+}
+
+impl<T> Shaper<T>
+where
+    T: ChokeItem,
+{
+    async fn drain(&mut self) -> Vec<T> {
+        let mut items = Vec::new();
+        let mut timer = Timer::new();
+        futures::future::poll_fn(|cx| loop {
+            match self.poll_emit(self.clock.now(), true) {
+                Poll::Ready(Some(item)) => items.push(item),
+                Poll::Ready(None) => return Poll::Ready(()),
+                Poll::Pending => {
+                    if let Some(deadline) = self.next_wakeup.take() {
+                        timer.wake_at(&self.clock, deadline, cx);
+                    }
+                    return Poll::Pending;
+                }
+            }
+        })
+        .await;
+        items
+    }
+
+    /// Take items from the inner stream and queue them, up to the poll budget. Sets `stream_ended` once the inner
+    /// stream has ended.
+    fn poll_inner<S>(&mut self, mut stream: Pin<&mut S>, cx: &mut Context<'_>)
+    where
+        S: Stream<Item = T>,
+    {
+        if VERBOSE {
+            debug!("waiting for packets from inner stream");
+        }
+        let mut budget = self.poll_budget;
+        self.stream_pending = false;
+        loop {
+            if budget == 0 {
+                // Yield to other tasks. The inner stream did not register the waker, so wake up again right away.
+                cx.waker().wake_by_ref();
+                break;
+            }
+            match stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(packet)) => {
+                    budget -= 1;
+                    // Polling the inner stream can advance a virtual clock, e.g. if it sleeps on the same clock
+                    let now = self.clock.now();
+                    self.ingest(packet, now);
+
+                    // With backpressure, only a single item is in flight at a time
+                    if self.backpressure {
+                        break;
+                    }
+                }
+
+                Poll::Ready(None) => {
+                    self.stream_ended = true;
+                    break;
+                }
+
+                Poll::Pending => {
+                    // No more packets to read at the moment
+                    self.stream_pending = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Queue an item taken from the inner stream, split into fragments if it is larger than the MTU.
+    fn ingest(&mut self, packet: T, now: Instant) {
+        if let Some(last) = self.last_arrival.replace(now) {
+            self.timing_stats.inter_arrival.record(now - last);
+        }
+        match self.mtu.filter(|mtu| packet.byte_len() > *mtu) {
+            Some(mtu) => {
+                for fragment in packet.split(mtu) {
+                    self.enqueue(fragment, now);
+                }
+            }
+            None => self.enqueue(packet, now),
+        }
+    }
+
+    /// Decide the fate of an item taken from the inner stream and queue it unless it is dropped.
+    fn enqueue(&mut self, mut packet: T, now: Instant) {
+        if VERBOSE {
+            debug!(bytes = %packet.byte_len(), "received packet");
+        }
+        let seq = self.stats.received;
+        self.stats.received += 1;
+        if let Some(recorder) = &self.recorder {
+            recorder.enqueued(now);
+        }
+        self.events.send(seq, ChokeEventKind::Enqueued, now);
+        // The connection setup starts with the first item
+        let (initial_delay, rng) = (&self.initial_delay, &mut self.rng);
+        let setup_until = *self
+            .setup_until
+            .get_or_insert_with(|| now + initial_delay.sample(rng).unwrap_or_default());
+
+        let replayed = match &mut self.decisions {
+            Some(DecisionMode::Replay(trace)) => trace.pop_front(),
+            _ => None,
+        };
+        let (decision, drop_reason) = replayed.map_or_else(
+            || {
+                // Items that are not dropped by the decision can still be dropped by a hook
+                let (mut decision, drop_reason) = match self.decide(now) {
+                    Ok(decision) => (decision, DropReason::Hook),
+                    Err(reason) => (Decision::Drop, reason),
+                };
+                let item = HookItem {
+                    seq,
+                    size: packet.byte_len(),
+                    payload: packet.payload(),
+                    now,
+                };
+                for hook in &self.decision_hooks {
+                    hook.process(&item, &mut decision);
+                }
+                (decision, drop_reason)
+            },
+            |decision| (decision, DropReason::Replay),
+        );
+        if let Some(DecisionMode::Record(recorder)) = &self.decisions {
+            recorder.record(decision.clone());
+        }
+
+        let Decision::Deliver {
+            delay,
+            corrupt,
+            ecn,
+            reorder,
+            duplicate,
+        } = decision
+        else {
+            self.discard_dropped(&packet, seq, drop_reason, now);
+            return;
+        };
+
+        // Simulate congestion signaled with ECN. Items that can't be marked are dropped like without ECN.
+        if ecn {
+            if !packet.mark_ecn() {
+                self.discard_dropped(&packet, seq, DropReason::BandwidthLimit, now);
+                return;
+            }
+            self.stats.marked += 1;
+        }
+
+        // Simulate packet corruption
+        if let Some(seed) = corrupt {
+            packet.corrupt_with(&mut StdRng::seed_from_u64(seed));
+            self.stats.corrupted += 1;
+            self.events.send(seq, ChokeEventKind::Corrupted, now);
+        }
+
+        // Simulate packet duplication
+        let duplicate = duplicate
+            .then(|| {
+                if let Some(packet) = packet.duplicate() {
+                    if VERBOSE {
+                        debug!("duplicated packet");
+                    }
+                    Some(packet)
+                } else {
+                    warn!("Failed to duplicate packet");
+                    None
+                }
+            })
+            .flatten();
+
+        // Hold back the packet until the connection is set up
+        let setup = setup_until.checked_duration_since(now).filter(|setup| !setup.is_zero());
+        let delay = match setup {
+            Some(setup) => Some(delay.unwrap_or_default() + setup),
+            None => delay,
+        };
+
+        // Keep the packet from overtaking packets outside of the reorder window
+        let delay = match &self.reorder_window {
+            Some(window) if self.ordering == ChokeSettingsOrder::Unordered => {
+                self.reorder_bound.bound(window, delay, now)
+            }
+            _ => delay,
+        };
+
+        if let Some(delay) = delay {
+            self.events.send(seq, ChokeEventKind::Delayed(delay), now);
+        }
+
+        // Insert the packet into the DelayQueue with the calculated delay
+        let info = ItemInfo {
+            seq,
+            enqueued: now,
+            delay,
+            corrupted: corrupt.is_some(),
+        };
+        let queued = Queued {
+            item: packet,
+            info,
+            duplicate: false,
+            reorder,
+        };
+        // An item delayed beyond its time to live would expire anyway, without a duplicate
+        let expired = delay.zip(self.item_ttl).is_some_and(|(delay, ttl)| delay > ttl);
+        if expired {
+            self.discard_expired(queued, now);
+        } else {
+            self.add_queued(queued.item.byte_len());
+            self.queue.push_back(queued, delay, now);
+        }
+        if let Some(duplicate) = duplicate.filter(|_| !expired) {
+            self.stats.duplicated += 1;
+            self.events.send(seq, ChokeEventKind::Duplicated, now);
+            let duplicate = Queued {
+                item: duplicate,
+                info: ItemInfo { delay: setup, ..info },
+                duplicate: true,
+                reorder: false,
+            };
+            self.add_queued(duplicate.item.byte_len());
+            self.queue.push_back(duplicate, setup, now);
+        }
+        self.stats.max_delayed = self.stats.max_delayed.max(self.queue.delayed());
+    }
+
+    /// Count an item that entered the queue, see [`ChokeStats::queued`].
+    fn add_queued(&mut self, bytes: usize) {
+        let stats = &mut self.stats;
+        stats.queued += 1;
+        stats.queued_bytes += bytes;
+        stats.max_queued = stats.max_queued.max(stats.queued);
+        stats.max_queued_bytes = stats.max_queued_bytes.max(stats.queued_bytes);
+    }
+
+    /// Count an item that left the queue, because it was emitted, merged into another item or expired.
+    fn remove_queued(&mut self, bytes: usize) {
+        self.stats.queued -= 1;
+        self.stats.queued_bytes -= bytes;
+    }
+
+    fn discard_dropped(&mut self, packet: &T, seq: usize, reason: DropReason, now: Instant) {
+        self.stats.dropped += 1;
+        self.stats.dropped_by.count(reason);
+        self.events.send(seq, ChokeEventKind::Dropped(reason), now);
+        if let Some((pcap, payload)) = self.pcap.as_ref().zip(packet.payload()) {
+            pcap.dropped(payload, now);
+        }
+        if let Some(recorder) = &self.recorder {
+            let info = ItemInfo {
+                seq,
+                enqueued: now,
+                delay: None,
+                corrupted: false,
+            };
+            recorder.record(&info, Fate::Dropped, None, packet.byte_len(), self.label.as_deref());
+        }
+    }
+
+    /// Emit the next item: the next queued item that is due or, with coalescing, several of them merged into one.
+    /// Returns `Poll::Ready(None)` if nothing is left to emit. With `flush`, a batch is emitted once the queue is empty
+    /// instead of waiting for more items. Returns `Poll::Pending` if items are waiting, with the deadline at which to
+    /// poll again in `next_wakeup` unless another item needs to be taken from the inner stream first.
+    fn poll_emit(&mut self, now: Instant, flush: bool) -> Poll<Option<T>> {
+        self.next_wakeup = None;
+        let Some(coalescing) = self.coalescing.clone() else {
+            return match self.poll_burst(now, flush) {
+                Poll::Ready(Some(queued)) => Poll::Ready(Some(self.emit(queued, now))),
+                Poll::Ready(None) => Poll::Ready(None),
+                Poll::Pending => Poll::Pending,
+            };
+        };
+
+        loop {
+            let next = self.poll_burst(now, flush);
+            let Some(mut batch) = self.batch.take() else {
+                match next {
+                    Poll::Ready(Some(queued)) => {
+                        self.batch = Some(Batch::new(queued, now));
+                        continue;
+                    }
+                    Poll::Ready(None) => return Poll::Ready(None),
+                    Poll::Pending => return Poll::Pending,
+                }
+            };
+
+            if let Poll::Ready(Some(Queued {
+                item,
+                info,
+                duplicate,
+                reorder,
+            })) = next
+            {
+                let size = item.byte_len();
+                let merged = if batch.item.byte_len() + size <= coalescing.max_bytes {
+                    batch.item.coalesce(item)
+                } else {
+                    Err(item)
+                };
+                match merged {
+                    Ok(()) => {
+                        batch.parts.push((info, duplicate, size));
+                        self.batch = Some(batch);
+                    }
+                    Err(item) => {
+                        self.batch = Some(Batch::new(
+                            Queued {
+                                item,
+                                info,
+                                duplicate,
+                                reorder,
+                            },
+                            now,
+                        ));
+                        return Poll::Ready(Some(self.emit_batch(batch, now)));
+                    }
+                }
+                continue;
+            }
+
+            let deadline = batch.started + coalescing.max_delay;
+            let queue_empty = matches!(next, Poll::Ready(None));
+            if (flush && queue_empty) || batch.item.byte_len() >= coalescing.max_bytes || deadline <= now {
+                return Poll::Ready(Some(self.emit_batch(batch, now)));
+            }
+
+            // Wait for more items until the deadline of the batch
+            self.batch = Some(batch);
+            self.wake_at(deadline);
+            return Poll::Pending;
+        }
+    }
+
+    /// Take the next item that is due and not held back for a burst, see [`ChokeSettings::set_burst`]. Returns
+    /// `Poll::Ready(None)` if no items are left. With `flush`, held items are released once nothing else is left.
+    fn poll_burst(&mut self, now: Instant, flush: bool) -> Poll<Option<Queued<T>>> {
+        let Some(burst) = self.burst.clone() else {
+            return self.poll_held(now, flush);
+        };
+
+        loop {
+            if let Some(queued) = self.bursting.released.pop_front() {
+                return Poll::Ready(Some(queued));
+            }
+
+            let next = self.poll_held(now, flush);
+            if let Poll::Ready(Some(queued)) = next {
+                self.bursting.hold(queued, now);
+                if burst.size.is_some_and(|size| self.bursting.items.len() >= size) {
+                    self.bursting.release();
+                }
+                continue;
+            }
+            let Some(since) = self.bursting.since else {
+                return next;
+            };
+
+            let deadline = burst.interval.map(|interval| since + interval);
+            if (flush && matches!(next, Poll::Ready(None))) || deadline.is_some_and(|deadline| deadline <= now) {
+                self.bursting.release();
+                continue;
+            }
+
+            // Wait for more items until the interval has passed
+            if let Some(deadline) = deadline {
+                self.wake_at(deadline);
+            }
+            return Poll::Pending;
+        }
+    }
+
+    /// Take the next item that is due and not held back, see [`ChokeSettings::set_nagle`]. Returns `Poll::Ready(None)`
+    /// if the queue is empty and no items are held. With `flush`, held items are released once the queue is empty.
+    fn poll_held(&mut self, now: Instant, flush: bool) -> Poll<Option<Queued<T>>> {
+        let Some(nagle) = self.nagle.clone() else {
+            return self.poll_reordered(now, flush);
+        };
+
+        loop {
+            if let Some(queued) = self.held.released.pop_front() {
+                return Poll::Ready(Some(queued));
+            }
+
+            let next = self.poll_reordered(now, flush);
+            if let Poll::Ready(Some(queued)) = next {
+                self.held.hold(queued, now);
+                if self.held.bytes >= nagle.min_bytes {
+                    self.held.release();
+                }
+                continue;
+            }
+            let Some(since) = self.held.since else {
+                return next;
+            };
+
+            let deadline = since + nagle.max_delay;
+            if (flush && matches!(next, Poll::Ready(None))) || deadline <= now {
+                self.held.release();
+                continue;
+            }
+
+            // Wait for more items until the deadline of the first held item
+            self.wake_at(deadline);
+            return Poll::Pending;
+        }
+    }
+
+    /// Take the next item that is due, pushing back items selected for reordering, see [`ChokeSettings::set_reorder`].
+    /// Returns `Poll::Ready(None)` if no items are left. With `flush`, pushed back items are released once nothing else
+    /// is left.
+    fn poll_reordered(&mut self, now: Instant, flush: bool) -> Poll<Option<Queued<T>>> {
+        loop {
+            if let Some(i) = self.reordering.iter().position(|(remaining, _)| *remaining == 0) {
+                let (_, queued) = self.reordering.remove(i).expect("position is in bounds");
+                return Poll::Ready(Some(queued));
+            }
+
+            let next = self.poll_queue(now);
+            match next {
+                Poll::Ready(Some(mut queued)) => {
+                    if std::mem::take(&mut queued.reorder) {
+                        if let Some(reorder) = &self.reorder {
+                            self.reordering.push_back((reorder.gap, queued));
+                            continue;
+                        }
+                    }
+                    for (remaining, _) in &mut self.reordering {
+                        *remaining -= 1;
+                    }
+                    return Poll::Ready(Some(queued));
+                }
+                // No later items are left to pass the pushed back items
+                Poll::Ready(None) if flush => {
+                    return Poll::Ready(self.reordering.pop_front().map(|(_, queued)| queued))
+                }
+                Poll::Ready(None) if !self.reordering.is_empty() => return Poll::Pending,
+                next => return next,
+            }
+        }
+    }
+
+    fn discard_expired(&mut self, queued: Queued<T>, now: Instant) {
+        if VERBOSE {
+            debug!("discarding expired packet");
+        }
+
+        self.stats.expired += 1;
+        self.stats.dropped_by.count(DropReason::Expired);
+        self.events
+            .send(queued.info.seq, ChokeEventKind::Dropped(DropReason::Expired), now);
+        if let Some((pcap, payload)) = self.pcap.as_ref().zip(queued.item.payload()) {
+            pcap.dropped(payload, now);
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.record(
+                &queued.info,
+                Fate::Expired,
+                None,
+                queued.item.byte_len(),
+                self.label.as_deref(),
+            );
+        }
+    }
+
+    fn emit(&mut self, queued: Queued<T>, now: Instant) -> T {
+        if VERBOSE {
+            debug!("emitting packet");
+        }
+
+        self.stats.emitted += 1;
+        self.record_departure(now);
+        self.record_delay(&queued.info, now);
+        self.count_bytes(&queued.info, queued.duplicate, queued.item.byte_len());
+        self.remove_queued(queued.item.byte_len());
+        self.events.send(queued.info.seq, ChokeEventKind::Emitted, now);
+        if let Some((pcap, payload)) = self.pcap.as_ref().zip(queued.item.payload()) {
+            pcap.emitted(payload, now);
+        }
+        if let Some(recorder) = &self.recorder {
+            let fate = if queued.duplicate {
+                Fate::Duplicate
+            } else {
+                Fate::Emitted
+            };
+            recorder.record(
+                &queued.info,
+                fate,
+                Some(now),
+                queued.item.byte_len(),
+                self.label.as_deref(),
+            );
+        }
+        self.on_emit(queued.item, &queued.info, queued.duplicate, now)
+    }
+
+    fn emit_batch(&mut self, batch: Batch<T>, now: Instant) -> T {
+        if VERBOSE {
+            debug!(items = batch.parts.len(), "emitting coalesced packet");
+        }
+
+        self.stats.emitted += 1;
+        self.stats.coalesced += batch.parts.len() - 1;
+        self.record_departure(now);
+        for (info, duplicate, size) in &batch.parts {
+            self.record_delay(info, now);
+            self.count_bytes(info, *duplicate, *size);
+            self.remove_queued(*size);
+            self.events.send(info.seq, ChokeEventKind::Emitted, now);
+        }
+        if let Some((pcap, payload)) = self.pcap.as_ref().zip(batch.item.payload()) {
+            pcap.emitted(payload, now);
+        }
+        if let Some(recorder) = &self.recorder {
+            for (info, duplicate, size) in &batch.parts {
+                let fate = if *duplicate { Fate::Duplicate } else { Fate::Emitted };
+                recorder.record(info, fate, Some(now), *size, self.label.as_deref());
+            }
+        }
+        let (info, duplicate, _) = &batch.parts[0];
+        self.on_emit(batch.item, info, *duplicate, now)
+    }
+
+    fn count_bytes(&mut self, info: &ItemInfo, duplicate: bool, size: usize) {
+        self.stats.emitted_bytes += size;
+        if info.corrupted {
+            self.stats.corrupted_bytes += size;
+        } else if !duplicate {
+            self.stats.goodput_bytes += size;
+        }
+    }
+
+    fn record_departure(&mut self, now: Instant) {
+        if let Some(last) = self.last_departure.replace(now) {
+            self.timing_stats.inter_departure.record(now - last);
+        }
+    }
+
+    fn record_delay(&mut self, info: &ItemInfo, now: Instant) {
+        let delay = info.delay.unwrap_or_default();
+        let sojourn = now - info.enqueued;
+        self.timing_stats.delay.record(delay);
+        self.timing_stats.sojourn.record(sojourn);
+        self.timing_stats.waiting.record(sojourn.saturating_sub(delay));
+    }
+
+    fn on_emit(&mut self, item: T, info: &ItemInfo, duplicate: bool, now: Instant) -> T {
+        let info = EmitInfo {
+            seq: info.seq,
+            enqueued: info.enqueued,
+            delay: info.delay,
+            emitted: now,
+            duplicate,
+        };
+        self.emitted = Some(info);
+        match &mut self.on_emit {
+            Some(on_emit) => on_emit(item, info),
+            None => item,
+        }
+    }
+
+    /// Whether an item of `bytes` can be emitted at `now` under the bandwidth limit of the stream as well as the shared
+    /// one, accounting for it in both if so.
+    fn try_send(&mut self, bytes: usize, now: Instant) -> bool {
+        let mut shared = self
+            .shared_bandwidth_limit
+            .as_ref()
+            .and_then(SharedBandwidthLimit::active);
+        let mut limits = [self.bandwidth_limit.as_mut(), shared.as_deref_mut()];
+        if !limits.iter_mut().flatten().all(|limit| limit.can_send(now)) {
+            return false;
+        }
+        for limit in limits.into_iter().flatten() {
+            limit.sent(bytes, now);
+        }
+        true
+    }
+
+    /// Take the next queued item that is due, unless the bandwidth limit is reached. Returns `Poll::Ready(None)` if the
+    /// queue is empty and registers the next deadline if items are pending.
+    fn poll_queue(&mut self, now: Instant) -> Poll<Option<Queued<T>>> {
+        self.queue.expire(now);
+
+        // Retrieve packets from the normal or delay queue
         if VERBOSE {
-            debug!(?settings, "creating new ChokeStream");
+            debug!(pending = self.queue.pending(), "retrieving packet");
         }
-        let ordering = settings.ordering.unwrap_or_default();
-        let mut stream = ChokeStream {
-            stream,
-            queue: Queue::queue_for_ordering(ordering),
-            latency_distribution: None,
-            drop_probability: 0.0,
-            corrupt_probability: 0.0,
-            duplicate_probability: 0.0,
-            bandwidth_limit: None,
-            timer: interval(Duration::from_millis(20)),
-            ordering,
-            settings_rx: None,
-            has_dropped_item: false,
-            total_packets: 0,
-            dropped_packets: 0,
-            packets_per_second: 0,
-            debug_timer: interval(Duration::from_secs_f64(2.5)),
-        };
-        stream.apply_settings(settings);
-        stream
-    }
+        while let Some(queued) = self.queue.pop_front(now) {
+            if self
+                .item_ttl
+                .is_some_and(|ttl| now.saturating_duration_since(queued.info.enqueued) > ttl)
+            {
+                self.remove_queued(queued.item.byte_len());
+                self.discard_expired(queued, now);
+                continue;
+            }
 
-    pub fn apply_settings(&mut self, settings: ChokeSettings) {
-        debug!(?settings, "applying settings");
+            // Simulate bandwidth limita
+            if !self.try_send(queued.item.byte_len(), now) {
+                if VERBOSE {
+                    debug!(i = %self.stats.emitted, "bandwidth limit reached");
+                }
+                self.queue.push_front(queued, None, now);
+                break;
+            }
+            return Poll::Ready(Some(queued));
+        }
 
-        if let Some(settings_rx) = settings.settings_rx {
-            self.settings_rx = Some(settings_rx);
+        if VERBOSE {
+            debug!(
+                queue = self.queue.queued(),
+                delayed = self.queue.delayed(),
+                "Poll::Pending"
+            );
         }
-        if let Some(latency_distribution) = settings.latency_distribution {
-            self.latency_distribution = latency_distribution;
+
+        if !self.pending() {
+            return Poll::Ready(None);
         }
-        if let Some(drop_probability) = settings.drop_probability {
-            self.drop_probability = drop_probability;
+
+        // While the bandwidth limit is reached, no item can be emitted before it has capacity again. Otherwise the next
+        // item is due at the deadline of the queue.
+        let now = self.clock.now();
+        let shared = self
+            .shared_bandwidth_limit
+            .as_ref()
+            .and_then(SharedBandwidthLimit::active);
+        let available = [self.bandwidth_limit.as_ref(), shared.as_deref()]
+            .into_iter()
+            .flatten()
+            .filter_map(|limit| limit.available_at(now))
+            .max();
+        drop(shared);
+        if let Some(deadline) = available.or(self.queue.deadline()) {
+            self.wake_at(deadline);
         }
-        if let Some(corrupt_probability) = settings.corrupt_probability {
-            self.corrupt_probability = corrupt_probability;
+        Poll::Pending
+    }
+}
+
+/// The deadlines of the recent items, to bound how far items are reordered, see [`ChokeSettings::set_reorder_window`].
+#[derive(Default)]
+struct ReorderBound {
+    /// When the items within the reorder window were taken from the inner stream and their deadlines, oldest first.
+    recent: VecDeque<(Instant, Instant)>,
+    /// The latest deadline of the items that left the window, later items must not overtake them.
+    floor: Option<Instant>,
+}
+
+impl ReorderBound {
+    /// Returns the delay of an item taken at `now`, extended if needed so that it does not overtake items outside of
+    /// the window.
+    fn bound(&mut self, window: &ReorderWindow, delay: Option<Duration>, now: Instant) -> Option<Duration> {
+        while let Some(&(enqueued, deadline)) = self.recent.front() {
+            let too_far = window.max_distance.is_some_and(|distance| self.recent.len() > distance);
+            let too_old = window
+                .max_time
+                .is_some_and(|time| now.saturating_duration_since(enqueued) > time);
+            if !too_far && !too_old {
+                break;
+            }
+            self.recent.pop_front();
+            self.floor = self.floor.max(Some(deadline));
         }
-        if let Some(duplicate_probability) = settings.duplicate_probability {
-            self.duplicate_probability = duplicate_probability;
+
+        let deadline = now + delay.unwrap_or_default();
+        match self.floor.filter(|floor| deadline <= *floor) {
+            Some(floor) => {
+                // Just after the floor rather than at it, so that the item is emitted after the one it would overtake.
+                // The floor moves along, so that items delayed this way keep their order.
+                let deadline = floor + Duration::from_nanos(1);
+                self.floor = Some(deadline);
+                self.recent.push_back((now, deadline));
+                Some(deadline - now)
+            }
+            None => {
+                self.recent.push_back((now, deadline));
+                delay
+            }
         }
-        if let Some(ordering) = settings.ordering {
-            self.ordering = ordering;
-            self.queue = Queue::queue_for_ordering(ordering);
+    }
+}
+
+/// Items merged into one, see [`ChokeSettings::set_coalescing`].
+struct Batch<T> {
+    item: T,
+    /// The info, whether it is a duplicate and the size of every item merged into `item`.
+    parts: Vec<(ItemInfo, bool, usize)>,
+    /// When the first item was taken from the queue.
+    started: Instant,
+}
+
+impl<T: ChokeItem> Batch<T> {
+    fn new(queued: Queued<T>, now: Instant) -> Self {
+        Self {
+            parts: vec![(queued.info, queued.duplicate, queued.item.byte_len())],
+            item: queued.item,
+            started: now,
         }
-        if let Some(bandwidth_limit) = settings.bandwidth_limit {
-            self.bandwidth_limit = bandwidth_limit;
+    }
+}
+
+/// Items that are held back waiting for more data, see [`ChokeSettings::set_nagle`] and [`ChokeSettings::set_burst`].
+struct Held<T> {
+    items: VecDeque<Queued<T>>,
+    /// The size of `items` in bytes.
+    bytes: usize,
+    /// When the first of `items` was held back.
+    since: Option<Instant>,
+    /// Items that were released and are emitted next.
+    released: VecDeque<Queued<T>>,
+}
+
+impl<T> Default for Held<T> {
+    fn default() -> Self {
+        Self {
+            items: VecDeque::new(),
+            bytes: 0,
+            since: None,
+            released: VecDeque::new(),
         }
     }
+}
 
-    pub(crate) fn pending(&self) -> bool {
-        self.queue.pending()
+impl<T: ChokeItem> Held<T> {
+    fn hold(&mut self, queued: Queued<T>, now: Instant) {
+        self.since.get_or_insert(now);
+        self.bytes += queued.item.byte_len();
+        self.items.push_back(queued);
     }
 
-    pub(crate) fn has_dropped_item(&self) -> bool {
-        self.has_dropped_item
+    fn release(&mut self) {
+        self.released.append(&mut self.items);
+        self.bytes = 0;
+        self.since = None;
+    }
+}
+
+impl<T> Held<T> {
+    fn is_empty(&self) -> bool {
+        self.items.is_empty() && self.released.is_empty()
     }
 
-    pub(crate) fn reset_dropped_item(&mut self) {
-        self.has_dropped_item = false;
+    fn len(&self) -> usize {
+        self.items.len() + self.released.len()
     }
 
-    fn backpressure(&self) -> bool {
-        self.ordering == ChokeSettingsOrder::Backpressure
+    /// The released and held items, in the order in which they would have been emitted.
+    fn into_items(self) -> impl Iterator<Item = Queued<T>> {
+        self.released.into_iter().chain(self.items)
     }
 }
 
+/// An item taken from the inner stream that waits to be emitted.
+struct Queued<T> {
+    item: T,
+    info: ItemInfo,
+    /// Whether the item is the duplicate of another item.
+    duplicate: bool,
+    /// Whether the item is pushed back once it is due, see [`ChokeSettings::set_reorder`].
+    reorder: bool,
+}
+
 enum Queue<T> {
     Unordered(UnorderedQueue<T>),
     Ordered(OrderedQueue<T>),
+    PartiallyOrdered(PartiallyOrderedQueue<T>),
 }
 
 impl<T> Queue<T> {
@@ -187,9 +1578,17 @@ impl<T> Queue<T> {
                 queue: VecDeque::new(),
                 delayed: 0,
             }),
-            ChokeSettingsOrder::Unordered | ChokeSettingsOrder::Backpressure => Queue::Unordered(UnorderedQueue {
+            ChokeSettingsOrder::Unordered => Queue::Unordered(UnorderedQueue {
                 queue: VecDeque::new(),
                 delay_queue: BTreeMap::new(),
+                next_id: 0,
+            }),
+            ChokeSettingsOrder::PartiallyOrdered => Queue::PartiallyOrdered(PartiallyOrderedQueue {
+                queue: VecDeque::new(),
+                delayed: OrderedQueue {
+                    queue: VecDeque::new(),
+                    delayed: 0,
+                },
             }),
         }
     }
@@ -198,6 +1597,16 @@ impl<T> Queue<T> {
         match self {
             Queue::Unordered(q) => q.queued(),
             Queue::Ordered(q) => q.queued(),
+            Queue::PartiallyOrdered(q) => q.queued(),
+        }
+    }
+
+    /// The number of queued and delayed items.
+    fn len(&self) -> usize {
+        match self {
+            Queue::Unordered(q) => q.queued() + q.delayed(),
+            Queue::Ordered(q) => q.queued(),
+            Queue::PartiallyOrdered(q) => q.queued(),
         }
     }
 
@@ -205,6 +1614,7 @@ impl<T> Queue<T> {
         match self {
             Queue::Unordered(q) => q.delayed(),
             Queue::Ordered(q) => q.delayed(),
+            Queue::PartiallyOrdered(q) => q.delayed.delayed(),
         }
     }
 
@@ -212,6 +1622,7 @@ impl<T> Queue<T> {
         match self {
             Queue::Unordered(q) => q.pending(),
             Queue::Ordered(q) => q.pending(),
+            Queue::PartiallyOrdered(q) => q.pending(),
         }
     }
 
@@ -219,13 +1630,14 @@ impl<T> Queue<T> {
         match self {
             Queue::Unordered(q) => q.deadline(),
             Queue::Ordered(q) => q.deadline(),
+            Queue::PartiallyOrdered(q) => q.deadline(),
         }
     }
 
     fn expire(&mut self, now: Instant) {
         match self {
             Queue::Unordered(q) => q.expire(now),
-            Queue::Ordered(_) => {}
+            Queue::Ordered(_) | Queue::PartiallyOrdered(_) => {}
         }
     }
 
@@ -233,6 +1645,7 @@ impl<T> Queue<T> {
         match self {
             Queue::Unordered(q) => q.pop_front(),
             Queue::Ordered(q) => q.pop_front(now),
+            Queue::PartiallyOrdered(q) => q.pop_front(now),
         }
     }
 
@@ -240,6 +1653,7 @@ impl<T> Queue<T> {
         match self {
             Queue::Unordered(q) => q.push_front(item, delay, now),
             Queue::Ordered(q) => q.push(true, item, delay, now),
+            Queue::PartiallyOrdered(q) => q.push(true, item, delay, now),
         }
     }
 
@@ -247,13 +1661,25 @@ impl<T> Queue<T> {
         match self {
             Queue::Unordered(q) => q.push_back(item, delay, now),
             Queue::Ordered(q) => q.push(false, item, delay, now),
+            Queue::PartiallyOrdered(q) => q.push(false, item, delay, now),
+        }
+    }
+
+    fn into_items(self) -> Vec<T> {
+        match self {
+            Queue::Unordered(q) => q.into_items(),
+            Queue::Ordered(q) => q.into_items(),
+            Queue::PartiallyOrdered(q) => q.into_items(),
         }
     }
 }
 
 struct UnorderedQueue<T> {
     queue: VecDeque<T>,
-    delay_queue: BTreeMap<Instant, T>,
+    /// The delayed items by deadline. The id keeps items with the same deadline apart and in the order they were
+    /// queued.
+    delay_queue: BTreeMap<(Instant, u64), T>,
+    next_id: u64,
 }
 
 impl<T> UnorderedQueue<T> {
@@ -270,13 +1696,16 @@ impl<T> UnorderedQueue<T> {
     }
 
     fn deadline(&self) -> Option<Instant> {
-        self.delay_queue.keys().next().copied()
+        self.delay_queue.keys().next().map(|(deadline, _)| *deadline)
     }
 
     fn expire(&mut self, now: Instant) {
-        let still_delayed = self.delay_queue.split_off(&now);
-        let expired = std::mem::replace(&mut self.delay_queue, still_delayed);
-        self.queue.extend(expired.into_values());
+        while let Some(entry) = self.delay_queue.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            self.queue.push_back(entry.remove());
+        }
     }
 
     fn pop_front(&mut self) -> Option<T> {
@@ -285,8 +1714,7 @@ impl<T> UnorderedQueue<T> {
 
     fn push_front(&mut self, item: T, delay: Option<Duration>, now: Instant) {
         if let Some(delay) = delay {
-            let instant = now + delay;
-            self.delay_queue.insert(instant, item);
+            self.delay(item, now + delay);
         } else {
             self.queue.push_front(item);
         }
@@ -294,12 +1722,20 @@ impl<T> UnorderedQueue<T> {
 
     fn push_back(&mut self, item: T, delay: Option<Duration>, now: Instant) {
         if let Some(delay) = delay {
-            let instant = now + delay;
-            self.delay_queue.insert(instant, item);
+            self.delay(item, now + delay);
         } else {
             self.queue.push_back(item);
         }
     }
+
+    fn delay(&mut self, item: T, deadline: Instant) {
+        self.delay_queue.insert((deadline, self.next_id), item);
+        self.next_id += 1;
+    }
+
+    fn into_items(self) -> Vec<T> {
+        self.queue.into_iter().chain(self.delay_queue.into_values()).collect()
+    }
 }
 
 struct OrderedQueue<T> {
@@ -348,177 +1784,458 @@ impl<T> OrderedQueue<T> {
             self.queue.push_back(item)
         };
     }
+
+    fn into_items(self) -> Vec<T> {
+        self.queue.into_iter().map(|(_, item)| item).collect()
+    }
+}
+
+/// Items without a delay in a FIFO queue that is not blocked by the delayed items, which are kept in order among each
+/// other.
+struct PartiallyOrderedQueue<T> {
+    queue: VecDeque<T>,
+    delayed: OrderedQueue<T>,
+}
+
+impl<T> PartiallyOrderedQueue<T> {
+    fn queued(&self) -> usize {
+        self.queue.len() + self.delayed.queued()
+    }
+
+    fn pending(&self) -> bool {
+        !self.queue.is_empty() || self.delayed.pending()
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        if self.queue.is_empty() {
+            self.delayed.deadline()
+        } else {
+            None
+        }
+    }
+
+    fn pop_front(&mut self, now: Instant) -> Option<T> {
+        self.queue.pop_front().or_else(|| self.delayed.pop_front(now))
+    }
+
+    fn push(&mut self, front: bool, item: T, delay: Option<Duration>, now: Instant) {
+        match delay {
+            Some(_) => self.delayed.push(front, item, delay, now),
+            None if front => self.queue.push_front(item),
+            None => self.queue.push_back(item),
+        }
+    }
+
+    fn into_items(self) -> Vec<T> {
+        self.queue.into_iter().chain(self.delayed.into_items()).collect()
+    }
+}
+
+/// Wraps any [`Stream`] in a [`ChokeStream`].
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{ChokeSettings, ChokeStreamExt as _};
+/// # use bytes::Bytes;
+/// # use futures::StreamExt as _;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let items = futures::stream::iter((0..10).map(|_| Bytes::from_static(b"hello")));
+/// let output = items
+///     .choke(ChokeSettings::default().set_drop_probability(Some(0.1)))
+///     .collect::<Vec<_>>()
+///     .await;
+/// assert!(output.len() <= 10);
+/// # }
+/// ```
+pub trait ChokeStreamExt: Stream + Sized {
+    /// Shape the items of this stream with `settings`, see [`ChokeStream::new`].
+    fn choke(self, settings: ChokeSettings) -> ChokeStream<Self::Item, Self>
+    where
+        Self::Item: ChokeItem,
+    {
+        ChokeStream::new(self, settings)
+    }
 }
 
-impl<T> Stream for ChokeStream<T>
+impl<S: Stream> ChokeStreamExt for S {}
+
+impl<T, S> Stream for ChokeStream<T, S>
 where
     T: ChokeItem,
+    S: Stream<Item = T>,
 {
     type Item = T;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let stream = this.stream;
+        let timer = this.timer;
+        let debug_timer = this.debug_timer;
+        let this = this.shaper;
+        let span = this.span.clone();
+        let _entered = span.enter();
+
         if VERBOSE {
             debug!(
-                queued = self.queue.queued(),
-                delayed = self.queue.delayed(),
+                queued = this.queue.queued(),
+                delayed = this.queue.delayed(),
                 "poll_next"
             );
         }
 
-        let this = self.get_mut();
+        this.update_settings(cx);
+        this.log_stats(this.clock.now());
 
-        if let Some(new_settings) = this.settings_rx.as_mut().and_then(|s| s.try_recv().ok()) {
-            debug!(?new_settings, "settings changed");
-            this.apply_settings(new_settings);
-        }
-
-        if this.debug_timer.poll_tick(cx).is_ready() {
-            this.debug_timer.reset();
-            debug!(
-                queued = this.queue.queued(),
-                delayed = this.queue.delayed(),
-                packets_per_second = %this.packets_per_second,
-                total_packets = %this.total_packets,
-                dropped_packets = %this.dropped_packets,
-                ordering = ?this.ordering,
-                "packets per second"
-            );
-            this.packets_per_second = 0;
+        // First, take packets from the receiver and process them.
+        let polled = this.accepts_items();
+        if polled {
+            this.poll_inner(stream, cx);
         }
 
-        let now = Instant::now();
-        let mut rng = rand::rng();
+        let stream_ended = this.stream_ended;
+        match this.poll_emit(this.clock.now(), stream_ended) {
+            Poll::Ready(Some(packet)) => {
+                // Poll the stream again immediately for processing the next packet
+                cx.waker().wake_by_ref();
 
-        // First, take packets from the receiver and process them.
-        if !this.backpressure() || !this.queue.pending() {
-            if VERBOSE {
-                debug!("waiting for packets from inner stream");
+                this.report_idle(false, cx);
+                return Poll::Ready(Some(packet));
             }
-            loop {
-                match this.stream.poll_next_unpin(cx) {
-                    Poll::Ready(Some(mut packet)) => {
-                        if VERBOSE {
-                            debug!(bytes = %packet.byte_len(), "received packet");
-                        }
+            Poll::Ready(None) if this.stream_ended => {
+                this.report_idle(true, cx);
+                return Poll::Ready(None);
+            }
+            _ => {}
+        }
+        // With backpressure, the inner stream was not polled until it was pending. Once the item taken from it is no
+        // longer in flight, e.g. because it is held back for a burst, take the next one right away.
+        if this.accepts_items() && !(polled && this.stream_pending) {
+            cx.waker().wake_by_ref();
+        }
+        if let Some(deadline) = this.next_wakeup.take() {
+            timer.wake_at(&this.clock, deadline, cx);
+        }
 
-                        let bandwidth_drop = this.bandwidth_limit.as_mut().map_or(false, |limit| {
-                            limit.window.limit_reached() && rng.random::<f64>() < limit.drop_ratio
-                        });
-
-                        // Simulate packet loss
-                        if bandwidth_drop || rng.random::<f64>() < this.drop_probability {
-                            if VERBOSE {
-                                debug!("dropped packet bandwith_drop={bandwidth_drop}");
-                            }
-                            this.dropped_packets += 1;
-                            this.has_dropped_item = true;
-                            continue;
-                        }
+        // The debug timer is only registered while idle, polling it otherwise would let a virtual clock skip ahead.
+        if let Some(deadline) = this.stats_deadline() {
+            debug_timer.wake_at(&this.clock, deadline, cx);
+        }
 
-                        // Simulate packet corruption
-                        if rng.random::<f64>() < this.corrupt_probability {
-                            packet.corrupt();
-                        }
+        // The inner stream has registered the waker when it returned `Poll::Pending` above, unless the poll budget was
+        // used up. Polling it again here could yield an item that would bypass the shaping.
+        this.report_idle(this.is_idle(), cx);
+        Poll::Pending
+    }
 
-                        // Simulate latency using the user-defined distribution
-                        let delay = this.latency_distribution.as_mut().and_then(|latency_fn| latency_fn());
-
-                        // Simulate packet duplication
-                        let duplicate = (rng.random::<f64>() < this.duplicate_probability)
-                            .then(|| {
-                                if let Some(packet) = packet.duplicate() {
-                                    if VERBOSE {
-                                        debug!("duplicated packet");
-                                    }
-                                    Some(packet)
-                                } else {
-                                    warn!("Failed to duplicate packet");
-                                    None
-                                }
-                            })
-                            .flatten();
-
-                        // Insert the packet into the DelayQueue with the calculated delay
-                        this.queue.push_back(packet, delay, now);
-                        if let Some(duplicate) = duplicate {
-                            this.queue.push_back(duplicate, None, now);
-                        }
-                    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let this = &self.shaper;
+        let (lower, upper) = if this.stream_ended {
+            (0, Some(0))
+        } else {
+            self.stream.size_hint()
+        };
+        let in_flight = this.in_flight();
 
-                    Poll::Ready(None) if !this.queue.pending() => {
-                        return Poll::Ready(None);
-                    }
+        // Settings that are received later can change the probabilities, so we can't promise anything about the
+        // remaining items of the inner stream.
+        let live_updates =
+            this.settings_rx.is_some() || this.settings_watch.is_some() || this.settings_source.is_some();
 
-                    Poll::Ready(None) | Poll::Pending => {
-                        // No more packets to read at the moment
-                        break;
-                    }
+        // Items in flight expire, are merged into a batch or are dropped when the ordering changes
+        let may_drop_in_flight = live_updates || this.item_ttl.is_some() || this.coalescing.is_some();
+        // A replayed decision or a hook can drop and duplicate any item
+        let may_decide = !this.decision_hooks.is_empty() || matches!(this.decisions, Some(DecisionMode::Replay(_)));
+        // A bandwidth limit with ECN drops the items that can't be marked instead
+        let may_drop = may_drop_in_flight
+            || may_decide
+            || this.drop_probability > 0.0
+            || this
+                .bandwidth_limit
+                .as_ref()
+                .is_some_and(|limit| limit.limit.drop_ratio > 0.0)
+            || this
+                .shared_bandwidth_limit
+                .as_ref()
+                .is_some_and(|shared| shared.limit().drop_ratio > 0.0);
+        let lower = if may_drop_in_flight {
+            0
+        } else if may_drop {
+            in_flight
+        } else {
+            lower.saturating_add(in_flight)
+        };
+
+        let may_duplicate = live_updates || may_decide || this.duplicate_probability > 0.0;
+        // An item can be split into any number of fragments
+        let may_split = live_updates || this.mtu.is_some();
+        let upper = upper
+            .filter(|_| !may_split)
+            .and_then(|upper| {
+                if may_duplicate {
+                    upper.checked_mul(2)
+                } else {
+                    Some(upper)
                 }
-            }
-        }
+            })
+            .and_then(|upper| upper.checked_add(in_flight));
 
-        this.queue.expire(now);
+        (lower, upper)
+    }
+}
 
-        // Retrieve packets from the normal or delay queue
-        if VERBOSE {
-            debug!(pending = this.queue.pending(), "retrieving packet");
-        }
-        if let Some(packet) = this.queue.pop_front(now) {
-            // debug!(pending = this.queue.len(), "packet from queue");
+/// An item emitted by a [`ShapedStream`] with its [`EmitInfo`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shaped<T> {
+    pub item: T,
+    pub info: EmitInfo,
+}
 
-            // Simulate bandwidth limita
-            let limit = this.bandwidth_limit.as_mut().map_or(false, |limit| {
-                limit.window.update_at(now);
-                if !limit.window.limit_reached() {
-                    limit.window.add_request(packet.byte_len());
-                    false
-                } else {
-                    true
-                }
-            });
+/// A [`ChokeStream`] that yields every item as [`Shaped`], with the timing the shaper applied to it. Created with
+/// [`ChokeStream::shaped`].
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{ChokeSettings, ChokeStream, Latency};
+/// # use bytes::Bytes;
+/// # use futures::StreamExt as _;
+/// # use std::time::Duration;
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let items = futures::stream::iter((0..10).map(|_| Bytes::from_static(b"hello")));
+/// let settings = ChokeSettings::default().set_latency(Some(Latency::Constant(Duration::from_millis(20))));
+/// let mut stream = ChokeStream::new(items, settings).shaped();
+///
+/// while let Some(shaped) = stream.next().await {
+///     assert_eq!(shaped.info.one_way_delay(), Duration::from_millis(20));
+/// }
+/// assert_eq!(stream.get_ref().stats().emitted, 10);
+/// # }
+/// ```
+#[pin_project]
+pub struct ShapedStream<T, S> {
+    #[pin]
+    stream: ChokeStream<T, S>,
+}
 
-            if limit {
-                if VERBOSE {
-                    debug!(i = %this.total_packets,"bandwidth limit reached");
-                }
-                this.queue.push_front(packet, None, now);
-            } else {
-                if VERBOSE {
-                    debug!("emitting packet");
-                }
+impl<T, S> ShapedStream<T, S> {
+    /// Returns a reference to the [`ChokeStream`].
+    pub fn get_ref(&self) -> &ChokeStream<T, S> {
+        &self.stream
+    }
 
-                this.total_packets += 1;
-                this.packets_per_second += 1;
+    /// Returns a mutable reference to the [`ChokeStream`], e.g. to apply settings.
+    pub fn get_mut(&mut self) -> &mut ChokeStream<T, S> {
+        &mut self.stream
+    }
 
-                // Poll the stream again immediately for processing the next packet
-                cx.waker().wake_by_ref();
+    pub fn into_inner(self) -> ChokeStream<T, S> {
+        self.stream
+    }
+}
 
-                return Poll::Ready(Some(packet));
+impl<T, S> Stream for ShapedStream<T, S>
+where
+    T: ChokeItem,
+    S: Stream<Item = T>,
+{
+    type Item = Shaped<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut stream = self.project().stream;
+        let item = ready!(stream.as_mut().poll_next(cx));
+        // Every emitted item passes `Shaper::on_emit`, which sets the info.
+        Poll::Ready(item.map(|item| {
+            Shaped {
+                item,
+                info: stream
+                    .project()
+                    .shaper
+                    .emitted
+                    .take()
+                    .expect("emitted item without info"),
             }
+        }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint()
+    }
+}
+
+/// Several shapers in a row, e.g. the links of a path through a network: an access link, a backbone and the access
+/// link of the peer. Created with [`ChokeStream::chain`].
+///
+/// Every hop is configured with its own [`ChokeSettings`] and counts its own [`ChokeStats`]. The items emitted by a hop
+/// are handed to the next one right away, so an item can pass all hops within a single poll. Unlike nested
+/// [`ChokeStream`]s, the hops don't wake up the task for every item they hand on and wait with a single timer, which
+/// follows the clock of the first hop. All hops should use the same [`crate::clock::Clock`].
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{ChokeSettings, ChokeStream, Latency};
+/// # use bytes::Bytes;
+/// # use futures::StreamExt as _;
+/// # use std::time::Duration;
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let link = |millis| ChokeSettings::default().set_latency(Some(Latency::Constant(Duration::from_millis(millis))));
+/// let items = futures::stream::iter((0..100).map(|_| Bytes::from_static(b"hello")));
+/// let mut path = ChokeStream::new(items, link(5).set_bandwidth_limit(Some(100_000)))
+///     .chain(link(40).set_drop_probability(Some(0.01)))
+///     .chain(link(5));
+///
+/// let start = tokio::time::Instant::now();
+/// let received = path.by_ref().count().await;
+/// assert!(start.elapsed() >= Duration::from_millis(50));
+/// let stats = path.stats();
+/// assert_eq!(stats.len(), 3);
+/// assert_eq!(received, stats[2].emitted);
+/// # }
+/// ```
+#[pin_project]
+pub struct ChokeChain<T, S> {
+    #[pin]
+    stream: S,
+    /// The hops in the order in which the items pass them, at least two.
+    hops: Vec<Shaper<T>>,
+    timer: Timer,
+    debug_timer: Timer,
+}
+
+impl<T, S> ChokeChain<T, S> {
+    /// Shape the items emitted by the last hop once more with `settings`.
+    pub fn chain(mut self, settings: ChokeSettings) -> Self
+    where
+        T: ChokeItem,
+    {
+        self.hops.push(Shaper::new(settings));
+        self
+    }
+
+    /// The number of hops.
+    pub fn hops(&self) -> usize {
+        self.hops.len()
+    }
+
+    /// Apply `settings` to a hop, counted from 0 for the hop that takes the items from the inner stream, see
+    /// [`ChokeStream::apply_settings`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hop` is out of bounds.
+    pub fn apply_settings(&mut self, hop: usize, settings: ChokeSettings)
+    where
+        T: ChokeItem,
+    {
+        self.hops[hop].apply_settings(settings);
+    }
+
+    /// Returns the settings that are currently in effect for every hop, see [`ChokeStream::current_settings`].
+    pub fn current_settings(&self) -> Vec<ChokeSettingsSnapshot> {
+        self.hops.iter().map(Shaper::current_settings).collect()
+    }
+
+    /// Returns the counters of every hop, see [`ChokeStream::stats`].
+    pub fn stats(&self) -> Vec<ChokeStats> {
+        self.hops.iter().map(Shaper::stats).collect()
+    }
+
+    /// Returns the timing of every hop, see [`ChokeStream::timing_stats`].
+    pub fn timing_stats(&self) -> Vec<&TimingStats> {
+        self.hops.iter().map(|hop| &hop.timing_stats).collect()
+    }
+
+    /// Returns a stream of the events of a hop from now on, see [`ChokeStream::events`]. The sequence numbers count the
+    /// items taken by the hop.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hop` is out of bounds.
+    pub fn events(&mut self, hop: usize) -> ChokeEvents {
+        self.hops[hop].events.subscribe()
+    }
+
+    /// Consumes the `ChokeChain`, returning the inner stream and the items that were taken from it but not emitted yet,
+    /// in the order in which they would have been emitted, see [`ChokeStream::into_parts`].
+    pub fn into_parts(self) -> (S, Vec<T>) {
+        let items = self.hops.into_iter().rev().flat_map(Shaper::into_items).collect();
+        (self.stream, items)
+    }
+}
+
+impl<T, S> Stream for ChokeChain<T, S>
+where
+    T: ChokeItem,
+    S: Stream<Item = T>,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let hops = this.hops;
+
+        for hop in hops.iter_mut() {
+            let _entered = hop.span.clone().entered();
+            hop.update_settings(cx);
+            let now = hop.clock.now();
+            hop.log_stats(now);
         }
 
-        if VERBOSE {
-            debug!(
-                queue = this.queue.queued(),
-                delayed = this.queue.delayed(),
-                "Poll::Pending"
-            );
+        let first = &mut hops[0];
+        let polled = first.accepts_items();
+        if polled {
+            let _entered = first.span.clone().entered();
+            first.poll_inner(this.stream, cx);
         }
 
-        if this.pending() {
-            let now = Instant::now();
-            match this.queue.deadline() {
-                Some(deadline) if deadline > now => {
-                    this.timer = interval(deadline - now);
-                }
-                _ => {
-                    this.timer = interval(Duration::from_millis(20));
+        // Hand on the items that are due, the next hop decides their fate right away
+        for i in 1..hops.len() {
+            let (done, rest) = hops.split_at_mut(i);
+            let (hop, next) = (&mut done[i - 1], &mut rest[0]);
+            let now = hop.clock.now();
+            let (span, next_span) = (hop.span.clone(), next.span.clone());
+            while next.accepts_items() {
+                match span.in_scope(|| hop.poll_emit(now, hop.stream_ended)) {
+                    Poll::Ready(Some(item)) => next_span.in_scope(|| next.ingest(item, now)),
+                    Poll::Ready(None) => {
+                        next.stream_ended = hop.stream_ended;
+                        break;
+                    }
+                    Poll::Pending => break,
                 }
             }
-            let _ = this.timer.poll_tick(cx);
-            Poll::Pending
-        } else {
-            this.stream.poll_next_unpin(cx)
         }
+
+        let last = hops.last_mut().expect("a chain has at least two hops");
+        let stream_ended = last.stream_ended;
+        let span = last.span.clone();
+        match span.in_scope(|| last.poll_emit(last.clock.now(), stream_ended)) {
+            Poll::Ready(Some(item)) => {
+                // Poll again immediately for handing on the next items
+                cx.waker().wake_by_ref();
+                return Poll::Ready(Some(item));
+            }
+            Poll::Ready(None) if stream_ended => return Poll::Ready(None),
+            _ => {}
+        }
+        // With backpressure on the first hop, the inner stream was not polled until it was pending. Once the item taken
+        // from it is no longer in flight, e.g. because a later hop dropped it, take the next one right away.
+        let first = &hops[0];
+        if first.accepts_items() && !(polled && first.stream_pending) {
+            cx.waker().wake_by_ref();
+        }
+
+        // A single timer for the earliest deadline of all hops
+        let deadline = hops.iter_mut().filter_map(|hop| hop.next_wakeup.take()).min();
+        if let Some(deadline) = deadline {
+            this.timer.wake_at(&hops[0].clock, deadline, cx);
+        }
+        let debug_deadline = hops.iter().filter_map(Shaper::stats_deadline).min();
+        if let Some(debug_deadline) = debug_deadline {
+            this.debug_timer.wake_at(&hops[0].clock, debug_deadline, cx);
+        }
+
+        Poll::Pending
     }
 }
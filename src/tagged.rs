@@ -0,0 +1,222 @@
+//! Tagging items with a sequence number, so that loss, reordering and duplication can be checked on the output without
+//! the payloads having to embed their own counters.
+//!
+//! [`tag`] wraps every item of a stream in a [`Tagged`] with a monotonically increasing id, assigned when the
+//! [`crate::ChokeStream`] takes the item. Duplicates keep the id of the original. A [`SequenceAnalysis`] of the ids in
+//! the output lists the lost ids, the duplicates and how far items were reordered.
+//!
+//! Example:
+//! ```rust
+//! # use chokepoint::{tagged::{tag, SequenceAnalysis}, ChokeSettings, ChokeSettingsOrder, ChokeStream};
+//! # use bytes::Bytes;
+//! # use futures::StreamExt as _;
+//! # use std::time::Duration;
+//! # #[tokio::main]
+//! # async fn main() {
+//! let items = futures::stream::iter((0..100).map(|_| Bytes::from_static(b"hello")));
+//! let settings = ChokeSettings::default()
+//!     .set_drop_probability(Some(0.1))
+//!     .set_ordering(Some(ChokeSettingsOrder::Unordered))
+//!     .set_latency_distribution(Some(|| Some(Duration::from_micros(rand::random_range(0..1000)))));
+//! let output = ChokeStream::new(tag(items), settings).collect::<Vec<_>>().await;
+//!
+//! let analysis = SequenceAnalysis::from_items(&output, 100);
+//! assert_eq!(analysis.lost.len() + output.len(), 100);
+//! assert_eq!(analysis.duplicate_count(), 0);
+//! # }
+//! ```
+
+use crate::ChokeItem;
+use futures::{
+    stream::{
+        Enumerate,
+        Map,
+    },
+    Stream,
+    StreamExt as _,
+};
+use rand::RngCore;
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+/// An item with a sequence number, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tagged<T> {
+    pub id: usize,
+    pub item: T,
+}
+
+impl<T> Tagged<T> {
+    pub fn new(id: usize, item: T) -> Self {
+        Self { id, item }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.item
+    }
+}
+
+impl<T> ChokeItem for Tagged<T>
+where
+    T: ChokeItem,
+{
+    fn byte_len(&self) -> usize {
+        self.item.byte_len()
+    }
+
+    fn corrupt(&mut self) {
+        self.item.corrupt();
+    }
+
+    fn corrupt_with(&mut self, rng: &mut dyn RngCore) {
+        self.item.corrupt_with(rng);
+    }
+
+    fn duplicate(&mut self) -> Option<Self> {
+        let item = self.item.duplicate()?;
+        Some(Self { id: self.id, item })
+    }
+
+    fn payload(&self) -> Option<&[u8]> {
+        self.item.payload()
+    }
+
+    /// The fragments keep the id of the item.
+    fn split(self, mtu: usize) -> Vec<Self> {
+        let id = self.id;
+        self.item
+            .split(mtu)
+            .into_iter()
+            .map(|item| Tagged { id, item })
+            .collect()
+    }
+
+    /// The merged item keeps the id of the first item.
+    fn coalesce(&mut self, other: Self) -> Result<(), Self> {
+        let id = other.id;
+        self.item.coalesce(other.item).map_err(|item| Tagged { id, item })
+    }
+
+    fn mark_ecn(&mut self) -> bool {
+        self.item.mark_ecn()
+    }
+}
+
+/// The stream returned by [`tag`].
+pub type TaggedStream<S> = Map<Enumerate<S>, fn((usize, <S as Stream>::Item)) -> Tagged<<S as Stream>::Item>>;
+
+/// Tag the items of `stream` with their position, starting at 0.
+pub fn tag<S: Stream>(stream: S) -> TaggedStream<S> {
+    stream.enumerate().map(|(id, item)| Tagged { id, item })
+}
+
+/// Tags items with increasing ids, e.g. before sending them to a [`crate::ChokeSink`]. For streams use [`tag`].
+#[derive(Debug, Default, Clone)]
+pub struct Tagger {
+    next: usize,
+}
+
+impl Tagger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tag<T>(&mut self, item: T) -> Tagged<T> {
+        let id = self.next;
+        self.next += 1;
+        Tagged { id, item }
+    }
+}
+
+/// Loss, duplication and reordering in a sequence of received ids, see the [module documentation](self).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SequenceAnalysis {
+    /// The number of received items, including duplicates.
+    pub received: usize,
+    /// The ids within `0..sent` that were not received, in ascending order.
+    pub lost: Vec<usize>,
+    /// The number of extra copies received per duplicated id.
+    pub duplicates: BTreeMap<usize, usize>,
+    /// The ids that were received after a higher id, each with its reorder distance: the difference to the highest id
+    /// received before it. Only the first copy of an id is considered.
+    pub reordered: Vec<(usize, usize)>,
+}
+
+impl SequenceAnalysis {
+    /// Analyze the ids of the output in the order they were received. `sent` is the number of tagged items, i.e. the
+    /// ids `0..sent` are expected.
+    pub fn from_ids(ids: impl IntoIterator<Item = usize>, sent: usize) -> Self {
+        let mut analysis = Self::default();
+        let mut seen = vec![false; sent];
+        let mut unexpected = BTreeSet::new();
+        let mut highest = None;
+
+        for id in ids {
+            analysis.received += 1;
+            let first = match seen.get_mut(id) {
+                Some(seen) => !std::mem::replace(seen, true),
+                // Ids outside of the expected range are only checked for duplicates
+                None => unexpected.insert(id),
+            };
+            if !first {
+                *analysis.duplicates.entry(id).or_default() += 1;
+                continue;
+            }
+            match highest {
+                Some(highest) if id < highest => analysis.reordered.push((id, highest - id)),
+                _ => highest = Some(id),
+            }
+        }
+
+        analysis.lost = seen
+            .iter()
+            .enumerate()
+            .filter(|(_, seen)| !**seen)
+            .map(|(id, _)| id)
+            .collect();
+        analysis
+    }
+
+    /// Analyze the tagged items of the output in the order they were received, see [`SequenceAnalysis::from_ids`].
+    pub fn from_items<'a, T: 'a>(items: impl IntoIterator<Item = &'a Tagged<T>>, sent: usize) -> Self {
+        Self::from_ids(items.into_iter().map(|tagged| tagged.id), sent)
+    }
+
+    /// The total number of extra copies.
+    pub fn duplicate_count(&self) -> usize {
+        self.duplicates.values().sum()
+    }
+
+    /// The largest reorder distance, 0 if the items were received in order.
+    pub fn max_reorder_distance(&self) -> usize {
+        self.reordered.iter().map(|(_, distance)| *distance).max().unwrap_or(0)
+    }
+
+    /// Returns `true` if every id was received exactly once and in order.
+    pub fn is_perfect(&self) -> bool {
+        self.lost.is_empty() && self.duplicates.is_empty() && self.reordered.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn analyze_ids() {
+        let analysis = SequenceAnalysis::from_ids([0, 2, 1, 2, 5, 3, 6, 6, 6], 7);
+        assert_eq!(analysis.received, 9);
+        assert_eq!(analysis.lost, [4]);
+        assert_eq!(analysis.duplicates, BTreeMap::from([(2, 1), (6, 2)]));
+        assert_eq!(analysis.duplicate_count(), 3);
+        assert_eq!(analysis.reordered, [(1, 1), (3, 2)]);
+        assert_eq!(analysis.max_reorder_distance(), 2);
+        assert!(!analysis.is_perfect());
+
+        assert!(SequenceAnalysis::from_ids(0..10, 10).is_perfect());
+        assert_eq!(SequenceAnalysis::from_ids(0..5, 10).lost, [5, 6, 7, 8, 9]);
+    }
+}
@@ -0,0 +1,191 @@
+//! Items and sinks for testing code that uses chokepoint, enabled with the `test-util` feature. The module depends on
+//! the feature alone, so it is available in release-mode test builds as well.
+//!
+//! [`TestPayload`] records when it was created, so that the time it spent in a [`crate::ChokeStream`] or
+//! [`crate::ChokeSink`] can be measured, and [`TestSink`] records when every payload was received.
+//!
+//! The assertions check the [`Tagged`] output of a shaper, see the [`crate::tagged`] module:
+//! [`assert_monotonic_order`], [`assert_loss_rate_within`] and [`reorder_metrics`], e.g. to assert the probabilistic
+//! behavior of a shaper with a tolerance instead of exact counts.
+//!
+//! Example:
+//! ```rust
+//! # use chokepoint::{test_util::{TestPayload, TestSink}, ChokeSettings, ChokeSink};
+//! # use futures::SinkExt as _;
+//! # #[tokio::main]
+//! # async fn main() {
+//! let mut sink = ChokeSink::new(TestSink::default(), ChokeSettings::default());
+//! for i in 0..10 {
+//!     sink.send(TestPayload::new(i, 100)).await.unwrap();
+//! }
+//! sink.close().await.unwrap();
+//! assert_eq!(sink.get_ref().received.borrow().len(), 10);
+//! # }
+//! ```
+
+use crate::{
+    tagged::{
+        SequenceAnalysis,
+        Tagged,
+    },
+    ChokeItem,
+};
+use chrono::prelude::*;
+use futures::Sink;
+use std::{
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::Duration,
+};
+
+#[derive(Debug)]
+pub struct TestPayload {
+    pub created: DateTime<Utc>,
+    pub i: usize,
+    pub size: usize,
+    /// Whether the payload was corrupted, as it has no bytes that could be.
+    pub corrupted: bool,
+}
+
+impl std::fmt::Display for TestPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Payload(time={}, i={})", self.created.to_rfc3339(), self.i)
+    }
+}
+
+impl TestPayload {
+    pub fn new(i: usize, size: usize) -> Self {
+        Self {
+            created: Utc::now(),
+            size,
+            i,
+            corrupted: false,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Utc::now().signed_duration_since(self.created).to_std().unwrap()
+    }
+}
+
+impl ChokeItem for TestPayload {
+    fn byte_len(&self) -> usize {
+        self.size
+    }
+
+    fn corrupt(&mut self) {
+        self.corrupted = true;
+    }
+
+    /// A copy with the same creation time, so that the time the duplicate spent in the shaper is measured as well.
+    fn duplicate(&mut self) -> Option<Self> {
+        Some(Self {
+            created: self.created,
+            i: self.i,
+            size: self.size,
+            corrupted: self.corrupted,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct TestSink {
+    pub received: std::cell::RefCell<Vec<(DateTime<Utc>, TestPayload)>>,
+}
+
+impl Sink<TestPayload> for TestSink {
+    type Error = ();
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        trace!("poll_ready");
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: TestPayload) -> Result<(), Self::Error> {
+        trace!("[{item}] received");
+        self.received.borrow_mut().push((Utc::now(), item));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        trace!("poll_flush");
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        trace!("poll_close");
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Asserts that the ids of the output never decrease, i.e. that no item overtook an earlier one. Duplicates may follow
+/// their original.
+///
+/// # Panics
+///
+/// Panics with the first id that was received after a higher one.
+#[track_caller]
+pub fn assert_monotonic_order<T>(output: &[Tagged<T>]) {
+    for (i, pair) in output.windows(2).enumerate() {
+        let (previous, id) = (pair[0].id, pair[1].id);
+        assert!(
+            id >= previous,
+            "id {id} at position {} was received after id {previous}",
+            i + 1
+        );
+    }
+}
+
+/// Asserts that the share of the ids `0..sent` missing from the output is within `tolerance` of `expected`, e.g.
+/// `assert_loss_rate_within(&output, 1000, 0.1, 0.03)` for a drop probability of 10%.
+///
+/// # Panics
+///
+/// Panics with the actual loss rate if it is off by more than the tolerance.
+#[track_caller]
+pub fn assert_loss_rate_within<T>(output: &[Tagged<T>], sent: usize, expected: f64, tolerance: f64) {
+    let lost = SequenceAnalysis::from_items(output, sent).lost.len();
+    let rate = lost as f64 / sent as f64;
+    assert!(
+        (rate - expected).abs() <= tolerance,
+        "loss rate {rate:.4} ({lost} of {sent}) is not within {expected} ± {tolerance}"
+    );
+}
+
+/// How much the output of a shaper was reordered, see [`reorder_metrics`].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ReorderMetrics {
+    /// The number of ids received after a higher id. Only the first copy of an id is considered.
+    pub reordered: usize,
+    /// The share of reordered ids among the received ids.
+    pub ratio: f64,
+    /// The largest reorder distance, see [`SequenceAnalysis::reordered`].
+    pub max_distance: usize,
+    /// The mean reorder distance of the reordered ids, 0 if none were reordered.
+    pub mean_distance: f64,
+}
+
+/// Measures how much the output was reordered.
+pub fn reorder_metrics<T>(output: &[Tagged<T>]) -> ReorderMetrics {
+    let analysis = SequenceAnalysis::from_items(output, 0);
+    let received = analysis.received - analysis.duplicate_count();
+    let reordered = analysis.reordered.len();
+    let total_distance = analysis.reordered.iter().map(|(_, distance)| distance).sum::<usize>();
+    ReorderMetrics {
+        reordered,
+        ratio: if received == 0 {
+            0.0
+        } else {
+            reordered as f64 / received as f64
+        },
+        max_distance: analysis.max_reorder_distance(),
+        mean_distance: if reordered == 0 {
+            0.0
+        } else {
+            total_distance as f64 / reordered as f64
+        },
+    }
+}
@@ -1,14 +1,88 @@
-#![allow(unused_imports)]
-
+use crate::clock::{
+    SharedClock,
+    Sleep,
+};
+use std::task::{
+    Context,
+    Poll,
+};
 #[cfg(not(target_arch = "wasm32"))]
 pub use std::time::*;
-#[cfg(not(target_arch = "wasm32"))]
-pub use tokio::time as tokio_time;
-#[cfg(not(target_arch = "wasm32"))]
-pub use tokio_util::time as tokio_util;
 #[cfg(target_arch = "wasm32")]
-pub use wasmtimer::{
-    std::*,
-    tokio as tokio_time,
-    tokio_util,
-};
+pub use wasmtimer::std::*;
+
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "tokio"), not(feature = "futures-timer")))]
+compile_error!("either the `tokio` or the `futures-timer` feature needs to be enabled");
+
+// The timer of the runtime used by the `SystemClock`. futures-timer takes precedence as it works with any runtime,
+// including tokio.
+#[cfg(all(not(target_arch = "wasm32"), feature = "futures-timer"))]
+type RuntimeSleep = futures_timer::Delay;
+#[cfg(all(not(target_arch = "wasm32"), feature = "tokio", not(feature = "futures-timer")))]
+type RuntimeSleep = tokio::time::Sleep;
+#[cfg(target_arch = "wasm32")]
+type RuntimeSleep = wasmtimer::tokio::Sleep;
+
+/// The current time of the runtime. With tokio, this follows the paused test clock (`tokio::time::pause`).
+#[cfg(all(not(target_arch = "wasm32"), feature = "tokio", not(feature = "futures-timer")))]
+pub(crate) fn now() -> Instant {
+    tokio::time::Instant::now().into_std()
+}
+/// The current time of the runtime.
+#[cfg(any(target_arch = "wasm32", feature = "futures-timer"))]
+pub(crate) fn now() -> Instant {
+    Instant::now()
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "futures-timer"))]
+pub(crate) fn sleep_until(deadline: Instant) -> RuntimeSleep {
+    futures_timer::Delay::new(deadline.saturating_duration_since(now()))
+}
+#[cfg(all(not(target_arch = "wasm32"), feature = "tokio", not(feature = "futures-timer")))]
+pub(crate) fn sleep_until(deadline: Instant) -> RuntimeSleep {
+    tokio::time::sleep_until(deadline.into())
+}
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn sleep_until(deadline: Instant) -> RuntimeSleep {
+    wasmtimer::tokio::sleep_until(deadline)
+}
+
+/// Wakes up the task polling it once a deadline of a [`crate::clock::Clock`] has passed.
+pub(crate) struct Timer {
+    sleep: Option<(Instant, Sleep)>,
+}
+
+impl Timer {
+    /// A timer that is not armed, polling it never completes.
+    pub(crate) fn new() -> Self {
+        Self { sleep: None }
+    }
+
+    /// Arm the timer to complete once `clock` reaches `deadline`, replacing the previous deadline.
+    pub(crate) fn reset(&mut self, clock: &SharedClock, deadline: Instant) {
+        if !matches!(&self.sleep, Some((current, _)) if *current == deadline) {
+            self.sleep = Some((deadline, clock.sleep_until(deadline)));
+        }
+    }
+
+    /// Arm the timer for `deadline` and poll it, so that the task is woken up then. Wakes the task up right away if the
+    /// deadline has passed in the meantime (e.g. a virtual clock advanced), as no wakeup is registered in that case.
+    pub(crate) fn wake_at(&mut self, clock: &SharedClock, deadline: Instant, cx: &mut Context<'_>) {
+        self.reset(clock, deadline);
+        if self.poll(cx).is_ready() {
+            cx.waker().wake_by_ref();
+        }
+    }
+
+    /// Completes once the deadline has passed, afterwards the timer is disarmed.
+    pub(crate) fn poll(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let Some((_, sleep)) = self.sleep.as_mut() else {
+            return Poll::Pending;
+        };
+        let poll = sleep.as_mut().poll(cx);
+        if poll.is_ready() {
+            self.sleep = None;
+        }
+        poll
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{
+    item::ChokeItem,
+    sink::ChokeSinkError,
+    ChokeSettings,
+    ChokeSink,
+    ChokeStream,
+};
+use futures::{
+    stream::SplitSink,
+    Sink,
+    Stream,
+    StreamExt,
+};
+use std::{
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+};
+
+/// Wraps a transport that is both a [`futures::Stream`] and a [`futures::Sink`] (e.g. a WebSocket or a framed TCP
+/// connection) and shapes the read direction with a [`ChokeStream`] and the write direction with a [`ChokeSink`], each
+/// with its own settings.
+///
+/// Delayed writes are forwarded whenever the transport is flushed or polled for the next item, so a task that is
+/// reading from the transport also delivers pending writes.
+///
+/// Example:
+/// ```rust
+/// # use chokepoint::{ChokeSettings, ChokeTransport};
+/// # use bytes::Bytes;
+/// # use futures::{Sink, SinkExt as _, Stream};
+/// async fn shape<Tr>(transport: Tr)
+/// where
+///     Tr: Stream<Item = Bytes> + Sink<Bytes> + 'static,
+///     Tr::Error: std::fmt::Debug,
+/// {
+///     let mut read = ChokeSettings::default().set_drop_probability(Some(0.1));
+///     let mut write = ChokeSettings::default().set_bandwidth_limit(Some(10_000));
+///
+///     // One live update handle per direction
+///     let read_updater = read.settings_watcher();
+///     let write_updater = write.settings_watcher();
+///
+///     let mut transport = ChokeTransport::new(transport, read, write);
+///     transport.send(Bytes::from_static(b"hello")).await.unwrap();
+///
+///     write_updater.send_replace(ChokeSettings::default().set_bandwidth_limit(None));
+/// }
+/// ```
+#[pin_project]
+pub struct ChokeTransport<Tr, W>
+where
+    Tr: Stream + Sink<W>,
+{
+    stream: ChokeStream<Tr::Item>,
+    sink: ChokeSink<SplitSink<Tr, W>, W>,
+    /// An error of the write direction that occurred while reading, returned by the next write operation.
+    write_error: Option<ChokeSinkError<<Tr as Sink<W>>::Error>>,
+}
+
+impl<Tr, W> ChokeTransport<Tr, W>
+where
+    Tr: Stream + Sink<W> + 'static,
+    Tr::Item: ChokeItem,
+    W: ChokeItem,
+{
+    /// Wrap `transport`, shaping items read from it according to `read` and items written to it according to `write`.
+    pub fn new(transport: Tr, read: ChokeSettings, write: ChokeSettings) -> Self {
+        let (sink, stream) = transport.split();
+        Self {
+            stream: ChokeStream::new(Box::new(stream), read),
+            sink: ChokeSink::new(sink, write),
+            write_error: None,
+        }
+    }
+
+    /// The [`ChokeStream`] shaping the read direction.
+    pub fn stream(&self) -> &ChokeStream<Tr::Item> {
+        &self.stream
+    }
+
+    /// The [`ChokeSink`] shaping the write direction.
+    pub fn sink(&self) -> &ChokeSink<SplitSink<Tr, W>, W> {
+        &self.sink
+    }
+
+    /// Split into the independently usable read and write halves.
+    #[allow(clippy::type_complexity)]
+    pub fn split(self) -> (ChokeStream<Tr::Item>, ChokeSink<SplitSink<Tr, W>, W>) {
+        (self.stream, self.sink)
+    }
+}
+
+impl<Tr, W> Stream for ChokeTransport<Tr, W>
+where
+    Tr: Stream + Sink<W> + 'static,
+    Tr::Item: ChokeItem,
+    W: ChokeItem,
+{
+    type Item = Tr::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if this.write_error.is_none() {
+            if let Poll::Ready(Err(err)) = Pin::new(&mut *this.sink).poll_flush(cx) {
+                *this.write_error = Some(err);
+            }
+        }
+        this.stream.poll_next_unpin(cx)
+    }
+}
+
+impl<Tr, W> Sink<W> for ChokeTransport<Tr, W>
+where
+    Tr: Stream + Sink<W> + 'static,
+    W: ChokeItem,
+{
+    type Error = ChokeSinkError<Tr::Error>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        if let Some(err) = this.write_error.take() {
+            return Poll::Ready(Err(err));
+        }
+        Pin::new(this.sink).poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: W) -> Result<(), Self::Error> {
+        let this = self.project();
+        if let Some(err) = this.write_error.take() {
+            return Err(err);
+        }
+        Pin::new(this.sink).start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        if let Some(err) = this.write_error.take() {
+            return Poll::Ready(Err(err));
+        }
+        Pin::new(this.sink).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        if let Some(err) = this.write_error.take() {
+            return Poll::Ready(Err(err));
+        }
+        Pin::new(this.sink).poll_close(cx)
+    }
+}
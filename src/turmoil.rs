@@ -0,0 +1,142 @@
+//! Shaping the UDP traffic of a [turmoil](https://docs.rs/turmoil) simulation.
+//!
+//! turmoil runs hosts with deterministic scheduling on a simulated network that only models basic link properties
+//! (latency, message loss and partitions). [`udp_stream`] and [`udp_sink`] put a [`ChokeStream`] / [`ChokeSink`] in
+//! front of a turmoil [`UdpSocket`] to add chokepoint's latency distributions, bandwidth limits, corruption,
+//! duplication and reordering on top.
+//!
+//! turmoil runs each host on a tokio runtime with paused time, which the default clock follows, so all delays are
+//! simulated time. Note that the random decisions of chokepoint are not derived from the seed of the simulation.
+//!
+//! Example:
+//! ```rust
+//! # use chokepoint::{turmoil::{udp_sink, Datagram}, ChokeSettings};
+//! # use futures::SinkExt as _;
+//! # use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+//! # use turmoil::net::UdpSocket;
+//! # fn main() -> turmoil::Result {
+//! let mut sim = turmoil::Builder::new().build();
+//! sim.client("client", async {
+//!     let socket = Arc::new(UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?);
+//!     let settings = ChokeSettings::default().set_latency_distribution(Some(|| Some(Duration::from_millis(100))));
+//!     let mut sink = udp_sink(socket, settings);
+//!     sink.send(Datagram::new("hello", (Ipv4Addr::LOCALHOST, 1234).into())).await?;
+//!     // Closing delivers all delayed datagrams
+//!     sink.close().await?;
+//!     Ok(())
+//! });
+//! sim.run()
+//! # }
+//! ```
+
+use crate::{
+    ChokeItem,
+    ChokeSettings,
+    ChokeSink,
+    ChokeStream,
+};
+use bytes::Bytes;
+use futures::{
+    stream::LocalBoxStream,
+    Sink,
+    StreamExt as _,
+};
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+};
+use turmoil::net::UdpSocket;
+
+/// The largest possible UDP payload.
+const MAX_DATAGRAM_SIZE: usize = 65_535;
+
+/// A datagram received from or sent to a turmoil [`UdpSocket`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    pub payload: Bytes,
+    /// The address the datagram was received from, or is sent to.
+    pub addr: SocketAddr,
+}
+
+impl Datagram {
+    pub fn new(payload: impl Into<Bytes>, addr: SocketAddr) -> Self {
+        Self {
+            payload: payload.into(),
+            addr,
+        }
+    }
+}
+
+impl ChokeItem for Datagram {
+    fn byte_len(&self) -> usize {
+        self.payload.byte_len()
+    }
+
+    fn corrupt(&mut self) {
+        self.payload.corrupt();
+    }
+
+    fn corrupt_with(&mut self, rng: &mut dyn rand::RngCore) {
+        self.payload.corrupt_with(rng);
+    }
+
+    fn duplicate(&mut self) -> Option<Self> {
+        Some(self.clone())
+    }
+
+    fn payload(&self) -> Option<&[u8]> {
+        Some(&self.payload)
+    }
+
+    fn split(self, mtu: usize) -> Vec<Self> {
+        let addr = self.addr;
+        self.payload
+            .split(mtu)
+            .into_iter()
+            .map(|payload| Datagram { payload, addr })
+            .collect()
+    }
+
+    /// Only datagrams from or to the same address are merged.
+    fn coalesce(&mut self, other: Self) -> Result<(), Self> {
+        if self.addr != other.addr {
+            return Err(other);
+        }
+        let addr = other.addr;
+        self.payload
+            .coalesce(other.payload)
+            .map_err(|payload| Datagram { payload, addr })
+    }
+}
+
+/// The datagrams received by a turmoil socket, shaped by a [`ChokeStream`].
+pub type UdpStream = ChokeStream<io::Result<Datagram>, LocalBoxStream<'static, io::Result<Datagram>>>;
+
+/// The sink returned by [`udp_sink`].
+pub type UdpSink = ChokeSink<Pin<Box<dyn Sink<Datagram, Error = io::Error>>>, Datagram>;
+
+/// Receive datagrams from `socket`, shaped according to `settings`. Receive errors are passed through, the stream
+/// never ends.
+pub fn udp_stream(socket: Arc<UdpSocket>, settings: ChokeSettings) -> UdpStream {
+    let datagrams = futures::stream::unfold(socket, |socket| async move {
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        let datagram = socket.recv_from(&mut buf).await.map(|(len, addr)| {
+            buf.truncate(len);
+            Datagram::new(buf, addr)
+        });
+        Some((datagram, socket))
+    });
+    ChokeStream::new(datagrams.boxed_local(), settings)
+}
+
+/// Send datagrams to their address via `socket`, shaped according to `settings`. Like any [`ChokeSink`], delayed
+/// datagrams are sent when the sink is flushed or closed.
+pub fn udp_sink(socket: Arc<UdpSocket>, settings: ChokeSettings) -> UdpSink {
+    let sink = futures::sink::unfold(socket, |socket, datagram: Datagram| async move {
+        socket.send_to(&datagram.payload, datagram.addr).await?;
+        Ok::<_, io::Error>(socket)
+    });
+    ChokeSink::new(Box::pin(sink), settings)
+}
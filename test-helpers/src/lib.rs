@@ -1,8 +0,0 @@
-#[macro_use]
-extern crate tracing;
-
-mod payload;
-mod sink;
-
-pub use payload::*;
-pub use sink::*;
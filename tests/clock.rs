@@ -0,0 +1,172 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use bytes::Bytes;
+use chokepoint::{
+    clock::{
+        Clock,
+        Instant,
+        Sleep,
+        VirtualClock,
+    },
+    ChokeSettings,
+    ChokeStream,
+};
+use futures::{
+    future::poll_fn,
+    stream::StreamExt,
+};
+use std::{
+    sync::{
+        Arc,
+        Mutex,
+    },
+    task::{
+        Poll,
+        Waker,
+    },
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+/// A clock that only advances when told to.
+#[derive(Clone)]
+struct MockClock(Arc<Mutex<MockClockState>>);
+
+struct MockClockState {
+    now: Instant,
+    wakers: Vec<Waker>,
+}
+
+impl MockClock {
+    fn new() -> Self {
+        Self(Arc::new(Mutex::new(MockClockState {
+            now: Instant::now(),
+            wakers: Vec::new(),
+        })))
+    }
+
+    fn advance(&self, duration: Duration) {
+        let mut state = self.0.lock().unwrap();
+        state.now += duration;
+        state.wakers.drain(..).for_each(Waker::wake);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.0.lock().unwrap().now
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        let clock = self.clone();
+        Box::pin(poll_fn(move |cx| {
+            let mut state = clock.0.lock().unwrap();
+            if state.now >= deadline {
+                Poll::Ready(())
+            } else {
+                state.wakers.push(cx.waker().clone());
+                Poll::Pending
+            }
+        }))
+    }
+}
+
+#[tokio::test]
+async fn delays_follow_the_clock() {
+    let clock = MockClock::new();
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut stream = ChokeStream::new(
+        UnboundedReceiverStream::new(rx),
+        ChokeSettings::default()
+            .set_clock(clock.clone())
+            .set_latency_distribution(Some(|| Some(Duration::from_secs(60)))),
+    );
+
+    tx.send(Bytes::from_static(b"delayed")).unwrap();
+    assert!(tokio::time::timeout(Duration::from_millis(20), stream.next())
+        .await
+        .is_err());
+
+    clock.advance(Duration::from_secs(30));
+    assert!(tokio::time::timeout(Duration::from_millis(20), stream.next())
+        .await
+        .is_err());
+
+    // A minute of simulated latency passes instantly
+    let start = std::time::Instant::now();
+    clock.advance(Duration::from_secs(30));
+    assert_eq!(stream.next().await.unwrap(), Bytes::from_static(b"delayed"));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn virtual_time() {
+    let clock = VirtualClock::new();
+
+    // One item per simulated second
+    let producer = futures::stream::unfold(0usize, {
+        let clock = clock.clone();
+        move |i| {
+            let clock = clock.clone();
+            async move {
+                if i == 600 {
+                    return None;
+                }
+                clock.sleep_until(clock.now() + Duration::from_secs(1)).await;
+                Some((Bytes::from(i.to_le_bytes().to_vec()), i + 1))
+            }
+        }
+    });
+    let stream = ChokeStream::new(
+        producer,
+        ChokeSettings::default()
+            .set_clock(clock.clone())
+            .set_latency_distribution(Some(|| Some(Duration::from_secs(30)))),
+    );
+
+    let start = std::time::Instant::now();
+    let output = stream
+        .map(|packet| usize::from_le_bytes(packet[0..8].try_into().unwrap()))
+        .collect::<Vec<_>>()
+        .await;
+
+    assert_eq!(output, (0..600).collect::<Vec<_>>());
+    assert_eq!(clock.elapsed(), Duration::from_secs(630));
+    assert!(start.elapsed() < Duration::from_secs(5), "{:?}", start.elapsed());
+}
+
+#[cfg(not(feature = "futures-timer"))]
+#[tokio::test(start_paused = true)]
+async fn paused_tokio_time() {
+    let start = tokio::time::Instant::now();
+
+    // One item per second
+    let producer = futures::stream::iter(0..600usize).then(|i| async move {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        Bytes::from(i.to_le_bytes().to_vec())
+    });
+    let stream = ChokeStream::new(
+        producer,
+        ChokeSettings::default().set_latency_distribution(Some(|| Some(Duration::from_secs(30)))),
+    );
+
+    let wall_clock = std::time::Instant::now();
+    let arrivals = stream
+        .map(|packet| {
+            let i = usize::from_le_bytes(packet[0..8].try_into().unwrap());
+            (i, start.elapsed())
+        })
+        .collect::<Vec<_>>()
+        .await;
+
+    let expected = (0..600)
+        .map(|i| (i, Duration::from_secs(i as u64 + 31)))
+        .collect::<Vec<_>>();
+    assert_eq!(arrivals, expected);
+    assert!(
+        wall_clock.elapsed() < Duration::from_secs(5),
+        "{:?}",
+        wall_clock.elapsed()
+    );
+}
@@ -0,0 +1,105 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use bytes::Bytes;
+use chokepoint::{
+    event::{
+        ChokeEventKind,
+        DropReason,
+    },
+    hook::{
+        DecisionHook,
+        HookItem,
+    },
+    replay::Decision,
+    ChokeSettings,
+    ChokeStream,
+    Latency,
+};
+use futures::StreamExt as _;
+use std::time::Duration;
+
+#[tokio::test(start_paused = true)]
+async fn events_match_stats() {
+    let mut stream = ChokeStream::new(
+        futures::stream::iter((0..100u8).map(|i| Bytes::from(vec![i; 8]))),
+        ChokeSettings::default()
+            .set_latency(Some(Latency::Constant(Duration::from_millis(10))))
+            .set_drop_probability(Some(0.2))
+            .set_corrupt_probability(Some(0.1))
+            .set_duplicate_probability(Some(0.1))
+            .set_seed(Some(7)),
+    );
+    let events = stream.events();
+    while stream.next().await.is_some() {}
+    let stats = stream.stats();
+    drop(stream);
+    let events = events.collect::<Vec<_>>().await;
+
+    let count = |kind: fn(&ChokeEventKind) -> bool| events.iter().filter(|event| kind(&event.kind)).count();
+    assert_eq!(count(|kind| *kind == ChokeEventKind::Enqueued), 100);
+    assert_eq!(
+        count(|kind| *kind == ChokeEventKind::Dropped(DropReason::Random)),
+        stats.dropped
+    );
+    assert_eq!(count(|kind| *kind == ChokeEventKind::Corrupted), stats.corrupted);
+    assert_eq!(count(|kind| *kind == ChokeEventKind::Duplicated), stats.duplicated);
+    assert_eq!(count(|kind| *kind == ChokeEventKind::Emitted), stats.emitted);
+    assert_eq!(
+        count(|kind| matches!(kind, ChokeEventKind::Delayed(_))),
+        stats.received - stats.dropped
+    );
+
+    // Every item starts with being enqueued and is emitted after its latency
+    for seq in 0..100 {
+        let item = events.iter().filter(|event| event.seq == seq).collect::<Vec<_>>();
+        assert_eq!(item[0].kind, ChokeEventKind::Enqueued);
+        for emitted in item.iter().filter(|event| event.kind == ChokeEventKind::Emitted) {
+            assert!(emitted.at - item[0].at >= Duration::from_millis(10));
+        }
+    }
+}
+
+struct DropOdd;
+
+impl DecisionHook for DropOdd {
+    fn process(&mut self, item: &HookItem<'_>, decision: &mut Decision) {
+        if item.seq % 2 == 1 {
+            *decision = Decision::Drop;
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn events_tell_drop_reason() {
+    let mut stream = ChokeStream::new(
+        futures::stream::iter((0..10).map(|_| Bytes::from_static(b"hello"))),
+        ChokeSettings::default()
+            .set_latency(Some(Latency::Constant(Duration::from_millis(10))))
+            .set_item_ttl(Some(Duration::from_millis(5)))
+            .add_decision_hook(DropOdd),
+    );
+    let events = stream.events();
+    assert_eq!(stream.next().await, None);
+    drop(stream);
+
+    let dropped = events
+        .filter_map(|event| async move {
+            match event.kind {
+                ChokeEventKind::Dropped(reason) => Some((event.seq, reason)),
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>()
+        .await;
+    let expected = (0..10)
+        .map(|seq| {
+            let reason = if seq % 2 == 1 {
+                DropReason::Hook
+            } else {
+                DropReason::Expired
+            };
+            (seq, reason)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(dropped, expected);
+}
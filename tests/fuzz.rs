@@ -0,0 +1,54 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use arbitrary::{
+    Arbitrary as _,
+    Unstructured,
+};
+use bytes::Bytes;
+use chokepoint::{
+    ChokeSettings,
+    ChokeSettingsSnapshot,
+    ChokeStream,
+};
+use futures::StreamExt as _;
+use rand::{
+    rngs::StdRng,
+    Rng as _,
+    SeedableRng as _,
+};
+use std::time::Duration;
+
+fn items() -> impl futures::Stream<Item = Bytes> + Unpin {
+    futures::stream::iter((0..100u8).map(|i| Bytes::from(vec![i; 100])))
+}
+
+#[tokio::test(start_paused = true)]
+async fn arbitrary_snapshots_configure_streams() {
+    let mut rng = StdRng::seed_from_u64(3);
+    for _ in 0..200 {
+        let data = (0..rng.random_range(0..512)).map(|_| rng.random()).collect::<Vec<u8>>();
+        let snapshot = ChokeSettingsSnapshot::arbitrary(&mut Unstructured::new(&data)).unwrap();
+        let settings = ChokeSettings::from(snapshot.clone());
+        assert_eq!(settings.validate(), Ok(()), "{snapshot:?}");
+
+        let mut stream = ChokeStream::new(items(), settings);
+        assert_eq!(stream.current_settings(), snapshot);
+        tokio::time::timeout(Duration::from_secs(60), async {
+            while stream.next().await.is_some() {}
+        })
+        .await
+        .unwrap_or_else(|_| panic!("stream did not end: {snapshot:?}"));
+        assert!(stream.stats().received >= 100);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn empty_input_does_not_shape() {
+    let snapshot = ChokeSettingsSnapshot::arbitrary(&mut Unstructured::new(&[])).unwrap();
+    let start = tokio::time::Instant::now();
+    let output = ChokeStream::new(items(), ChokeSettings::from(snapshot))
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(output, items().collect::<Vec<_>>().await);
+    assert_eq!(start.elapsed(), Duration::ZERO);
+}
@@ -0,0 +1,216 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use bytes::Bytes;
+use chokepoint::{
+    test_util::TestPayload,
+    ChokeItem as _,
+    ChokeSettings,
+    ChokeStream,
+};
+use futures::StreamExt as _;
+use rand::{
+    rngs::StdRng,
+    SeedableRng as _,
+};
+
+#[test]
+fn strings_split_at_char_boundaries() {
+    let text = "aé€😀".to_string();
+    assert_eq!(text.clone().split(2), ["a", "é", "€", "😀"]);
+    assert_eq!(text.clone().split(3), ["aé", "€", "😀"]);
+    assert_eq!(text.clone().split(100), [text]);
+}
+
+#[test]
+fn strings_coalesce_and_corrupt() {
+    let mut text = "hello ".to_string();
+    text.coalesce("world".to_string()).unwrap();
+    assert_eq!(text, "hello world");
+
+    let mut corrupted = text.clone();
+    corrupted.corrupt_with(&mut StdRng::seed_from_u64(0));
+    assert_ne!(corrupted, text);
+    assert!(corrupted.contains('\u{FFFD}'));
+
+    let mut empty = String::new();
+    empty.corrupt_with(&mut StdRng::seed_from_u64(0));
+    assert_eq!(empty, "");
+}
+
+#[tokio::test(start_paused = true)]
+async fn strings_are_fragmented_by_the_mtu() {
+    let lines = futures::stream::iter(["first line", "second line"].map(String::from));
+    let output = ChokeStream::new(lines, ChokeSettings::default().set_mtu(Some(4)))
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(output.concat(), "first linesecond line");
+    assert!(output.iter().all(|fragment| fragment.len() <= 4));
+}
+
+#[test]
+fn websocket_messages() {
+    use tungstenite::Message;
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut text = Message::text("hello");
+    assert_eq!(text.byte_len(), 5);
+    assert_eq!(text.payload(), Some(&b"hello"[..]));
+    text.corrupt_with(&mut rng);
+    assert_ne!(text, Message::text("hello"));
+    assert!(text.is_text());
+
+    let mut binary = Message::binary(Bytes::from_static(b"data"));
+    binary.corrupt_with(&mut rng);
+    assert_ne!(binary, Message::binary(Bytes::from_static(b"data")));
+    assert_eq!(binary.byte_len(), 4);
+
+    let mut ping = Message::Ping(Bytes::new());
+    ping.corrupt_with(&mut rng);
+    assert_eq!(ping, Message::Ping(Bytes::new()));
+
+    let mut close = Message::Close(None);
+    close.corrupt_with(&mut rng);
+    assert_eq!(close, Message::Close(None));
+    assert_eq!(close.payload(), None);
+    assert_eq!(close.clone().split(1), [close]);
+}
+
+#[test]
+fn http_messages_are_sized_in_http_1_1() {
+    let request = http::Request::post("/upload")
+        .header("host", "example.com")
+        .body(Bytes::from_static(b"body"))
+        .unwrap();
+    let encoded = "POST /upload HTTP/1.1\r\nhost: example.com\r\n\r\nbody";
+    assert_eq!(request.byte_len(), encoded.len());
+
+    let response = http::Response::builder()
+        .status(404)
+        .header("content-length", "4")
+        .body(Bytes::from_static(b"gone"))
+        .unwrap();
+    let encoded = "HTTP/1.1 404 Not Found\r\ncontent-length: 4\r\n\r\ngone";
+    assert_eq!(response.byte_len(), encoded.len());
+}
+
+#[test]
+fn http_messages_corrupt_the_body() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut response = http::Response::new(Bytes::from_static(b"body"));
+    response.corrupt_with(&mut rng);
+    assert_ne!(response.body(), &Bytes::from_static(b"body"));
+    assert_eq!(response.status(), http::StatusCode::OK);
+
+    let mut request = http::Request::get("/").body(Bytes::new()).unwrap();
+    request.corrupt_with(&mut rng);
+    assert!(request.body().is_empty());
+    assert!(request.duplicate().is_some());
+}
+
+#[tokio::test(start_paused = true)]
+async fn metadata_rides_along() {
+    let items = futures::stream::iter([
+        (Bytes::from_static(b"abcdef"), "first"),
+        (Bytes::from_static(b"gh"), "second"),
+    ]);
+    let settings = ChokeSettings::default()
+        .set_mtu(Some(4))
+        .set_duplicate_probability(Some(1.0))
+        .set_seed(Some(0));
+    let output = ChokeStream::new(items, settings).collect::<Vec<_>>().await;
+    assert_eq!(output.len(), 6);
+    for (payload, metadata) in output {
+        let expected = if payload.iter().all(|byte| b"abcdef".contains(byte)) {
+            "first"
+        } else {
+            "second"
+        };
+        assert_eq!(metadata, expected, "{payload:?}");
+    }
+}
+
+#[test]
+fn items_with_metadata_are_not_coalesced() {
+    let mut item = (Bytes::from_static(b"a"), 1);
+    assert_eq!(
+        item.coalesce((Bytes::from_static(b"b"), 2)),
+        Err((Bytes::from_static(b"b"), 2))
+    );
+    assert_eq!(item.byte_len(), 1);
+}
+
+#[cfg(feature = "serde")]
+mod serde_item {
+    use super::*;
+    use chokepoint::{
+        SerdeItem,
+        SerdeItemError,
+    };
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Message {
+        id: u32,
+        text: String,
+    }
+
+    fn message() -> SerdeItem<Message> {
+        SerdeItem::new(Message {
+            id: 1,
+            text: "hello".into(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn size_is_the_encoded_size() {
+        let item = message();
+        assert_eq!(item.encoded().as_ref(), br#"{"id":1,"text":"hello"}"#);
+        assert_eq!(item.byte_len(), item.encoded().len());
+        assert_eq!(item.payload(), Some(item.encoded().as_ref()));
+    }
+
+    #[test]
+    fn corruption_is_decoded() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let mut item = message();
+            item.corrupt_with(&mut rng);
+            assert_ne!(item.encoded(), message().encoded());
+            match item.value() {
+                Ok(value) => assert_ne!(value, message().value().unwrap()),
+                Err(err) => assert!(err.to_string().starts_with("invalid item")),
+            }
+            let duplicate = item.duplicate().unwrap();
+            assert_eq!(duplicate.encoded(), item.encoded());
+            assert_eq!(duplicate.value(), item.value());
+        }
+    }
+
+    #[test]
+    fn invalid_encodings_carry_an_error() {
+        let item = SerdeItem::<Message>::decode(Bytes::from_static(b"{}"));
+        assert!(matches!(item.into_value(), Err(SerdeItemError { .. })));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn corrupted_items_reach_the_receiver() {
+        let items = futures::stream::iter((0..10).map(|_| message()));
+        let settings = ChokeSettings::default().set_corrupt_probability(Some(1.0));
+        let output = ChokeStream::new(items, settings).collect::<Vec<_>>().await;
+        assert_eq!(output.len(), 10);
+        assert!(output.iter().all(|item| item.value() != message().value()));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_payloads_are_corrupted_and_duplicated() {
+    let payloads = futures::stream::iter((0..100).map(|i| TestPayload::new(i, 10)));
+    let settings = ChokeSettings::default()
+        .set_corrupt_probability(Some(1.0))
+        .set_duplicate_probability(Some(1.0))
+        .set_seed(Some(0));
+    let output = ChokeStream::new(payloads, settings).collect::<Vec<_>>().await;
+    assert_eq!(output.len(), 200);
+    assert!(output.iter().all(|payload| payload.corrupted));
+    assert_eq!(output.iter().filter(|payload| payload.i == 7).count(), 2);
+}
@@ -0,0 +1,86 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use chokepoint::{
+    jitter::{
+        JitterBuffer,
+        JitterBufferConfig,
+        JitterBufferStats,
+    },
+    tagged::Tagged,
+};
+use futures::StreamExt as _;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+/// Sends the ids after the given delays in ms and keeps the stream open for a while afterwards. Returns the played out
+/// ids with the time since the start, and the counters.
+async fn play_out(config: JitterBufferConfig, arrivals: Vec<(u64, usize)>) -> (Vec<(u128, usize)>, JitterBufferStats) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        for (delay, id) in arrivals {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            tx.send(Tagged::new(id, ())).unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    });
+
+    let mut buffer = JitterBuffer::new(UnboundedReceiverStream::new(rx), config);
+    let start = tokio::time::Instant::now();
+    let mut output = Vec::new();
+    while let Some(tagged) = buffer.next().await {
+        output.push((start.elapsed().as_millis(), tagged.id));
+    }
+    (output, buffer.stats())
+}
+
+#[tokio::test(start_paused = true)]
+async fn resequences_items() {
+    let config = JitterBufferConfig::builder()
+        .target_delay(Duration::from_millis(50))
+        .build();
+    // Arrivals at 0, 10, 30, 40, 40, 50 and 120ms
+    let arrivals = vec![(0, 0), (10, 2), (20, 1), (10, 3), (0, 3), (10, 5), (70, 4)];
+    let (output, stats) = play_out(config, arrivals).await;
+
+    // 2 waits for 1, 4 is skipped and dropped when it arrives late
+    assert_eq!(output, [(50, 0), (80, 1), (80, 2), (90, 3), (100, 5)]);
+    assert_eq!(
+        stats,
+        JitterBufferStats {
+            received: 7,
+            emitted: 5,
+            lost: 1,
+            late: 1,
+            duplicates: 1,
+            overflows: 0,
+        }
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn plays_out_at_interval() {
+    let config = JitterBufferConfig::builder()
+        .target_delay(Duration::from_millis(50))
+        .interval(Duration::from_millis(20))
+        .build();
+    // Arrivals at 0, 35, 45 and 120ms
+    let arrivals = vec![(0, 0), (35, 1), (10, 2), (75, 3)];
+    let (output, stats) = play_out(config, arrivals).await;
+
+    // The jitter is removed, 3 arrives after its playout time at 110ms
+    assert_eq!(output, [(50, 0), (70, 1), (90, 2)]);
+    assert_eq!(stats.late, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn plays_out_early_when_full() {
+    let config = JitterBufferConfig::builder()
+        .target_delay(Duration::from_millis(50))
+        .max_buffer(2)
+        .build();
+    let (output, stats) = play_out(config, vec![(0, 0), (0, 1), (0, 2)]).await;
+
+    assert_eq!(output, [(0, 0), (50, 1), (50, 2)]);
+    assert_eq!(stats.overflows, 1);
+}
@@ -0,0 +1,67 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use bytes::Bytes;
+use chokepoint::{
+    pcap::PcapWriter,
+    ChokeSettings,
+    ChokeStream,
+};
+use futures::StreamExt as _;
+use std::{
+    io::{
+        self,
+        Write,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn emitted_and_dropped_packets() {
+    let buffer = Buffer::default();
+    let pcap = PcapWriter::new(buffer.clone()).unwrap();
+    let mut stream = ChokeStream::new(
+        futures::stream::iter((0..100u8).map(|i| Bytes::from(vec![i; 8]))),
+        ChokeSettings::default()
+            .set_drop_probability(Some(0.3))
+            .set_pcap_writer(Some(pcap.clone())),
+    );
+    while stream.next().await.is_some() {}
+    pcap.flush().unwrap();
+    let stats = stream.stats();
+
+    // Every packet is an enhanced packet block, dropped ones carry a comment
+    let data = buffer.0.lock().unwrap().clone();
+    let (mut packets, mut dropped) = (0, 0);
+    let mut offset = 0;
+    while offset < data.len() {
+        let block_type = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let length = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        if block_type == 6 {
+            packets += 1;
+            dropped += data[offset..offset + length]
+                .windows(7)
+                .any(|window| window == b"dropped") as usize;
+        }
+        offset += length;
+    }
+    assert_eq!(offset, data.len());
+    assert_eq!(packets, 100);
+    assert!(stats.dropped > 0);
+    assert_eq!(dropped, stats.dropped);
+}
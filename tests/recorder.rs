@@ -0,0 +1,116 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use bytes::Bytes;
+use chokepoint::{
+    recorder::Recorder,
+    ChokeSettings,
+    ChokeStream,
+};
+use futures::StreamExt as _;
+use std::{
+    collections::BTreeSet,
+    io::{
+        self,
+        Write,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn one_event_per_item() {
+    let buffer = Buffer::default();
+    let recorder = Recorder::json_lines(buffer.clone()).unwrap();
+    let mut stream = ChokeStream::new(
+        futures::stream::iter((0..100u8).map(|i| Bytes::from(vec![i; 8]))),
+        ChokeSettings::default()
+            .set_latency_distribution(Some(|| Some(Duration::from_millis(10))))
+            .set_drop_probability(Some(0.2))
+            .set_duplicate_probability(Some(0.1))
+            .set_seed(Some(7))
+            .set_recorder(Some(recorder.clone())),
+    );
+    while stream.next().await.is_some() {}
+    recorder.flush().unwrap();
+    let stats = stream.stats();
+
+    let data = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let events = data
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(events.len(), stats.received + stats.duplicated);
+
+    let count = |fate: &str| events.iter().filter(|event| event["fate"] == fate).count();
+    assert!(stats.dropped > 0);
+    assert_eq!(count("dropped"), stats.dropped);
+    assert!(stats.duplicated > 0);
+    assert_eq!(count("duplicate"), stats.duplicated);
+    assert_eq!(count("emitted"), stats.received - stats.dropped);
+
+    let seqs = events
+        .iter()
+        .map(|event| event["seq"].as_u64().unwrap())
+        .collect::<BTreeSet<_>>();
+    assert_eq!(seqs, (0..100).collect());
+
+    for event in &events {
+        assert_eq!(event["size"], 8);
+        match event["fate"].as_str().unwrap() {
+            "dropped" => {
+                assert!(event["delay_ms"].is_null());
+                assert!(event["emitted_ms"].is_null());
+            }
+            "emitted" => {
+                assert_eq!(event["delay_ms"].as_f64(), Some(10.0));
+                let waited = event["emitted_ms"].as_f64().unwrap() - event["enqueued_ms"].as_f64().unwrap();
+                assert!(waited >= 10.0, "{event}");
+            }
+            _ => assert!(event["delay_ms"].is_null()),
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn events_carry_the_label_of_their_hop() {
+    let buffer = Buffer::default();
+    let recorder = Recorder::csv(buffer.clone()).unwrap();
+    let items = futures::stream::iter((0..10u8).map(|i| Bytes::from(vec![i; 8])));
+    let path = ChokeStream::new(
+        items,
+        ChokeSettings::default()
+            .set_label("uplink")
+            .set_recorder(Some(recorder.clone())),
+    )
+    .chain(
+        ChokeSettings::default()
+            .set_label("core, east")
+            .set_recorder(Some(recorder.clone())),
+    );
+    let stats = path.stats();
+    assert_eq!(stats[0].label.as_deref(), Some("uplink"));
+    assert_eq!(stats[1].label.as_deref(), Some("core, east"));
+
+    assert_eq!(path.count().await, 10);
+    recorder.flush().unwrap();
+    let data = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let labels = data.lines().skip(1).map(|line| line.rsplit_once(",false,").unwrap().1);
+    assert_eq!(labels.clone().filter(|label| *label == "uplink").count(), 10);
+    assert_eq!(labels.filter(|label| *label == "\"core, east\"").count(), 10);
+}
@@ -0,0 +1,59 @@
+#![cfg(all(feature = "futures-timer", not(target_arch = "wasm32")))]
+
+//! Runs the shapers on an executor other than tokio.
+
+use bytes::Bytes;
+use chokepoint::{
+    ChokeSettings,
+    ChokeSink,
+    ChokeStream,
+};
+use futures::{
+    channel::mpsc,
+    executor::block_on,
+    SinkExt as _,
+    StreamExt as _,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+
+fn items() -> impl futures::Stream<Item = Bytes> {
+    futures::stream::iter((0..3usize).map(|i| Bytes::from(i.to_le_bytes().to_vec())))
+}
+
+#[test]
+fn stream_without_tokio() {
+    block_on(async {
+        let stream = ChokeStream::new(
+            items(),
+            ChokeSettings::default().set_latency_distribution(Some(|| Some(Duration::from_millis(50)))),
+        );
+
+        let start = Instant::now();
+        assert_eq!(stream.collect::<Vec<_>>().await, items().collect::<Vec<_>>().await);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    });
+}
+
+#[test]
+fn sink_without_tokio() {
+    block_on(async {
+        let (tx, rx) = mpsc::unbounded();
+        let mut sink = ChokeSink::new(
+            tx,
+            ChokeSettings::default().set_latency_distribution(Some(|| Some(Duration::from_millis(50)))),
+        );
+
+        let start = Instant::now();
+        let mut input = items();
+        while let Some(item) = input.next().await {
+            sink.send(item).await.unwrap();
+        }
+        sink.close().await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        assert_eq!(rx.collect::<Vec<_>>().await, items().collect::<Vec<_>>().await);
+    });
+}
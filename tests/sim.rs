@@ -0,0 +1,72 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use bytes::Bytes;
+use chokepoint::{
+    sim::{
+        Simulation,
+        SimulationReport,
+    },
+    BandwidthLimit,
+    ChokeSettings,
+    ChokeStream,
+};
+use rand::Rng as _;
+use std::time::Duration;
+
+fn scenario(sim: &mut Simulation) -> SimulationReport<Bytes> {
+    let mut rng = sim.rng();
+    let settings = ChokeSettings::default()
+        .set_drop_probability(Some(0.1))
+        .set_corrupt_probability(Some(0.05))
+        .set_duplicate_probability(Some(0.05))
+        .set_bandwidth_limit_with(BandwidthLimit::builder().bytes_per_sec(100_000).drop_ratio(0.1))
+        .set_latency_distribution(Some(move || Some(Duration::from_millis(rng.random_range(10..500)))));
+    let items = (0..10_000usize).map(|i| Bytes::from(i.to_le_bytes().repeat(10)));
+    sim.send(items, settings)
+}
+
+#[test]
+fn same_seed_same_outcome() {
+    let start = std::time::Instant::now();
+    let report = scenario(&mut Simulation::new(7));
+    assert_eq!(report, scenario(&mut Simulation::new(7)));
+    assert_ne!(report, scenario(&mut Simulation::new(8)));
+    assert!(start.elapsed() < Duration::from_secs(10), "{:?}", start.elapsed());
+
+    // 800 kB at 100 kB/s, reduced by the dropped items
+    assert!(report.elapsed > Duration::from_secs(5), "{:?}", report.elapsed);
+}
+
+#[test]
+fn stats_add_up() {
+    let report = scenario(&mut Simulation::new(1));
+    let stats = report.stats;
+
+    assert_eq!(stats.received, 10_000);
+    assert!(
+        stats.dropped > 0 && stats.corrupted > 0 && stats.duplicated > 0,
+        "{stats:?}"
+    );
+    assert_eq!(stats.emitted, stats.received - stats.dropped + stats.duplicated);
+    assert_eq!(report.items.len(), stats.emitted);
+    assert!(report.items.windows(2).all(|items| items[0].0 <= items[1].0));
+}
+
+#[test]
+fn multiple_streams() {
+    let mut sim = Simulation::new(3);
+    let streams = [100, 200]
+        .into_iter()
+        .map(|millis| {
+            let settings = sim
+                .settings()
+                .set_latency_distribution(Some(move || Some(Duration::from_millis(millis))));
+            ChokeStream::new(futures::stream::iter(vec![Bytes::from_static(b"item"); 10]), settings)
+        })
+        .collect();
+
+    let reports = sim.run(streams);
+    assert_eq!(reports[0].elapsed, Duration::from_millis(100));
+    assert_eq!(reports[1].elapsed, Duration::from_millis(200));
+    assert_eq!(sim.clock().elapsed(), Duration::from_millis(200));
+}
@@ -1,11 +1,22 @@
+#![cfg(not(target_arch = "wasm32"))]
+
 use chokepoint::{
     normal_distribution,
+    test_util::*,
     ChokeSettings,
     ChokeSettingsOrder,
     ChokeSink,
+    ChokeSinkError,
 };
-use chokepoint_test_helpers::*;
+use chrono::Utc;
 use futures::SinkExt as _;
+use std::{
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
 
 #[tokio::test]
 async fn unchanged() {
@@ -80,3 +91,158 @@ async fn sink_with_a_hole() {
 
     assert!(received.len() < 10);
 }
+
+#[tokio::test]
+async fn ordered_backpressure() {
+    let enqueued = Arc::new(Mutex::new(Vec::new()));
+    let mut sink = ChokeSink::new(
+        TestSink::default(),
+        ChokeSettings::default()
+            .set_ordering(Some(ChokeSettingsOrder::Ordered))
+            .set_backpressure(Some(true))
+            .set_latency_distribution(Some({
+                let enqueued = enqueued.clone();
+                let mut delays = [30, 20, 10, 0, 30].into_iter();
+                move || {
+                    enqueued.lock().unwrap().push(Utc::now());
+                    delays.next().map(Duration::from_millis)
+                }
+            })),
+    );
+
+    for i in 0..5usize {
+        sink.send(TestPayload::new(i, 1)).await.unwrap();
+    }
+
+    sink.close().await.unwrap();
+
+    let received = sink.into_inner().received.into_inner();
+    let enqueued = enqueued.lock().unwrap();
+
+    assert_eq!(
+        received.iter().map(|(_, TestPayload { i, .. })| *i).collect::<Vec<_>>(),
+        (0..5).collect::<Vec<_>>()
+    );
+    // Every item only enters the shaper once the previous one has been delivered.
+    for (enqueued, (received, _)) in enqueued.iter().skip(1).zip(received.iter()) {
+        assert!(enqueued >= received, "{enqueued} < {received}");
+    }
+}
+
+#[tokio::test]
+async fn flush_forwards_all_ready_items() {
+    let mut sink = ChokeSink::new(TestSink::default(), Default::default());
+
+    for i in 0..10usize {
+        sink.feed(TestPayload::new(i, 1)).await.unwrap();
+    }
+    sink.flush().await.unwrap();
+
+    assert_eq!(sink.get_ref().received.borrow().len(), 10);
+
+    sink.get_mut().received.borrow_mut().clear();
+    sink.send(TestPayload::new(10, 1)).await.unwrap();
+    assert_eq!(sink.get_ref().received.borrow().len(), 1);
+}
+
+#[tokio::test]
+async fn close_delivers_delayed_items() {
+    let mut sink = ChokeSink::new(
+        TestSink::default(),
+        ChokeSettings::default()
+            .set_ordering(Some(ChokeSettingsOrder::Unordered))
+            .set_latency_distribution(Some({
+                let mut delays = (0..10).rev().map(|n| Duration::from_millis(200 + n * 10));
+                move || delays.next()
+            })),
+    );
+
+    for i in 0..10usize {
+        sink.feed(TestPayload::new(i, 1)).await.unwrap();
+    }
+
+    sink.close().await.unwrap();
+
+    let received = sink.into_inner().received.into_inner();
+    assert_eq!(received.len(), 10);
+    for (received, payload) in received {
+        assert!(received - payload.created >= chrono::Duration::milliseconds(200));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn idle_delivers_delayed_items() {
+    let mut sink = ChokeSink::new(
+        TestSink::default(),
+        ChokeSettings::default().set_latency_distribution(Some(|| Some(Duration::from_millis(100)))),
+    );
+
+    for i in 0..10usize {
+        sink.feed(TestPayload::new(i, 1)).await.unwrap();
+    }
+    sink.idle().await.unwrap();
+    assert_eq!(sink.get_ref().received.borrow().len(), 10);
+
+    // Unlike after closing, more items can be sent
+    sink.feed(TestPayload::new(10, 1)).await.unwrap();
+    sink.idle().await.unwrap();
+    assert_eq!(sink.get_ref().received.borrow().len(), 11);
+}
+
+#[tokio::test]
+async fn send_after_close() {
+    let mut sink = ChokeSink::new(TestSink::default(), Default::default());
+
+    sink.send(TestPayload::new(0, 1)).await.unwrap();
+    sink.close().await.unwrap();
+
+    assert_eq!(sink.send(TestPayload::new(1, 1)).await, Err(ChokeSinkError::Closed));
+    assert_eq!(sink.into_inner().received.into_inner().len(), 1);
+}
+
+#[tokio::test]
+async fn with_map_only_maps_forwarded_items() {
+    let mapped = Arc::new(Mutex::new(0));
+    let (tx, rx) = futures::channel::mpsc::unbounded::<String>();
+    let settings = ChokeSettings::default()
+        .set_drop_probability(Some(0.5))
+        .set_seed(Some(1));
+    let mut sink = ChokeSink::with_map(tx, settings, {
+        let mapped = Arc::clone(&mapped);
+        move |payload: TestPayload| {
+            *mapped.lock().unwrap() += 1;
+            format!("item {}", payload.i)
+        }
+    });
+
+    for i in 0..100usize {
+        sink.send(TestPayload::new(i, 1)).await.unwrap();
+    }
+    sink.close().await.unwrap();
+
+    let received = futures::StreamExt::collect::<Vec<_>>(rx).await;
+    let stats = sink.stats();
+    assert!(stats.dropped > 0);
+    assert_eq!(received.len(), stats.emitted);
+    assert_eq!(*mapped.lock().unwrap(), received.len());
+    assert!(received.iter().all(|item| item.starts_with("item ")));
+}
+
+#[tokio::test(start_paused = true)]
+async fn with_map_applies_backpressure() {
+    let (tx, mut rx) = futures::channel::mpsc::unbounded::<usize>();
+    let settings = ChokeSettings::default()
+        .set_latency_distribution(normal_distribution(10.0, 0.0, 10.0))
+        .set_backpressure(Some(true));
+    let mut sink = ChokeSink::with_map(tx, settings, |payload: TestPayload| payload.i);
+
+    let start = tokio::time::Instant::now();
+    for i in 0..3usize {
+        sink.send(TestPayload::new(i, 1)).await.unwrap();
+    }
+    // Every send waits for the previous item to be forwarded.
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    sink.close().await.unwrap();
+    rx.close();
+    assert_eq!(futures::StreamExt::collect::<Vec<_>>(rx).await, [0, 1, 2]);
+}
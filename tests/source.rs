@@ -0,0 +1,193 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use bytes::Bytes;
+use chokepoint::{
+    source::{
+        DriftSource,
+        FileSource,
+        ScenarioSource,
+        SettingsSource,
+        WatchSource,
+    },
+    ChokeSettings,
+    ChokeStream,
+    Latency,
+};
+use futures::StreamExt as _;
+use std::{
+    fs::File,
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+use tokio::sync::{
+    mpsc,
+    watch,
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+#[tokio::test(start_paused = true)]
+async fn scenario_applies_settings_at_times() {
+    let scenario = ScenarioSource::new([
+        (
+            Duration::from_secs(2),
+            ChokeSettings::default().set_drop_probability(Some(0.0)),
+        ),
+        (
+            Duration::from_secs(1),
+            ChokeSettings::default().set_drop_probability(Some(1.0)),
+        ),
+    ]);
+    // An item every 100ms, starting after 100ms
+    let items = futures::stream::iter(0..30)
+        .then(|_| async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Bytes::from_static(b"hello")
+        })
+        .boxed();
+    let mut stream = ChokeStream::new(items, ChokeSettings::default().set_settings_source(scenario));
+    while stream.next().await.is_some() {}
+
+    // The items taken from 1s until before 2s are dropped
+    assert_eq!(stream.stats().dropped, 10);
+    assert_eq!(stream.stats().emitted, 20);
+}
+
+#[tokio::test(start_paused = true)]
+async fn drift_source_walks_within_bounds() {
+    let drift = DriftSource::new(Duration::from_millis(100))
+        .latency(
+            Duration::from_millis(20)..=Duration::from_millis(60),
+            Duration::from_millis(5),
+        )
+        .drop_probability(0.0..=0.1, 0.01)
+        .bandwidth(100_000..=200_000, 10_000)
+        .seed(5);
+    // An item every 100ms, starting after 100ms
+    let items = futures::stream::iter(0..200)
+        .then(|_| async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Bytes::from_static(b"hello")
+        })
+        .boxed();
+    let mut stream = ChokeStream::new(items, ChokeSettings::default().set_settings_source(drift));
+
+    let mut previous: Option<(tokio::time::Instant, Duration, f64, usize)> = None;
+    let mut changes = 0;
+    while stream.next().await.is_some() {
+        let now = tokio::time::Instant::now();
+        let settings = stream.current_settings();
+        let Some(Latency::Constant(latency)) = settings.latency else {
+            panic!("unexpected latency {:?}", settings.latency);
+        };
+        let bandwidth = settings.bandwidth_limit.unwrap().bytes_per_second();
+        assert!((Duration::from_millis(20)..=Duration::from_millis(60)).contains(&latency));
+        assert!((0.0..=0.1).contains(&settings.drop_probability));
+        assert!((100_000..=200_000).contains(&bandwidth));
+        if let Some((previous_now, previous_latency, previous_probability, previous_bandwidth)) = previous {
+            // At most one step per interval, plus one for an interval that started in between
+            let steps = (now - previous_now).as_millis() as u32 / 100 + 1;
+            assert!(latency.abs_diff(previous_latency) <= Duration::from_millis(5) * steps);
+            assert!((settings.drop_probability - previous_probability).abs() <= 0.01 * f64::from(steps) + 1e-9);
+            assert!(bandwidth.abs_diff(previous_bandwidth) <= 10_000 * steps as usize + 1);
+            changes += usize::from(latency != previous_latency);
+        }
+        previous = Some((now, latency, settings.drop_probability, bandwidth));
+    }
+    assert!(changes > 100, "{changes}");
+}
+
+#[tokio::test]
+async fn channel_update_wakes_up_stream() {
+    let (tx, rx) = mpsc::unbounded_channel::<Bytes>();
+    let (settings_tx, settings_rx) = mpsc::unbounded_channel();
+    let mut stream = ChokeStream::new(
+        UnboundedReceiverStream::new(rx),
+        ChokeSettings::default().set_settings_source(settings_rx),
+    );
+
+    let mut next = tokio_test::task::spawn(stream.next());
+    assert!(next.poll().is_pending());
+    settings_tx
+        .send(ChokeSettings::default().set_drop_probability(Some(0.5)))
+        .unwrap();
+    assert!(next.is_woken());
+    assert!(next.poll().is_pending());
+    drop(next);
+    assert_eq!(stream.current_settings().drop_probability, 0.5);
+
+    drop(tx);
+    assert_eq!(stream.next().await, None);
+}
+
+#[tokio::test]
+async fn watch_source_applies_initial_and_latest_value() {
+    let (settings_tx, settings_rx) = watch::channel(ChokeSettings::default().set_corrupt_probability(Some(0.5)));
+    let mut source = WatchSource::new(settings_rx);
+    let initial = source.next_settings().await.unwrap();
+    assert!(format!("{initial:?}").contains("corrupt_probability: Some(0.5)"));
+
+    settings_tx.send_replace(ChokeSettings::default().set_corrupt_probability(Some(0.1)));
+    settings_tx.send_replace(ChokeSettings::default().set_corrupt_probability(Some(0.2)));
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut stream = ChokeStream::new(
+        UnboundedReceiverStream::new(rx),
+        ChokeSettings::default().set_settings_source(source),
+    );
+    tx.send(Bytes::from_static(b"a")).unwrap();
+    stream.next().await.unwrap();
+    assert_eq!(stream.current_settings().corrupt_probability, 0.2);
+
+    drop(settings_tx);
+    drop(tx);
+    assert_eq!(stream.next().await, None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn file_source_reads_changed_file() {
+    let path = std::env::temp_dir().join(format!("chokepoint-source-{}.txt", std::process::id()));
+    std::fs::write(&path, "0.5").unwrap();
+    let mut source = FileSource::new(&path, |contents: &str| {
+        let probability = contents.trim().parse::<f64>()?;
+        Ok::<_, std::num::ParseFloatError>(ChokeSettings::default().set_drop_probability(Some(probability)))
+    })
+    .interval(Duration::from_millis(100));
+
+    let settings = source.next_settings().await.unwrap();
+    assert!(format!("{settings:?}").contains("drop_probability: Some(0.5)"));
+
+    // Unchanged files and contents that can't be parsed are skipped
+    let next = tokio::time::timeout(Duration::from_secs(1), source.next_settings()).await;
+    assert!(next.is_err());
+    let modified = SystemTime::now() + Duration::from_secs(10);
+    std::fs::write(&path, "invalid").unwrap();
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    let next = tokio::time::timeout(Duration::from_secs(1), source.next_settings()).await;
+    assert!(next.is_err());
+
+    std::fs::write(&path, "0.25").unwrap();
+    let modified = modified + Duration::from_secs(10);
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    let settings = source.next_settings().await.unwrap();
+    assert!(format!("{settings:?}").contains("drop_probability: Some(0.25)"));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "file check interval must not be zero")]
+fn file_source_rejects_zero_interval() {
+    let _ =
+        FileSource::new("settings.txt", |_: &str| Ok::<_, String>(ChokeSettings::default())).interval(Duration::ZERO);
+}
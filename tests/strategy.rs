@@ -0,0 +1,63 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use bytes::Bytes;
+use chokepoint::{
+    strategy,
+    ChokeSettings,
+    ChokeStream,
+};
+use futures::StreamExt as _;
+use proptest::prelude::*;
+use std::{
+    cell::Cell,
+    time::Duration,
+};
+
+/// Shape 100 items with the settings and return the number of items taken from the inner stream, or `None` if the
+/// stream did not end within a minute.
+fn run(settings: ChokeSettings) -> Option<usize> {
+    let taken = Cell::new(0);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let items = futures::stream::iter((0..100u8).map(|i| Bytes::from(vec![i; 100])))
+            .inspect(|_| taken.set(taken.get() + 1));
+        let mut stream = ChokeStream::new(items, settings);
+        tokio::time::timeout(Duration::from_secs(60), async {
+            while stream.next().await.is_some() {}
+        })
+        .await
+        .ok()?;
+        Some(taken.get())
+    })
+}
+
+proptest! {
+    #[test]
+    fn generated_settings_are_valid(settings in any::<ChokeSettings>()) {
+        prop_assert_eq!(settings.validate(), Ok(()));
+    }
+
+    #[test]
+    fn generated_settings_have_a_seed(settings in strategy::settings()) {
+        let debug = format!("{settings:?}");
+        prop_assert!(debug.contains("seed: Some("), "{}", debug);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn stream_ends_under_any_settings(settings in any::<ChokeSettings>()) {
+        prop_assert_eq!(run(settings), Some(100));
+    }
+
+    #[test]
+    fn stream_ends_under_any_scenario(scenario in strategy::scenario()) {
+        prop_assert_eq!(run(ChokeSettings::default().set_settings_source(scenario)), Some(100));
+    }
+}
@@ -1,14 +1,76 @@
+#![cfg(not(target_arch = "wasm32"))]
+
 use bytes::Bytes;
 use chokepoint::{
+    event::{
+        ChokeEventKind,
+        DropReason,
+    },
+    hook::{
+        DecisionHook,
+        HookItem,
+    },
+    replay::{
+        Decision,
+        DecisionRecorder,
+    },
+    BandwidthLimit,
+    Burst,
+    ChokeItem,
     ChokeSettings,
     ChokeSettingsOrder,
     ChokeStream,
+    Coalescing,
+    Congestion,
+    CrossTraffic,
+    CrossTrafficPattern,
+    Latency,
+    Nagle,
+    Reorder,
+    SharedBandwidthLimit,
+    SlowStart,
+};
+use futures::stream::{
+    Stream as _,
+    StreamExt,
 };
-use futures::stream::StreamExt;
 use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
+#[tokio::test(start_paused = true)]
+async fn idle_resolves_once_items_are_delivered() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut stream = ChokeStream::new(
+        UnboundedReceiverStream::new(rx),
+        ChokeSettings::default().set_latency(Some(Latency::Constant(Duration::from_millis(100)))),
+    );
+    let (first, second) = (stream.idle(), stream.idle());
+    let (output_tx, output) = mpsc::unbounded_channel();
+    let consume = async move {
+        while let Some(item) = stream.next().await {
+            output_tx.send(item).unwrap();
+        }
+    };
+
+    let wait = async move {
+        let start = tokio::time::Instant::now();
+        for i in 0..10u8 {
+            tx.send(Bytes::from(vec![i])).unwrap();
+        }
+        first.await;
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+        assert_eq!(output.len(), 10);
+
+        // Only reports after the future is awaited count
+        tx.send(Bytes::from_static(b"more")).unwrap();
+        second.await;
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+        assert_eq!(output.len(), 11);
+    };
+    tokio::join!(consume, wait);
+}
+
 #[tokio::test]
 async fn delivery_without_modifications() {
     let (tx, rx) = mpsc::unbounded_channel();
@@ -29,17 +91,19 @@ async fn delivery_without_modifications() {
 }
 
 #[yare::parameterized(
-        unordered = { ChokeSettingsOrder::Unordered, vec![2, 3, 1] },
-        ordered = { ChokeSettingsOrder::Ordered, vec![1, 2, 3] },
-        // backpressure = { ChokeSettingsOrder::Backpressure, vec![1, 2, 3] }
+        unordered = { ChokeSettingsOrder::Unordered, false, vec![2, 3, 1] },
+        ordered = { ChokeSettingsOrder::Ordered, false, vec![1, 2, 3] },
+        unordered_backpressure = { ChokeSettingsOrder::Unordered, true, vec![1, 2, 3] },
+        ordered_backpressure = { ChokeSettingsOrder::Ordered, true, vec![1, 2, 3] },
     )]
 #[test_macro(tokio::test)]
-async fn ordering(ordering: ChokeSettingsOrder, expected: Vec<usize>) {
+async fn ordering(ordering: ChokeSettingsOrder, backpressure: bool, expected: Vec<usize>) {
     let (tx, rx) = mpsc::unbounded_channel();
     let stream = ChokeStream::new(
         Box::new(UnboundedReceiverStream::new(rx)),
         ChokeSettings::default()
             .set_ordering(Some(ordering))
+            .set_backpressure(Some(backpressure))
             .set_latency_distribution(Some({
                 let mut n = 0;
                 move || {
@@ -66,3 +130,1459 @@ async fn ordering(ordering: ChokeSettingsOrder, expected: Vec<usize>) {
 
     assert_eq!(output, expected);
 }
+
+#[tokio::test(start_paused = true)]
+async fn partially_ordered_passes_undelayed_items() {
+    let delays = [Some(150), None, Some(50), None, Some(100)];
+    let mut delays = delays.into_iter().map(|delay| delay.map(Duration::from_millis));
+    let stream = ChokeStream::new(
+        futures::stream::iter((1..=5u8).map(|i| Bytes::from(vec![i]))),
+        ChokeSettings::default()
+            .set_ordering(Some(ChokeSettingsOrder::PartiallyOrdered))
+            .set_latency_distribution(Some(move || delays.next().flatten())),
+    );
+
+    // The undelayed items 2 and 4 don't wait for 1, the delayed items keep their order
+    let output = stream.map(|item| item[0]).collect::<Vec<_>>().await;
+    assert_eq!(output, [2, 4, 1, 3, 5]);
+}
+
+#[tokio::test]
+async fn current_settings_reflect_partial_updates() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut settings = ChokeSettings::default().set_drop_probability(Some(0.0));
+    let settings_tx = settings.settings_updater();
+    let mut stream = ChokeStream::new(Box::new(UnboundedReceiverStream::new(rx)), settings);
+
+    settings_tx
+        .send(ChokeSettings::default().set_ordering(Some(ChokeSettingsOrder::Unordered)))
+        .await
+        .unwrap();
+    tx.send(Bytes::from_static(b"a")).unwrap();
+    stream.next().await.unwrap();
+
+    settings_tx
+        .send(ChokeSettings::default().set_bandwidth_limit(Some(1000)))
+        .await
+        .unwrap();
+    tx.send(Bytes::from_static(b"b")).unwrap();
+    stream.next().await.unwrap();
+
+    let current = stream.current_settings();
+    assert_eq!(current.drop_probability, 0.0);
+    assert_eq!(current.ordering, ChokeSettingsOrder::Unordered);
+    assert_eq!(
+        current.bandwidth_limit.map(|limit| limit.bytes_per_second()),
+        Some(1000)
+    );
+    assert!(!current.latency_distribution);
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_string(&stream.current_settings()).unwrap();
+        let snapshot: chokepoint::ChokeSettingsSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(snapshot, stream.current_settings());
+    }
+}
+
+#[tokio::test]
+async fn settings_watcher_applies_latest_settings() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut settings = ChokeSettings::default();
+    let settings_tx = settings.settings_watcher();
+    let mut stream = ChokeStream::new(Box::new(UnboundedReceiverStream::new(rx)), settings);
+
+    // Neither update blocks, only the latest one is applied.
+    settings_tx.send_replace(ChokeSettings::default().set_drop_probability(Some(0.5)));
+    settings_tx.send_replace(ChokeSettings::default().set_corrupt_probability(Some(0.0)));
+    settings_tx.send_modify(|settings| settings.merge(ChokeSettings::default().set_bandwidth_limit(Some(1000))));
+
+    tx.send(Bytes::from_static(b"a")).unwrap();
+    stream.next().await.unwrap();
+
+    let current = stream.current_settings();
+    assert_eq!(current.drop_probability, 0.0);
+    assert_eq!(
+        current.bandwidth_limit.map(|limit| limit.bytes_per_second()),
+        Some(1000)
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn settings_watcher_wakes_an_idle_stream() {
+    let items = futures::stream::iter([Bytes::from_static(b"a")]).chain(futures::stream::pending());
+    let mut settings = ChokeSettings::default()
+        .set_ordering(Some(ChokeSettingsOrder::Ordered))
+        .set_latency(Some(Latency::Constant(Duration::from_secs(3600))));
+    let settings_tx = settings.settings_watcher();
+    let mut stream = ChokeStream::new(items, settings);
+    let mut events = stream.events();
+    let start = tokio::time::Instant::now();
+
+    // The item waits for an hour, the update is applied right away and drops it.
+    let dropped = async {
+        assert_eq!(events.next().await.unwrap().kind, ChokeEventKind::Enqueued);
+        settings_tx.send_replace(ChokeSettings::default().set_ordering(Some(ChokeSettingsOrder::Unordered)));
+        events
+            .filter(|event| std::future::ready(matches!(event.kind, ChokeEventKind::Dropped(_))))
+            .next()
+            .await
+            .unwrap()
+    };
+    let dropped = tokio::select! {
+        _ = stream.next() => unreachable!("the item is dropped"),
+        dropped = dropped => dropped,
+    };
+    assert_eq!(dropped.kind, ChokeEventKind::Dropped(DropReason::Reconfigured));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn cloned_settings_configure_multiple_streams() {
+    let mut settings = ChokeSettings::default().set_latency_distribution(Some({
+        let mut n = 0;
+        move || {
+            n += 1;
+            Some(Duration::from_millis(n))
+        }
+    }));
+    let settings_tx = settings.settings_watcher();
+
+    let mut senders = Vec::new();
+    let mut streams = Vec::new();
+    for _ in 0..3 {
+        let (tx, rx) = mpsc::unbounded_channel();
+        senders.push(tx);
+        streams.push(ChokeStream::new(
+            Box::new(UnboundedReceiverStream::new(rx)),
+            settings.clone(),
+        ));
+    }
+
+    settings_tx.send_replace(ChokeSettings::default().set_ordering(Some(ChokeSettingsOrder::Unordered)));
+
+    for (tx, stream) in senders.iter().zip(streams.iter_mut()) {
+        tx.send(Bytes::from_static(b"a")).unwrap();
+        stream.next().await.unwrap();
+        let current = stream.current_settings();
+        assert!(current.latency_distribution);
+        assert_eq!(current.ordering, ChokeSettingsOrder::Unordered);
+    }
+}
+
+#[tokio::test]
+async fn bandwidth_limit_drops_when_not_full() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let stream = ChokeStream::new(
+        Box::new(UnboundedReceiverStream::new(rx)),
+        ChokeSettings::default().set_bandwidth_limit_with(
+            BandwidthLimit::builder()
+                .bytes_per_sec(1_000_000)
+                .drop_ratio(1.0)
+                .only_drop_when_full(false),
+        ),
+    );
+
+    for i in 0..10usize {
+        tx.send(Bytes::from(i.to_le_bytes().to_vec())).unwrap();
+    }
+    drop(tx);
+
+    assert_eq!(stream.collect::<Vec<_>>().await.len(), 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn bandwidth_limit_pacing() {
+    let emissions = |pacing: bool| async move {
+        let stream = ChokeStream::new(
+            futures::stream::iter((0..5).map(|_| Bytes::from(vec![0; 1000]))),
+            ChokeSettings::default().set_bandwidth_limit_with(
+                BandwidthLimit::builder()
+                    .bytes_per_sec(10_000)
+                    .only_drop_when_full(true)
+                    .pacing(pacing),
+            ),
+        );
+        let start = tokio::time::Instant::now();
+        stream.map(|_| start.elapsed().as_millis()).collect::<Vec<_>>().await
+    };
+
+    // The window has room for all items, so without pacing they are emitted as a burst
+    assert_eq!(emissions(false).await, [0, 0, 0, 0, 0]);
+    // With pacing, one item every 1000 bytes / 10000 bytes per second
+    assert_eq!(emissions(true).await, [0, 100, 200, 300, 400]);
+}
+
+#[yare::parameterized(
+        ordered = { ChokeSettingsOrder::Ordered },
+        unordered = { ChokeSettingsOrder::Unordered },
+        partially_ordered = { ChokeSettingsOrder::PartiallyOrdered },
+    )]
+#[test_macro(tokio::test(start_paused = true))]
+async fn items_are_emitted_at_their_deadline(ordering: ChokeSettingsOrder) {
+    let mut delays = [5, 15, 25].into_iter();
+    let stream = ChokeStream::new(
+        futures::stream::iter((0..3).map(|_| Bytes::from_static(b"payload"))),
+        ChokeSettings::default()
+            .set_ordering(Some(ordering))
+            .set_latency_distribution(Some(move || delays.next().map(Duration::from_millis))),
+    );
+    let start = tokio::time::Instant::now();
+    let output = stream.map(|_| start.elapsed()).collect::<Vec<_>>().await;
+    assert_eq!(output, [5, 15, 25].map(Duration::from_millis));
+}
+
+#[tokio::test(start_paused = true)]
+async fn bandwidth_limit_waits_for_capacity() {
+    let stream = ChokeStream::new(
+        futures::stream::iter((0..5).map(|_| Bytes::from(vec![0; 500]))),
+        ChokeSettings::default().set_bandwidth_limit(Some(1000)),
+    );
+    let start = tokio::time::Instant::now();
+    let output = stream.map(|_| start.elapsed()).collect::<Vec<_>>().await;
+    // Emitted as soon as the previous items have left the 1s window
+    assert_eq!(output, [0, 0, 1000, 1000, 2000].map(Duration::from_millis));
+}
+
+#[tokio::test]
+async fn poll_budget_yields_to_other_tasks() {
+    // Always ready, without a budget the first poll would take items forever. The latency is long enough that no item
+    // is due before the task yields.
+    let items = futures::stream::repeat(Bytes::from_static(b"payload"));
+    let mut stream = ChokeStream::new(
+        items,
+        ChokeSettings::default()
+            .set_latency_distribution(Some(|| Some(Duration::from_millis(50))))
+            .set_poll_budget(Some(10)),
+    );
+    assert!(futures::poll!(stream.next()).is_pending());
+    assert_eq!(stream.stats().received, 10);
+
+    let other = tokio::spawn(async {});
+    let output = stream.take(3).collect::<Vec<_>>().await;
+    assert_eq!(output.len(), 3);
+    assert!(other.is_finished());
+}
+
+#[tokio::test(start_paused = true)]
+async fn shared_bandwidth_limit_spans_streams() {
+    let shared = SharedBandwidthLimit::new(BandwidthLimit::builder().bytes_per_sec(1000));
+    let settings = ChokeSettings::default().set_shared_bandwidth_limit(Some(shared.clone()));
+    let start = tokio::time::Instant::now();
+    let emissions = |settings: ChokeSettings| {
+        let items = futures::stream::iter((0..3).map(|_| Bytes::from(vec![0; 500])));
+        ChokeStream::new(items, settings)
+            .map(move |_| start.elapsed().as_millis())
+            .collect::<Vec<_>>()
+    };
+
+    // Together the streams emit 1000 bytes per second
+    let (a, b) = futures::join!(emissions(settings.clone()), emissions(settings.clone()));
+    let mut output = [a, b].concat();
+    output.sort();
+    assert_eq!(output, [0, 0, 1000, 1000, 2000, 2000]);
+
+    // Changing the limit applies to all streams
+    shared.set_limit(BandwidthLimit::builder().bytes_per_sec(500));
+    let stream = ChokeStream::new(futures::stream::empty::<Bytes>(), settings);
+    assert_eq!(
+        stream.current_settings().shared_bandwidth_limit,
+        Some(BandwidthLimit::builder().bytes_per_sec(500).build())
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn congestion_lowers_bandwidth_limit() {
+    let limit = BandwidthLimit::builder().bytes_per_sec(10_000).pacing(true).congestion(
+        Congestion::builder()
+            .interval(Duration::from_millis(100))
+            .min_ratio(0.5),
+    );
+    let mut stream = ChokeStream::new(
+        futures::stream::iter((0..100).map(|_| Bytes::from(vec![0; 100]))),
+        ChokeSettings::default().set_bandwidth_limit_with(limit),
+    );
+    assert_eq!(stream.effective_bandwidth_limit(), Some(10_000));
+
+    // The items queue up behind the limit, so it is halved after 100ms at the configured rate: from then on, an item is
+    // emitted every 20ms instead of every 10ms
+    let start = tokio::time::Instant::now();
+    let mut emitted = Vec::new();
+    while stream.next().await.is_some() {
+        emitted.push(start.elapsed().as_millis());
+    }
+    assert_eq!(stream.effective_bandwidth_limit(), Some(5000));
+    assert_eq!(emitted[9..13], [90, 100, 110, 130]);
+    assert_eq!(emitted[99], 1870);
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_start_ramps_up_bandwidth_limit() {
+    let limit = BandwidthLimit::builder()
+        .bytes_per_sec(10_000)
+        .pacing(true)
+        .slow_start(SlowStart::builder().duration(Duration::from_secs(1)).initial_ratio(0.1));
+    let mut stream = ChokeStream::new(
+        futures::stream::iter((0..100).map(|_| Bytes::from(vec![0; 100]))),
+        ChokeSettings::default().set_bandwidth_limit_with(limit),
+    );
+
+    let start = tokio::time::Instant::now();
+    let mut emitted = Vec::new();
+    while stream.next().await.is_some() {
+        emitted.push(start.elapsed().as_millis());
+    }
+    // 100ms between the first items at a tenth of the limit, 10ms once it is reached after a second
+    let gaps = emitted.windows(2).map(|pair| pair[1] - pair[0]).collect::<Vec<_>>();
+    assert_eq!(gaps[..3], [100, 80, 66]);
+    let ramped = emitted.iter().position(|millis| *millis >= 1000).unwrap();
+    assert!(gaps[ramped..].iter().all(|gap| *gap == 10), "{gaps:?}");
+    assert_eq!(stream.effective_bandwidth_limit(), Some(10_000));
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_start_restarts() {
+    let limit = BandwidthLimit::builder()
+        .bytes_per_sec(10_000)
+        .slow_start(SlowStart::builder().duration(Duration::from_secs(1)).initial_ratio(0.1))
+        .build();
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut stream = ChokeStream::new(
+        UnboundedReceiverStream::new(rx),
+        ChokeSettings::default().set_bandwidth_limit_with(limit.clone()),
+    );
+    let emit = async |stream: &mut ChokeStream<Bytes, UnboundedReceiverStream<Bytes>>| {
+        tx.send(Bytes::from(vec![0; 10])).unwrap();
+        stream.next().await.unwrap();
+        stream.effective_bandwidth_limit()
+    };
+
+    assert_eq!(emit(&mut stream).await, Some(1000));
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(emit(&mut stream).await, Some(10_000));
+
+    stream.restart_slow_start();
+    assert_eq!(emit(&mut stream).await, Some(1000));
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(emit(&mut stream).await, Some(10_000));
+
+    // Disabling the limit and enabling it again starts over as well
+    stream.apply_settings(ChokeSettings::default().set_bandwidth_limit(Some(0)));
+    assert_eq!(emit(&mut stream).await, None);
+    stream.apply_settings(ChokeSettings::default().set_bandwidth_limit_with(limit));
+    assert_eq!(emit(&mut stream).await, Some(1000));
+}
+
+#[tokio::test(start_paused = true)]
+async fn cross_traffic_competes_for_bandwidth_limit() {
+    let cross_traffic = CrossTraffic::builder()
+        .pattern(CrossTrafficPattern::Constant { bytes_per_second: 5000 })
+        .packet_size(100);
+    let limit = BandwidthLimit::builder()
+        .bytes_per_sec(10_000)
+        .pacing(true)
+        .cross_traffic(cross_traffic);
+    let mut stream = ChokeStream::new(
+        futures::stream::iter((0..50).map(|_| Bytes::from(vec![0; 100]))),
+        ChokeSettings::default().set_bandwidth_limit_with(limit),
+    );
+
+    let start = tokio::time::Instant::now();
+    let mut emitted = Vec::new();
+    while stream.next().await.is_some() {
+        emitted.push(start.elapsed().as_millis());
+    }
+    // Every other 10ms slot of the limit is taken by a packet of the cross traffic
+    let gaps = emitted.windows(2).map(|pair| pair[1] - pair[0]).collect::<Vec<_>>();
+    assert!(gaps.iter().all(|gap| *gap == 20), "{gaps:?}");
+}
+
+/// A packet with the two bits of the ECN field.
+#[derive(Debug)]
+struct EcnPacket {
+    ect: bool,
+    ce: bool,
+}
+
+impl ChokeItem for EcnPacket {
+    fn byte_len(&self) -> usize {
+        100
+    }
+
+    fn corrupt(&mut self) {}
+
+    fn mark_ecn(&mut self) -> bool {
+        self.ce = self.ect;
+        self.ect
+    }
+}
+
+#[tokio::test]
+async fn ecn_marks_instead_of_dropping() {
+    let limit = BandwidthLimit::builder()
+        .bytes_per_sec(1_000_000)
+        .drop_ratio(1.0)
+        .only_drop_when_full(false)
+        .ecn(true);
+    let items = (0..10).map(|i| EcnPacket {
+        ect: i % 2 == 0,
+        ce: false,
+    });
+    let mut stream = ChokeStream::new(
+        futures::stream::iter(items),
+        ChokeSettings::default().set_bandwidth_limit_with(limit),
+    );
+
+    // Only the ECN-capable packets are delivered, all of them marked
+    let mut delivered = 0;
+    while let Some(packet) = stream.next().await {
+        assert!(packet.ect && packet.ce);
+        delivered += 1;
+    }
+    assert_eq!(delivered, 5);
+    assert_eq!(stream.stats().marked, 5);
+    assert_eq!(stream.stats().dropped, 5);
+}
+
+#[tokio::test(start_paused = true)]
+async fn item_ttl_discards_stale_items() {
+    let mut delays = [Duration::from_millis(10), Duration::from_millis(600)]
+        .into_iter()
+        .cycle();
+    let mut stream = ChokeStream::new(
+        futures::stream::iter((0..30).map(|_| Bytes::from(vec![0; 100]))),
+        ChokeSettings::default()
+            .set_latency_distribution(Some(move || delays.next()))
+            .set_bandwidth_limit(Some(500))
+            .set_item_ttl(Some(Duration::from_millis(500))),
+    );
+    let mut emitted = 0;
+    while stream.next().await.is_some() {
+        emitted += 1;
+    }
+
+    // Half of the items are delayed beyond the TTL, of the others only 5 fit into the bandwidth limit in time
+    let stats = stream.stats();
+    assert_eq!(emitted, 5);
+    assert_eq!(stats.emitted, 5);
+    assert_eq!(stats.expired, 25);
+    assert_eq!(stats.dropped, 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn item_ttl_discards_duplicates_of_stale_items() {
+    let mut stream = ChokeStream::new(
+        futures::stream::iter((0..10).map(|_| Bytes::from(vec![0; 100]))),
+        ChokeSettings::default()
+            .set_latency(Some(Latency::Constant(Duration::from_millis(100))))
+            .set_duplicate_probability(Some(1.0))
+            .set_item_ttl(Some(Duration::from_millis(50))),
+    );
+    assert_eq!((&mut stream).count().await, 0);
+
+    let stats = stream.stats();
+    assert_eq!((stats.expired, stats.duplicated, stats.emitted), (10, 0, 0));
+}
+
+#[tokio::test(start_paused = true)]
+async fn initial_delay_holds_back_first_items() {
+    // An item every 10ms, starting after 10ms
+    let items = futures::stream::iter(0..30)
+        .then(|_| async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Bytes::from_static(b"hello")
+        })
+        .boxed();
+    let mut stream = ChokeStream::new(
+        items,
+        ChokeSettings::default()
+            .set_latency(Some(Latency::Constant(Duration::from_millis(20))))
+            .set_initial_delay(Some(Latency::Constant(Duration::from_millis(150)))),
+    );
+
+    let start = tokio::time::Instant::now();
+    let mut emitted = Vec::new();
+    while stream.next().await.is_some() {
+        emitted.push(start.elapsed().as_millis());
+    }
+    // The setup ends at 160ms, the items taken until then are emitted together after their latency
+    assert!(emitted[..16].iter().all(|millis| *millis == 180), "{emitted:?}");
+    assert_eq!(emitted[16..19], [190, 200, 210]);
+}
+
+#[tokio::test]
+async fn into_parts_returns_pending_items() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut stream = ChokeStream::new(
+        Box::new(UnboundedReceiverStream::new(rx)),
+        ChokeSettings::default().set_latency_distribution(Some(|| Some(Duration::from_secs(60)))),
+    );
+
+    for i in 0..3usize {
+        tx.send(Bytes::from(i.to_le_bytes().to_vec())).unwrap();
+    }
+    assert!(tokio::time::timeout(Duration::from_millis(50), stream.next())
+        .await
+        .is_err());
+
+    let (mut inner, pending) = stream.into_parts();
+    let pending = pending
+        .iter()
+        .map(|packet| usize::from_le_bytes(packet[0..8].try_into().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(pending, vec![0, 1, 2]);
+
+    // The inner stream keeps working without any shaping
+    tx.send(Bytes::from(3usize.to_le_bytes().to_vec())).unwrap();
+    assert_eq!(inner.next().await.unwrap(), Bytes::from(3usize.to_le_bytes().to_vec()));
+}
+
+#[tokio::test]
+async fn drain_emits_delayed_items() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut stream = ChokeStream::new(
+        Box::new(UnboundedReceiverStream::new(rx)),
+        ChokeSettings::default().set_latency_distribution(Some(|| Some(Duration::from_millis(100)))),
+    );
+
+    for i in 0..3usize {
+        tx.send(Bytes::from(i.to_le_bytes().to_vec())).unwrap();
+    }
+    assert!(tokio::time::timeout(Duration::from_millis(10), stream.next())
+        .await
+        .is_err());
+
+    // Items sent after the stream was last polled are not part of the drain
+    tx.send(Bytes::from(3usize.to_le_bytes().to_vec())).unwrap();
+
+    let start = std::time::Instant::now();
+    let drained = stream
+        .drain()
+        .await
+        .iter()
+        .map(|packet| usize::from_le_bytes(packet[0..8].try_into().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(drained, vec![0, 1, 2]);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert!(stream.drain().await.is_empty());
+}
+
+#[tokio::test]
+async fn size_hint() {
+    let items = || futures::stream::iter((0..10usize).map(|i| Bytes::from(i.to_le_bytes().to_vec())));
+
+    let stream = ChokeStream::new(Box::new(items()), ChokeSettings::default());
+    assert_eq!(stream.size_hint(), (10, Some(10)));
+    assert_eq!(stream.collect::<Vec<_>>().await.len(), 10);
+
+    let stream = ChokeStream::new(
+        Box::new(items()),
+        ChokeSettings::default().set_drop_probability(Some(0.5)),
+    );
+    assert_eq!(stream.size_hint(), (0, Some(10)));
+
+    let stream = ChokeStream::new(
+        Box::new(items()),
+        ChokeSettings::default().set_duplicate_probability(Some(0.5)),
+    );
+    assert_eq!(stream.size_hint(), (10, Some(20)));
+
+    let stream = ChokeStream::new(Box::new(items()), ChokeSettings::default().set_mtu(Some(4)));
+    assert_eq!(stream.size_hint(), (10, None));
+
+    let stream = ChokeStream::new(
+        Box::new(items()),
+        ChokeSettings::default().set_item_ttl(Some(Duration::from_secs(1))),
+    );
+    assert_eq!(stream.size_hint(), (0, Some(10)));
+
+    struct Keep;
+
+    impl DecisionHook for Keep {
+        fn process(&mut self, _item: &HookItem<'_>, _decision: &mut Decision) {}
+    }
+
+    let stream = ChokeStream::new(Box::new(items()), ChokeSettings::default().add_decision_hook(Keep));
+    assert_eq!(stream.size_hint(), (0, Some(20)));
+
+    // Queued items are accounted for
+    let mut stream = ChokeStream::new(
+        Box::new(items()),
+        ChokeSettings::default().set_latency_distribution(Some(|| Some(Duration::from_secs(60)))),
+    );
+    assert!(tokio::time::timeout(Duration::from_millis(10), stream.next())
+        .await
+        .is_err());
+    assert_eq!(stream.size_hint(), (10, Some(10)));
+
+    // And so are held items, which are released when the inner stream ends
+    let mut stream = ChokeStream::new(
+        Box::new(items().chain(futures::stream::pending())),
+        ChokeSettings::default().set_burst(Some(Burst::builder().size(100).build())),
+    );
+    assert!(tokio::time::timeout(Duration::from_millis(10), stream.next())
+        .await
+        .is_err());
+    assert_eq!(stream.size_hint(), (10, Some(10)));
+}
+
+#[tokio::test]
+async fn wraps_unpin_and_not_unpin_streams() {
+    // `unfold` with an async block is not `Unpin`
+    let not_unpin = futures::stream::unfold(0usize, |i| async move {
+        (i < 3).then(|| (Bytes::from(i.to_le_bytes().to_vec()), i + 1))
+    });
+    let stream = ChokeStream::new(
+        not_unpin,
+        ChokeSettings::default().set_latency_distribution(Some(|| Some(Duration::from_millis(10)))),
+    );
+    assert_eq!(stream.collect::<Vec<_>>().await.len(), 3);
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut stream = ChokeStream::new(UnboundedReceiverStream::new(rx), ChokeSettings::default());
+    tx.send(Bytes::from_static(b"unpin")).unwrap();
+    assert_eq!(stream.next().await.unwrap(), Bytes::from_static(b"unpin"));
+}
+
+#[tokio::test]
+async fn replay_with_recorded_seed() {
+    let items = || futures::stream::iter((0..1000usize).map(|i| Bytes::from(i.to_le_bytes().to_vec())));
+    let settings = || {
+        ChokeSettings::default()
+            .set_drop_probability(Some(0.3))
+            .set_duplicate_probability(Some(0.1))
+    };
+
+    // The seed is chosen randomly and recorded in the stats
+    let mut stream = ChokeStream::new(items(), settings());
+    let mut output = Vec::new();
+    while let Some(item) = stream.next().await {
+        output.push(item);
+    }
+    let stats = stream.stats();
+    assert_eq!(stats.received, 1000);
+    assert_eq!(stats.emitted, output.len());
+
+    let mut replay = ChokeStream::new(items(), settings().set_seed(Some(stats.seed)));
+    let mut replayed = Vec::new();
+    while let Some(item) = replay.next().await {
+        replayed.push(item);
+    }
+    assert_eq!(replayed, output);
+    assert_eq!(replay.stats(), stats);
+}
+
+#[tokio::test(start_paused = true)]
+async fn latency_models_are_reproduced_with_the_seed() {
+    let latency = Latency::Normal {
+        mean: Duration::from_millis(20),
+        std_dev: Duration::from_millis(10),
+        max: Duration::from_millis(50),
+    };
+    let settings = ChokeSettings::default()
+        .set_ordering(Some(ChokeSettingsOrder::Unordered))
+        .set_latency(Some(latency.clone()))
+        .set_seed(Some(5));
+    let run = |settings: ChokeSettings| {
+        let items = futures::stream::iter((0..200usize).map(|i| Bytes::from(i.to_le_bytes().to_vec())));
+        let stream = ChokeStream::new(items, settings);
+        let start = tokio::time::Instant::now();
+        stream.map(move |item| (start.elapsed(), item)).collect::<Vec<_>>()
+    };
+    assert_eq!(
+        ChokeStream::new(futures::stream::empty::<Bytes>(), settings.clone())
+            .current_settings()
+            .latency,
+        Some(latency.clone())
+    );
+
+    let output = run(settings.clone()).await;
+    assert_eq!(output.len(), 200);
+    assert!(output.iter().all(|(elapsed, _)| *elapsed <= Duration::from_millis(50)));
+    assert!(output.windows(2).any(|pair| pair[0].1 > pair[1].1));
+    assert_eq!(run(settings).await, output);
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_string(&latency).unwrap();
+        assert_eq!(serde_json::from_str::<Latency>(&json).unwrap(), latency);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn latency_models_sample_their_distributions() {
+    let millis = Duration::from_millis;
+    let latencies = |latency: Latency| async move {
+        let items = futures::stream::iter((0..500usize).map(|i| Bytes::from(i.to_le_bytes().to_vec())));
+        let settings = ChokeSettings::default()
+            .set_ordering(Some(ChokeSettingsOrder::Unordered))
+            .set_latency(Some(latency))
+            .set_seed(Some(3));
+        let start = tokio::time::Instant::now();
+        let mut latencies = ChokeStream::new(items, settings)
+            .map(|_| start.elapsed())
+            .collect::<Vec<_>>()
+            .await;
+        latencies.sort();
+        latencies
+    };
+
+    let uniform = latencies(Latency::Uniform {
+        min: millis(10),
+        max: millis(30),
+    })
+    .await;
+    assert!(uniform
+        .iter()
+        .all(|latency| (millis(10)..=millis(30)).contains(latency)));
+    assert!((millis(17)..=millis(23)).contains(&uniform[250]));
+
+    let log_normal = latencies(Latency::LogNormal {
+        median: millis(20),
+        sigma: 0.5,
+        max: millis(200),
+    })
+    .await;
+    assert!((millis(17)..=millis(23)).contains(&log_normal[250]));
+    assert!(log_normal[495] > millis(40));
+    assert!(log_normal.iter().all(|latency| *latency <= millis(200)));
+
+    let pareto = latencies(Latency::Pareto {
+        scale: millis(10),
+        shape: 1.5,
+        max: millis(200),
+    })
+    .await;
+    assert!(pareto
+        .iter()
+        .all(|latency| (millis(10)..=millis(200)).contains(latency)));
+    assert!(pareto[495] > millis(50));
+}
+
+#[tokio::test(start_paused = true)]
+async fn invalid_latency_models_do_not_panic() {
+    let millis = Duration::from_millis;
+    let first_latency = |latency: Latency| async move {
+        let items = futures::stream::iter([Bytes::from_static(b"a")]);
+        let start = tokio::time::Instant::now();
+        let mut stream = ChokeStream::new(items, ChokeSettings::default().set_latency(Some(latency)));
+        stream.next().await.unwrap();
+        start.elapsed()
+    };
+
+    let uniform = first_latency(Latency::Uniform {
+        min: millis(20),
+        max: millis(10),
+    })
+    .await;
+    assert!((millis(10)..=millis(20)).contains(&uniform));
+    for latency in [
+        Latency::LogNormal {
+            median: millis(20),
+            sigma: -1.0,
+            max: millis(200),
+        },
+        Latency::LogNormal {
+            median: millis(20),
+            sigma: f64::NAN,
+            max: millis(200),
+        },
+        Latency::Pareto {
+            scale: millis(10),
+            shape: 0.0,
+            max: millis(200),
+        },
+        Latency::SkewNormal {
+            location: millis(20),
+            scale: millis(5),
+            shape: f64::NAN,
+            max: millis(200),
+        },
+        Latency::SkewNormal {
+            location: millis(20),
+            scale: millis(5),
+            shape: f64::INFINITY,
+            max: millis(200),
+        },
+    ] {
+        assert_eq!(first_latency(latency).await, Duration::ZERO);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn decision_hooks_change_decisions() {
+    struct DropLarge(usize);
+
+    impl DecisionHook for DropLarge {
+        fn process(&mut self, item: &HookItem<'_>, decision: &mut Decision) {
+            if item.size > self.0 {
+                *decision = Decision::Drop;
+            }
+        }
+    }
+
+    struct Delay(Duration);
+
+    impl DecisionHook for Delay {
+        fn process(&mut self, _item: &HookItem<'_>, decision: &mut Decision) {
+            if let Decision::Deliver { delay, .. } = decision {
+                *delay = Some(self.0);
+            }
+        }
+    }
+
+    let recorder = DecisionRecorder::new();
+    let settings = ChokeSettings::default()
+        .add_decision_hook(DropLarge(4))
+        .add_decision_hook(Delay(Duration::from_millis(10)))
+        .set_decision_recorder(Some(recorder.clone()));
+    let items = futures::stream::iter([1, 8, 2, 8, 3].map(|len| Bytes::from(vec![0; len])));
+    let stream = ChokeStream::new(items, settings);
+    assert_eq!(stream.current_settings().decision_hooks, 2);
+
+    let start = tokio::time::Instant::now();
+    let output = stream
+        .map(|item| (start.elapsed().as_millis(), item.len()))
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(output, [(10, 1), (10, 2), (10, 3)]);
+    let dropped = recorder
+        .trace()
+        .decisions()
+        .map(|decision| *decision == Decision::Drop)
+        .collect::<Vec<_>>();
+    assert_eq!(dropped, [false, true, false, true, false]);
+}
+
+#[tokio::test]
+async fn replay_recorded_decisions() {
+    let items = || futures::stream::iter((0..500usize).map(|i| Bytes::from(i.to_le_bytes().to_vec())));
+    let collect = |mut stream: ChokeStream<Bytes, _>| async move {
+        let mut output = Vec::new();
+        while let Some(item) = stream.next().await {
+            output.push(item);
+        }
+        (output, stream.stats())
+    };
+
+    let recorder = DecisionRecorder::new();
+    let settings = ChokeSettings::default()
+        .set_drop_probability(Some(0.2))
+        .set_corrupt_probability(Some(0.2))
+        .set_duplicate_probability(Some(0.1))
+        .set_latency_distribution(Some(|| Some(Duration::from_micros(rand::random_range(0..1000)))))
+        .set_decision_recorder(Some(recorder.clone()));
+    let (output, stats) = collect(ChokeStream::new(items(), settings)).await;
+
+    let trace = recorder.take();
+    assert_eq!(trace.len(), 500);
+    assert!(recorder.trace().is_empty());
+
+    // No probabilities, everything comes from the trace
+    let settings = ChokeSettings::default().set_decision_replay(Some(trace.to_string().parse().unwrap()));
+    let (replayed, replay_stats) = collect(ChokeStream::new(items(), settings)).await;
+    assert_eq!(replayed, output);
+    assert_eq!(
+        (replay_stats.dropped, replay_stats.corrupted, replay_stats.duplicated),
+        (stats.dropped, stats.corrupted, stats.duplicated)
+    );
+}
+
+#[tokio::test]
+async fn reorder_pushes_items_back() {
+    // Items 2, 5 and 9 are pushed back, the last one until the inner stream has ended
+    let trace = "_\n_\n_ r\n_\n_\n_ r\n_\n_\n_\n_ r\n";
+    let stream = ChokeStream::new(
+        futures::stream::iter((0..10u8).map(|i| Bytes::from(vec![i]))),
+        ChokeSettings::default()
+            .set_reorder(Some(Reorder::builder().gap(2).build()))
+            .set_decision_replay(Some(trace.parse().unwrap())),
+    );
+
+    let output = stream.map(|item| item[0]).collect::<Vec<_>>().await;
+    assert_eq!(output, [0, 1, 3, 4, 2, 6, 7, 5, 8, 9]);
+}
+
+#[tokio::test]
+async fn mtu_fragments_items() {
+    let items = || futures::stream::iter((0..10u8).map(|i| Bytes::from(vec![i; 2500])));
+
+    let stream = ChokeStream::new(items(), ChokeSettings::default().set_mtu(Some(1000)));
+    let fragments = stream.collect::<Vec<_>>().await;
+    assert_eq!(
+        fragments.iter().map(|fragment| fragment.len()).collect::<Vec<_>>(),
+        [1000, 1000, 500].repeat(10)
+    );
+    assert_eq!(
+        fragments.concat(),
+        (0..10u8).flat_map(|i| vec![i; 2500]).collect::<Vec<_>>()
+    );
+
+    // Each fragment is shaped on its own
+    let mut stream = ChokeStream::new(
+        items(),
+        ChokeSettings::default()
+            .set_mtu(Some(1000))
+            .set_drop_probability(Some(0.3))
+            .set_seed(Some(5)),
+    );
+    let fragments = (&mut stream).collect::<Vec<_>>().await;
+    let stats = stream.stats();
+    assert_eq!(stats.received, 30);
+    assert!(stats.dropped > 0);
+    assert_eq!(fragments.len(), 30 - stats.dropped);
+
+    // Items up to the MTU are not split
+    let stream = ChokeStream::new(items(), ChokeSettings::default().set_mtu(Some(2500)));
+    assert_eq!(stream.collect::<Vec<_>>().await.len(), 10);
+}
+
+#[tokio::test]
+async fn coalescing_merges_due_items() {
+    let items = || futures::stream::iter((0..10u8).map(|i| Bytes::from(vec![i; 100])));
+    let coalescing = || Coalescing::builder().max_bytes(450).build();
+
+    let mut stream = ChokeStream::new(items(), ChokeSettings::default().set_coalescing(Some(coalescing())));
+    let output = (&mut stream).collect::<Vec<_>>().await;
+    assert_eq!(
+        output.iter().map(|item| item.len()).collect::<Vec<_>>(),
+        [400, 400, 200]
+    );
+    assert_eq!(
+        output.concat(),
+        (0..10u8).flat_map(|i| vec![i; 100]).collect::<Vec<_>>()
+    );
+    let stats = stream.stats();
+    assert_eq!((stats.emitted, stats.coalesced), (3, 7));
+
+    // Items that can't be merged are emitted on their own
+    let items = futures::stream::iter([Some(Bytes::from_static(b"a")), None, Some(Bytes::from_static(b"b"))]);
+    let stream = ChokeStream::new(items, ChokeSettings::default().set_coalescing(Some(coalescing())));
+    assert_eq!(
+        stream.collect::<Vec<_>>().await,
+        [Some(Bytes::from_static(b"a")), None, Some(Bytes::from_static(b"b"))]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn coalescing_holds_items() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let stream = ChokeStream::new(
+        UnboundedReceiverStream::new(rx),
+        ChokeSettings::default().set_coalescing(Some(
+            Coalescing::builder()
+                .max_bytes(1000)
+                .max_delay(Duration::from_millis(5))
+                .build(),
+        )),
+    );
+    tokio::spawn(async move {
+        for delay in [0, 1, 1, 10] {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            tx.send(Bytes::from_static(b"abc")).unwrap();
+        }
+        // The end of the stream would emit the held item right away
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    });
+
+    let start = tokio::time::Instant::now();
+    let output = stream
+        .map(|item| (start.elapsed().as_millis(), item.len()))
+        .collect::<Vec<_>>()
+        .await;
+    // The first three items are held until 5ms after the first one, the last one until 5ms after it was received
+    assert_eq!(output, [(5, 9), (17, 3)]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn nagle_holds_small_items() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let stream = ChokeStream::new(
+        UnboundedReceiverStream::new(rx),
+        ChokeSettings::default().set_nagle(Some(
+            Nagle::builder()
+                .min_bytes(250)
+                .max_delay(Duration::from_millis(40))
+                .build(),
+        )),
+    );
+    tokio::spawn(async move {
+        for (delay, size) in [(0, 100), (1, 100), (1, 100), (10, 100), (100, 1000)] {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            tx.send(Bytes::from(vec![0; size])).unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    });
+
+    let start = tokio::time::Instant::now();
+    let output = stream
+        .map(|item| (start.elapsed().as_millis(), item.len()))
+        .collect::<Vec<_>>()
+        .await;
+    // Released once 250 bytes are held, after 40ms, and right away for a large item
+    assert_eq!(output, [(2, 100), (2, 100), (2, 100), (52, 100), (112, 1000)]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn burst_releases_held_items() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let stream = ChokeStream::new(
+        UnboundedReceiverStream::new(rx),
+        ChokeSettings::default().set_burst(Some(
+            Burst::builder().size(3).interval(Duration::from_millis(25)).build(),
+        )),
+    );
+    tokio::spawn(async move {
+        for (i, delay) in [0, 10, 10, 10, 50].into_iter().enumerate() {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            tx.send(Bytes::from(vec![i as u8])).unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    });
+
+    let start = tokio::time::Instant::now();
+    let output = stream
+        .map(|item| (start.elapsed().as_millis(), item[0]))
+        .collect::<Vec<_>>()
+        .await;
+    // Released once 3 items are held and 25ms after the first held item otherwise
+    assert_eq!(output, [(20, 0), (20, 1), (20, 2), (55, 3), (105, 4)]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn backpressure_takes_items_held_for_a_burst() {
+    let stream = ChokeStream::new(
+        futures::stream::iter((0..7u8).map(|i| Bytes::from(vec![i]))),
+        ChokeSettings::default()
+            .set_backpressure(Some(true))
+            .set_burst(Some(Burst::builder().size(3).build())),
+    );
+    let start = tokio::time::Instant::now();
+    let output = stream.map(|item| item[0]).collect::<Vec<_>>().await;
+    assert_eq!(output, [0, 1, 2, 3, 4, 5, 6]);
+    assert_eq!(start.elapsed(), Duration::ZERO);
+}
+
+#[tokio::test(start_paused = true)]
+async fn chain_matches_nested_streams() {
+    let hop = |millis, seed| {
+        ChokeSettings::default()
+            .set_latency(Some(Latency::Constant(Duration::from_millis(millis))))
+            .set_drop_probability(Some(0.1))
+            .set_bandwidth_limit(Some(1000))
+            .set_seed(Some(seed))
+    };
+    let items = || futures::stream::iter((0..100u32).map(|i| Bytes::from(i.to_le_bytes().to_vec())));
+    let start = tokio::time::Instant::now();
+    let timed = move |item: Bytes| {
+        (
+            start.elapsed().as_millis(),
+            u32::from_le_bytes(item[..].try_into().unwrap()),
+        )
+    };
+
+    let nested = ChokeStream::new(
+        ChokeStream::new(ChokeStream::new(items(), hop(5, 1)), hop(40, 2)),
+        hop(5, 3),
+    );
+    let mut chain = ChokeStream::new(items(), hop(5, 1)).chain(hop(40, 2)).chain(hop(5, 3));
+    assert_eq!(chain.hops(), 3);
+    let (nested, chained) = futures::join!(
+        nested.map(timed).collect::<Vec<_>>(),
+        chain.by_ref().map(timed).collect::<Vec<_>>()
+    );
+    assert_eq!(chained, nested);
+    assert_eq!(chained.first().map(|(millis, _)| *millis), Some(50));
+
+    // Every hop takes the items emitted by the previous one
+    let stats = chain.stats();
+    assert_eq!(stats[0].received, 100);
+    assert_eq!(stats[1].received, stats[0].emitted);
+    assert_eq!(stats[2].received, stats[1].emitted);
+    assert_eq!(stats[2].emitted, chained.len());
+    assert_eq!(
+        stats.iter().map(|stats| stats.dropped).sum::<usize>(),
+        100 - chained.len()
+    );
+    assert_eq!(
+        chain.current_settings()[1].latency,
+        Some(Latency::Constant(Duration::from_millis(40)))
+    );
+}
+
+#[tokio::test]
+async fn chain_with_backpressure_takes_items_dropped_by_later_hops() {
+    let chain = ChokeStream::new(
+        futures::stream::iter((0..5).map(|_| Bytes::from_static(b"hello"))),
+        ChokeSettings::default().set_backpressure(Some(true)),
+    )
+    .chain(ChokeSettings::default().set_drop_probability(Some(1.0)));
+    let received = tokio::time::timeout(Duration::from_secs(2), chain.count()).await;
+    assert_eq!(received, Ok(0));
+}
+
+#[tokio::test]
+async fn chain_into_parts_returns_pending_items() {
+    let latency = |millis| ChokeSettings::default().set_latency(Some(Latency::Constant(Duration::from_millis(millis))));
+    let mut chain = ChokeStream::new(
+        futures::stream::iter(0..3usize).map(|i| Bytes::from(vec![i as u8])),
+        latency(0),
+    )
+    .chain(latency(60_000))
+    .chain(latency(0));
+    assert!(tokio::time::timeout(Duration::from_millis(50), chain.next())
+        .await
+        .is_err());
+
+    let (_, pending) = chain.into_parts();
+    assert_eq!(pending, [vec![0], vec![1], vec![2]]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn unordered_keeps_items_with_equal_deadlines() {
+    // With the clock paused, all items are taken at the same instant, so the deadlines of equal delays collide
+    let mut delays = [0, 10, 20, 30].into_iter().cycle();
+    let stream = ChokeStream::new(
+        futures::stream::iter((0..10_000u32).map(|i| Bytes::from(i.to_le_bytes().to_vec()))),
+        ChokeSettings::default()
+            .set_ordering(Some(ChokeSettingsOrder::Unordered))
+            .set_latency_distribution(Some(move || delays.next().map(Duration::from_millis))),
+    );
+
+    let output = stream
+        .map(|item| u32::from_le_bytes(item[..].try_into().unwrap()))
+        .collect::<Vec<_>>()
+        .await;
+    // All items arrive, ordered by their delay and in the order they were sent for equal delays
+    let mut expected = (0..10_000).collect::<Vec<_>>();
+    expected.sort_by_key(|i| i % 4);
+    assert_eq!(output, expected);
+}
+
+#[tokio::test(start_paused = true)]
+async fn on_emit_stamps_items_with_the_shaping() {
+    let items = futures::stream::iter((0..4u8).map(|i| (Bytes::from(vec![i; 100]), None)));
+    let settings = ChokeSettings::default()
+        .set_latency(Some(Latency::Constant(Duration::from_millis(20))))
+        .set_duplicate_probability(Some(1.0));
+    let mut stream = ChokeStream::new(items, settings);
+    stream.set_on_emit(|(item, _), info| (item, Some(info)));
+
+    let output = stream.collect::<Vec<_>>().await;
+    assert_eq!(output.len(), 8);
+    for (item, info) in &output {
+        let info = info.unwrap();
+        assert_eq!(usize::from(item[0]), info.seq);
+        assert_eq!(info.delay, (!info.duplicate).then_some(Duration::from_millis(20)));
+        assert!(info.emitted >= info.enqueued + info.delay.unwrap_or_default());
+    }
+    assert_eq!(output.iter().filter(|(_, info)| info.unwrap().duplicate).count(), 4);
+}
+
+#[tokio::test(start_paused = true)]
+async fn on_emit_is_called_once_per_coalesced_item() {
+    let items = futures::stream::iter((0..10u8).map(|i| Bytes::from(vec![i; 100])));
+    let settings = ChokeSettings::default().set_coalescing(Some(Coalescing::builder().max_bytes(450).build()));
+    let mut stream = ChokeStream::new(items, settings);
+    let seqs = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    stream.set_on_emit({
+        let seqs = seqs.clone();
+        move |item, info| {
+            seqs.lock().unwrap().push(info.seq);
+            item
+        }
+    });
+    assert_eq!(stream.count().await, 3);
+    assert_eq!(*seqs.lock().unwrap(), [0, 4, 8]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn shaped_items_carry_their_timing() {
+    let items = futures::stream::iter((0..100u8).map(|i| Bytes::from(vec![i; 100])));
+    let settings = ChokeSettings::default()
+        .set_latency(Some(Latency::Constant(Duration::from_millis(20))))
+        .set_drop_probability(Some(0.2))
+        .set_bandwidth_limit(Some(5_000))
+        .set_seed(Some(7));
+    let mut stream = ChokeStream::new(items, settings).shaped();
+
+    let start = tokio::time::Instant::now().into_std();
+    let mut seqs = Vec::new();
+    while let Some(shaped) = stream.next().await {
+        assert_eq!(usize::from(shaped.item[0]), shaped.info.seq);
+        assert_eq!(shaped.info.delay, Some(Duration::from_millis(20)));
+        assert!(shaped.info.one_way_delay() >= Duration::from_millis(20));
+        assert_eq!(shaped.info.emitted, tokio::time::Instant::now().into_std());
+        assert!(shaped.info.enqueued >= start);
+        seqs.push(shaped.info.seq);
+    }
+    let stats = stream.get_ref().stats();
+    assert_eq!(seqs.len(), stats.emitted);
+    assert!(stats.dropped > 0);
+    assert!(seqs.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[tokio::test(start_paused = true)]
+async fn timing_stats_percentiles() {
+    let items = futures::stream::iter((0..100u8).map(|i| Bytes::from(vec![i; 100])));
+    let latency = Latency::Normal {
+        mean: Duration::from_millis(20),
+        std_dev: Duration::from_millis(5),
+        max: Duration::from_millis(30),
+    };
+    let settings = ChokeSettings::default().set_latency(Some(latency)).set_seed(Some(3));
+    let mut stream = ChokeStream::new(items, settings);
+    assert_eq!(stream.timing_stats().sojourn.p95(), None);
+    assert_eq!((&mut stream).count().await, 100);
+
+    let stats = stream.timing_stats();
+    let delay = &stats.delay;
+    assert_eq!(delay.count(), 100);
+    assert!(delay.p50().unwrap() > Duration::from_millis(15) && delay.p50().unwrap() < Duration::from_millis(25));
+    assert!(delay.p50() <= delay.p90() && delay.p90() <= delay.p95() && delay.p95() <= delay.p99());
+    assert_eq!(delay.max(), Some(Duration::from_millis(30)));
+    // Ordered items wait for the items before them
+    let sojourn = &stats.sojourn;
+    assert_eq!(sojourn.count(), 100);
+    assert!(sojourn.p50() > delay.p50(), "{sojourn:?}");
+    assert_eq!(sojourn.max(), Some(Duration::from_millis(30)));
+}
+
+#[tokio::test(start_paused = true)]
+async fn waiting_shows_queueing_beyond_the_latency() {
+    let items = futures::stream::iter((0..50u8).map(|i| Bytes::from(vec![i; 100])));
+    let settings = ChokeSettings::default()
+        .set_latency(Some(Latency::Constant(Duration::from_millis(10))))
+        .set_bandwidth_limit_with(
+            BandwidthLimit::builder()
+                .bytes_per_sec(10_000)
+                .only_drop_when_full(true)
+                .pacing(true),
+        );
+    let mut stream = ChokeStream::new(items, settings);
+    assert_eq!((&mut stream).count().await, 50);
+
+    let stats = stream.timing_stats();
+    assert_eq!(stats.delay.max(), Some(Duration::from_millis(10)));
+    assert_eq!(stats.waiting.count(), 50);
+    assert_eq!(stats.waiting.percentile(0.0), Some(Duration::ZERO));
+    // 100 bytes every 10ms
+    let waited = stats.waiting.max().unwrap();
+    assert!(waited >= Duration::from_millis(450), "{stats:?}");
+    assert_eq!(stats.sojourn.max(), Some(waited + Duration::from_millis(10)));
+}
+
+/// Collects the logs of a test.
+#[derive(Clone, Default)]
+struct Logs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Logs {
+    /// The logged statistics, one line each.
+    fn stats(&self) -> Vec<String> {
+        String::from_utf8_lossy(&self.0.lock().unwrap())
+            .lines()
+            .filter(|line| line.contains("items_per_second="))
+            .map(str::to_owned)
+            .collect()
+    }
+}
+
+async fn log_stats(settings: ChokeSettings) -> Logs {
+    let logs = Logs::default();
+    let writer = logs.clone();
+    let _guard = tracing::subscriber::set_default(
+        tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish(),
+    );
+    let (tx, rx) = mpsc::unbounded_channel();
+    let stream = ChokeStream::new(UnboundedReceiverStream::new(rx), settings);
+    tokio::spawn(async move {
+        for i in 0..10u8 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            tx.send(Bytes::from(vec![i; 100])).unwrap();
+        }
+    });
+    assert_eq!(stream.count().await, 10);
+    logs
+}
+
+#[tokio::test(start_paused = true)]
+async fn stats_are_logged_every_interval() {
+    let logs = log_stats(ChokeSettings::default().set_stats_interval(Some(Duration::from_millis(250)))).await;
+    let stats = logs.stats();
+    // At 250, 500, 750 and 1000ms, an item arrives every 100ms
+    assert_eq!(stats.len(), 4, "{stats:?}");
+    assert!(
+        stats[0].contains("items_per_second=8.0 bytes_per_second=800.0 "),
+        "{stats:?}"
+    );
+    assert!(stats[1].contains("emitted=4 "), "{stats:?}");
+}
+
+#[tokio::test(start_paused = true)]
+async fn logs_carry_the_label() {
+    let logs = log_stats(
+        ChokeSettings::default()
+            .set_stats_interval(Some(Duration::from_millis(250)))
+            .set_label("uplink"),
+    )
+    .await;
+    let stats = logs.stats();
+    assert_eq!(stats.len(), 4, "{stats:?}");
+    assert!(
+        stats.iter().all(|line| line.contains("chokepoint{label=uplink}")),
+        "{stats:?}"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn stats_are_not_logged_by_default() {
+    let logs = log_stats(ChokeSettings::default()).await;
+    assert_eq!(logs.stats(), Vec::<String>::new());
+}
+
+#[tokio::test(start_paused = true)]
+async fn timing_stats_show_added_burstiness() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut stream = ChokeStream::new(
+        UnboundedReceiverStream::new(rx),
+        ChokeSettings::default().set_burst(Some(Burst::builder().size(4).build())),
+    );
+    tokio::spawn(async move {
+        for i in 0..20u8 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            tx.send(Bytes::from(vec![i])).unwrap();
+        }
+    });
+    assert_eq!((&mut stream).count().await, 20);
+
+    let stats = stream.timing_stats();
+    assert_eq!(stats.inter_arrival.count(), 19);
+    assert_eq!(stats.inter_arrival.p50(), Some(Duration::from_millis(10)));
+    assert_eq!(stats.inter_arrival.max(), Some(Duration::from_millis(10)));
+    // Released in bursts of 4 items every 40ms
+    assert_eq!(stats.inter_departure.count(), 19);
+    assert_eq!(stats.inter_departure.p50(), Some(Duration::ZERO));
+    assert_eq!(stats.inter_departure.max(), Some(Duration::from_millis(40)));
+}
+
+#[tokio::test(start_paused = true)]
+async fn goodput_excludes_duplicates_and_corrupted_items() {
+    let items = futures::stream::iter((0..100u8).map(|i| Bytes::from(vec![i; 100])));
+    let settings = ChokeSettings::default()
+        .set_corrupt_probability(Some(0.3))
+        .set_duplicate_probability(Some(0.5))
+        .set_seed(Some(5));
+    let mut stream = ChokeStream::new(items, settings);
+    assert_eq!((&mut stream).count().await, 100 + stream.stats().duplicated);
+
+    let stats = stream.stats();
+    assert!(stats.corrupted > 0 && stats.duplicated > 0);
+    assert_eq!(stats.emitted_bytes, 100 * stats.emitted);
+    assert_eq!(stats.goodput_bytes, 100 * (stats.received - stats.corrupted));
+    assert!(stats.corrupted_bytes >= 100 * stats.corrupted);
+    assert!(stats.goodput_bytes + stats.corrupted_bytes < stats.emitted_bytes);
+}
+
+#[tokio::test(start_paused = true)]
+async fn drops_are_counted_by_reason() {
+    let items = futures::stream::iter((0..100u8).map(|i| Bytes::from(vec![i; 100])));
+    let latency = Latency::Normal {
+        mean: Duration::from_millis(10),
+        std_dev: Duration::from_millis(5),
+        max: Duration::from_millis(20),
+    };
+    let settings = ChokeSettings::default()
+        .set_drop_probability(Some(0.2))
+        .set_latency(Some(latency))
+        .set_ordering(Some(ChokeSettingsOrder::Unordered))
+        .set_item_ttl(Some(Duration::from_millis(12)))
+        .set_seed(Some(9));
+    let mut stream = ChokeStream::new(items, settings);
+    (&mut stream).count().await;
+
+    let stats = stream.stats();
+    assert!(stats.dropped > 0 && stats.expired > 0, "{stats}");
+    assert_eq!(stats.dropped_by.random, stats.dropped);
+    assert_eq!(stats.dropped_by.get(DropReason::Expired), stats.expired);
+    assert_eq!(
+        stats.dropped_by.bandwidth_limit + stats.dropped_by.hook + stats.dropped_by.replay,
+        0
+    );
+    assert_eq!(stats.emitted + stats.dropped + stats.expired, 100);
+}
+
+#[tokio::test(start_paused = true)]
+async fn queue_occupancy_and_high_water_marks() {
+    let items = futures::stream::iter((0..50u8).map(|i| Bytes::from(vec![i; 100])));
+    let settings = ChokeSettings::default().set_latency(Some(Latency::Constant(Duration::from_millis(100))));
+    let mut stream = ChokeStream::new(items, settings);
+
+    stream.next().await.unwrap();
+    let stats = stream.stats();
+    assert_eq!((stats.queued, stats.queued_bytes, stats.delayed), (49, 4900, 49));
+
+    assert_eq!((&mut stream).count().await, 49);
+    let stats = stream.stats();
+    assert_eq!((stats.queued, stats.queued_bytes, stats.delayed), (0, 0, 0));
+    assert_eq!(
+        (stats.max_queued, stats.max_queued_bytes, stats.max_delayed),
+        (50, 5000, 50)
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn ordering_change_drops_queued_items() {
+    let items = futures::stream::iter((0..10u8).map(|i| Bytes::from(vec![i; 100])));
+    let settings = ChokeSettings::default()
+        .set_ordering(Some(ChokeSettingsOrder::Ordered))
+        .set_latency(Some(Latency::Constant(Duration::from_millis(100))));
+    let mut stream = ChokeStream::new(items, settings);
+
+    stream.next().await.unwrap();
+    assert_eq!(stream.stats().queued, 9);
+
+    stream.apply_settings(ChokeSettings::default().set_ordering(Some(ChokeSettingsOrder::Unordered)));
+    let stats = stream.stats();
+    assert_eq!((stats.queued, stats.queued_bytes, stats.delayed), (0, 0, 0));
+    assert_eq!((stats.dropped, stats.dropped_by.reconfigured), (9, 9));
+    assert_eq!((&mut stream).count().await, 0);
+}
+
+#[cfg(chokepoint_local)]
+#[tokio::test]
+async fn local_latency_distribution() {
+    use std::{
+        cell::Cell,
+        rc::Rc,
+    };
+
+    let samples = Rc::new(Cell::new(0));
+    let stream = ChokeStream::new(
+        futures::stream::iter((0..3usize).map(|i| Bytes::from(i.to_le_bytes().to_vec()))),
+        ChokeSettings::default().set_latency_distribution(Some({
+            let samples = samples.clone();
+            move || {
+                samples.set(samples.get() + 1);
+                Some(Duration::from_millis(10))
+            }
+        })),
+    );
+
+    assert_eq!(stream.collect::<Vec<_>>().await.len(), 3);
+    assert_eq!(samples.get(), 3);
+}
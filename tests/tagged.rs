@@ -0,0 +1,150 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use bytes::Bytes;
+use chokepoint::{
+    sim::Simulation,
+    tagged::{
+        tag,
+        SequenceAnalysis,
+        Tagged,
+    },
+    test_util::{
+        assert_loss_rate_within,
+        assert_monotonic_order,
+        reorder_metrics,
+    },
+    ChokeSettings,
+    ChokeSettingsOrder,
+    ChokeStream,
+    ReorderWindow,
+};
+use futures::StreamExt as _;
+use rand::{
+    rngs::StdRng,
+    Rng as _,
+    SeedableRng as _,
+};
+use std::time::Duration;
+
+#[test]
+fn analysis_matches_stats() {
+    let mut sim = Simulation::new(3);
+    let mut rng = sim.rng();
+    let mut settings = sim.settings();
+    settings.merge(
+        ChokeSettings::default()
+            .set_drop_probability(Some(0.1))
+            .set_duplicate_probability(Some(0.05))
+            .set_ordering(Some(ChokeSettingsOrder::Unordered))
+            .set_latency_distribution(Some(move || {
+                Some(Duration::from_nanos(rng.random_range(10_000_000..100_000_000)))
+            })),
+    );
+    let items = futures::stream::iter((0..1000).map(|_| Bytes::from_static(b"payload")));
+    let report = sim.run(vec![ChokeStream::new(tag(items), settings)]).remove(0);
+
+    let analysis = SequenceAnalysis::from_items(report.items.iter().map(|(_, item)| item), 1000);
+    assert_eq!(analysis.received, report.items.len());
+    assert_eq!(analysis.lost.len(), report.stats.dropped);
+    assert_eq!(analysis.duplicate_count(), report.stats.duplicated);
+    assert!(!analysis.reordered.is_empty());
+    assert!(analysis.max_reorder_distance() > 1);
+    assert!(report.items.iter().all(|(_, tagged)| tagged.item == b"payload"[..]));
+}
+
+#[test]
+fn assertion_helpers() {
+    let mut sim = Simulation::new(3);
+    let mut rng = sim.rng();
+    let mut settings = sim.settings();
+    settings.merge(
+        ChokeSettings::default()
+            .set_drop_probability(Some(0.1))
+            .set_latency_distribution(Some(move || Some(Duration::from_millis(rng.random_range(10..100))))),
+    );
+    let items = futures::stream::iter((0..1000).map(|_| Bytes::from_static(b"payload")));
+    let report = sim.run(vec![ChokeStream::new(tag(items), settings)]).remove(0);
+    let output = report.items.into_iter().map(|(_, item)| item).collect::<Vec<_>>();
+
+    assert_monotonic_order(&output);
+    assert_loss_rate_within(&output, 1000, 0.1, 0.03);
+    assert_eq!(reorder_metrics(&output).reordered, 0);
+
+    let reordered = [0, 3, 1, 2, 4].map(|id| Tagged::new(id, ()));
+    let metrics = reorder_metrics(&reordered);
+    assert_eq!(metrics.reordered, 2);
+    assert_eq!(metrics.ratio, 0.4);
+    assert_eq!(metrics.max_distance, 2);
+    assert_eq!(metrics.mean_distance, 1.5);
+}
+
+#[test]
+#[should_panic(expected = "id 1 at position 2 was received after id 3")]
+fn monotonic_order_fails_for_reordered_output() {
+    assert_monotonic_order(&[0, 3, 1].map(|id| Tagged::new(id, ())));
+}
+
+#[test]
+#[should_panic(expected = "loss rate 0.5000 (2 of 4) is not within 0.1 ± 0.2")]
+fn loss_rate_fails_outside_tolerance() {
+    assert_loss_rate_within(&[0, 2].map(|id| Tagged::new(id, ())), 4, 0.1, 0.2);
+}
+
+#[test]
+fn ordered_without_loss_is_perfect() {
+    let mut sim = Simulation::new(3);
+    let mut rng = sim.rng();
+    let mut settings = sim.settings();
+    settings.merge(
+        ChokeSettings::default()
+            .set_latency_distribution(Some(move || Some(Duration::from_millis(rng.random_range(10..100))))),
+    );
+    let items = futures::stream::iter((0..1000).map(|_| Bytes::from_static(b"payload")));
+    let report = sim.run(vec![ChokeStream::new(tag(items), settings)]).remove(0);
+
+    let analysis = SequenceAnalysis::from_items(report.items.iter().map(|(_, item)| item), 1000);
+    assert!(analysis.is_perfect(), "{analysis:?}");
+}
+
+fn unordered_with_window(mut rng: StdRng, window: ReorderWindow) -> ChokeSettings {
+    ChokeSettings::default()
+        .set_ordering(Some(ChokeSettingsOrder::Unordered))
+        .set_latency_distribution(Some(move || {
+            Some(Duration::from_nanos(rng.random_range(0..100_000_000)))
+        }))
+        .set_reorder_window(Some(window))
+}
+
+#[test]
+fn reorder_window_bounds_distance() {
+    let mut sim = Simulation::new(3);
+    let mut settings = sim.settings();
+    let window = ReorderWindow::builder().max_distance(3).build();
+    settings.merge(unordered_with_window(sim.rng(), window));
+    let items = futures::stream::iter((0..1000).map(|_| Bytes::from_static(b"payload")));
+    let report = sim.run(vec![ChokeStream::new(tag(items), settings)]).remove(0);
+
+    let analysis = SequenceAnalysis::from_items(report.items.iter().map(|(_, item)| item), 1000);
+    assert!(analysis.lost.is_empty());
+    assert!(!analysis.reordered.is_empty());
+    assert_eq!(analysis.max_reorder_distance(), 3);
+}
+
+#[tokio::test(start_paused = true)]
+async fn reorder_window_bounds_time() {
+    // An item every 10ms, so only the next 2 items are sent within 25ms
+    let items = futures::stream::iter(0..200)
+        .then(|_| async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Bytes::from_static(b"payload")
+        })
+        .boxed();
+    let window = ReorderWindow::builder().max_time(Duration::from_millis(25)).build();
+    let settings = unordered_with_window(StdRng::seed_from_u64(3), window);
+    let output = ChokeStream::new(tag(items), settings).collect::<Vec<_>>().await;
+
+    let analysis = SequenceAnalysis::from_items(&output, 200);
+    assert!(analysis.lost.is_empty());
+    assert!(!analysis.reordered.is_empty());
+    assert_eq!(analysis.max_reorder_distance(), 2);
+}
@@ -0,0 +1,92 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use bytes::Bytes;
+use chokepoint::{
+    ChokeSettings,
+    ChokeTransport,
+};
+use futures::{
+    channel::mpsc,
+    Sink,
+    SinkExt as _,
+    Stream,
+    StreamExt as _,
+};
+use std::{
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// A transport that echoes everything written to it back to the reader.
+struct Loopback {
+    tx: mpsc::UnboundedSender<Bytes>,
+    rx: mpsc::UnboundedReceiver<Bytes>,
+}
+
+impl Loopback {
+    fn new() -> Self {
+        let (tx, rx) = mpsc::unbounded();
+        Self { tx, rx }
+    }
+}
+
+impl Stream for Loopback {
+    type Item = Bytes;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx)
+    }
+}
+
+impl Sink<Bytes> for Loopback {
+    type Error = mpsc::SendError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.tx.poll_ready_unpin(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        self.tx.start_send_unpin(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.tx.poll_flush_unpin(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.tx.poll_close_unpin(cx)
+    }
+}
+
+#[tokio::test]
+async fn independent_directions() {
+    let latency = |ms| Some(move || Some(Duration::from_millis(ms)));
+    let mut read = ChokeSettings::default().set_latency_distribution(latency(50));
+    let write = ChokeSettings::default().set_latency_distribution(latency(100));
+    let read_updater = read.settings_watcher();
+
+    let mut transport = ChokeTransport::new(Loopback::new(), read, write);
+
+    let start = Instant::now();
+    transport.send(Bytes::from_static(b"a")).await.unwrap();
+    assert_eq!(transport.next().await.unwrap(), Bytes::from_static(b"a"));
+    assert!(start.elapsed() >= Duration::from_millis(150));
+
+    read_updater.send_replace(ChokeSettings::default().set_latency_distribution(latency(0)));
+
+    let start = Instant::now();
+    transport.send(Bytes::from_static(b"b")).await.unwrap();
+    assert_eq!(transport.next().await.unwrap(), Bytes::from_static(b"b"));
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed >= Duration::from_millis(100) && elapsed < Duration::from_millis(150),
+        "{elapsed:?}"
+    );
+}
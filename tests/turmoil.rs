@@ -0,0 +1,76 @@
+#![cfg(all(feature = "turmoil", not(target_arch = "wasm32")))]
+
+use chokepoint::{
+    turmoil::{
+        udp_sink,
+        udp_stream,
+        Datagram,
+    },
+    ChokeSettings,
+};
+use futures::{
+    SinkExt as _,
+    StreamExt as _,
+};
+use std::{
+    net::{
+        Ipv4Addr,
+        SocketAddr,
+    },
+    sync::Arc,
+    time::Duration,
+};
+use turmoil::net::UdpSocket;
+
+const PORT: u16 = 1234;
+
+fn latency(millis: u64) -> ChokeSettings {
+    ChokeSettings::default().set_latency_distribution(Some(move || Some(Duration::from_millis(millis))))
+}
+
+#[test]
+fn udp_round_trip() -> turmoil::Result {
+    // A fixed link latency keeps the order of the datagrams
+    let mut sim = turmoil::Builder::new()
+        .min_message_latency(Duration::from_millis(10))
+        .max_message_latency(Duration::from_millis(10))
+        .build();
+
+    // Echoes every datagram, receiving is delayed by 200ms
+    sim.host("server", || async {
+        let socket = Arc::new(UdpSocket::bind((Ipv4Addr::UNSPECIFIED, PORT)).await?);
+        let mut datagrams = udp_stream(socket.clone(), latency(200));
+        while let Some(datagram) = datagrams.next().await {
+            let datagram = datagram?;
+            socket.send_to(&datagram.payload, datagram.addr).await?;
+        }
+        Ok(())
+    });
+
+    // Sending is delayed by 300ms
+    sim.client("client", async {
+        let socket = Arc::new(UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?);
+        let server = SocketAddr::from((turmoil::lookup("server"), PORT));
+        let start = tokio::time::Instant::now();
+
+        let mut sink = udp_sink(socket.clone(), latency(300));
+        for i in 0..5u8 {
+            sink.feed(Datagram::new(vec![i], server)).await?;
+        }
+        sink.close().await?;
+
+        let mut buf = [0; 16];
+        for i in 0..5u8 {
+            let (len, _) = socket.recv_from(&mut buf).await?;
+            assert_eq!(&buf[..len], &[i]);
+        }
+        let rtt = start.elapsed();
+        assert!(
+            rtt >= Duration::from_millis(520) && rtt < Duration::from_millis(600),
+            "{rtt:?}"
+        );
+        Ok(())
+    });
+
+    sim.run()
+}
@@ -0,0 +1,58 @@
+#![cfg(target_arch = "wasm32")]
+
+use bytes::Bytes;
+use chokepoint::{
+    ChokeSettings,
+    ChokeSink,
+    ChokeStream,
+};
+use futures::{
+    channel::mpsc,
+    SinkExt as _,
+    StreamExt as _,
+};
+use std::time::Duration;
+use wasm_bindgen_test::wasm_bindgen_test;
+use wasmtimer::std::Instant;
+
+fn items() -> impl futures::Stream<Item = Bytes> {
+    futures::stream::iter((0..3usize).map(|i| Bytes::from(i.to_le_bytes().to_vec())))
+}
+
+#[wasm_bindgen_test]
+async fn stream_delays_items() {
+    let stream = ChokeStream::new(
+        items(),
+        ChokeSettings::default().set_latency_distribution(Some(|| Some(Duration::from_millis(50)))),
+    );
+
+    let start = Instant::now();
+    let output = stream.collect::<Vec<_>>().await;
+    assert_eq!(output, items().collect::<Vec<_>>().await);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[wasm_bindgen_test]
+async fn sink_forwards_delayed_items_on_close() {
+    let (tx, rx) = mpsc::unbounded();
+    let mut sink = ChokeSink::new(
+        tx,
+        ChokeSettings::default().set_latency_distribution(Some(|| Some(Duration::from_millis(50)))),
+    );
+
+    let start = Instant::now();
+    let mut input = items();
+    while let Some(item) = input.next().await {
+        sink.send(item).await.unwrap();
+    }
+    sink.close().await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    assert_eq!(rx.collect::<Vec<_>>().await, items().collect::<Vec<_>>().await);
+}
+
+#[wasm_bindgen_test]
+async fn drop_probability() {
+    let stream = ChokeStream::new(items(), ChokeSettings::default().set_drop_probability(Some(1.0)));
+    assert!(stream.collect::<Vec<_>>().await.is_empty());
+}